@@ -1,6 +1,6 @@
 use crate::structs::SortType;
 use crate::structs::{
-    Document, RESTSearchData, Relation, RelationSearchOutput, RelationalSearchParameters,
+    Cursor, Document, Facets, RESTSearchData, Relation, RelationSearchOutput, RelationalSearchParameters,
     SearchParameters, UserFeedback,
 };
 use crate::{RelationDocument, SearchOutput};
@@ -12,24 +12,50 @@ use actix_web::{
     web::{Data, Json, Query},
     HttpResponse, Responder, Result,
 };
-use futures::stream::{FuturesUnordered, FuturesOrdered};
-use futures::StreamExt;
 
 use index::index_structs::Posting;
 use log::{debug, info};
 
-use parser::parser::parse_query;
-use retrieval::search::{execute_query, preprocess_query, score_query, ScoredDocument};
-use retrieval::{execute_relational_query, ScoredRelationDocument};
-use sqlx::postgres::PgPoolOptions;
-use sqlx::Row;
+use parser::ast::Query as QueryNode;
+use parser::parser::{parse_date, parse_query};
+use retrieval::search::{
+    execute_query_documents_within, execute_query_within, needs_positions, preprocess_query,
+    score_query_within, ScoredDocument,
+};
+use retrieval::{
+    build_snippet, count_facets, execute_relational_query, filter_by_facets, matched_positions,
+    matched_positions_within, supports_top_k, top_k_query_within, Facet, QueryBudget, ScoredRelationDocument,
+    FACET_LIMIT, SNIPPET_LENGTH,
+};
 use std::cmp::{min, Ordering, max};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::{self, Display};
 
 use retrieval::correct_query;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+const DEFAULT_QUERY_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_QUERY_MAX_POSTINGS: u64 = 50_000_000;
+
+/// the limits on the work done for a single search, a limit of 0 lifts it
+fn query_budget() -> QueryBudget {
+    let limit = |var: &str, default: u64| {
+        match env::var(var)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(default)
+        {
+            0 => None,
+            v => Some(v),
+        }
+    };
+
+    QueryBudget::new(
+        limit("QUERY_TIMEOUT_MS", DEFAULT_QUERY_TIMEOUT_MS).map(Duration::from_millis),
+        limit("QUERY_MAX_POSTINGS", DEFAULT_QUERY_MAX_POSTINGS),
+    )
+}
+
 pub struct APIError {
     pub code: StatusCode,
     pub msg: String,
@@ -102,6 +128,28 @@ pub async fn search(
         ))
     }
 
+    if q.page.0 == 0 {
+        let msg = "Pages are numbered from 1, please request a valid page.".to_string();
+        return Err(APIError::new_user_error(&msg, &msg));
+    }
+    if q.results_per_page.0 == 0 {
+        let msg = "At least one result per page has to be requested.".to_string();
+        return Err(APIError::new_user_error(&msg, &msg));
+    }
+    let cursor = match &q.cursor {
+        Some(c) => Some(
+            Cursor::decode(c)
+                .filter(|c| c.sort_by == q.sort_by)
+                .ok_or_else(|| {
+                    APIError::new_user_error(
+                        &"The cursor provided is not valid for this search.",
+                        &format!("invalid cursor: {}", c),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+
     // construct + execute query
     let idx = data
         .index_rest
@@ -109,9 +157,33 @@ pub async fn search(
         .map_err(|e| APIError::new_internal_error(&e))?;
     let (_, ref mut query) = parse_query(&q.query)
         .map_err(|e| APIError::new_user_error(&format!("Your query: {} is not valid, please form a valid query.",q.query),&e))?;
+
+    // a date window restricts whatever was queried, the same as an `#EDITED` clause would
+    let bound = |b: &Option<String>| match b {
+        Some(v) => match parse_date(v) {
+            Ok(("", d)) => Ok(Some(d)),
+            _ => Err(APIError::new_user_error(
+                &format!("`{}` is not a valid date, please use the YYYY-MM-DD format.", v),
+                v,
+            )),
+        },
+        None => Ok(None),
+    };
+    let after = bound(&q.edited_after)?;
+    let before = bound(&q.edited_before)?;
+    if after.is_some() || before.is_some() {
+        *query = Box::new(QueryNode::EditedQuery {
+            after,
+            before,
+            sub: query.clone(),
+        });
+    }
     
 
 
+    // the clock starts before any of the heavy lifting
+    let budget = query_budget();
+
     let mut timer = Instant::now();
     preprocess_query(query)
         .map_err(|e| APIError::new_user_error(
@@ -123,8 +195,27 @@ pub async fn search(
         timer.elapsed().as_secs_f32()
     );
 
+    let selected_facets = q
+        .category
+        .iter()
+        .map(|c| Facet::Category(c.clone()))
+        .chain(q.infobox.iter().map(|i| Facet::Infobox(i.clone())))
+        .collect::<Vec<Facet>>();
+    let ranking = q.ranking.into();
+    // without a cursor or facet filters only the documents up to the end of the page need ranking,
+    // the rest of the matches are only counted from their documents
+    let top_k = q.sort_by == SortType::Relevance
+        && cursor.is_none()
+        && selected_facets.is_empty()
+        && supports_top_k(query, ranking);
+
     timer = Instant::now();
-    let postings_query = execute_query(query, &idx);
+    // positions are only read when the query can't be answered without them
+    let positional = !top_k && needs_positions(query);
+    let postings_query = match positional {
+        true => execute_query_within(query, &idx, &budget),
+        false => execute_query_documents_within(query, &idx, &budget),
+    };
     info!("executed query: {}s", timer.elapsed().as_secs_f32());
 
     timer = Instant::now();
@@ -135,31 +226,62 @@ pub async fn search(
     timer = Instant::now();
     let mut postings = postings_query.collect::<Vec<Posting>>();
 
-    let capped_max_results = min(q.results_per_page.0, 150);
-    // score documents if necessary and sort appropriately
+    filter_by_facets(&mut postings, &selected_facets, &idx);
+
+    // counted over every result rather than just the page
+    let facets = match q.facets {
+        true => {
+            let documents = postings.iter().map(|p| p.document_id).collect();
+            Some(Facets::from(count_facets(&documents, &idx, FACET_LIMIT)))
+        }
+        false => None,
+    };
+    // gathered before the postings are deduplicated, otherwise only for the page once it is known
+    let positions = match positional {
+        true => Some(matched_positions(&postings)),
+        false => None,
+    };
+
+    // postings are ordered by document, so this leaves one per matching document
+    postings.dedup_by_key(|v| v.document_id);
+    let total_hits = postings.len();
+
+    let capped_max_results = min(q.results_per_page.0, 150) as usize;
+    // score documents if necessary and sort appropriately, ties are broken by document id
     let ordered_docs: Vec<ScoredDocument> = match q.sort_by {
         SortType::Relevance => {
-            let mut scored_documents = score_query(query, &idx, &mut postings);
-            scored_documents.sort_unstable_by(|doc1, doc2| {
-                doc2.score
-                    .partial_cmp(&doc1.score)
-                    .unwrap_or(Ordering::Less)
-            });
-            scored_documents
-                .into_iter() // consumes scored_documents
-                .skip((capped_max_results * (q.page.0 - 1)) as usize)
-                .take(capped_max_results as usize)
-                .collect()
+            let top_k = match top_k {
+                true => top_k_query_within(
+                    query,
+                    &idx,
+                    capped_max_results.saturating_mul(q.page.0 as usize),
+                    ranking,
+                    &budget,
+                ),
+                false => None,
+            };
+
+            match top_k {
+                Some(scored_documents) => scored_documents,
+                None => {
+                    let mut scored_documents =
+                        score_query_within(query, &idx, &mut postings, ranking, &budget);
+                    scored_documents.sort_unstable_by(|doc1, doc2| {
+                        doc2.score
+                            .partial_cmp(&doc1.score)
+                            .unwrap_or(Ordering::Less)
+                            .then(doc1.doc_id.cmp(&doc2.doc_id))
+                    });
+                    scored_documents
+                }
+            }
         }
         SortType::LastEdited => {
-            postings.dedup_by_key(|v| v.document_id);
             postings.sort_by_cached_key(|Posting { document_id, .. }| {
                 idx.get_last_updated_date(*document_id)
             });
             postings
                 .into_iter() // consumes postings
-                .skip((capped_max_results * (q.page.0 - 1)) as usize)
-                .take(capped_max_results as usize)
                 .map(|p| ScoredDocument {
                     doc_id: p.document_id,
                     score: 1.0,
@@ -168,39 +290,78 @@ pub async fn search(
         }
     };
 
-    let future_documents = ordered_docs
-        .into_iter() // consumes ordered_docs
+    // whatever was found in time is returned, as long as there is something
+    let partial_results = match budget.exceeded() {
+        Some(reason) if ordered_docs.is_empty() => {
+            return Err(APIError::new_user_error(
+                &"Your query took too long to run, please make it more specific.",
+                &format!(
+                    "query budget exceeded ({:?}) after reading {} postings",
+                    reason,
+                    budget.postings_read()
+                ),
+            ));
+        }
+        Some(reason) => {
+            info!(
+                "Query: {} exceeded its budget ({:?}), returning partial results",
+                &q.query, reason
+            );
+            true
+        }
+        None => false,
+    };
+
+    let offset = match cursor {
+        Some(c) => ordered_docs
+            .iter()
+            .position(|d| c.precedes(d, &idx))
+            .unwrap_or(ordered_docs.len()),
+        None => capped_max_results * (q.page.0 as usize - 1),
+    };
+    let page_docs = ordered_docs
+        .iter()
+        .skip(offset)
+        .take(capped_max_results)
+        .collect::<Vec<&ScoredDocument>>();
+
+    // pages past partial results or an empty page could never be filled
+    let has_more =
+        !partial_results && !page_docs.is_empty() && offset + page_docs.len() < total_hits;
+    let next_cursor = match (has_more, page_docs.last()) {
+        (true, Some(last)) => Some(Cursor::at(q.sort_by, last, &idx).encode()),
+        _ => None,
+    };
+
+    let positions = positions.unwrap_or_else(|| {
+        let page_ids = page_docs.iter().map(|d| d.doc_id).collect::<Vec<u32>>();
+        matched_positions_within(query, &idx, &page_ids)
+    });
+
+    let documents = page_docs
+        .into_iter()
         .map(|doc| {
-            let pool_cpy = data.pool.clone();
-            async move {
-                let sql = sqlx::query(
-                    "SELECT a.title, c.abstracts
-                    From article as a, \"content\" as c
-                    where a.articleid= $1 AND a.articleid = c.articleid",
-                )
-                .bind(doc.doc_id as i64)
-                .fetch_one(&pool_cpy)
-                .await
-                .map_err(|e| APIError::new_internal_error(&e))?;
-
-                let title: String = sql
-                    .try_get("title")
-                    .map_err(|e| APIError::new_internal_error(&e))?;
-                let abstracts: String = sql
-                    .try_get("abstracts")
-                    .map_err(|e| APIError::new_internal_error(&e))?;
-                Ok::<Document, APIError>(Document {
-                    id: doc.doc_id,
-                    title: title,
-                    article_abstract: abstracts,
-                    score: doc.score,
-                })
-            }
+            let metadata = idx.get_metadata(doc.doc_id).ok_or_else(|| {
+                APIError::new_internal_error(&format!("No metadata for document {}", doc.doc_id))
+            })?;
+
+            let snippet = build_snippet(
+                doc.doc_id,
+                positions.get(&doc.doc_id).map(|v| v.as_slice()).unwrap_or(&[]),
+                &idx,
+                SNIPPET_LENGTH,
+            )
+            .unwrap_or_default();
+
+            Ok(Document {
+                id: doc.doc_id,
+                title: metadata.title.clone(),
+                article_abstract: metadata.article_abstract.clone(),
+                score: doc.score,
+                snippet: snippet.text,
+                highlights: snippet.highlights,
+            })
         })
-        .collect::<FuturesOrdered<_>>()
-        .collect::<Vec<Result<Document, APIError>>>()
-        .await
-        .into_iter()
         .collect::<Result<Vec<Document>, APIError>>()?; // fail on a single internal error
 
     info!("sorted query: {}s", timer.elapsed().as_secs_f32());
@@ -212,9 +373,16 @@ pub async fn search(
     );
 
     Ok(Json(SearchOutput {
-        documents: future_documents,
+        documents: documents,
         domain: env::var("DOMAIN").unwrap_or("en".to_string()),
         suggested_query: suggested_query,
+        total_hits,
+        page: offset / capped_max_results + 1,
+        results_per_page: capped_max_results,
+        has_more,
+        next_cursor,
+        partial_results,
+        facets: facets,
     }))
 }
 
@@ -233,34 +401,20 @@ pub async fn relational(
         ))
     }
 
-    // construct + execute query
-    let root_article = sqlx::query(
-        "SELECT a.articleid
-        From article as a
-        where a.title=$1",
-    )
-    .bind(q.root.clone())
-    .fetch_one(&data.pool)
-    .await
-    .map_err(|e| {
-        APIError::new_user_error(
-            &format!(
-                "The root article provided `{}` is not a valid root article title",
-                q.root
-            ),
-            &e,
-        )
-    })?;
-
-    let root_id: i64 = root_article
-        .try_get("articleid")
-        .map_err(|e| APIError::new_internal_error(&e))?;
-
     let idx = data
         .index_rest
         .read()
         .map_err(|e| APIError::new_internal_error(&e))?;
 
+    // construct + execute query
+    let root_id = idx.get_document_id(&q.root).ok_or_else(|| {
+        let msg = format!(
+            "The root article provided `{}` is not a valid root article title",
+            q.root
+        );
+        APIError::new_user_error(&msg, &msg)
+    })?;
+
     let query_string = format!(
         "#LINKSTO, {},{} {}",
         root_id,
@@ -295,38 +449,18 @@ pub async fn relational(
     let documents = scored_documents
         .iter()
         .map(|doc| {
-            let pool_cpy = data.pool.clone();
-            async move {
-                let sql = sqlx::query(
-                    "SELECT a.title, c.abstracts
-                From article as a, \"content\" as c
-                where a.articleid= $1 AND a.articleid = c.articleid",
-                )
-                .bind(doc.doc_id as i64)
-                .fetch_one(&pool_cpy)
-                .await
-                .map_err(|e| APIError::new_internal_error(&e))?;
-
-                let title: String = sql
-                    .try_get("title")
-                    .map_err(|e| APIError::new_internal_error(&e))?;
-                let abstracts: String = sql
-                    .try_get("abstracts")
-                    .map_err(|e| APIError::new_internal_error(&e))?;
-
-                Ok::<RelationDocument, APIError>(RelationDocument {
-                    id: doc.doc_id,
-                    title: title,
-                    article_abstract: abstracts,
-                    score: doc.score,
-                    hops: doc.hops,
-                })
-            }
+            let metadata = idx.get_metadata(doc.doc_id).ok_or_else(|| {
+                APIError::new_internal_error(&format!("No metadata for document {}", doc.doc_id))
+            })?;
+
+            Ok(RelationDocument {
+                id: doc.doc_id,
+                title: metadata.title.clone(),
+                article_abstract: metadata.article_abstract.clone(),
+                score: doc.score,
+                hops: doc.hops,
+            })
         })
-        .collect::<FuturesOrdered<_>>()
-        .collect::<Vec<Result<RelationDocument, APIError>>>()
-        .await
-        .into_iter()
         .collect::<Result<Vec<RelationDocument>, APIError>>()?; // fail on a single internal error
 
     let mut title_map: HashMap<u32, &str> = HashMap::with_capacity(documents.len());
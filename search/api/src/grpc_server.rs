@@ -1,9 +1,13 @@
 use api_rs::wiki_search::{wiki_search_server::WikiSearch, CheckIndexReply, CheckIndexRequest};
 use index::index::Index;
-use index::index_builder::{IndexBuilder, SqlIndexBuilder};
-use log::info;
+use index::index_builder::{cache_capacity_from_env, IndexBuilder, SqlIndexBuilder};
+use index::{latest_snapshot_dir, IndexError, TermUsage};
+use log::{error, info};
 use sqlx::{Pool, Postgres};
-use std::sync::{Arc, RwLock};
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 use tonic::{Request, Response, Status};
 
@@ -14,6 +18,95 @@ pub struct CheckIndexService {
     pub index: Arc<RwLock<Index>>,
     pub connection_string: String,
     pub pool: Pool<Postgres>,
+    /// where the index is persisted after every rebuild or update, and restored from on startup
+    pub snapshot_dir: Option<PathBuf>,
+    /// where the posting list usage is kept across restarts, if anywhere
+    pub term_usage_log: Option<PathBuf>,
+    /// how often the posting list of each term was looked up by the indices served so far
+    pub term_usage: Mutex<TermUsage>,
+}
+
+impl CheckIndexService {
+    /// replaces the served index with the one in the snapshot directory if there is one,
+    /// returns whether anything was restored
+    pub fn restore_snapshot(&self) -> Result<bool, IndexError> {
+        let dir = match self.snapshot_dir.as_ref().and_then(latest_snapshot_dir) {
+            Some(d) => d,
+            None => return Ok(false),
+        };
+
+        let (cap, cap_per) = cache_capacity_from_env();
+        let idx = Index::load_snapshot(dir, cap, cap_per)?;
+        self.warm_cache(&idx);
+
+        let old = std::mem::replace(
+            &mut *self.index.write().map_err(|e| IndexError {
+                msg: e.to_string(),
+                kind: index::IndexErrorKind::PoisonedThread,
+            })?,
+            idx,
+        );
+        drop(old);
+
+        Ok(true)
+    }
+
+    /// the usage recorded in the given log, if there is one
+    pub fn load_term_usage(path: Option<&Path>) -> TermUsage {
+        match path {
+            Some(p) if p.exists() => TermUsage::load(p).unwrap_or_else(|e| {
+                error!("Could not read term usage, starting over: {}", e);
+                TermUsage::default()
+            }),
+            _ => TermUsage::default(),
+        }
+    }
+
+    /// adds what the served index looked up since it was last asked to the usage, and writes it to the log.
+    /// The uses are taken from the index beforehand, so the log is never written under the index lock
+    fn record_usage(&self, uses: Vec<(String, u64)>) {
+        let mut usage = self.term_usage.lock().unwrap();
+        usage.record(uses);
+
+        if let Some(path) = &self.term_usage_log {
+            if let Err(e) = usage.save(path) {
+                error!("Could not write term usage: {}", e);
+            }
+        }
+    }
+
+    /// fills the posting cache of an index which is about to be served with the most used posting lists
+    fn warm_cache(&self, idx: &Index) {
+        let usage = self.term_usage.lock().unwrap();
+        if usage.is_empty() {
+            return;
+        }
+
+        let timer = Instant::now();
+        let warmed = idx.warm_posting_cache(&usage);
+        info!(
+            "Warmed posting cache with {} of {} used posting lists in {:?}",
+            warmed,
+            usage.len(),
+            timer.elapsed()
+        );
+    }
+
+    fn save_snapshot(&self) {
+        let dir = match &self.snapshot_dir {
+            Some(d) => d,
+            None => return,
+        };
+
+        match self.index.read() {
+            Ok(idx) => {
+                if let Err(e) = idx.save_snapshot(dir) {
+                    error!("Could not write index snapshot: {}", e);
+                }
+            }
+            Err(e) => error!("Could not write index snapshot: {}", e),
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -24,56 +117,159 @@ impl WikiSearch for CheckIndexService {
     ) -> Result<Response<CheckIndexReply>, Status> {
         info!("Received index build signal.");
 
+        let (dump_id, indexed_documents, uses) = match self.index.try_read() {
+            Ok(v) => {
+                // how the posting cache has done since the index was built or loaded, for tuning CACHE_SIZE
+                let disk = v.posting_disk_stats();
+                info!(
+                    "Posting cache ({}): {} hits, {} misses, hit rate {:.3}",
+                    disk.policy,
+                    disk.hits,
+                    disk.misses,
+                    disk.hit_rate()
+                );
+
+                (
+                    v.get_dump_id(),
+                    v.last_updated_docs.keys().cloned().collect::<HashSet<u32>>(),
+                    v.take_posting_uses(),
+                )
+            }
+            Err(e) => {
+                return Ok(Response::new(CheckIndexReply {
+                    success: false,
+                    err_code: e.to_string(),
+                }))
+            }
+        };
+        self.record_usage(uses);
+
         let index_builder = SqlIndexBuilder {
             connection_string: self.connection_string.clone(),
-            dump_id: match self.index.try_read() {
-                Ok(v) => v,
+            dump_id,
+        };
+
+        let incremental = env::var("INCREMENTAL_UPDATES")
+            .unwrap_or("true".to_string())
+            .parse::<bool>()
+            .unwrap_or(true);
+
+        let timer = Instant::now();
+
+        // only an index which already holds documents can be updated in place
+        if incremental && !indexed_documents.is_empty() {
+            let update = match index_builder
+                .build_update_if_needed(&indexed_documents)
+                .await
+            {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    info!("Index is already up to date. Not updating.");
+                    return Ok(Response::new(CheckIndexReply {
+                        success: true,
+                        err_code: "".to_string(),
+                    }));
+                }
+                Err(e) => {
+                    return Ok(Response::new(CheckIndexReply {
+                        success: false,
+                        err_code: format!("{:?}", e),
+                    }))
+                }
+            };
+
+            // the update is worked out while queries are still served, the index is only locked to swap it in
+            let prepared = match self.index.read() {
+                Ok(v) => v.prepare_update(update),
                 Err(e) => {
                     return Ok(Response::new(CheckIndexReply {
                         success: false,
                         err_code: e.to_string(),
                     }))
                 }
-            }
-            .get_dump_id(),
-        };
+            };
+            let prepared = match prepared {
+                Ok(v) => v,
+                Err(e) => {
+                    return Ok(Response::new(CheckIndexReply {
+                        success: false,
+                        err_code: format!("{:?}", e),
+                    }))
+                }
+            };
 
-        let timer = Instant::now();
+            let mut guard = match self.index.write() {
+                Ok(v) => v,
+                Err(e) => {
+                    return Ok(Response::new(CheckIndexReply {
+                        success: false,
+                        err_code: e.to_string(),
+                    }))
+                }
+            };
 
-        let res = match index_builder.build_index_if_needed().await {
-            Ok(v) => v,
-            Err(e) => {
+            if let Err(e) = guard.commit_update(prepared) {
                 return Ok(Response::new(CheckIndexReply {
                     success: false,
                     err_code: format!("{:?}", e),
-                }))
+                }));
             }
-        };
 
-        let rebuilt = res.is_some();
+            // updated posting lists are written out anew, leaving holes where they used to be,
+            // compaction only runs here, still under the write lock, so queries wait for it
+            let disk = guard.posting_disk_stats();
+            if disk.free_bytes * 2 > disk.file_bytes {
+                if let Err(e) = guard.compact_postings() {
+                    error!("Could not compact posting file: {}", e);
+                }
+            }
+            drop(guard);
 
-        if !rebuilt {
-            info!("Index is already up to date. Not rebuilding.");
-            return Ok(Response::new(CheckIndexReply {
-                success: true,
-                err_code: "".to_string(),
-            }));
-        }
+            info!("Updating index took {:?}", timer.elapsed());
+        } else {
+            let res = match index_builder.build_index_if_needed().await {
+                Ok(v) => v,
+                Err(e) => {
+                    return Ok(Response::new(CheckIndexReply {
+                        success: false,
+                        err_code: format!("{:?}", e),
+                    }))
+                }
+            };
 
-        let time = timer.elapsed();
-        info!("Building index took {:?}", time);
+            let rebuilt = res.is_some();
 
-        let mut guard = match self.index.try_write() {
-            Ok(v) => v,
-            Err(e) => {
+            if !rebuilt {
+                info!("Index is already up to date. Not rebuilding.");
                 return Ok(Response::new(CheckIndexReply {
-                    success: false,
-                    err_code: e.to_string(),
-                }))
+                    success: true,
+                    err_code: "".to_string(),
+                }));
             }
-        };
 
-        *guard = res.expect("Something impossible happened!");
+            let time = timer.elapsed();
+            info!("Building index took {:?}", time);
+
+            let idx = res.expect("Something impossible happened!");
+            self.warm_cache(&idx);
+
+            let mut guard = match self.index.try_write() {
+                Ok(v) => v,
+                Err(e) => {
+                    return Ok(Response::new(CheckIndexReply {
+                        success: false,
+                        err_code: e.to_string(),
+                    }))
+                }
+            };
+
+            // the old index and its backing file are released once nothing can reach it
+            let old = std::mem::replace(&mut *guard, idx);
+            drop(guard);
+            drop(old);
+        }
+
+        self.save_snapshot();
 
         Ok(Response::new(CheckIndexReply {
             success: true,
@@ -19,12 +19,12 @@ use sqlx::Pool;
 use sqlx::Postgres;
 use sqlx::postgres::PgPoolOptions;
 use core::time;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::{
     env,
     io::{Error, ErrorKind},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
     thread,
     fs::read_dir
 };
@@ -52,6 +52,8 @@ async fn main() -> std::io::Result<()> {
     let rest_port = env::var("SEARCH_PORT").unwrap_or(DEFAULT_REST_PORT.to_string());
     let static_serve_dir = env::var("STATIC_DIR").unwrap_or(DEFAULT_STATICFILES_DIR.to_string());
     let max_clients = env::var("SQL_MAX_CLIENTS").unwrap_or(DEFAULT_SQL_MAX_CLIENTS.to_string());
+    let snapshot_dir = env::var("INDEX_SNAPSHOT_DIR").ok().map(PathBuf::from);
+    let term_usage_log = env::var("CACHE_WARMING_LOG").ok().map(PathBuf::from);
 
     let pool = PgPoolOptions::new()
         .max_connections(max_clients.parse::<u32>().unwrap_or(150))
@@ -78,6 +80,8 @@ async fn main() -> std::io::Result<()> {
                 grpc_address.clone(),
                 connection_string_grpc.clone(),
                 pool_rest.clone(),
+                snapshot_dir.clone(),
+                term_usage_log.clone(),
             );
 
             if status.is_err() {
@@ -90,7 +94,6 @@ async fn main() -> std::io::Result<()> {
             }
         }
     });
-    let connection_string_rest = connection_string.clone();
     let index_rest = index.clone();
     let handle = thread::spawn(move || {
         loop {
@@ -99,8 +102,6 @@ async fn main() -> std::io::Result<()> {
                 rest_port.clone(),
                 static_serve_dir.clone(),
                 index_rest.clone(),
-                connection_string_rest.clone(),
-                pool.clone(),
             );
             if status.is_err() {
                 error!("REST service error: {:?}", status.err().unwrap());
@@ -126,7 +127,9 @@ async fn run_grpc<'a>(
     index_grpc: Arc<RwLock<Index>>,
     grpc_address: String,
     connection_string: String,
-    pool : Pool<Postgres>
+    pool : Pool<Postgres>,
+    snapshot_dir: Option<PathBuf>,
+    term_usage_log: Option<PathBuf>,
 ) -> std::io::Result<()> {
     // launc grpc serices and server
     info!("Lauching gRPC server");
@@ -137,8 +140,17 @@ async fn run_grpc<'a>(
         index: index_grpc.clone(),
         connection_string: connection_string,
         pool,
+        snapshot_dir,
+        term_usage: Mutex::new(CheckIndexService::load_term_usage(term_usage_log.as_deref())),
+        term_usage_log,
     };
 
+    match service.restore_snapshot() {
+        Ok(true) => info!("Restored index snapshot."),
+        Ok(false) => info!("No index snapshot to restore."),
+        Err(e) => error!("Could not restore index snapshot, rebuilding: {}", e),
+    }
+
     info!("Building initial index..");
 
     let response = service
@@ -177,8 +189,6 @@ async fn run_rest(
     port: String,
     static_dir: String,
     index_rest: Arc<RwLock<Index>>,
-    connection_string: String,
-    pool : Pool<Postgres>
 ) -> std::io::Result<()> {
     // launch REST api
     info!("Lauching Search API");
@@ -190,9 +200,6 @@ async fn run_rest(
         let cors = Cors::permissive();
         let data = RESTSearchData {
             index_rest: index_rest.clone(),
-            connection_string: connection_string.clone(),
-            pool: pool.clone(),
-
         };
         let logger = Logger::default();
 
@@ -1,13 +1,13 @@
+use chrono::{Datelike, Timelike};
 use serde::Deserialize;
 use serde::Serialize;
 
 use index::index::Index;
-use sqlx::Pool;
-use sqlx::Postgres;
+use retrieval::{FacetCounts, Ranking, ScoredDocument};
 use std::sync::{Arc, RwLock};
 
 /// Represents the type of order to be imposed on list of documents
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum SortType {
     Relevance,
@@ -20,6 +20,31 @@ impl Default for SortType {
     }
 }
 
+/// Represents the ranking function used to order results by relevance
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RankingType {
+    TfIdf,
+    Bm25,
+    Bm25F,
+}
+
+impl Default for RankingType {
+    fn default() -> Self {
+        RankingType::TfIdf
+    }
+}
+
+impl From<RankingType> for Ranking {
+    fn from(r: RankingType) -> Self {
+        match r {
+            RankingType::TfIdf => Ranking::TfIdf,
+            RankingType::Bm25 => Ranking::Bm25,
+            RankingType::Bm25F => Ranking::Bm25F,
+        }
+    }
+}
+
 /// Represents the parameters of a given standard search
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,6 +59,32 @@ pub struct SearchParameters {
 
     #[serde(default)]
     pub results_per_page: ResultsCount,
+
+    #[serde(default)]
+    pub ranking: RankingType,
+
+    /// whether to count the categories and infobox types over all results
+    #[serde(default)]
+    pub facets: bool,
+
+    /// narrows the results down to documents listed under the category
+    #[serde(default)]
+    pub category: Option<String>,
+
+    /// narrows the results down to documents with an infobox of the type
+    #[serde(default)]
+    pub infobox: Option<String>,
+
+    /// inclusive bounds on the day results were last edited, as YYYY-MM-DD
+    #[serde(default)]
+    pub edited_after: Option<String>,
+
+    #[serde(default)]
+    pub edited_before: Option<String>,
+
+    /// continues from where a previous page ended, takes precedence over the page number
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 /// Represents the parameters of a given relational search
@@ -73,6 +124,10 @@ pub struct Document {
 
     #[serde(rename = "abstract")]
     pub article_abstract: String,
+
+    /// the words around the matches, highlights are character ranges within it
+    pub snippet: String,
+    pub highlights: Vec<(usize, usize)>,
 }
 
 #[derive(Serialize, Debug)]
@@ -107,13 +162,132 @@ pub struct RelationSearchOutput {
     pub suggested_query: String,
 }
 
+/// Represents a facet value and the number of results carrying it
+#[derive(Serialize, Debug)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: u32,
+}
+
+/// Represents the facet counts over the full set of results
+#[derive(Serialize, Debug)]
+pub struct Facets {
+    pub categories: Vec<FacetValue>,
+    pub infoboxes: Vec<FacetValue>,
+}
+
+impl From<FacetCounts> for Facets {
+    fn from(counts: FacetCounts) -> Self {
+        let values = |v: Vec<(String, u32)>| {
+            v.into_iter()
+                .map(|(value, count)| FacetValue { value, count })
+                .collect()
+        };
+
+        Facets {
+            categories: values(counts.categories),
+            infoboxes: values(counts.infoboxes),
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct SearchOutput {
     pub documents: Vec<Document>,
     pub domain: String,
-    pub suggested_query: String
+    pub suggested_query: String,
+
+    /// the number of matching documents across all pages
+    pub total_hits: usize,
+    pub page: usize,
+    pub results_per_page: usize,
+    /// set when the next page holds results, never for partial results
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+    /// set when the query ran out of time or postings to read, leaving some results out
+    pub partial_results: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets: Option<Facets>,
+}
+
+
+/// Represents where a page of results ended, clients only ever see it encoded as an opaque string.
+/// Results are ordered by a sort key and then by document id, so the cursor stays meaningful
+/// even when the results before it change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort_by: SortType,
+    pub key: u64,
+    pub doc_id: u32,
 }
 
+impl Cursor {
+    pub fn at(sort_by: SortType, doc: &ScoredDocument, idx: &Index) -> Self {
+        let key = match sort_by {
+            SortType::Relevance => doc.score.to_bits(),
+            SortType::LastEdited => Self::edited_key(doc.doc_id, idx) as u64,
+        };
+
+        Cursor {
+            sort_by,
+            key,
+            doc_id: doc.doc_id,
+        }
+    }
+
+    // documents without a date sort first
+    fn edited_key(doc_id: u32, idx: &Index) -> i64 {
+        idx.get_last_updated_date(doc_id)
+            .map(|d| {
+                d.date_time.num_days_from_ce() as i64 * 86400
+                    + d.date_time.num_seconds_from_midnight() as i64
+            })
+            .unwrap_or(i64::MIN)
+    }
+
+    /// whether the document is ordered after the cursor
+    pub fn precedes(&self, doc: &ScoredDocument, idx: &Index) -> bool {
+        match self.sort_by {
+            // best scores first
+            SortType::Relevance => {
+                let score = f64::from_bits(self.key);
+                doc.score < score || (doc.score == score && doc.doc_id > self.doc_id)
+            }
+            SortType::LastEdited => {
+                let key = Self::edited_key(doc.doc_id, idx);
+                let edited = self.key as i64;
+                key > edited || (key == edited && doc.doc_id > self.doc_id)
+            }
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let tag = match self.sort_by {
+            SortType::Relevance => 'r',
+            SortType::LastEdited => 'e',
+        };
+        format!("{}{:016x}{:08x}", tag, self.key, self.doc_id)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        if cursor.len() != 25 || !cursor.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+
+        let sort_by = match &cursor[..1] {
+            "r" => SortType::Relevance,
+            "e" => SortType::LastEdited,
+            _ => return None,
+        };
+
+        Some(Cursor {
+            sort_by,
+            key: u64::from_str_radix(&cursor[1..17], 16).ok()?,
+            doc_id: u32::from_str_radix(&cursor[17..], 16).ok()?,
+        })
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ResultsCount(pub u16);
@@ -134,6 +308,4 @@ impl Default for DefaultPage {
 #[derive(Debug)]
 pub struct RESTSearchData {
     pub index_rest: Arc<RwLock<Index>>,
-    pub connection_string: String, //Used to query Database for metadata results like Title or Abstracts
-    pub pool: Pool<Postgres>
 }
@@ -1,12 +1,15 @@
-use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
 use index::{
     index::{Index},
     index_structs::{Citation, Document, Infobox},
-    PreIndex, Posting,
+    EncodedPostingList, PostingCodec, PreIndex, Posting,
 };
 use parser::ast::{BinaryOp, Query, UnaryOp};
 
 use retrieval::search::{execute_query, preprocess_query, score_query};
+use retrieval::Ranking;
 use streaming_iterator::StreamingIterator;
 
 use std::fmt::{Debug, Display};
@@ -111,6 +114,8 @@ pub fn get_random_documents(p: &IndexBenchParameters) -> Vec<Box<Document>> {
         docs.push(Box::new(Document {
             doc_id: i,
             title: i.to_string(),
+            article_abstract: "".to_string(),
+            namespace: 0,
             categories: "".to_string(),
             last_updated_date: "".to_string(),
             main_text: get_random_string(words_main, rng),
@@ -131,6 +136,40 @@ pub fn get_random_documents(p: &IndexBenchParameters) -> Vec<Box<Document>> {
     Vec::default()
 }
 
+/// the posting lists of every vocabulary word over a random corpus, earlier words are more frequent
+pub fn get_random_posting_lists(p: &IndexBenchParameters) -> Vec<Vec<Posting>> {
+    let ref mut rng = StdRng::seed_from_u64(69420); // <- Here we set the seed
+
+    let mut lists = vec![Vec::default(); VOCAB.len()];
+    for d in 0..p.articles_count {
+        for position in 0..p.words_count {
+            let w = (rng.gen::<f64>().powi(3) * VOCAB.len() as f64) as usize;
+            lists[w].push(Posting {
+                document_id: d,
+                position,
+            });
+        }
+    }
+
+    lists
+}
+
+/// encodes each list with the codec of the given name
+pub fn encode_posting_lists(
+    lists: &[Vec<Posting>],
+    codec: &str,
+) -> Vec<EncodedPostingList<PostingCodec>> {
+    lists
+        .iter()
+        .map(|l| {
+            let mut o = EncodedPostingList::with_encoder(PostingCodec::from_name(codec).unwrap());
+            l.iter().for_each(|p| o.push(*p));
+            o.finish();
+            o
+        })
+        .collect()
+}
+
 pub fn get_random_query(p: &IndexBenchParameters) -> Box<Query> {
     let ref mut rng = StdRng::seed_from_u64(69420); // <- Here we set the seed
 
@@ -140,8 +179,13 @@ pub fn get_random_query(p: &IndexBenchParameters) -> Box<Query> {
 
     let dist_q = Box::new(Query::DistanceQuery {
         dst: 3,
-        lhs: get_random_string(1, rng),
-        rhs: get_random_string(1, rng),
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery {
+            tokens: get_random_strings(1, rng),
+        }),
+        rhs: Box::new(Query::FreetextQuery {
+            tokens: get_random_strings(1, rng),
+        }),
     });
 
     let and_q = Box::new(Query::BinaryQuery {
@@ -152,6 +196,7 @@ pub fn get_random_query(p: &IndexBenchParameters) -> Box<Query> {
 
     let phrase_q = Box::new(Query::PhraseQuery {
         tks: get_random_strings(5, rng),
+        offsets: (0..5).collect(),
     });
 
     let not_q = Box::new(Query::UnaryQuery {
@@ -189,7 +234,7 @@ pub fn build_index_with_docs(docs: Vec<Box<Document>>) -> Index {
 pub fn execute_query_with_index(idx: &Index, mut q: Box<Query>) {
     preprocess_query(&mut *q).unwrap();
     let mut postings = execute_query(&q, &idx).collect::<Vec<Posting>>();
-    score_query(&q, &idx, &mut postings);
+    score_query(&q, &idx, &mut postings, Ranking::TfIdf);
 }
 
 impl IndexBenchParameters {
@@ -265,5 +310,44 @@ pub fn query_execution_time(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, index_build_time, query_execution_time);
+/// decodes the same corpus encoded with each codec, the size of each encoding is printed alongside
+pub fn posting_codec_decode_time(c: &mut Criterion) {
+    let mut group = c.benchmark_group("posting list decode time: codec");
+    group.sample_size(20);
+
+    let ref param = IndexBenchParameters::from_article_count(20000);
+    let lists = get_random_posting_lists(param);
+    let postings = lists.iter().map(|l| l.len() as u64).sum::<u64>();
+
+    for name in PostingCodec::NAMES.iter() {
+        let encoded = encode_posting_lists(&lists, name);
+
+        let bytes = encoded.iter().map(|o| o.byte_len()).sum::<usize>();
+        println!(
+            "{}: {} postings in {} bytes ({:.3} bytes per posting)",
+            name,
+            postings,
+            bytes,
+            bytes as f64 / postings as f64
+        );
+
+        group.throughput(Throughput::Elements(postings));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, e| {
+            b.iter(|| {
+                e.iter()
+                    .map(|o| o.into_iter().map(|p| black_box(p).position as u64).sum::<u64>())
+                    .sum::<u64>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    index_build_time,
+    query_execution_time,
+    posting_codec_decode_time
+);
 criterion_main!(benches);
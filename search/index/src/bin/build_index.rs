@@ -0,0 +1,61 @@
+use index::{
+    build_index_from_source, cache_capacity_from_env, posting_codec_from_env, DocumentSource,
+    IndexError, MediaWikiXmlSource, NdjsonSource, SqlDocumentSource,
+};
+use log::{error, info};
+use std::{env, process, time::Instant};
+
+const USAGE: &str = "usage: build-index <postgres|ndjson|mediawiki> <connection string or file> <snapshot directory>";
+
+/// builds an index from the given source and writes it out as a snapshot the search API can restore
+fn main() {
+    pretty_env_logger::init();
+
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.len() != 3 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    if let Err(e) = runtime.block_on(build(&args[0], &args[1], &args[2])) {
+        error!("Could not build index: {}", e);
+        process::exit(1);
+    }
+}
+
+async fn build(kind: &str, input: &str, snapshot_dir: &str) -> Result<(), IndexError> {
+    let batch_size = env::var("BATCH_SIZE")
+        .unwrap_or("5000".to_string())
+        .parse::<u32>()
+        .unwrap();
+    let (cap, cap_per) = cache_capacity_from_env();
+    let codec = posting_codec_from_env()?;
+
+    let mut source: Box<dyn DocumentSource> = match kind {
+        "postgres" => Box::new(SqlDocumentSource::connect(input, batch_size).await?),
+        "ndjson" => Box::new(NdjsonSource::open(input, batch_size as usize)?),
+        "mediawiki" => Box::new(MediaWikiXmlSource::open(input, batch_size as usize)?),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let timer = Instant::now();
+    let idx = build_index_from_source(source.as_mut(), cap, cap_per, codec).await?;
+    info!(
+        "Built index of {} documents ({}s)",
+        idx.last_updated_docs.len(),
+        timer.elapsed().as_secs()
+    );
+
+    idx.save_snapshot(snapshot_dir)?;
+    info!("Wrote snapshot to {}", snapshot_dir);
+
+    Ok(())
+}
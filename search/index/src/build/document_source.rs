@@ -0,0 +1,221 @@
+use crate::{
+    errors::{IndexError, IndexErrorKind},
+    index::Index,
+    index_structs::{Citation, Document, Infobox},
+    PostingCodec, PreIndex,
+};
+use async_trait::async_trait;
+use log::{error, info};
+use serde::Deserialize;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Instant;
+
+/// anything documents can be read from to build an index, in batches so not all of them need to be in memory
+#[async_trait]
+pub trait DocumentSource: Send {
+    /// the next batch of documents, none once every document has been read
+    async fn next_batch(&mut self) -> Result<Option<Vec<Box<Document>>>, IndexError>;
+
+    /// the dump the documents belong to, recorded in the index built from them
+    fn dump_id(&self) -> u32 {
+        0
+    }
+
+    /// the number of documents the source will yield, if known up front
+    fn size_hint(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl PreIndex {
+    /// adds every document of the source a batch at a time, each batch is spread over the threads of the rayon pool.
+    /// Documents which cannot be added are logged and skipped, but a batch starting before a document
+    /// which is already indexed fails the whole build since the source is not in document order.
+    /// Returns the number of documents read
+    pub async fn add_source<S: DocumentSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<u32, IndexError> {
+        self.dump_id = source.dump_id();
+
+        let mut processed_docs = 0;
+        loop {
+            let timer = Instant::now();
+            let batch = match source.next_batch().await? {
+                Some(v) => v,
+                None => break,
+            };
+
+            let first = batch.iter().map(|d| d.doc_id).min();
+            if let (Some(first), Some(last)) = (first, self.last_doc_id) {
+                if first <= last {
+                    return Err(IndexError {
+                        msg: format!(
+                            "Batch starting at document {} does not come after document {} which is already indexed, sources have to yield documents in order of their ids",
+                            first, last
+                        ),
+                        kind: IndexErrorKind::BuildFailed,
+                    });
+                }
+            }
+
+            processed_docs += batch.len() as u32;
+            for (document, e) in self.add_documents(batch) {
+                error!(
+                    "Error in adding document with {{id: {}, title: {}}}:{}",
+                    document.doc_id, document.title, e
+                );
+            }
+
+            match source.size_hint() {
+                Some(n) => info!(
+                    "Building pre-index: {}% ({}s) - processed {} docs, cache size {}",
+                    (processed_docs as f32 / n as f32) * 100.0,
+                    timer.elapsed().as_secs(),
+                    processed_docs,
+                    self.cache_size()
+                ),
+                None => info!(
+                    "Building pre-index: ({}s) - processed {} docs, cache size {}",
+                    timer.elapsed().as_secs(),
+                    processed_docs,
+                    self.cache_size()
+                ),
+            }
+        }
+
+        Ok(processed_docs)
+    }
+}
+
+/// builds an index out of every document of the source, with posting lists encoded by the given codec
+pub async fn build_index_from_source<S: DocumentSource + ?Sized>(
+    source: &mut S,
+    cap: u64,
+    cap_per: u64,
+    codec: PostingCodec,
+) -> Result<Index, IndexError> {
+    let mut pre_index = PreIndex::with_capacity(cap, cap_per);
+    pre_index.codec = codec;
+    pre_index.add_source(source).await?;
+
+    Ok(Index::from_pre_index(pre_index))
+}
+
+/// a document as written on a single line of a newline delimited JSON file, any field may be left out
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct JsonDocument {
+    id: u32,
+    title: String,
+    #[serde(rename = "abstract")]
+    article_abstract: String,
+    namespace: i16,
+    categories: Vec<String>,
+    /// in the `%Y-%m-%d %H:%M:%S` format
+    last_updated: String,
+    text: String,
+    /// ids of the linked documents
+    links: Vec<u32>,
+    infoboxes: Vec<JsonInfobox>,
+    citations: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct JsonInfobox {
+    #[serde(rename = "type")]
+    itype: String,
+    text: String,
+}
+
+impl From<JsonDocument> for Document {
+    fn from(d: JsonDocument) -> Self {
+        Document {
+            doc_id: d.id,
+            title: d.title,
+            article_abstract: d.article_abstract,
+            namespace: d.namespace,
+            categories: d.categories.join("\t"),
+            last_updated_date: d.last_updated,
+            main_text: d.text,
+            article_links: d
+                .links
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<String>>()
+                .join("\t"),
+            infoboxes: d
+                .infoboxes
+                .into_iter()
+                .map(|i| Infobox {
+                    itype: i.itype,
+                    text: i.text,
+                })
+                .collect(),
+            citations: d
+                .citations
+                .into_iter()
+                .map(|c| Citation { text: c })
+                .collect(),
+        }
+    }
+}
+
+/// reads one JSON document per line, blank lines are ignored
+pub struct NdjsonSource<R: BufRead + Send> {
+    reader: R,
+    batch_size: usize,
+    /// the number of lines read so far
+    line: usize,
+}
+
+impl NdjsonSource<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, batch_size: usize) -> Result<Self, IndexError> {
+        Ok(Self::new(BufReader::new(File::open(path)?), batch_size))
+    }
+}
+
+impl<R: BufRead + Send> NdjsonSource<R> {
+    pub fn new(reader: R, batch_size: usize) -> Self {
+        Self {
+            reader,
+            batch_size: batch_size.max(1),
+            line: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: BufRead + Send> DocumentSource for NdjsonSource<R> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Box<Document>>>, IndexError> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut buf = String::new();
+
+        while batch.len() < self.batch_size {
+            buf.clear();
+            if self.reader.read_line(&mut buf)? == 0 {
+                break;
+            }
+            self.line += 1;
+
+            if buf.trim().is_empty() {
+                continue;
+            }
+
+            let document: JsonDocument = serde_json::from_str(&buf).map_err(|e| IndexError {
+                msg: format!("Malformed document on line {}: {}", self.line, e),
+                kind: IndexErrorKind::BuildFailed,
+            })?;
+            batch.push(Box::new(document.into()));
+        }
+
+        match batch.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(batch)),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::future::Future;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use preprocessor::{Preprocessor, ProcessingOptions};
+use uuid::Uuid;
+
+use crate::{
+    build_index_from_source, DocumentSource, IndexErrorKind, MediaWikiXmlSource, NdjsonSource,
+    PostingCodec, DATE_TIME_FORMAT,
+};
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+/// the text as the preprocessor leaves it, dropped words are left as empty words
+fn processed(text: &str) -> String {
+    text.split_whitespace()
+        .map(|w| Preprocessor::process(&ProcessingOptions::default(), w.to_string()).join(" "))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn dump_path() -> PathBuf {
+    std::env::temp_dir().join(format!("dump-{}.xml", Uuid::new_v4()))
+}
+
+const NDJSON: &str = r#"{"id": 1, "title": "Source One", "abstract": "first", "categories": ["alpha", "beta"], "last_updated": "2021-02-03 04:05:06", "text": "hello ndjson world", "links": [2], "infoboxes": [{"type": "person", "text": "born somewhere"}], "citations": ["a book"]}
+
+{"id": 2, "title": "Source Two", "text": "ndjson again"}
+"#;
+
+#[test]
+fn test_ndjson_source_batches() {
+    let mut source = NdjsonSource::new(Cursor::new(NDJSON), 1);
+
+    let first = block_on(source.next_batch()).unwrap().unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].doc_id, 1);
+    assert_eq!(first[0].categories, "alpha\tbeta");
+    assert_eq!(first[0].article_links, "2");
+
+    let second = block_on(source.next_batch()).unwrap().unwrap();
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].doc_id, 2);
+    assert_eq!(second[0].title, "Source Two");
+
+    assert!(block_on(source.next_batch()).unwrap().is_none());
+}
+
+#[test]
+fn test_ndjson_source_builds_index() {
+    let mut source = NdjsonSource::new(Cursor::new(NDJSON), 10);
+    let idx = block_on(build_index_from_source(
+        &mut source,
+        1000,
+        1000,
+        PostingCodec::default(),
+    ))
+    .unwrap();
+
+    assert_eq!(idx.get_number_of_documents(), 2);
+    assert_eq!(idx.df("ndjson"), 2);
+    assert_eq!(idx.get_links(1), &[2]);
+    assert_eq!(
+        idx.get_categories(1),
+        &["alpha".to_string(), "beta".to_string()]
+    );
+    assert_eq!(idx.get_infobox_types(1), vec!["person"]);
+    assert_eq!(idx.get_metadata(1).unwrap().article_abstract, "first");
+    assert_eq!(
+        idx.get_last_updated_date(1).unwrap().date_time,
+        NaiveDateTime::parse_from_str("2021-02-03 04:05:06", DATE_TIME_FORMAT).unwrap()
+    );
+}
+
+#[test]
+fn test_ndjson_source_out_of_order() {
+    let mut source = NdjsonSource::new(
+        Cursor::new("{\"id\": 2, \"text\": \"b\"}\n{\"id\": 3, \"text\": \"b\"}\n{\"id\": 1, \"text\": \"b\"}\n"),
+        2,
+    );
+    let err = block_on(build_index_from_source(
+        &mut source,
+        1000,
+        1000,
+        PostingCodec::default(),
+    ))
+    .err()
+    .unwrap();
+    assert!(matches!(err.kind, IndexErrorKind::BuildFailed));
+    assert!(err.msg.contains("document 1"));
+}
+
+#[test]
+fn test_ndjson_source_malformed() {
+    let mut source = NdjsonSource::new(Cursor::new("{\"id\": 1}\n{\"id\": \"one\"}\n"), 10);
+
+    let err = block_on(source.next_batch()).err().unwrap();
+    assert!(matches!(err.kind, IndexErrorKind::BuildFailed));
+    assert!(err.msg.contains("line 2"));
+}
+
+const DUMP: &str = r#"<mediawiki xmlns="http://www.mediawiki.org/xml/export-0.10/" version="0.10" xml:lang="en">
+  <siteinfo>
+    <sitename>Wikipedia</sitename>
+  </siteinfo>
+  <page>
+    <title>Rust (language)</title>
+    <ns>0</ns>
+    <id>10</id>
+    <revision>
+      <id>900</id>
+      <timestamp>2022-01-02T03:04:05Z</timestamp>
+      <contributor>
+        <username>Someone</username>
+        <id>77</id>
+      </contributor>
+      <text bytes="300" xml:space="preserve">{{Infobox programming language
+| name = Rust
+| designer = Graydon
+}}
+'''Rust''' is a [[programming language|language]] with ownership&lt;ref&gt;The book&lt;/ref&gt;, unlike [[C (language)]] &amp; [[Nowhere]].
+
+It is fast.{{citation needed}}
+
+[[Category:Languages]]
+[[Category:Systems|Rust]]</text>
+    </revision>
+  </page>
+  <page>
+    <title>C (language)</title>
+    <ns>0</ns>
+    <id>20</id>
+    <revision>
+      <id>901</id>
+      <timestamp>2021-05-06T07:08:09Z</timestamp>
+      <text bytes="40" xml:space="preserve">C is older than [[rust (language)|Rust]].</text>
+    </revision>
+  </page>
+  <page>
+    <title>Programming language</title>
+    <ns>0</ns>
+    <id>30</id>
+    <redirect title="C (language)" />
+    <revision>
+      <id>902</id>
+      <text bytes="30" xml:space="preserve">#REDIRECT [[C (language)]]</text>
+    </revision>
+  </page>
+</mediawiki>
+"#;
+
+#[test]
+fn test_mediawiki_source() {
+    let path = dump_path();
+    fs::write(&path, DUMP).unwrap();
+
+    let mut source = MediaWikiXmlSource::open(&path, 10).unwrap();
+    assert_eq!(source.size_hint(), Some(2));
+
+    let batch = block_on(source.next_batch()).unwrap().unwrap();
+    assert!(block_on(source.next_batch()).unwrap().is_none());
+    fs::remove_file(&path).unwrap();
+
+    // the redirect is left out
+    assert_eq!(
+        batch.iter().map(|d| d.doc_id).collect::<Vec<u32>>(),
+        vec![10, 20]
+    );
+
+    let rust = &batch[0];
+    assert_eq!(rust.title, "Rust (language)");
+    assert_eq!(rust.namespace, 0);
+    assert_eq!(rust.last_updated_date, "2022-01-02 03:04:05");
+    assert_eq!(
+        rust.categories,
+        format!("{}\t{}", processed("Languages"), processed("Systems"))
+    );
+    // linked through the redirect, to a page and to a missing one
+    assert_eq!(rust.article_links, "20");
+    assert_eq!(rust.infoboxes.len(), 1);
+    assert_eq!(rust.infoboxes[0].itype, "programming language");
+    assert!(rust.infoboxes[0].text.contains(&processed("Graydon")));
+    assert_eq!(rust.citations.len(), 1);
+    assert_eq!(rust.citations[0].text, processed("The book"));
+    // the text is stored the way the database holds it, the abstract as it is displayed
+    assert_eq!(
+        rust.main_text,
+        processed(
+            "'''Rust''' is a language with ownership, unlike C (language) & Nowhere.\n\nIt is fast."
+        )
+    );
+    assert!(!rust.main_text.contains("'''"));
+    assert_eq!(
+        rust.article_abstract,
+        "'''Rust''' is a language with ownership, unlike C (language) & Nowhere."
+    );
+
+    // links are matched regardless of the case of their first letter
+    assert_eq!(batch[1].article_links, "10");
+    assert_eq!(batch[1].main_text, processed("C is older than Rust."));
+}
+
+#[test]
+fn test_mediawiki_source_builds_index() {
+    let path = dump_path();
+    fs::write(&path, DUMP).unwrap();
+
+    let mut source = MediaWikiXmlSource::open(&path, 1).unwrap();
+    let idx = block_on(build_index_from_source(
+        &mut source,
+        1000,
+        1000,
+        PostingCodec::default(),
+    ))
+    .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(idx.get_number_of_documents(), 2);
+    assert_eq!(idx.get_document_id("C (language)"), Some(20));
+    assert_eq!(idx.get_links(10), &[20]);
+    assert_eq!(idx.get_incoming_links(20), &[10]);
+    assert_eq!(idx.get_infobox_types(10), vec!["programming language"]);
+
+    // terms are found the way a query looks them up, whatever punctuation surrounds them in the wikitext
+    let term = |word: &str| processed(word);
+    let docs = |word: &str| {
+        idx.stream_documents(&term(word))
+            .map(|s| s.map(|d| d.document_id).collect::<Vec<u32>>())
+            .unwrap_or_default()
+    };
+    assert_eq!(docs("Rust"), vec![10, 20]);
+    assert_eq!(docs("ownership"), vec![10]);
+    assert_eq!(docs("fast"), vec![10]);
+    assert_eq!(docs("Graydon"), vec![10]);
+    assert_eq!(docs("book"), vec![10]);
+    assert_eq!(docs("Systems"), vec![10]);
+    assert_eq!(idx.df(&term("older")), 1);
+}
+
+#[test]
+fn test_mediawiki_source_truncated() {
+    let path = dump_path();
+    fs::write(&path, &DUMP[..DUMP.find("</page>").unwrap()]).unwrap();
+
+    let err = MediaWikiXmlSource::open(&path, 10).err().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(err.kind, IndexErrorKind::BuildFailed));
+}
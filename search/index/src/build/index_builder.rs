@@ -1,27 +1,205 @@
-use crate::{PreIndex};
 use crate::{
-    errors::{IndexError},
+    build_index_from_source, default_cache_policy, DocumentSource, IndexUpdate, PostingCodec,
+};
+use crate::{
+    errors::{IndexError, IndexErrorKind},
     index::{Index},
     index_structs::{Citation, Document, Infobox},
 };
 use async_trait::async_trait;
 
 use itertools::izip;
-use log::{info, error};
+use log::info;
 use sqlx::Row;
-use sqlx::{postgres::PgPoolOptions, query, query_scalar};
+use sqlx::postgres::PgRow;
+use sqlx::{postgres::{PgPool, PgPoolOptions}, query, query_scalar};
 
 
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
-use num_integer::Integer;
 
 use std::env;
 
+/// reads the posting cache configuration shared by every way of constructing an index
+/// returns the capacity and persistent capacity in records, or bytes for the size aware cache policy
+pub fn cache_capacity_from_env() -> (u64, u64) {
+    let disable_cache = env::var("CACHE_DISABLE").unwrap_or("false".to_string()).parse::<bool>().unwrap_or(false);
+    let cap_str = env::var("CACHE_SIZE").unwrap_or("500000".to_string());
+    let cap_per_str = env::var("CACHE_PERSISTENT_SIZE").unwrap_or("100000".to_string());
+
+    let mut cap = cap_str.parse::<u64>().unwrap();
+    let mut cap_per = cap_per_str.parse::<u64>().unwrap();
+
+    let policy = default_cache_policy();
+    if disable_cache{
+        cap = u64::MAX;
+        cap_per = cap;
+    }
+
+    info!("CACHE_DISABLE found/default: {}", disable_cache);
+    info!("CACHE_POLICY found/default: {}", policy.name());
+    info!("CACHE_SIZE size found/default: {} {}", cap, policy.unit());
+    info!("CACHE_PERSISTENT_SIZE size found/default: {} {}", cap_per, policy.unit());
+
+    (cap, cap_per)
+}
+
+/// reads which codec posting lists of newly built indexes are encoded with, fails on an unknown one
+pub fn posting_codec_from_env() -> Result<PostingCodec, IndexError> {
+    let name = env::var("POSTING_CODEC").unwrap_or("vbyte".to_string());
+    let codec = PostingCodec::from_name(&name).ok_or_else(|| IndexError {
+        msg: format!(
+            "Unknown POSTING_CODEC {}, expected one of {:?}",
+            name,
+            PostingCodec::NAMES
+        ),
+        kind: IndexErrorKind::BuildFailed,
+    })?;
+
+    info!("POSTING_CODEC found/default: {}", codec.name());
+    Ok(codec)
+}
+
+/// assembles a document from a row of the article/content join, taking its infoboxes and citations
+/// out of the given maps
+fn document_from_row(d: &PgRow, infoboxes: &mut HashMap<i64,Vec<(String,String)>>, citations: &mut HashMap<i64,Vec<String>>) -> Box<Document> {
+    let doc_id : i64 = d.get("articleid");
+    let infoboxes : Option<Vec<(String,String)>> = infoboxes.remove(&doc_id);
+    let citations : Option<Vec<String>> = citations.remove(&doc_id);
+    Box::new(Document {
+        doc_id: doc_id as u32,
+        categories: d.get("categories"),
+        main_text: d.get("text"),
+        article_links: d.get("links"),
+        title: d.get("title"),
+        article_abstract: d.get::<Option<String>,_>("abstracts").unwrap_or_default(),
+        namespace: d.get::<Option<i16>,_>("namespace").unwrap_or_default(),
+        last_updated_date: d.get("lastupdated"),
+        infoboxes: infoboxes.unwrap_or_default().into_iter().map(|v| Infobox {itype : v.0, text :v.1}).collect(),
+        citations: citations.unwrap_or_default().into_iter().map(|v| Citation {text :v}).collect(),
+    })
+}
+
+/// reads the articles of the database in batches of consecutive article ids
+pub struct SqlDocumentSource {
+    pool: PgPool,
+    dump_id: u32,
+    batch_size: u32,
+    num_docs: u32,
+    batch: u32,
+}
+
+impl SqlDocumentSource {
+    pub async fn connect(connection_string: &str, batch_size: u32) -> Result<Self, IndexError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(connection_string)
+            .await?;
+
+        let dump_id = query_scalar!(
+            "SELECT MAX(article.dumpid)
+             FROM article"
+        )
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(0) as u32;
+
+        let nquery : Option<i64> = query("SELECT MAX(a.articleid)
+                            FROM article as a
+                            ")
+                            .fetch_one(&pool)
+                            .await?
+                            .get("max");
+
+        Ok(Self {
+            pool,
+            dump_id,
+            batch_size: batch_size.max(1),
+            num_docs: nquery.unwrap_or(0) as u32,
+            batch: 0,
+        })
+    }
+}
+
+#[async_trait]
+impl DocumentSource for SqlDocumentSource {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Box<Document>>>, IndexError> {
+        // the highest article id is part of the last batch
+        if self.batch > self.num_docs / self.batch_size {
+            self.pool.close().await;
+            return Ok(None);
+        }
+
+        let start_idx = self.batch * self.batch_size;
+        let end_idx = ((self.batch + 1) * self.batch_size) - 1;
+        self.batch += 1;
+
+        let batch_documents_q = query("
+            SELECT a.articleid, a.title, a.namespace, a.lastupdated, c.abstracts, c.categories, c.links, c.text
+            FROM article as a 
+            INNER JOIN \"content\" as c
+                ON  a.articleid = c.articleid
+                AND a.articleid BETWEEN $1 AND $2
+            ORDER BY a.articleid ASC
+        ").bind(&start_idx)
+        .bind(&end_idx)
+        .fetch_all(&self.pool);
+        
+        let infoboxes_q = query("
+        SELECT a.articleid, ARRAY_AGG( (CASE WHEN i.infoboxtype IS NULL THEN (NULL) ELSE (i.infoboxtype,i.body) END)) as infoboxes
+        FROM article as a
+        INNER JOIN infoboxes as i
+            ON a.articleid = i.articleid
+            AND a.articleid BETWEEN $1 AND $2
+        GROUP BY a.articleid
+        ORDER BY a.articleid ASC
+        ").bind(&start_idx)
+        .bind(&end_idx)
+        .fetch_all(&self.pool);
+
+
+        let citations_q = query("
+        SELECT a.articleid, ARRAY_AGG(c.body) as citations
+        FROM article as a
+        INNER JOIN citations as c
+            ON a.articleid = c.articleid
+            AND a.articleid BETWEEN $1 AND $2
+        GROUP BY a.articleid
+        ORDER BY a.articleid ASC
+        ").bind(&start_idx)
+        .bind(&end_idx)
+        .fetch_all(&self.pool);
+
+        // let them run in parallel
+        let batch_documents = batch_documents_q.await?;
+        let mut infoboxes = infoboxes_q.await?
+            .into_iter().map(|i| (i.get("articleid"),i.get("infoboxes")))
+            .collect::<HashMap<i64,Vec<(String,String)>>>();
+        let mut citations = citations_q.await?
+            .into_iter().map(|c| (c.get("articleid"),c.get("citations")))
+            .collect::<HashMap<i64,Vec<String>>>();
+
+        Ok(Some(batch_documents.iter()
+            .map(|d| document_from_row(d, &mut infoboxes, &mut citations))
+            .collect()))
+    }
+
+    fn dump_id(&self) -> u32 {
+        self.dump_id
+    }
+
+    fn size_hint(&self) -> Option<u32> {
+        Some(self.num_docs)
+    }
+}
+
 #[async_trait]
 pub trait IndexBuilder  {
     async fn build_index_if_needed(&self) -> Result<Option<Index>, IndexError>;
+
+    /// collects the changes since the index was built, given the ids of the documents it currently holds
+    async fn build_update_if_needed(&self, indexed_documents: &HashSet<u32>) -> Result<Option<IndexUpdate>, IndexError>;
 }
 
 pub struct SqlIndexBuilder {
@@ -32,6 +210,27 @@ pub struct SqlIndexBuilder {
 #[async_trait]
 impl IndexBuilder for SqlIndexBuilder {
     async fn build_index_if_needed(&self) -> Result<Option<Index>, IndexError> 
+    {
+        let batch_str = env::var("BATCH_SIZE").unwrap_or("5000".to_string());
+        let batch_size = batch_str.parse::<u32>().unwrap();
+        let (cap, cap_per) = cache_capacity_from_env();
+        let codec = posting_codec_from_env()?;
+
+        info!("BATCH_SIZE size found/default: {} documents", batch_size);
+
+        let mut source = SqlDocumentSource::connect(&self.connection_string, batch_size).await?;
+
+        if source.dump_id() <= self.dump_id {
+            source.pool.close().await;
+            return Ok(None);
+        }
+
+        let idx = build_index_from_source(&mut source, cap, cap_per, codec).await?;
+
+        Ok(Some(idx))
+    }
+
+    async fn build_update_if_needed(&self, indexed_documents: &HashSet<u32>) -> Result<Option<IndexUpdate>, IndexError>
     {
         let pool = PgPoolOptions::new()
             .max_connections(1)
@@ -50,126 +249,70 @@ impl IndexBuilder for SqlIndexBuilder {
             return Ok(None);
         }
 
+        let timer = Instant::now();
+        let dump_id = self.dump_id as i64;
 
+        // articles are stamped with the dump they last changed in
+        let documents_q = query("
+            SELECT a.articleid, a.title, a.namespace, a.lastupdated, c.abstracts, c.categories, c.links, c.text
+            FROM article as a 
+            INNER JOIN \"content\" as c
+                ON  a.articleid = c.articleid
+                AND a.dumpid > $1
+            ORDER BY a.articleid ASC
+        ").bind(&dump_id)
+        .fetch_all(&pool);
 
-        let disable_cache = env::var("CACHE_DISABLE").unwrap_or("false".to_string()).parse::<bool>().unwrap_or(false);
-        let cap_str = env::var("CACHE_SIZE").unwrap_or("500000".to_string());
-        let cap_per_str = env::var("CACHE_PERSISTENT_SIZE").unwrap_or("100000".to_string());
-        let batch_str = env::var("BATCH_SIZE").unwrap_or("5000".to_string());
-
-        let mut cap = cap_str.parse::<u32>().unwrap();
-        let batch_size = batch_str.parse::<u32>().unwrap();
-        let mut cap_per = cap_per_str.parse::<u32>().unwrap();
-
-        if disable_cache{
-            cap = 10000000;
-            cap_per = cap;
-        }
-
-        info!("CACHE_DISABLE found/default: {} records", disable_cache);
-        info!("CACHE_SIZE size found/default: {} records", cap);
-        info!("CACHE_PERSISTENT_SIZE size found/default: {} records", cap_per);
-        info!("BATCH_SIZE size found/default: {} documents", batch_size);
-
-        let mut pre_index = PreIndex::with_capacity(cap,cap_per);
-        pre_index.dump_id = highest_dump_id;
+        let infoboxes_q = query("
+        SELECT a.articleid, ARRAY_AGG( (CASE WHEN i.infoboxtype IS NULL THEN (NULL) ELSE (i.infoboxtype,i.body) END)) as infoboxes
+        FROM article as a
+        INNER JOIN infoboxes as i
+            ON a.articleid = i.articleid
+            AND a.dumpid > $1
+        GROUP BY a.articleid
+        ORDER BY a.articleid ASC
+        ").bind(&dump_id)
+        .fetch_all(&pool);
 
-        // do this in batches 
-        let nquery : i64 = query("SELECT MAX(a.articleid)
-                            FROM article as a
-                            ")
-                            .fetch_one(&pool)
-                            .await
-                            .unwrap()
-                            .get("max");
-                        
-        let num_docs = nquery as u32;
-        let num_batches = num_docs.div_ceil(&batch_size);
-        let mut processed_docs = 0;
-
-        let mut timer;
-        for batch in 0..num_batches {
-            timer = Instant::now();
-            let start_idx = batch * batch_size;
-            let end_idx = ((batch + 1) * batch_size) - 1;
-
-            let batch_documents_q = query("
-                SELECT a.articleid, a.title, a.lastupdated, c.categories, c.links, c.text
-                FROM article as a 
-                INNER JOIN \"content\" as c
-                    ON  a.articleid = c.articleid
-                    AND a.articleid BETWEEN $1 AND $2
-                ORDER BY a.articleid ASC
-            ").bind(&start_idx)
-            .bind(&end_idx)
-            .fetch_all(&pool);
-            
-            let infoboxes_q = query("
-            SELECT a.articleid, ARRAY_AGG( (CASE WHEN i.infoboxtype IS NULL THEN (NULL) ELSE (i.infoboxtype,i.body) END)) as infoboxes
-            FROM article as a
-            INNER JOIN infoboxes as i
-                ON a.articleid = i.articleid
-                AND a.articleid BETWEEN $1 AND $2
-            GROUP BY a.articleid
-            ORDER BY a.articleid ASC
-            ").bind(&start_idx)
-            .bind(&end_idx)
-            .fetch_all(&pool);
+        let citations_q = query("
+        SELECT a.articleid, ARRAY_AGG(c.body) as citations
+        FROM article as a
+        INNER JOIN citations as c
+            ON a.articleid = c.articleid
+            AND a.dumpid > $1
+        GROUP BY a.articleid
+        ORDER BY a.articleid ASC
+        ").bind(&dump_id)
+        .fetch_all(&pool);
 
+        let existing_q = query("
+        SELECT a.articleid
+        FROM article as a
+        ").fetch_all(&pool);
 
-            let citations_q = query("
-            SELECT a.articleid, ARRAY_AGG(c.body) as citations
-            FROM article as a
-            INNER JOIN citations as c
-                ON a.articleid = c.articleid
-                AND a.articleid BETWEEN $1 AND $2
-            GROUP BY a.articleid
-            ORDER BY a.articleid ASC
-            ").bind(&start_idx)
-            .bind(&end_idx)
-            .fetch_all(&pool);
-
-            // let them run in parallel
-            let batch_documents = batch_documents_q.await.unwrap();
-            let mut infoboxes = infoboxes_q.await.unwrap()
-                .into_iter().map(|i| (i.get("articleid"),i.get("infoboxes")))
-                .collect::<HashMap<i64,Vec<(String,String)>>>();
-            let mut citations = citations_q.await.unwrap()
-                .into_iter().map(|c| (c.get("articleid"),c.get("citations")))
-                .collect::<HashMap<i64,Vec<String>>>();
-            
-            batch_documents.into_iter().for_each(|d| {
-                processed_docs += 1;
-                let doc_id : i64 = d.get("articleid");
-                let infoboxes : Option<Vec<(String,String)>> = infoboxes.remove(&doc_id);
-                let citations : Option<Vec<String>> = citations.remove(&doc_id);
-                let new_document = Box::new(Document {
-                    doc_id: doc_id as u32,
-                    categories: d.get("categories"),
-                    main_text: d.get("text"),
-                    article_links: d.get("links"),
-                    title: d.get("title"),
-                    last_updated_date: d.get("lastupdated"),
-                    infoboxes: infoboxes.unwrap_or_default().into_iter().map(|v| Infobox {itype : v.0, text :v.1}).collect(),//v.get("infoboxes").map(|v| v).unwrap_or_default(),
-                    citations: citations.unwrap_or_default().into_iter().map(|v| Citation {text :v}).collect(),//v.try_get("citations").unwrap_or_default(),
-                });
-                    
-                if let Err(e) = pre_index.add_document(new_document){
-                    let title : String = d.get("title");
-                    error!("Error in adding document with {{id: {}, title: {}}}:{}",doc_id,title,e);
-                }
-
-            });
-
-
-
-            info!("Building pre-index: {}% ({}s) - processed {} docs, cache size {}",(processed_docs as f32 / num_docs as f32) * 100.0,timer.elapsed().as_secs(),processed_docs,pre_index.cache_size());
-        }
+        let changed_documents = documents_q.await?;
+        let mut infoboxes = infoboxes_q.await?
+            .into_iter().map(|i| (i.get("articleid"),i.get("infoboxes")))
+            .collect::<HashMap<i64,Vec<(String,String)>>>();
+        let mut citations = citations_q.await?
+            .into_iter().map(|c| (c.get("articleid"),c.get("citations")))
+            .collect::<HashMap<i64,Vec<String>>>();
+        let existing = existing_q.await?
+            .into_iter().map(|r| r.get::<i64,_>("articleid") as u32)
+            .collect::<HashSet<u32>>();
 
         pool.close().await;
 
-        let idx = Index::from_pre_index(pre_index);
+        let update = IndexUpdate {
+            dump_id: highest_dump_id,
+            documents: changed_documents.iter()
+                .map(|d| document_from_row(d, &mut infoboxes, &mut citations))
+                .collect(),
+            deleted: indexed_documents.difference(&existing).cloned().collect(),
+        };
 
-        Ok(Some(idx))
+        info!("Fetched update with {} changed and {} deleted documents ({}s)",update.documents.len(),update.deleted.len(),timer.elapsed().as_secs());
+
+        Ok(Some(update))
     }
 }
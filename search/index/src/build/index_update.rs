@@ -0,0 +1,284 @@
+use crate::index::next_generation;
+use crate::{
+    compute_page_ranks, Document, DocumentEntry, DocumentMetaData, EncodedPostingNode, Index,
+    IndexError, IndexErrorKind, LastUpdatedDate, PosRange, Posting, PostingCodec,
+    SequentialEncoder,
+};
+use itertools::Itertools;
+use log::info;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// a batch of changes to the articles behind a live index
+#[derive(Default)]
+pub struct IndexUpdate {
+    /// the dump the index reflects once the update is applied
+    pub dump_id: u32,
+    /// articles which are new or whose content changed, these replace any previous version
+    pub documents: Vec<Box<Document>>,
+    /// articles which no longer exist
+    pub deleted: Vec<u32>,
+}
+
+impl IndexUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// what a document of an update leaves behind outside of the posting lists and links
+struct UpdatedDocument {
+    doc_id: u32,
+    extent: HashMap<String, PosRange>,
+    last_updated_date: LastUpdatedDate,
+    length: u32,
+    text: String,
+    metadata: DocumentMetaData,
+}
+
+/// an update worked out against an index without changing it, so the index can go on serving queries
+/// in the meantime. The posting lists it touches are re-encoded and the link graph and page rank
+/// recomputed up front, leaving only swapping them in to [Index::commit_update]
+pub struct PreparedUpdate {
+    dump_id: u32,
+    /// the generation of the index it was prepared against
+    generation: u64,
+    removed: HashSet<u32>,
+    documents: Vec<UpdatedDocument>,
+    links: HashMap<u32, Vec<u32>>,
+    incoming_links: HashMap<u32, Vec<u32>>,
+    page_rank: HashMap<u32, f64>,
+    /// replacements for existing posting lists, by their token
+    rewritten: Vec<(String, EncodedPostingNode<PostingCodec>)>,
+    /// posting lists of tokens the index doesn't hold yet
+    added: Vec<(String, EncodedPostingNode<PostingCodec>)>,
+}
+
+impl Index {
+    /// applies the changes in place, leaving the index as it would be if it was rebuilt from scratch
+    /// over the updated set of articles. Posting lists which lose all their postings are kept around empty.
+    pub fn apply_update(&mut self, update: IndexUpdate) -> Result<(), IndexError> {
+        let prepared = self.prepare_update(update)?;
+        self.commit_update(prepared)
+    }
+
+    /// works out everything the update changes without touching the index. Only the posting lists of tokens
+    /// in a changed article are visited and re-encoded, the tokens of old versions are read from their stored texts.
+    /// The index must not change until the update is committed
+    pub fn prepare_update(&self, mut update: IndexUpdate) -> Result<PreparedUpdate, IndexError> {
+        let mut timer = Instant::now();
+
+        update.documents.sort_by_key(|d| d.doc_id);
+        if update
+            .documents
+            .windows(2)
+            .any(|w| w[0].doc_id == w[1].doc_id)
+        {
+            return Err(IndexError {
+                msg: "Attempted to update a document more than once in the same update."
+                    .to_string(),
+                kind: IndexErrorKind::InvalidOperation,
+            });
+        }
+
+        // replaced and deleted documents lose all their old data first
+        let removed = update
+            .deleted
+            .iter()
+            .cloned()
+            .chain(update.documents.iter().map(|d| d.doc_id))
+            .filter(|id| self.links.contains_key(id))
+            .collect::<HashSet<u32>>();
+
+        // the posting lists the old versions of the documents are in
+        let mut tokens = HashSet::new();
+        for id in &removed {
+            if let Some(text) = self.get_text(*id) {
+                tokens.extend(text.split(' ').filter(|t| !t.is_empty()).map(|t| t.to_string()));
+            }
+        }
+
+        let mut links = self.links.clone();
+        let mut incoming_links = self.incoming_links.clone();
+        for id in &removed {
+            remove_links(&mut links, &mut incoming_links, *id);
+        }
+
+        // documents are sorted, so each token's new postings end up sorted as well
+        let mut new_postings: HashMap<String, Vec<Posting>> = HashMap::new();
+        let mut documents = Vec::with_capacity(update.documents.len());
+        for document in &update.documents {
+            let entry = DocumentEntry::from_document(document);
+
+            for (token, position) in entry.tokens {
+                let posting = Posting {
+                    document_id: entry.doc_id,
+                    position,
+                };
+                match new_postings.get_mut(token.as_ref()) {
+                    Some(v) => v.push(posting),
+                    None => {
+                        new_postings.insert(token.to_string(), vec![posting]);
+                    }
+                }
+            }
+
+            let mut document_links = entry.links;
+            document_links.sort();
+            for target in &document_links {
+                let sources = incoming_links.entry(*target).or_default();
+                let pos = sources.binary_search(&entry.doc_id).unwrap_or_else(|p| p);
+                sources.insert(pos, entry.doc_id);
+            }
+            links.insert(entry.doc_id, document_links);
+
+            documents.push(UpdatedDocument {
+                doc_id: entry.doc_id,
+                extent: entry.extent,
+                last_updated_date: entry.last_updated_date,
+                length: entry.length,
+                text: entry.text,
+                metadata: entry.metadata,
+            });
+        }
+        info!(
+            "Removed {} and tokenized {} documents ({}s)",
+            removed.len(),
+            update.documents.len(),
+            timer.elapsed().as_secs()
+        );
+
+        timer = Instant::now();
+        tokens.extend(new_postings.keys().cloned());
+        let mut rewritten = Vec::new();
+        for token in tokens {
+            // tokens new to the index are added below
+            let ptr = match self.posting_nodes.entry(&token) {
+                Some(v) => v,
+                None => continue,
+            };
+            let additions = new_postings.remove(&token);
+
+            let mut lock = ptr.lock();
+            // the lookup might have evicted this very entry
+            let node = lock.get().map_err(|e| IndexError {
+                msg: e.to_string(),
+                kind: IndexErrorKind::InvalidIndexState,
+            })?;
+
+            let updated = EncodedPostingNode::from_postings(
+                node.documents.encoder().fresh(),
+                node.postings()
+                    .into_iter()
+                    .filter(|p| !removed.contains(&p.document_id))
+                    .merge(additions.unwrap_or_default()),
+            );
+            rewritten.push((token, updated));
+        }
+
+        let added = new_postings
+            .into_iter()
+            .map(|(token, postings)| {
+                let node = EncodedPostingNode::from_postings(self.codec.fresh(), postings);
+                (token, node)
+            })
+            .collect::<Vec<_>>();
+        info!(
+            "Rewrote {} and added {} posting lists ({}s)",
+            rewritten.len(),
+            added.len(),
+            timer.elapsed().as_secs()
+        );
+
+        info!("Calculating page rank");
+        timer = Instant::now();
+        let page_rank = compute_page_ranks(&links, &incoming_links, 0.85);
+        info!("Took {}s", timer.elapsed().as_secs());
+
+        Ok(PreparedUpdate {
+            dump_id: update.dump_id,
+            generation: self.generation,
+            removed,
+            documents,
+            links,
+            incoming_links,
+            page_rank,
+            rewritten,
+            added,
+        })
+    }
+
+    /// swaps in what was prepared, only the documents and posting lists of the update are visited.
+    /// Nothing is changed if the update was prepared against another generation of the index
+    pub fn commit_update(&mut self, prepared: PreparedUpdate) -> Result<(), IndexError> {
+        if prepared.generation != self.generation {
+            return Err(IndexError {
+                msg: "Attempted to commit an update prepared against a different index.".to_string(),
+                kind: IndexErrorKind::InvalidOperation,
+            });
+        }
+
+        // replacing records can't fail, so the index is never left half updated
+        for (token, node) in prepared.rewritten.into_iter().chain(prepared.added) {
+            self.posting_nodes.insert(&token, node);
+        }
+
+        prepared
+            .removed
+            .iter()
+            .for_each(|id| self.remove_document_data(*id));
+
+        for document in prepared.documents {
+            for (structure_elem, range) in document.extent {
+                self.extent
+                    .entry(structure_elem)
+                    .or_default()
+                    .insert(document.doc_id, range);
+            }
+
+            self.last_updated_docs
+                .insert(document.doc_id, document.last_updated_date);
+            self.doc_lengths.insert(document.doc_id, document.length);
+            self.doc_texts
+                .insert(&document.doc_id.to_string(), document.text);
+            self.metadata.insert(document.doc_id, document.metadata);
+        }
+        self.links = prepared.links;
+        self.incoming_links = prepared.incoming_links;
+        self.page_rank = prepared.page_rank;
+        self.compute_field_lengths();
+        self.compute_title_ids();
+
+        self.dump_id = self.dump_id.max(prepared.dump_id);
+        self.generation = next_generation();
+
+        Ok(())
+    }
+
+    /// drops everything outside the posting lists and links which refers to the document
+    fn remove_document_data(&mut self, doc_id: u32) {
+        self.last_updated_docs.remove(&doc_id);
+        self.doc_lengths.remove(&doc_id);
+        self.doc_texts.remove(&doc_id.to_string());
+        self.metadata.remove(&doc_id);
+        self.extent.values_mut().for_each(|e| {
+            e.remove(&doc_id);
+        });
+    }
+}
+
+/// drops the links from the document along with the incoming links they made up
+fn remove_links(
+    links: &mut HashMap<u32, Vec<u32>>,
+    incoming_links: &mut HashMap<u32, Vec<u32>>,
+    doc_id: u32,
+) {
+    for target in links.remove(&doc_id).unwrap_or_default() {
+        if let Some(sources) = incoming_links.get_mut(&target) {
+            sources.retain(|s| *s != doc_id);
+            if sources.is_empty() {
+                incoming_links.remove(&target);
+            }
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use crate::{
+    errors::{IndexError, IndexErrorKind},
+    index_structs::{Citation, Document, Infobox, DATE_TIME_FORMAT},
+    DocumentSource,
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use itertools::Itertools;
+use preprocessor::{Preprocessor, ProcessingOptions};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// the format of revision timestamps in a dump
+const DUMP_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// a page as it appears in the dump, before its wikitext is processed
+struct RawPage {
+    id: u32,
+    title: String,
+    namespace: i16,
+    timestamp: String,
+    /// the title of the page this one redirects to
+    redirect: Option<String>,
+    text: String,
+}
+
+/// reads the `<page>` elements of a dump one at a time
+struct PageReader<R: BufRead> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> PageReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+
+    /// the xml of the next page, from its opening to its closing tag
+    fn next_page_xml(&mut self) -> Result<Option<String>, IndexError> {
+        let mut page: Option<String> = None;
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return match page {
+                    Some(_) => Err(IndexError {
+                        msg: "Dump ended in the middle of a page".to_string(),
+                        kind: IndexErrorKind::BuildFailed,
+                    }),
+                    None => Ok(None),
+                };
+            }
+
+            match page.as_mut() {
+                Some(p) => p.push_str(&self.line),
+                None => {
+                    if let Some(i) = self.line.find("<page>") {
+                        page = Some(self.line[i..].to_string());
+                    }
+                }
+            }
+
+            if let Some(p) = &page {
+                if p.contains("</page>") {
+                    return Ok(page);
+                }
+            }
+        }
+    }
+
+    fn next_page(&mut self) -> Result<Option<RawPage>, IndexError> {
+        let xml = match self.next_page_xml()? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        // revisions and contributors have ids of their own
+        let header = &xml[..xml.find("<revision").unwrap_or(xml.len())];
+        let id = element(header, "id")
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .ok_or_else(|| IndexError {
+                msg: format!(
+                    "Page without a valid id: {}",
+                    element(header, "title").unwrap_or("")
+                ),
+                kind: IndexErrorKind::BuildFailed,
+            })?;
+
+        Ok(Some(RawPage {
+            id,
+            title: unescape(element(header, "title").unwrap_or_default()),
+            namespace: element(header, "ns")
+                .and_then(|v| v.trim().parse::<i16>().ok())
+                .unwrap_or_default(),
+            timestamp: element(&xml, "timestamp")
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            redirect: attribute(header, "redirect", "title").map(unescape),
+            text: unescape(element(&xml, "text").unwrap_or_default()),
+        }))
+    }
+}
+
+/// the content of the first element with the given tag, empty for self closing elements
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let (start, open) = open_tag(xml, tag)?;
+    if open.ends_with('/') {
+        return Some("");
+    }
+
+    let content = &xml[start + open.len() + 1..];
+    let end = content.find(&format!("</{}>", tag))?;
+    Some(&content[..end])
+}
+
+/// the value of an attribute on the first element with the given tag
+fn attribute<'a>(xml: &'a str, tag: &str, attr: &str) -> Option<&'a str> {
+    let (_, open) = open_tag(xml, tag)?;
+    let pattern = format!(" {}=\"", attr);
+    let value = &open[open.find(&pattern)? + pattern.len()..];
+    Some(&value[..value.find('"')?])
+}
+
+/// the position and text of the first opening tag with the given name, without its closing `>`
+fn open_tag<'a>(xml: &'a str, tag: &str) -> Option<(usize, &'a str)> {
+    let pattern = format!("<{}", tag);
+    let mut from = 0;
+    while let Some(i) = xml[from..].find(&pattern).map(|i| i + from) {
+        let after = &xml[i + pattern.len()..];
+        if after.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            let end = after.find('>')?;
+            return Some((i, &xml[i..i + pattern.len() + end]));
+        }
+        from = i + pattern.len();
+    }
+    None
+}
+
+/// replaces the xml entities of the dump with the characters they stand for
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let decoded = entity.and_then(|(name, end)| {
+            let c = match name {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => name
+                        .strip_prefix('#')
+                        .and_then(|dec| dec.parse::<u32>().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// the title a link or redirect refers to, as it would appear in the title of the page
+fn normalise_title(title: &str) -> String {
+    let title = title
+        .split('#')
+        .next()
+        .unwrap_or_default()
+        .replace('_', " ");
+    let mut chars = title.trim().chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// replaces every outermost `open ... close` span with whatever `f` makes of its inside,
+/// unbalanced openings are left as they are
+fn replace_nested<F: FnMut(&str) -> String>(
+    text: &str,
+    open: &str,
+    close: &str,
+    mut f: F,
+) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(open) {
+        let mut depth = 0;
+        let mut i = start;
+        let mut end = None;
+        while i < rest.len() {
+            if rest[i..].starts_with(open) {
+                depth += 1;
+                i += open.len();
+            } else if rest[i..].starts_with(close) {
+                depth -= 1;
+                i += close.len();
+                if depth == 0 {
+                    end = Some(i);
+                    break;
+                }
+            } else {
+                i += rest[i..].chars().next().map_or(1, |c| c.len_utf8());
+            }
+        }
+
+        let end = match end {
+            Some(v) => v,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&f(&rest[start + open.len()..end - close.len()]));
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// the parts of a page's wikitext which make up a document
+#[derive(Default)]
+struct Wikitext {
+    main_text: String,
+    categories: Vec<String>,
+    /// titles of the linked pages
+    links: Vec<String>,
+    infoboxes: Vec<Infobox>,
+    citations: Vec<Citation>,
+}
+
+impl Wikitext {
+    /// citations and infoboxes are taken out of the text along with all other templates,
+    /// links are replaced by their labels and category links are dropped
+    fn parse(text: &str) -> Self {
+        let mut wikitext = Wikitext::default();
+
+        // references, either <ref>...</ref> or self closing ones pointing at a named reference
+        let mut text = text.to_string();
+        while let Some((start, open)) = open_tag(&text, "ref") {
+            let (body, end) = match open.ends_with('/') {
+                true => (None, start + open.len() + 1),
+                false => match text[start..].find("</ref>") {
+                    Some(close) => (
+                        Some(text[start + open.len() + 1..start + close].to_string()),
+                        start + close + "</ref>".len(),
+                    ),
+                    None => (None, start + open.len() + 1),
+                },
+            };
+
+            if let Some(body) = body {
+                wikitext.citations.push(Citation { text: body });
+            }
+            text.replace_range(start..end, "");
+        }
+
+        let text = replace_nested(&text, "{{", "}}", |template| {
+            let (name, body) = template.split_once('|').unwrap_or((template, ""));
+            let name = name.trim().to_lowercase();
+            if let Some(itype) = name.strip_prefix("infobox") {
+                wikitext.infoboxes.push(Infobox {
+                    itype: match itype.trim() {
+                        "" => "infobox".to_string(),
+                        v => v.to_string(),
+                    },
+                    text: body.to_string(),
+                });
+            }
+            String::new()
+        });
+
+        let text = replace_nested(&text, "[[", "]]", |link| {
+            let (target, label) = match link.split_once('|') {
+                Some((t, l)) => (t, l.rsplit('|').next().unwrap_or(l)),
+                None => (link, link),
+            };
+            let target = target.trim().trim_start_matches(':');
+
+            match target.split_once(':') {
+                Some((namespace, category)) if namespace.eq_ignore_ascii_case("category") => {
+                    wikitext.categories.push(category.trim().to_string());
+                    String::new()
+                }
+                // files, images and interwiki links
+                Some(_) => String::new(),
+                None => {
+                    wikitext.links.push(normalise_title(target));
+                    label.to_string()
+                }
+            }
+        });
+
+        wikitext.main_text = text.trim().to_string();
+        wikitext
+    }
+
+    /// the first paragraph of the text which isn't a heading
+    fn summary(&self) -> String {
+        self.main_text
+            .split("\n\n")
+            .map(|p| p.trim())
+            .find(|p| !p.is_empty() && !p.starts_with('='))
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// the tokens of the text separated by spaces, the form text is stored in the database in.
+/// Every word dropped by the preprocessor is left as an empty word, so phrases keep their gaps
+fn preprocess(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| Preprocessor::process(&ProcessingOptions::default(), word.to_string()).join(" "))
+        .join(" ")
+}
+
+/// reads the pages of a MediaWiki XML dump (`pages-articles.xml`), redirects are skipped.
+/// Links are resolved to page ids, following a single redirect, and links to pages not in the dump are dropped
+pub struct MediaWikiXmlSource<R: BufRead + Send> {
+    pages: PageReader<R>,
+    /// the id of the page each title resolves to
+    titles: HashMap<String, u32>,
+    /// the number of pages which aren't redirects
+    page_count: u32,
+    batch_size: usize,
+}
+
+impl MediaWikiXmlSource<BufReader<File>> {
+    /// reads the dump twice, once for the ids of all the titles and then for the documents
+    pub fn open<P: AsRef<Path>>(path: P, batch_size: usize) -> Result<Self, IndexError> {
+        let (titles, page_count) = Self::read_titles(BufReader::new(File::open(&path)?))?;
+        Ok(Self {
+            pages: PageReader::new(BufReader::new(File::open(&path)?)),
+            titles,
+            page_count,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// the id each title resolves to and the number of pages which aren't redirects
+    fn read_titles<T: BufRead>(reader: T) -> Result<(HashMap<String, u32>, u32), IndexError> {
+        let mut pages = PageReader::new(reader);
+        let mut titles = HashMap::new();
+        let mut redirects = Vec::new();
+
+        while let Some(page) = pages.next_page()? {
+            match page.redirect {
+                Some(target) => redirects.push((page.title, normalise_title(&target))),
+                None => {
+                    titles.insert(page.title, page.id);
+                }
+            }
+        }
+
+        let page_count = titles.len() as u32;
+        for (title, target) in redirects {
+            if let Some(id) = titles.get(&target).cloned() {
+                titles.entry(title).or_insert(id);
+            }
+        }
+
+        Ok((titles, page_count))
+    }
+}
+
+impl<R: BufRead + Send> MediaWikiXmlSource<R> {
+    /// the text of every field is preprocessed, the title and abstract are kept as they are displayed
+    fn document(&self, page: RawPage) -> Box<Document> {
+        let wikitext = Wikitext::parse(&page.text);
+
+        Box::new(Document {
+            doc_id: page.id,
+            title: page.title,
+            article_abstract: wikitext.summary(),
+            namespace: page.namespace,
+            categories: wikitext.categories.iter().map(|c| preprocess(c)).join("\t"),
+            last_updated_date: NaiveDateTime::parse_from_str(&page.timestamp, DUMP_TIME_FORMAT)
+                .map(|d| d.format(DATE_TIME_FORMAT).to_string())
+                .unwrap_or_default(),
+            article_links: wikitext
+                .links
+                .iter()
+                .filter_map(|l| self.titles.get(l))
+                .unique()
+                .join("\t"),
+            main_text: preprocess(&wikitext.main_text),
+            infoboxes: wikitext
+                .infoboxes
+                .into_iter()
+                .map(|i| Infobox {
+                    itype: i.itype,
+                    text: preprocess(&i.text),
+                })
+                .collect(),
+            citations: wikitext
+                .citations
+                .into_iter()
+                .map(|c| Citation {
+                    text: preprocess(&c.text),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[async_trait]
+impl<R: BufRead + Send> DocumentSource for MediaWikiXmlSource<R> {
+    async fn next_batch(&mut self) -> Result<Option<Vec<Box<Document>>>, IndexError> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+
+        while batch.len() < self.batch_size {
+            let page = match self.pages.next_page()? {
+                Some(v) => v,
+                None => break,
+            };
+
+            if page.redirect.is_none() {
+                batch.push(self.document(page));
+            }
+        }
+
+        match batch.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(batch)),
+        }
+    }
+
+    fn size_hint(&self) -> Option<u32> {
+        Some(self.page_count)
+    }
+}
@@ -1,4 +1,10 @@
+pub mod document_source;
 pub mod index_builder;
+pub mod index_update;
+pub mod mediawiki_source;
 pub mod pre_index;
 
-pub use {index_builder::*, pre_index::*};
+#[cfg(test)]
+pub mod document_source_tests;
+
+pub use {document_source::*, index_builder::*, index_update::*, mediawiki_source::*, pre_index::*};
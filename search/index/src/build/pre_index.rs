@@ -1,23 +1,32 @@
+use crate::index::doc_text_store;
 use crate::{
-    DiskHashMap, Document, EncodedPostingNode, IndexError, IndexErrorKind, LastUpdatedDate,
-    PosRange, Posting, PostingNode, VbyteEncoder, DATE_TIME_FORMAT,
+    DiskHashMap, Document, DocumentMetaData, EncodedPostingList, EncodedPostingNode, IndexError, IndexErrorKind, LastUpdatedDate,
+    PosRange, Posting, PostingCodec, PostingNode, SequentialEncoder, DATE_TIME_FORMAT,
 };
 use bimap::BiMap;
 use chrono::NaiveDateTime;
 use itertools::Itertools;
 use parser::StructureElem;
-use std::collections::{HashMap, HashSet};
+use preprocessor::{Preprocessor, ProcessingOptions};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 /// a common backbone from which any index can be intialized
 pub struct PreIndex {
     pub dump_id: u32,
-    pub posting_nodes: DiskHashMap<EncodedPostingNode<VbyteEncoder<Posting, true>>, 0>,
+    pub posting_nodes: DiskHashMap<EncodedPostingNode<PostingCodec>>,
+    /// what the posting lists are encoded with, only to be changed before any document is added
+    pub codec: PostingCodec,
     pub links: HashMap<u32, Vec<u32>>,
     pub extent: HashMap<String, HashMap<u32, PosRange>>,
-    // pub id_title_map: BiMap<u32, String>,
+    pub metadata: HashMap<u32, DocumentMetaData>,
     pub last_updated_docs: HashMap<u32, LastUpdatedDate>,
-    // for keeping track of unique token appearances in the current document
-    curr_doc_appearances: HashSet<String>,
+    pub doc_lengths: HashMap<u32, u32>,
+    /// the token stream of every document, kept on disk as the index is built
+    pub doc_texts: DiskHashMap<String>,
+    /// the highest document id added so far, any document added later has to come after it
+    pub last_doc_id: Option<u32>,
 }
 
 impl Default for PreIndex {
@@ -25,24 +34,31 @@ impl Default for PreIndex {
         Self {
             dump_id: Default::default(),
             posting_nodes: DiskHashMap::new(10000, 100, true),
+            codec: Default::default(),
             links: Default::default(),
             extent: Default::default(),
+            metadata: Default::default(),
             last_updated_docs: Default::default(),
-            curr_doc_appearances: Default::default(),
+            doc_lengths: Default::default(),
+            doc_texts: doc_text_store(true),
+            last_doc_id: None,
         }
     }
 }
 
 impl PreIndex {
-    pub fn with_capacity(cap: u32, persistent_cap: u32) -> Self {
+    pub fn with_capacity(cap: u64, persistent_cap: u64) -> Self {
         Self {
             dump_id: Default::default(),
             posting_nodes: DiskHashMap::new(cap, persistent_cap, true),
+            codec: Default::default(),
             links: Default::default(),
             extent: Default::default(),
-            // id_title_map: Default::default(),
+            metadata: Default::default(),
             last_updated_docs: Default::default(),
-            curr_doc_appearances: Default::default(),
+            doc_lengths: Default::default(),
+            doc_texts: doc_text_store(true),
+            last_doc_id: None,
         }
     }
 
@@ -54,119 +70,321 @@ impl PreIndex {
         self.posting_nodes.clean_cache();
     }
 
-    pub fn add_document(&mut self, document: Box<Document>) -> Result<(), IndexError> {
-        if self.links.contains_key(&document.doc_id) {
+    /// fails if the document is already present or comes before one which is,
+    /// posting lists can only be appended to in document order
+    fn check_insertable(&self, doc_id: u32) -> Result<(), IndexError> {
+        if self.links.contains_key(&doc_id) {
             return Err(IndexError {
                 msg: "Attempted to insert document into index which already exists.".to_string(),
                 kind: IndexErrorKind::InvalidOperation,
             });
         }
 
-        let mut word_pos = 0;
+        match self.last_doc_id {
+            Some(last) if doc_id <= last => Err(IndexError {
+                msg: format!(
+                    "Attempted to insert document {} before document {} which is already in the index, documents have to be added in order of their ids.",
+                    doc_id, last
+                ),
+                kind: IndexErrorKind::InvalidOperation,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn add_document(&mut self, document: Box<Document>) -> Result<(), IndexError> {
+        self.check_insertable(document.doc_id)?;
+
+        let mut shard = PreIndexShard::default();
+        shard.add_document(&document);
+        self.merge_shard(shard)
+    }
+
+    /// adds the documents on all threads of the rayon pool, each thread building a shard out of a run of
+    /// documents which are then merged in document order. Documents which cannot be added are returned with the reason
+    pub fn add_documents(&mut self, mut documents: Vec<Box<Document>>) -> Vec<(Box<Document>, IndexError)> {
+        documents.sort_by_key(|d| d.doc_id);
+
+        let mut rejected = Vec::new();
+        let mut accepted = Vec::with_capacity(documents.len());
+        for document in documents {
+            let duplicate = accepted.last().map_or(false, |d: &Box<Document>| d.doc_id == document.doc_id);
+            let check = match duplicate {
+                true => Err(IndexError {
+                    msg: "Attempted to insert document into index which already exists.".to_string(),
+                    kind: IndexErrorKind::InvalidOperation,
+                }),
+                false => self.check_insertable(document.doc_id),
+            };
+
+            match check {
+                Err(e) => rejected.push((document, e)),
+                Ok(()) => accepted.push(document),
+            }
+        }
+
+        let threads = rayon::current_num_threads();
+        let run_length = ((accepted.len() + threads - 1) / threads).max(1);
+        let shards = accepted
+            .par_chunks(run_length)
+            .map(|run| {
+                let mut shard = PreIndexShard::default();
+                run.iter().for_each(|d| shard.add_document(d));
+                shard
+            })
+            .collect::<Vec<PreIndexShard>>();
+
+        for shard in shards {
+            self.merge_shard(shard)
+                .expect("Documents were checked to come after those in the index");
+        }
+
+        rejected
+    }
+
+    /// appends the shard to the index, its documents must be new and come after any already present
+    /// for the posting lists to stay in document order. Shards which don't are rejected without being merged
+    pub fn merge_shard(&mut self, shard: PreIndexShard) -> Result<(), IndexError> {
+        let (first, last) = match shard.links.keys().minmax().into_option() {
+            Some((first, last)) => (*first, *last),
+            None => return Ok(()),
+        };
+        self.check_insertable(first)?;
+        self.last_doc_id = Some(last);
+
+        for (token, shard_node) in shard.posting_nodes {
+            let ptr = self.posting_nodes.entry_or_default(&token);
+            let mut lock = ptr.lock();
+            let node = lock.get_mut().unwrap();
+
+            if node.postings_count == 0 {
+                node.documents = EncodedPostingList::with_encoder(self.codec.fresh());
+            }
+            node.extend(shard_node.postings);
+        }
+
+        for (structure_elem, ranges) in shard.extent {
+            self.extent
+                .entry(structure_elem)
+                .or_insert(HashMap::new())
+                .extend(ranges);
+        }
+
+        self.links.extend(shard.links);
+        self.metadata.extend(shard.metadata);
+        self.last_updated_docs.extend(shard.last_updated_docs);
+        self.doc_lengths.extend(shard.doc_lengths);
+        for (doc_id, text) in shard.doc_texts {
+            self.doc_texts.insert(&doc_id.to_string(), text);
+        }
+
+        Ok(())
+    }
+}
+
+/// the part of a [PreIndex] built from a run of documents, held in memory so that runs can be built
+/// on separate threads before being merged
+#[derive(Default)]
+pub struct PreIndexShard {
+    pub posting_nodes: HashMap<String, PostingNode>,
+    pub links: HashMap<u32, Vec<u32>>,
+    pub extent: HashMap<String, HashMap<u32, PosRange>>,
+    pub metadata: HashMap<u32, DocumentMetaData>,
+    pub last_updated_docs: HashMap<u32, LastUpdatedDate>,
+    pub doc_lengths: HashMap<u32, u32>,
+    pub doc_texts: HashMap<u32, String>,
+}
+
+impl PreIndexShard {
+    /// documents have to be added in document order
+    pub fn add_document(&mut self, document: &Document) {
+        let entry = DocumentEntry::from_document(document);
 
         // metadata
-        self.last_updated_docs.insert(
-            document.doc_id,
-            LastUpdatedDate {
+        self.metadata.insert(document.doc_id, entry.metadata);
+        self.last_updated_docs
+            .insert(document.doc_id, entry.last_updated_date);
+        self.doc_lengths.insert(document.doc_id, entry.length);
+        self.doc_texts.insert(document.doc_id, entry.text);
+
+        // postings
+        for (token, word_pos) in entry.tokens {
+            let node = match self.posting_nodes.get_mut(token.as_ref()) {
+                Some(v) => v,
+                None => self.posting_nodes.entry(token.into_owned()).or_default(),
+            };
+
+            node.postings.push(Posting {
+                document_id: document.doc_id,
+                position: word_pos,
+            });
+            node.postings_count += 1;
+
+            let tf = node.tf.entry(document.doc_id).or_default();
+            *tf += 1;
+            // first appearance within the document
+            if *tf == 1 {
+                node.df += 1;
+            }
+        }
+
+        // structure elements
+        for (structure_elem, range) in entry.extent {
+            self.extent
+                .entry(structure_elem)
+                .or_insert(HashMap::new())
+                .insert(document.doc_id, range);
+        }
+
+        //Links
+        self.links.insert(document.doc_id, entry.links);
+    }
+}
+
+/// everything the index records about a single document,
+/// token positions run through the infoboxes, main text, citations, categories and title in that order
+pub struct DocumentEntry<'a> {
+    pub doc_id: u32,
+    pub tokens: Vec<(Cow<'a, str>, u32)>,
+    pub extent: HashMap<String, PosRange>,
+    pub links: Vec<u32>,
+    pub last_updated_date: LastUpdatedDate,
+    pub metadata: DocumentMetaData,
+    /// the number of tokens across all the fields
+    pub length: u32,
+    /// the tokens separated by single spaces, the n-th word holding the token at position n
+    pub text: String,
+}
+
+impl<'a> DocumentEntry<'a> {
+    pub fn from_document(document: &'a Document) -> Self {
+        let mut entry = Self {
+            doc_id: document.doc_id,
+            tokens: Vec::new(),
+            extent: HashMap::new(),
+            links: Self::parse_links(&document.article_links),
+            last_updated_date: LastUpdatedDate {
                 date_time: NaiveDateTime::parse_from_str(
                     &document.last_updated_date,
                     DATE_TIME_FORMAT,
                 )
                 .unwrap_or(NaiveDateTime::from_timestamp(0, 0)),
             },
-        );
+            metadata: DocumentMetaData {
+                title: document.title.clone(),
+                article_abstract: document.article_abstract.clone(),
+                namespace: document.namespace,
+                categories: Self::parse_categories(&document.categories),
+            },
+            length: 0,
+            text: String::new(),
+        };
+
+        let mut word_pos = 0;
 
         //Infoboxes
         word_pos = document.infoboxes.iter().fold(word_pos, |a, i| {
-            self.add_structure_elem(document.doc_id, &i.itype, &i.text, a)
+            entry.add_structure_elem(&i.itype, &i.text, a)
         });
 
         //Main body
-        word_pos = self.add_main_text(document.doc_id, &document.main_text, word_pos);
+        word_pos = entry.add_tokens(&document.main_text, word_pos);
 
         //Citations
         word_pos = document.citations.iter().fold(word_pos, |a, c| {
-            self.add_structure_elem(document.doc_id, StructureElem::Citation.into(), &c.text, a)
+            entry.add_structure_elem(StructureElem::Citation.into(), &c.text, a)
         });
 
         //Categories
-        let _ = self.add_structure_elem(
-            document.doc_id,
+        word_pos = entry.add_structure_elem(
             StructureElem::Category.into(),
             &document.categories,
             word_pos,
         );
 
-        //Links
-        self.add_links(document.doc_id, &document.article_links)?;
-
-        // collect DF values
-        for s in self.curr_doc_appearances.drain() {
-            self.posting_nodes
-                .entry(&s)
-                .unwrap()
-                .lock()
-                .get_mut()
-                .unwrap()
-                .df += 1;
-        }
+        //Title, the stored text is already processed but titles are kept as they are displayed
+        entry.length = entry.add_title(&document.title, word_pos);
+        entry.text = entry.token_stream();
 
-        Ok(())
+        entry
     }
 
-    fn add_tokens(&mut self, doc_id: u32, text_to_add: &str, mut word_pos: u32) -> u32 {
-        for token in text_to_add.split(" ").filter(|s| s.len() != 0) {
-            self.add_posting(token, doc_id, word_pos);
+    /// empty words between two separators are words dropped by the preprocessor, they still take up a position
+    fn add_tokens(&mut self, text_to_add: &'a str, mut word_pos: u32) -> u32 {
+        let text_to_add = text_to_add.trim_end_matches(|c| c == ' ' || c == '\t');
+        if text_to_add.is_empty() {
+            return word_pos;
+        }
+
+        for token in text_to_add.split(|c| c == ' ' || c == '\t') {
+            if token.len() != 0 {
+                self.tokens.push((Cow::Borrowed(token), word_pos));
+            }
             word_pos += 1;
         }
         return word_pos;
     }
 
-    fn add_posting(&mut self, token: &str, docid: u32, word_pos: u32) {
-        let ptr = self.posting_nodes.entry_or_default(token);
-
-        let mut lock = ptr.lock();
-
-        let node = lock.get_mut().unwrap();
+    /// words dropped by the preprocessor still take up a position, the same way they do in phrase queries
+    fn add_title(&mut self, title: &str, mut word_pos: u32) -> u32 {
+        let prev_pos = word_pos;
+        let prev_tokens = self.tokens.len();
+        for word in title.split_whitespace() {
+            let tokens = Preprocessor::process(&ProcessingOptions::default(), word.to_string());
+            let taken = (tokens.len() as u32).max(1);
+            for (i, token) in tokens.into_iter().enumerate() {
+                self.tokens.push((Cow::Owned(token), word_pos + i as u32));
+            }
+            word_pos += taken;
+        }
 
-        self.curr_doc_appearances.insert(token.to_owned());
+        // titles made up of stop words alone are left out
+        if self.tokens.len() == prev_tokens {
+            return prev_pos;
+        }
 
-        node.postings.push(Posting {
-            document_id: docid,
-            position: word_pos,
-        });
-        node.postings_count += 1;
+        self.extent.insert(
+            StructureElem::Title.into(),
+            PosRange {
+                start_pos: prev_pos,
+                end_pos: word_pos,
+            },
+        );
 
-        *node.tf.entry(docid).or_default() += 1;
+        return word_pos;
     }
 
-    fn add_links(&mut self, doc_id: u32, article_links: &str) -> Result<(), IndexError> {
-        // out links
-        let link_titles: Vec<u32> = article_links
-            .split("\t")
-            .filter_map(|c| c.trim().to_string().parse::<u32>().ok())
-            .collect();
-
-        // in links
-        self.links.insert(doc_id, link_titles);
-
-        Ok(())
+    /// positions without a token (gaps in the title) are left as empty words
+    fn token_stream(&self) -> String {
+        let mut text = String::new();
+        let mut next_pos = 0;
+        for (token, pos) in &self.tokens {
+            while next_pos < *pos {
+                if next_pos > 0 {
+                    text.push(' ');
+                }
+                next_pos += 1;
+            }
+            if *pos > 0 {
+                text.push(' ');
+            }
+            text.push_str(token);
+            next_pos = pos + 1;
+        }
+        text
     }
 
     fn add_structure_elem(
         &mut self,
-        doc_id: u32,
         structure_elem: &str,
-        text: &str,
+        text: &'a str,
         mut word_pos: u32,
     ) -> u32 {
         let prev_pos = word_pos;
-        word_pos = self.add_tokens(doc_id, &text, word_pos);
+        word_pos = self.add_tokens(text, word_pos);
 
         self.extent
             .entry(structure_elem.to_string())
-            .or_insert(HashMap::new())
-            .entry(doc_id)
             .or_insert(PosRange {
                 start_pos: prev_pos, // if not exists, initialize range
                 end_pos: word_pos,
@@ -176,8 +394,19 @@ impl PreIndex {
         return word_pos;
     }
 
-    fn add_main_text(&mut self, doc_id: u32, main_text: &str, mut word_pos: u32) -> u32 {
-        word_pos = self.add_tokens(doc_id, main_text, word_pos);
-        return word_pos;
+    fn parse_categories(categories: &str) -> Vec<String> {
+        categories
+            .split("\t")
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    fn parse_links(article_links: &str) -> Vec<u32> {
+        article_links
+            .split("\t")
+            .filter_map(|c| c.trim().to_string().parse::<u32>().ok())
+            .collect()
     }
 }
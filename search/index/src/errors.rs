@@ -9,6 +9,7 @@ pub enum IndexErrorKind {
     InvalidOperation,
     BuildFailed,
     GRPCBadStatus,
+    Snapshot,
     LogicError,
     Error,
 }
@@ -5,39 +5,79 @@ use utils::MemFootprintCalculator;
 use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 use std::{collections::HashMap, fmt};
 
 use crate::DiskHashMap;
+use crate::{default_disk_hash_map_dir, CachePolicy};
+use crate::DiskStats;
 
 use crate::EncodedPostingNode;
+use crate::{IndexError, IndexErrorKind};
 
 use crate::index_structs::PosRange;
+use crate::DocumentField;
+use crate::DocumentMetaData;
 use crate::Entry;
 use crate::LastUpdatedDate;
 use crate::Posting;
+use crate::{DocumentStream, PostingStream};
 use crate::SequentialEncoder;
-use crate::VbyteEncoder;
+use crate::PostingCodec;
+use crate::TermUsage;
 
 use crate::compute_page_ranks;
 use crate::PreIndex;
 use parking_lot::Mutex;
 
+/// how many document texts are held in memory, the texts of a few pages of results
+pub const DOC_TEXT_CACHE_SIZE: u64 = 1000;
+
+/// an empty store for the texts of documents, apart from the recently used ones they are kept on disk
+pub fn doc_text_store(build_mode: bool) -> DiskHashMap<String> {
+    DiskHashMap::with_policy(
+        default_disk_hash_map_dir(),
+        DOC_TEXT_CACHE_SIZE,
+        DOC_TEXT_CACHE_SIZE / 10,
+        build_mode,
+        CachePolicy::Lru,
+    )
+}
+
+/// a generation no other state of any index in the process had
+pub(crate) fn next_generation() -> u64 {
+    static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
 pub struct Index {
     pub dump_id: u32,
-    pub posting_nodes: DiskHashMap<EncodedPostingNode<VbyteEncoder<Posting, true>>, 0>, // index map because we want to keep this sorted
+    /// changes with every committed update, updates are only committed to the generation they were prepared against
+    pub(crate) generation: u64,
+    pub posting_nodes: DiskHashMap<EncodedPostingNode<PostingCodec>>, // index map because we want to keep this sorted
+    /// what new posting lists are encoded with
+    pub codec: PostingCodec,
     pub links: HashMap<u32, Vec<u32>>,
     pub incoming_links: HashMap<u32, Vec<u32>>,
     pub extent: HashMap<String, HashMap<u32, PosRange>>,
     pub last_updated_docs: HashMap<u32, LastUpdatedDate>,
     pub page_rank: HashMap<u32, f64>,
-    enabled: bool,
+    pub doc_lengths: HashMap<u32, u32>,
+    /// the token stream of every document by its id, see [Index::get_text]
+    pub doc_texts: DiskHashMap<String>,
+    pub metadata: HashMap<u32, DocumentMetaData>,
+    // title -> document, derived from the metadata
+    pub(crate) title_ids: HashMap<String, u32>,
+    // total tokens per field across all documents, derived from the above
+    pub(crate) field_lengths: HashMap<DocumentField, u64>,
+    pub(crate) enabled: bool,
 }
 
 impl Default for Index {
     fn default() -> Self {
-        Self { dump_id: Default::default(), posting_nodes: Default::default(), links: Default::default(), incoming_links: Default::default(), extent: Default::default(), last_updated_docs: Default::default(), page_rank: Default::default(), enabled: false }
+        Self { dump_id: Default::default(), generation: next_generation(), posting_nodes: Default::default(), codec: Default::default(), links: Default::default(), incoming_links: Default::default(), extent: Default::default(), last_updated_docs: Default::default(), page_rank: Default::default(), doc_lengths: Default::default(), doc_texts: doc_text_store(false), metadata: Default::default(), title_ids: Default::default(), field_lengths: Default::default(), enabled: false }
     }
 }
 
@@ -47,6 +87,8 @@ impl MemFootprintCalculator for Index {
             + self.posting_nodes.real_mem()
             + self.links.real_mem()
             + self.extent.real_mem()
+            + self.doc_texts.real_mem()
+            + self.metadata.real_mem()
     }
 }
 
@@ -59,16 +101,23 @@ impl Debug for Index {
         let incoming_links_mem = self.incoming_links.real_mem();
         let extent_mem = self.extent.real_mem();
         let last_updated_docs_mem = self.last_updated_docs.real_mem();
+        let doc_lengths_mem = self.doc_lengths.real_mem();
+        let doc_texts_mem = self.doc_texts.real_mem();
+        let metadata_mem = self.metadata.real_mem() + self.title_ids.real_mem();
 
         let real_mem = self.dump_id.real_mem()
             + posting_mem
             + links_mem
             + incoming_links_mem
             + extent_mem
-            + last_updated_docs_mem;
+            + last_updated_docs_mem
+            + doc_lengths_mem
+            + doc_texts_mem
+            + metadata_mem;
 
         let mem = real_mem as f64 / 1000000.0;
         let docs = self.links.len();
+        let disk = self.posting_nodes.disk_stats();
         write!(
             f,
             "BasicIndex{{\n\
@@ -84,6 +133,14 @@ impl Debug for Index {
             \t\tlinks:{:.3}Mb\n\
             \t\textent:{:.3}Mb\n\
             \t\tmetadata:{:.3}Mb\n\
+            \t\ttexts:{:.3}Mb\n\
+            \t}}\n\
+            \tPostingFile={:.3}Mb\n\
+            \t{{\n\
+            \t\tlive:{:.3}Mb\n\
+            \t\tfree:{:.3}Mb in {} holes\n\
+            \t\thits:{} misses:{} evictions:{}\n\
+            \t\tcache:{} {}/{} {} hit rate:{:.3}\n\
             \t}}\n\
             }}",
             self.dump_id,
@@ -96,7 +153,20 @@ impl Debug for Index {
             posting_mem as f64 / 1000000.0,
             (links_mem + incoming_links_mem) as f64 / 1000000.0,
             extent_mem as f64 / 1000000.0,
-            last_updated_docs_mem as f64 / 1000000.0
+            (last_updated_docs_mem + doc_lengths_mem + metadata_mem) as f64 / 1000000.0,
+            doc_texts_mem as f64 / 1000000.0,
+            disk.file_bytes as f64 / 1000000.0,
+            disk.live_bytes as f64 / 1000000.0,
+            disk.free_bytes as f64 / 1000000.0,
+            disk.holes,
+            disk.hits,
+            disk.misses,
+            disk.evictions,
+            disk.policy,
+            disk.cache_usage,
+            self.posting_nodes.capacity(),
+            self.posting_nodes.cache_policy().unit(),
+            disk.hit_rate()
         )
     }
 }
@@ -133,15 +203,19 @@ impl Index {
         }
 
         match self.posting_nodes.entry(token) {
-            Some(v) => v
-                .deref()
-                .lock()
-                .get()
-                .unwrap()
-                .tf
-                .get(&docid)
-                .cloned()
-                .unwrap_or(0),
+            Some(v) => v.deref().lock().get().unwrap().tf(docid),
+            None => 0,
+        }
+    }
+
+    /// the highest term frequency of the token within any single document
+    pub fn max_tf(&self, token: &str) -> u32 {
+        if !self.enabled{
+            return 0;
+        }
+
+        match self.posting_nodes.entry(token) {
+            Some(v) => v.deref().lock().get().unwrap().max_tf,
             None => 0,
         }
     }
@@ -153,7 +227,7 @@ impl Index {
     pub fn get_postings(
         &self,
         token: &str,
-    ) -> Option<Arc<Mutex<Entry<EncodedPostingNode<VbyteEncoder<Posting, true>>, 0>>>> {
+    ) -> Option<Arc<Mutex<Entry<EncodedPostingNode<PostingCodec>>>>> {
         if !self.enabled{
             return None;
         }
@@ -161,10 +235,82 @@ impl Index {
         self.posting_nodes.entry(token)
     }
 
+    /// the posting list of a token a query asked for, counted towards the usage the cache is warmed with
+    fn query_postings(
+        &self,
+        token: &str,
+    ) -> Option<Arc<Mutex<Entry<EncodedPostingNode<PostingCodec>>>>> {
+        if !self.enabled {
+            return None;
+        }
+
+        self.posting_nodes.lookup(token)
+    }
+
+    /// the postings of the token with their positions, decoded as they are read
+    pub fn stream_postings(&self, token: &str) -> Option<PostingStream> {
+        self.query_postings(token).map(PostingStream::new)
+    }
+
+    /// the documents holding the token with its frequency in each, positions are never decoded
+    pub fn stream_documents(&self, token: &str) -> Option<DocumentStream> {
+        self.query_postings(token).map(DocumentStream::new)
+    }
+
+    /// the positions of the token within each of the given documents holding it,
+    /// the positions of any other document are never decoded
+    pub fn positions_within<I: IntoIterator<Item = u32>>(
+        &self,
+        token: &str,
+        documents: I,
+    ) -> HashMap<u32, Vec<u32>> {
+        let mut positions: HashMap<u32, Vec<u32>> = HashMap::default();
+        let mut stream = match self.stream_postings(token) {
+            Some(v) => v,
+            None => return positions,
+        };
+
+        let mut documents = documents.into_iter().collect::<Vec<u32>>();
+        documents.sort_unstable();
+        for d in documents {
+            while let Some(p) = stream.seek(d).filter(|p| p.document_id == d) {
+                positions.entry(d).or_default().push(p.position);
+                stream.next();
+            }
+        }
+        positions
+    }
+
     pub fn get_extent_for(&self, itype: &str, doc_id: &u32) -> Option<&PosRange> {
         self.extent.get(itype).and_then(|r| r.get(doc_id))
     }
 
+    /// how the file evicted posting lists are written to is used
+    pub fn posting_disk_stats(&self) -> DiskStats {
+        self.posting_nodes.disk_stats()
+    }
+
+    /// how often each posting list was looked up by queries since the last call
+    pub fn take_posting_uses(&self) -> Vec<(String, u64)> {
+        self.posting_nodes.take_uses()
+    }
+
+    /// loads the most used posting lists of the given usage until the posting cache is full,
+    /// returns how many are in memory
+    pub fn warm_posting_cache(&self, usage: &TermUsage) -> usize {
+        self.posting_nodes.warm(usage.most_used())
+    }
+
+    /// removes the holes left in the posting list file by lists which were loaded back or replaced,
+    /// the server calls this after an incremental update once over half of the file is free,
+    /// while still holding the write lock, so queries wait until it is done
+    pub fn compact_postings(&mut self) -> Result<DiskStats, IndexError> {
+        self.posting_nodes.compact().map_err(|e| IndexError {
+            msg: e.to_string(),
+            kind: IndexErrorKind::Error,
+        })
+    }
+
     pub fn get_dump_id(&self) -> u32 {
         return self.dump_id;
     }
@@ -173,24 +319,127 @@ impl Index {
         self.last_updated_docs.get(&doc_id).cloned()
     }
 
+    pub fn get_metadata(&self, doc_id: u32) -> Option<&DocumentMetaData> {
+        self.metadata.get(&doc_id)
+    }
+
+    /// the indexed tokens of the document separated by single spaces, the n-th word holds the token
+    /// at position n and is left empty where there is none. Read back from disk unless used recently,
+    /// none for documents not in the index
+    pub fn get_text(&self, doc_id: u32) -> Option<String> {
+        self.doc_texts
+            .entry(&doc_id.to_string())
+            .map(|e| e.lock().get().unwrap().clone())
+    }
+
+    /// the categories the document is listed under
+    pub fn get_categories(&self, doc_id: u32) -> &[String] {
+        match self.metadata.get(&doc_id) {
+            Some(m) => &m.categories,
+            None => &[],
+        }
+    }
+
+    /// the types of the infoboxes within the document
+    #[cfg(test)]
+    pub(crate) fn get_infobox_types(&self, doc_id: u32) -> Vec<&str> {
+        self.extent
+            .iter()
+            .filter(|(k, v)| {
+                DocumentField::from(k.as_str()) == DocumentField::Infobox && v.contains_key(&doc_id)
+            })
+            .map(|(k, _)| k.as_str())
+            .collect()
+    }
+
+    /// looks up a document by its exact title
+    pub fn get_document_id(&self, title: &str) -> Option<u32> {
+        self.title_ids.get(title).cloned()
+    }
+
+    /// rebuilds the title lookup from the metadata,
+    /// should titles clash the lowest document id wins so that the outcome doesn't depend on iteration order
+    pub(crate) fn compute_title_ids(&mut self) {
+        let mut title_ids: HashMap<String, u32> = HashMap::with_capacity(self.metadata.len());
+        for (id, m) in &self.metadata {
+            title_ids
+                .entry(m.title.clone())
+                .and_modify(|v| *v = (*v).min(*id))
+                .or_insert(*id);
+        }
+        self.title_ids = title_ids;
+    }
+
+    /// the number of tokens indexed for the document
+    pub fn get_document_length(&self, doc_id: u32) -> u32 {
+        self.doc_lengths.get(&doc_id).cloned().unwrap_or(0)
+    }
+
+    pub fn avg_document_length(&self) -> f64 {
+        if self.doc_lengths.is_empty() {
+            return 0.0;
+        }
+        self.field_lengths.values().sum::<u64>() as f64 / self.doc_lengths.len() as f64
+    }
+
+    /// the average length of the field over all documents, including those without it
+    pub fn avg_field_length(&self, field: DocumentField) -> f64 {
+        if self.doc_lengths.is_empty() {
+            return 0.0;
+        }
+        self.field_lengths.get(&field).cloned().unwrap_or(0) as f64 / self.doc_lengths.len() as f64
+    }
+
+    /// every structure element range within the document, positions outside of them are body text
+    pub fn get_field_ranges(&self, doc_id: u32) -> Vec<(DocumentField, &PosRange)> {
+        self.extent
+            .iter()
+            .filter_map(|(k, v)| v.get(&doc_id).map(|r| (DocumentField::from(k.as_str()), r)))
+            .collect()
+    }
+
+    /// recalculates the field length totals, needs to happen whenever documents or their extents change
+    pub(crate) fn compute_field_lengths(&mut self) {
+        let mut field_lengths: HashMap<DocumentField, u64> = HashMap::default();
+        let total = self.doc_lengths.values().map(|l| *l as u64).sum::<u64>();
+
+        for (k, ranges) in &self.extent {
+            *field_lengths.entry(DocumentField::from(k.as_str())).or_default() += ranges
+                .values()
+                .map(|r| (r.end_pos - r.start_pos) as u64)
+                .sum::<u64>();
+        }
+
+        let structured = field_lengths.values().sum::<u64>();
+        field_lengths.insert(DocumentField::Body, total.saturating_sub(structured));
+
+        self.field_lengths = field_lengths;
+    }
+
     pub fn with_capacity(
-        posting_list_mem_limit: u32,
-        posting_list_persistent_mem_limit: u32,
+        posting_list_mem_limit: u64,
+        posting_list_persistent_mem_limit: u64,
         articles: u32,
     ) -> Self {
         Self {
             dump_id: 0,
+            generation: next_generation(),
             posting_nodes: DiskHashMap::new(
                 posting_list_mem_limit,
                 posting_list_persistent_mem_limit,
                 true,
             ),
-
+            codec: Default::default(),
             links: HashMap::with_capacity(articles as usize),
             incoming_links: HashMap::with_capacity(articles as usize),
             extent: HashMap::with_capacity(256),
             last_updated_docs: HashMap::with_capacity(articles as usize),
             page_rank: HashMap::with_capacity(articles as usize),
+            doc_lengths: HashMap::with_capacity(articles as usize),
+            doc_texts: doc_text_store(true),
+            metadata: HashMap::with_capacity(articles as usize),
+            title_ids: HashMap::with_capacity(articles as usize),
+            field_lengths: HashMap::default(),
             enabled: true,
         }
     }
@@ -202,18 +451,39 @@ impl Index {
         // sort links before moving
         p.links.values_mut().for_each(|v| v.sort());
 
+        // block codecs hold back the last few postings of each list until finished
+        if p.codec.is_blocked() {
+            info!("Finishing posting lists ({})", p.codec.name());
+            for (_, i) in p.posting_nodes.keys() {
+                let ptr = p.posting_nodes.entry_by_index(i).unwrap();
+                ptr.lock().get_mut().unwrap().finish();
+            }
+            info!("Took {}s", timer.elapsed().as_secs());
+        }
+
         let mut index = Self {
             dump_id: p.dump_id,
+            generation: next_generation(),
             posting_nodes: p.posting_nodes,
+            codec: p.codec,
             incoming_links: HashMap::with_capacity(p.links.len()),
             page_rank: HashMap::with_capacity(p.links.len()),
             links: p.links,
             extent: p.extent,
             last_updated_docs: p.last_updated_docs,
+            doc_lengths: p.doc_lengths,
+            doc_texts: p.doc_texts,
+            metadata: p.metadata,
+            title_ids: HashMap::default(),
+            field_lengths: HashMap::default(),
             enabled: true,
         };
 
+        index.compute_field_lengths();
+        index.compute_title_ids();
+
         index.posting_nodes.set_runtime_mode();
+        index.doc_texts.set_runtime_mode();
 
         // back links
         info!("Generating back links");
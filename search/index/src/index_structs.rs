@@ -2,7 +2,7 @@ use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use indexmap::IndexMap;
 
 use crate::{
-    DeltaEncoder, EncodedPostingList, EncodedSequentialObject, SequentialEncoder, VbyteEncoder,
+    EncodedPositionList, EncodedPostingList, SequentialEncoder, SkipEncoder, SkipIterator,
 };
 use fxhash::FxBuildHasher;
 use utils::MemFootprintCalculator;
@@ -35,15 +35,133 @@ pub struct PostingNode {
     pub postings_count: u32,
 }
 
+/// how often a token appears in a document
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct DocumentFrequency {
+    pub document_id: u32,
+    pub tf: u32,
+}
+
+impl From<Posting> for DocumentFrequency {
+    fn from(p: Posting) -> Self {
+        Self {
+            document_id: p.document_id,
+            tf: p.position,
+        }
+    }
+}
+
+/// the postings of a token kept as two parallel streams, so documents can be read without their positions
 #[derive(Debug, Eq, PartialEq, Default, Clone)]
 pub struct EncodedPostingNode<E>
 where
     E: SequentialEncoder<Posting>,
 {
-    pub postings: EncodedPostingList<E>,
+    /// one posting per document holding the token, with the term frequency in place of the position
+    pub documents: EncodedPostingList<E>,
+    /// the positions within each of the documents, in the same order
+    pub positions: EncodedPositionList,
     pub df: u32,
-    pub tf: IndexMap<u32, u32, FxBuildHasher>,
     pub postings_count: u32,
+    /// the highest term frequency within a single document, bounds the score of the term.
+    /// Stored in place of the score bounds themselves, which depend on the idf and so on the number of
+    /// documents, every update would otherwise have to rewrite the node of every term
+    pub max_tf: u32,
+}
+
+/// builds a node from postings sorted by document and position
+impl<E> FromIterator<Posting> for EncodedPostingNode<E>
+where
+    E: SequentialEncoder<Posting>,
+    Self: Default,
+{
+    fn from_iter<I: IntoIterator<Item = Posting>>(iter: I) -> Self {
+        Self::from_postings(E::default(), iter)
+    }
+}
+
+impl<E> EncodedPostingNode<E>
+where
+    E: SequentialEncoder<Posting>,
+    Self: Default,
+{
+    /// builds a node from postings sorted by document and position, encoded with the given encoder
+    pub fn from_postings<I: IntoIterator<Item = Posting>>(encoder: E, iter: I) -> Self {
+        let mut node = Self {
+            documents: EncodedPostingList::with_encoder(encoder),
+            ..Default::default()
+        };
+        node.extend(iter);
+        node.finish();
+
+        node
+    }
+
+    /// appends postings sorted by document and position, the documents must come after any already present
+    pub fn extend<I: IntoIterator<Item = Posting>>(&mut self, iter: I) {
+        let mut doc_id = None;
+        let mut positions = Vec::default();
+        for p in iter {
+            if doc_id != Some(p.document_id) {
+                if let Some(d) = doc_id {
+                    self.push_document(d, &positions);
+                }
+                doc_id = Some(p.document_id);
+                positions.clear();
+            }
+            positions.push(p.position);
+        }
+        if let Some(d) = doc_id {
+            self.push_document(d, &positions);
+        }
+    }
+
+    /// appends a document after any already present along with the sorted positions of the token within it
+    pub fn push_document(&mut self, doc_id: u32, positions: &[u32]) {
+        let tf = positions.len() as u32;
+        self.documents.push(Posting {
+            document_id: doc_id,
+            position: tf,
+        });
+        self.positions.push_document(doc_id, positions);
+        self.df += 1;
+        self.postings_count += tf;
+        self.max_tf = self.max_tf.max(tf);
+    }
+
+    /// writes out the documents held back by the encoder, needed before reading
+    pub fn finish(&mut self) {
+        self.documents.finish();
+    }
+
+    /// every posting, decoding the whole of both streams
+    pub fn postings(&self) -> Vec<Posting> {
+        let mut postings = Vec::with_capacity(self.postings_count as usize);
+        let mut positions = Vec::default();
+        let mut offset = 0;
+        for d in self.documents.into_iter() {
+            positions.clear();
+            offset = self.positions.decode(offset, d.position, &mut positions);
+            postings.extend(positions.iter().map(|p| Posting {
+                document_id: d.document_id,
+                position: *p,
+            }));
+        }
+        postings
+    }
+}
+
+impl<E> EncodedPostingNode<E>
+where
+    E: SkipEncoder,
+{
+    /// the number of times the token appears in the document
+    pub fn tf(&self, doc_id: u32) -> u32 {
+        match self.documents.into_iter().skip_to(doc_id) {
+            Some(p) if p.document_id == doc_id => p.position,
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Default, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -52,6 +170,39 @@ pub struct PosRange {
     pub end_pos: u32,
 }
 
+/// the parts of a document which can be weighted separately when ranking,
+/// anything outside of the recorded structure elements is body text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DocumentField {
+    Body,
+    Title,
+    Category,
+    Citation,
+    Infobox,
+}
+
+impl DocumentField {
+    pub const ALL: [DocumentField; 5] = [
+        DocumentField::Body,
+        DocumentField::Title,
+        DocumentField::Category,
+        DocumentField::Citation,
+        DocumentField::Infobox,
+    ];
+}
+
+/// classifies an extent key, every key other than the named structure elements is an infobox type
+impl From<&str> for DocumentField {
+    fn from(key: &str) -> Self {
+        match key {
+            "Title" => DocumentField::Title,
+            "Category" => DocumentField::Category,
+            "Citation" => DocumentField::Citation,
+            _ => DocumentField::Infobox,
+        }
+    }
+}
+
 pub struct Infobox {
     pub itype: String,
     pub text: String,
@@ -65,6 +216,9 @@ pub struct Citation {
 pub struct Document {
     pub doc_id: u32,
     pub title: String,
+    pub article_abstract: String,
+    pub namespace: i16,
+    /// category names separated by tabs
     pub categories: String,
     pub last_updated_date: String,
     pub main_text: String,
@@ -73,11 +227,14 @@ pub struct Document {
     pub citations: Vec<Citation>,
 }
 
-#[derive(Debug)]
+/// what is displayed for a document in search results,
+/// its last updated date is kept alongside the other per document data used for sorting
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct DocumentMetaData {
-    pub title: String, //TODO: Implement another field with doc_id -> title and title -> doc_id
-    pub last_updated_date: Option<LastUpdatedDate>, //TODO: Change to DateTime type using chrono
+    pub title: String,
+    pub article_abstract: String,
     pub namespace: i16, //TODO: Could change this field to enum
+    pub categories: Vec<String>,
 }
 #[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
 pub struct LastUpdatedDate {
@@ -90,12 +247,12 @@ impl MemFootprintCalculator for LastUpdatedDate {
     }
 }
 
-// impl Default for LastUpdatedDate {
-//     fn default() -> Self {
-//         let d = NaiveDate::from_ymd(0, 1, 1);
-//         let t = NaiveTime::from_hms(0, 0, 0);
-//         LastUpdatedDate {
-//             date_time: NaiveDateTime::new(d, t),
-//         }
-//     }
-// }
+impl Default for LastUpdatedDate {
+    fn default() -> Self {
+        let d = NaiveDate::from_ymd(0, 1, 1);
+        let t = NaiveTime::from_hms(0, 0, 0);
+        LastUpdatedDate {
+            date_time: NaiveDateTime::new(d, t),
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{get_document_with_date_time, PreIndex, DATE_TIME_FORMAT};
+use crate::{
+    get_document_with_date_time, get_document_with_text_and_links, Document, DocumentField,
+    IndexUpdate, PreIndex,
+    DATE_TIME_FORMAT,
+};
 
 use crate::utils::{get_document_with_links, get_document_with_text};
 use crate::{
@@ -156,7 +160,7 @@ fn test_basic_index_get_postings() {
             .lock()
             .get()
             .unwrap()
-            .postings
+            .postings()
             .into_iter()
             .collect::<Vec<Posting>>(),
         vec![Posting {
@@ -171,7 +175,7 @@ fn test_basic_index_get_postings() {
             .lock()
             .get()
             .unwrap()
-            .postings
+            .postings()
             .into_iter()
             .collect::<Vec<Posting>>(),
         vec![Posting {
@@ -186,7 +190,7 @@ fn test_basic_index_get_postings() {
             .lock()
             .get()
             .unwrap()
-            .postings
+            .postings()
             .into_iter()
             .collect::<Vec<Posting>>(),
         vec![Posting {
@@ -200,7 +204,7 @@ fn test_basic_index_get_postings() {
             .lock()
             .get()
             .unwrap()
-            .postings
+            .postings()
             .into_iter()
             .collect::<Vec<Posting>>()),
         None
@@ -242,7 +246,7 @@ fn test_sorted_postings() {
             .lock()
             .get()
             .unwrap()
-            .postings
+            .postings()
             .into_iter()
             .collect::<Vec<Posting>>(),
         vec![
@@ -327,6 +331,40 @@ fn test_basic_index_df() {
     assert_eq!(idx.df("kirby"), 0);
 }
 
+#[test]
+fn test_basic_index_max_tf() {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(
+            0,
+            "d0",
+            vec![("infobox", "hello world"), ("infobox2", "hello")],
+            "eggs world",
+            vec!["this that", "that", "eggs"],
+            "hello world",
+        ))
+        .unwrap();
+
+    pre_idx
+        .add_document(get_document_with_text(
+            1,
+            "d1",
+            vec![("infobox", "eggs")],
+            "eggs eggs eggs world",
+            vec![],
+            "eggs",
+        ))
+        .unwrap();
+
+    let idx = Index::from_pre_index(pre_idx);
+
+    assert_eq!(idx.max_tf("hello"), 3);
+    assert_eq!(idx.max_tf("eggs"), 5);
+    assert_eq!(idx.max_tf("that"), 2);
+    assert_eq!(idx.max_tf("kirby"), 0);
+}
+
 #[test]
 fn test_basic_index_links() {
     let mut pre_idx = PreIndex::default();
@@ -368,3 +406,495 @@ fn test_basic_index_links() {
 //make_sure_tokens_are_sorted(){
 //     todo!()
 // }
+
+fn original_documents() -> Vec<Box<Document>> {
+    vec![
+        get_document_with_text_and_links(
+            0,
+            "d0",
+            vec![("infobox", "aaa bbb")],
+            "ccc ddd",
+            vec!["eee"],
+            "fff",
+            "1\t2",
+        ),
+        get_document_with_text_and_links(1, "d1", vec![], "aaa ccc ccc", vec!["ggg"], "", "0\t2"),
+        get_document_with_text_and_links(
+            2,
+            "d2",
+            vec![("infobox", "hhh")],
+            "aaa",
+            vec![],
+            "fff",
+            "0",
+        ),
+        get_document_with_text_and_links(4, "d4", vec![], "ddd iii", vec![], "", "1"),
+    ]
+}
+
+fn updated_documents() -> Vec<Box<Document>> {
+    vec![
+        get_document_with_text_and_links(
+            1,
+            "d1",
+            vec![("infobox", "jjj")],
+            "ccc aaa",
+            vec![],
+            "fff",
+            "4",
+        ),
+        get_document_with_text_and_links(3, "d3", vec![], "aaa kkk", vec!["eee"], "", "0\t1"),
+    ]
+}
+
+/// builds an index out of the documents in order of their ids
+fn build(mut documents: Vec<Box<Document>>) -> Index {
+    documents.sort_by_key(|d| d.doc_id);
+    let mut pre_idx = PreIndex::default();
+    for d in documents {
+        pre_idx.add_document(d).unwrap();
+    }
+    Index::from_pre_index(pre_idx)
+}
+
+fn postings_of(idx: &Index, token: &str) -> Vec<Posting> {
+    match idx.get_postings(token) {
+        Some(v) => v.lock().get().unwrap().postings().into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// the text of every document the index holds
+fn texts_of(idx: &Index) -> HashMap<u32, Option<String>> {
+    idx.doc_lengths
+        .keys()
+        .map(|d| (*d, idx.get_text(*d)))
+        .collect()
+}
+
+#[test]
+fn test_update_matches_rebuild() {
+    let tokens = vec![
+        "aaa", "bbb", "ccc", "ddd", "eee", "fff", "ggg", "hhh", "iii", "jjj", "kkk",
+    ];
+
+    let mut expected_documents = original_documents();
+    expected_documents.retain(|d| d.doc_id != 1 && d.doc_id != 2);
+    expected_documents.extend(updated_documents());
+    let rebuilt = build(expected_documents);
+
+    let postings = tokens
+        .iter()
+        .map(|t| postings_of(&rebuilt, t))
+        .collect::<Vec<_>>();
+    let dfs = tokens.iter().map(|t| rebuilt.df(t)).collect::<Vec<_>>();
+    let tfs = tokens.iter().map(|t| rebuilt.tf(t, 1)).collect::<Vec<_>>();
+    let max_tfs = tokens.iter().map(|t| rebuilt.max_tf(t)).collect::<Vec<_>>();
+    let links = rebuilt.links.clone();
+    let incoming_links = rebuilt.incoming_links.clone();
+    let last_updated_docs = rebuilt.last_updated_docs.clone();
+    let doc_lengths = rebuilt.doc_lengths.clone();
+    let doc_texts = texts_of(&rebuilt);
+    let metadata = rebuilt.metadata.clone();
+    let field_lengths = DocumentField::ALL
+        .iter()
+        .map(|f| rebuilt.avg_field_length(*f))
+        .collect::<Vec<_>>();
+    let page_rank = rebuilt.page_rank.clone();
+    let extent = rebuilt
+        .extent
+        .iter()
+        .map(|(k, v)| {
+            let mut v = v
+                .iter()
+                .map(|(d, r)| (*d, r.start_pos, r.end_pos))
+                .collect::<Vec<_>>();
+            v.sort();
+            (k.clone(), v)
+        })
+        .collect::<HashMap<_, _>>();
+    let text_count = rebuilt.doc_texts.len();
+    drop(rebuilt);
+
+    let mut idx = build(original_documents());
+    idx.apply_update(IndexUpdate {
+        dump_id: 2,
+        documents: updated_documents(),
+        deleted: vec![2],
+    })
+    .unwrap();
+
+    assert_eq!(idx.get_dump_id(), 2);
+    assert_eq!(idx.get_number_of_documents(), 4);
+    for (i, t) in tokens.iter().enumerate() {
+        assert_eq!(postings_of(&idx, t), postings[i], "postings of {}", t);
+        assert_eq!(idx.df(t), dfs[i], "df of {}", t);
+        assert_eq!(idx.tf(t, 1), tfs[i], "tf of {}", t);
+        assert_eq!(idx.max_tf(t), max_tfs[i], "max tf of {}", t);
+    }
+    assert_eq!(idx.links, links);
+    assert_eq!(idx.incoming_links, incoming_links);
+    assert_eq!(idx.last_updated_docs, last_updated_docs);
+    assert_eq!(idx.doc_lengths, doc_lengths);
+    assert_eq!(texts_of(&idx), doc_texts);
+    assert!(idx.get_text(2).is_none());
+    // texts of deleted documents are dropped from the store
+    assert_eq!(idx.doc_texts.len(), text_count);
+    assert_eq!(idx.metadata, metadata);
+    assert_eq!(idx.get_document_id("d3"), Some(3));
+    assert_eq!(idx.get_document_id("d2"), None);
+    assert_eq!(
+        DocumentField::ALL
+            .iter()
+            .map(|f| idx.avg_field_length(*f))
+            .collect::<Vec<_>>(),
+        field_lengths
+    );
+    assert_eq!(idx.page_rank.len(), page_rank.len());
+    for (d, pr) in page_rank {
+        assert!((idx.page_rank[&d] - pr).abs() < 1e-9, "page rank of {}", d);
+    }
+    for (k, v) in extent {
+        let mut updated = idx.extent[&k]
+            .iter()
+            .map(|(d, r)| (*d, r.start_pos, r.end_pos))
+            .collect::<Vec<_>>();
+        updated.sort();
+        assert_eq!(updated, v, "extent of {}", k);
+    }
+}
+
+#[test]
+fn test_update_deleted_token_empties_posting_list() {
+    let mut idx = build(original_documents());
+
+    idx.apply_update(IndexUpdate {
+        dump_id: 1,
+        documents: vec![],
+        deleted: vec![2],
+    })
+    .unwrap();
+
+    assert_eq!(postings_of(&idx, "hhh"), vec![]);
+    assert_eq!(idx.df("hhh"), 0);
+    assert_eq!(idx.tf("hhh", 2), 0);
+    assert_eq!(idx.get_last_updated_date(2), None);
+    assert_eq!(idx.get_links(2), &[] as &[u32]);
+    assert_eq!(idx.get_incoming_links(0), &[1]);
+}
+
+#[test]
+fn test_prepared_update_leaves_index_untouched() {
+    let mut idx = build(original_documents());
+    let expected = postings_of(&idx, "aaa");
+
+    let prepared = idx
+        .prepare_update(IndexUpdate {
+            dump_id: 2,
+            documents: updated_documents(),
+            deleted: vec![2],
+        })
+        .unwrap();
+
+    // queries go on against the old version until it is committed
+    assert_eq!(postings_of(&idx, "aaa"), expected);
+    assert_eq!(postings_of(&idx, "kkk"), vec![]);
+    assert!(idx.get_last_updated_date(2).is_some());
+    assert_eq!(idx.get_dump_id(), 0);
+
+    idx.commit_update(prepared).unwrap();
+    assert_eq!(idx.get_dump_id(), 2);
+    assert_eq!(idx.get_last_updated_date(2), None);
+    assert_eq!(idx.df("kkk"), 1);
+
+    // an update only fits the index it was prepared against
+    let prepared = build(original_documents())
+        .prepare_update(IndexUpdate {
+            dump_id: 3,
+            documents: vec![],
+            deleted: vec![4],
+        })
+        .unwrap();
+    let mut other = build(vec![]);
+    assert!(other.commit_update(prepared).is_err());
+
+    // even if it looks just like it
+    let prepared = build(original_documents())
+        .prepare_update(IndexUpdate {
+            dump_id: 3,
+            documents: vec![],
+            deleted: vec![4],
+        })
+        .unwrap();
+    let mut other = build(original_documents());
+    assert!(other.commit_update(prepared).is_err());
+
+    // or was since updated
+    let stale = idx
+        .prepare_update(IndexUpdate {
+            dump_id: 3,
+            documents: vec![],
+            deleted: vec![4],
+        })
+        .unwrap();
+    idx.apply_update(IndexUpdate {
+        dump_id: 3,
+        documents: vec![],
+        deleted: vec![0],
+    })
+    .unwrap();
+    assert!(idx.commit_update(stale).is_err());
+    assert!(idx.get_text(4).is_some());
+}
+
+#[test]
+fn test_prepare_update_reads_affected_lists_only() {
+    let idx = build(original_documents());
+    idx.posting_nodes.clean_cache();
+
+    // document 4 holds d4, ddd and iii, document 3 adds to aaa and eee along with two new lists
+    let before = idx.posting_disk_stats().misses;
+    idx.prepare_update(IndexUpdate {
+        dump_id: 1,
+        documents: vec![updated_documents().remove(1)],
+        deleted: vec![4],
+    })
+    .unwrap();
+    assert_eq!(idx.posting_disk_stats().misses - before, 5);
+}
+
+#[test]
+fn test_update_duplicate_document() {
+    let mut idx = build(original_documents());
+
+    let mut documents = updated_documents();
+    documents.extend(updated_documents());
+
+    assert!(idx
+        .apply_update(IndexUpdate {
+            dump_id: 1,
+            documents,
+            deleted: vec![],
+        })
+        .is_err());
+}
+
+#[test]
+fn test_document_and_field_lengths() {
+    let idx = build(original_documents());
+
+    // infobox, main text, citation, category, title
+    assert_eq!(idx.get_document_length(0), 2 + 2 + 1 + 1 + 1);
+    assert_eq!(idx.get_document_length(4), 2 + 1);
+    assert_eq!(idx.get_document_length(9), 0);
+
+    assert_eq!(idx.avg_document_length(), (7 + 5 + 4 + 3) as f64 / 4.0);
+    assert_eq!(idx.avg_field_length(DocumentField::Infobox), 3.0 / 4.0);
+    assert_eq!(idx.avg_field_length(DocumentField::Citation), 2.0 / 4.0);
+    assert_eq!(idx.avg_field_length(DocumentField::Category), 2.0 / 4.0);
+    assert_eq!(idx.avg_field_length(DocumentField::Body), 8.0 / 4.0);
+    assert_eq!(idx.avg_field_length(DocumentField::Title), 1.0);
+
+    let mut ranges = idx
+        .get_field_ranges(0)
+        .into_iter()
+        .map(|(f, r)| (f, r.start_pos, r.end_pos))
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|(_, s, _)| *s);
+    assert_eq!(
+        ranges,
+        vec![
+            (DocumentField::Infobox, 0, 2),
+            (DocumentField::Citation, 4, 5),
+            (DocumentField::Category, 5, 6),
+            (DocumentField::Title, 6, 7)
+        ]
+    );
+}
+
+
+#[test]
+fn test_document_metadata() {
+    let mut article = get_document_with_text(5, "Alan Turing", vec![], "aaa", vec![], "");
+    article.article_abstract = "English mathematician".to_string();
+    article.namespace = 0;
+    let mut talk = get_document_with_text(
+        7,
+        "Talk:Alan Turing",
+        vec![("infobox person", "ccc")],
+        "bbb",
+        vec!["ddd"],
+        "Talk pages\t Computer scientists",
+    );
+    talk.namespace = 1;
+
+    let idx = build(vec![article, talk]);
+
+    let metadata = idx.get_metadata(5).unwrap();
+    assert_eq!(metadata.title, "Alan Turing");
+    assert_eq!(metadata.article_abstract, "English mathematician");
+    assert_eq!(idx.get_metadata(7).unwrap().namespace, 1);
+    assert!(idx.get_metadata(6).is_none());
+
+    assert!(idx.get_categories(5).is_empty());
+    assert_eq!(idx.get_categories(7), &["Talk pages", "Computer scientists"]);
+    assert!(idx.get_categories(6).is_empty());
+    assert_eq!(idx.get_infobox_types(7), vec!["infobox person"]);
+
+    assert_eq!(idx.get_document_id("Alan Turing"), Some(5));
+    assert_eq!(idx.get_document_id("Talk:Alan Turing"), Some(7));
+    assert_eq!(idx.get_document_id("alan turing"), None);
+}
+
+#[test]
+fn test_document_text() {
+    let idx = build(vec![get_document_with_text(
+        0,
+        "The Lord of the Rings",
+        vec![("infobox", "aaa bbb")],
+        "ccc  ddd",
+        vec!["eee"],
+        "fff",
+    )]);
+
+    // gaps in the title and body are kept so that every word sits at its position
+    let text = idx.get_text(0).unwrap();
+    assert_eq!(text, "aaa bbb ccc  ddd eee fff  lord   ring");
+    assert_eq!(text.split(' ').nth(4), Some("ddd"));
+    assert_eq!(text.split(' ').nth(8), Some("lord"));
+    assert_eq!(text.split(' ').nth(11), Some("ring"));
+    assert!(idx.get_text(1).is_none());
+
+    // texts are read back from disk once out of memory
+    idx.doc_texts.clean_cache();
+    assert_eq!(idx.doc_texts.cache_population(), 0);
+    assert_eq!(idx.get_text(0), Some(text));
+}
+
+fn parallel_documents() -> Vec<Box<Document>> {
+    let words = ["aaa", "bbb", "ccc", "ddd", "eee", "fff", "ggg"];
+    (0..60u32)
+        .map(|d| {
+            let text = (0..d % 9 + 1)
+                .map(|i| words[((d + i * i) % 7) as usize])
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let links = format!("{}\t{}", (d + 1) % 60, (d * 7) % 60);
+            get_document_with_text_and_links(
+                d,
+                &format!("d{}", d),
+                match d % 4 {
+                    0 => vec![("infobox", "aaa hhh")],
+                    _ => vec![],
+                },
+                &text,
+                vec![words[(d % 7) as usize]],
+                match d % 3 {
+                    0 => "fff",
+                    _ => "",
+                },
+                &links,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_parallel_build_matches_sequential() {
+    let tokens = vec!["aaa", "bbb", "ccc", "ddd", "eee", "fff", "ggg", "hhh"];
+
+    let sequential = build(parallel_documents());
+    let postings = tokens
+        .iter()
+        .map(|t| postings_of(&sequential, t))
+        .collect::<Vec<_>>();
+    let dfs = tokens.iter().map(|t| sequential.df(t)).collect::<Vec<_>>();
+    let tfs = tokens
+        .iter()
+        .map(|t| (0..60).map(|d| sequential.tf(t, d)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let max_tfs = tokens.iter().map(|t| sequential.max_tf(t)).collect::<Vec<_>>();
+    let links = sequential.links.clone();
+    let incoming_links = sequential.incoming_links.clone();
+    let doc_lengths = sequential.doc_lengths.clone();
+    let doc_texts = texts_of(&sequential);
+    let metadata = sequential.metadata.clone();
+    let extent = sequential
+        .extent
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .iter()
+                .map(|(d, r)| (*d, (r.start_pos, r.end_pos)))
+                .collect::<HashMap<_, _>>();
+            (k.clone(), v)
+        })
+        .collect::<HashMap<_, _>>();
+    drop(sequential);
+
+    // out of order, with a duplicate and a document which is already present
+    let mut pre_idx = PreIndex::default();
+    pre_idx.add_document(parallel_documents().remove(0)).unwrap();
+    let mut documents = parallel_documents();
+    documents.reverse();
+    documents.push(parallel_documents().remove(30));
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    let rejected = pool.install(|| pre_idx.add_documents(documents));
+    let mut rejected = rejected.iter().map(|(d, _)| d.doc_id).collect::<Vec<_>>();
+    rejected.sort();
+    assert_eq!(rejected, vec![0, 30]);
+
+    let idx = Index::from_pre_index(pre_idx);
+    assert_eq!(idx.get_number_of_documents(), 60);
+    for (i, t) in tokens.iter().enumerate() {
+        assert_eq!(postings_of(&idx, t), postings[i], "postings of {}", t);
+        assert_eq!(idx.df(t), dfs[i], "df of {}", t);
+        assert_eq!(
+            (0..60).map(|d| idx.tf(t, d)).collect::<Vec<_>>(),
+            tfs[i],
+            "tf of {}",
+            t
+        );
+        assert_eq!(idx.max_tf(t), max_tfs[i], "max tf of {}", t);
+    }
+    assert_eq!(idx.links, links);
+    assert_eq!(idx.incoming_links, incoming_links);
+    assert_eq!(idx.doc_lengths, doc_lengths);
+    assert_eq!(texts_of(&idx), doc_texts);
+    assert_eq!(idx.metadata, metadata);
+    for (k, v) in extent {
+        let parallel = idx.extent[&k]
+            .iter()
+            .map(|(d, r)| (*d, (r.start_pos, r.end_pos)))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(parallel, v, "extent of {}", k);
+    }
+}
+
+#[test]
+fn test_pre_index_rejects_documents_out_of_order() {
+    let mut pre_idx = PreIndex::default();
+    let mut documents = parallel_documents();
+    let later = documents.split_off(10);
+    pre_idx.add_documents(documents.split_off(5));
+
+    // posting lists can only be appended to, earlier documents are turned away
+    let err = pre_idx.add_document(documents.remove(0)).err().unwrap();
+    assert!(matches!(err.kind, crate::IndexErrorKind::InvalidOperation));
+
+    let rejected = pre_idx.add_documents(documents);
+    assert_eq!(
+        rejected.iter().map(|(d, _)| d.doc_id).collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+    assert!(pre_idx.add_documents(later).is_empty());
+    assert_eq!(pre_idx.last_doc_id, Some(59));
+
+    let idx = Index::from_pre_index(pre_idx);
+    assert_eq!(idx.get_number_of_documents(), 55);
+    assert_eq!(idx.df("bbb") as usize, idx.stream_documents("bbb").unwrap().count());
+}
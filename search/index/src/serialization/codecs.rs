@@ -0,0 +1,510 @@
+use crate::{
+    IndexError, IndexErrorKind, Posting, SequentialEncoder, Serializable, SkipEncoder, SkipPointer,
+    VbyteEncoder, SKIP_INTERVAL,
+};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+};
+
+type Vbyte = VbyteEncoder<Posting, true>;
+
+/// the number of bits needed to hold the value
+#[inline(always)]
+fn bit_width(v: u32) -> u8 {
+    (32 - v.leading_zeros()) as u8
+}
+
+/// writes each value in `width` bits, least significant bits first
+fn pack_bits<W: Write>(values: &[u32], width: u8, out: &mut W) -> usize {
+    let mut bytes = vec![0u8; (values.len() * width as usize + 7) / 8];
+    let mut bit = 0;
+    for v in values {
+        for i in 0..width as usize {
+            if (v >> i) & 1 == 1 {
+                bytes[(bit + i) / 8] |= 1 << ((bit + i) % 8);
+            }
+        }
+        bit += width as usize;
+    }
+    out.write_all(&bytes).unwrap();
+    bytes.len()
+}
+
+/// reads back `n` values written by [pack_bits]
+fn unpack_bits<R: Read>(bytes: &mut R, width: u8, n: usize, out: &mut Vec<u32>) -> usize {
+    let mut packed = vec![0u8; (n * width as usize + 7) / 8];
+    bytes.read_exact(&mut packed).unwrap();
+
+    let mut bit = 0;
+    for _ in 0..n {
+        let mut v = 0;
+        for i in 0..width as usize {
+            v |= (((packed[(bit + i) / 8] >> ((bit + i) % 8)) & 1) as u32) << i;
+        }
+        out.push(v);
+        bit += width as usize;
+    }
+    packed.len()
+}
+
+/// patched frame of reference: the values are packed with a width which fits most of them,
+/// the high bits of the few which do not fit are stored afterwards as exceptions
+fn write_pfor<W: Write>(values: &[u32], out: &mut W) -> usize {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let width = sorted
+        .get((values.len() * 9) / 10)
+        .or(sorted.last())
+        .map(|v| bit_width(*v))
+        .unwrap_or(0);
+
+    let exceptions = values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| bit_width(**v) > width)
+        .collect::<Vec<(usize, &u32)>>();
+
+    out.write_u8(width).unwrap();
+    let mut count = 1 + Vbyte::into_vbyte_serialise(exceptions.len() as u32, out);
+    count += pack_bits(values, width, out);
+    for (i, v) in exceptions {
+        count += Vbyte::into_vbyte_serialise(i as u32, out);
+        count += Vbyte::into_vbyte_serialise(v >> width, out);
+    }
+    count
+}
+
+/// reads back `n` values written by [write_pfor]
+fn read_pfor<R: Read>(bytes: &mut R, n: usize, out: &mut Vec<u32>) -> usize {
+    let width = bytes.read_u8().unwrap();
+    let (exceptions, mut count) = Vbyte::from_vbyte_deserialise(bytes);
+
+    let start = out.len();
+    count += 1 + unpack_bits(bytes, width, n, out);
+    for _ in 0..exceptions {
+        let (i, count_a) = Vbyte::from_vbyte_deserialise(bytes);
+        let (high, count_b) = Vbyte::from_vbyte_deserialise(bytes);
+        // exceptions only exist below a width of 32
+        out[start + i as usize] |= high << width;
+        count += count_a + count_b;
+    }
+    count
+}
+
+/// a way of laying out a block of postings
+pub trait BlockCodec {
+    /// writes the postings, document ids are relative to `base`, the document of the posting before the block
+    fn write_block<W: Write>(postings: &[Posting], base: u32, out: &mut W) -> usize;
+    fn read_block<R: Read>(bytes: &mut R, base: u32, out: &mut Vec<Posting>) -> usize;
+}
+
+/// postings are encoded a block of [SKIP_INTERVAL] at a time, each block can be decoded on its own
+/// given the last document before it, so every block start is a skip pointer.
+/// Postings are held back until their block fills up or the encoder is finished
+#[derive(Default, Eq, PartialEq, Debug, Clone)]
+pub struct BlockEncoder<C: BlockCodec> {
+    /// encoded but not yet written
+    pending: Vec<Posting>,
+    /// the document of the last posting written or decoded
+    last_doc: Option<u32>,
+    skips: Vec<SkipPointer>,
+    /// bytes encoded so far
+    written: u32,
+    /// the block being decoded and the next posting in it
+    decoded: Vec<Posting>,
+    next: usize,
+    /// the size of the block being decoded
+    block_bytes: usize,
+    _ph: PhantomData<C>,
+}
+
+impl<C: BlockCodec> BlockEncoder<C> {
+    fn flush<W: Write>(&mut self, out: &mut W) -> usize {
+        if self.pending.is_empty() {
+            return 0;
+        }
+
+        if let Some(doc_id) = self.last_doc {
+            self.skips.push(SkipPointer {
+                doc_id,
+                offset: self.written,
+            });
+        }
+
+        let count = C::write_block(&self.pending, self.last_doc.unwrap_or(0), out);
+        self.last_doc = self.pending.last().map(|p| p.document_id);
+        self.pending.clear();
+        self.written += count as u32;
+        count
+    }
+}
+
+/// the size of a block is only reported with its last posting, so that everything before the end of
+/// the object is decoded while stopping at the end
+impl<C: BlockCodec + Default> SequentialEncoder<Posting> for BlockEncoder<C> {
+    fn encode<W: Write>(&mut self, curr: &Posting, out: &mut W) -> usize {
+        self.pending.push(*curr);
+        if self.pending.len() >= SKIP_INTERVAL as usize {
+            self.flush(out)
+        } else {
+            0
+        }
+    }
+
+    fn decode<R: Read>(&mut self, bytes: &mut R) -> (Posting, usize) {
+        if self.next >= self.decoded.len() {
+            self.decoded.clear();
+            self.next = 0;
+            self.block_bytes = C::read_block(bytes, self.last_doc.unwrap_or(0), &mut self.decoded);
+            self.last_doc = self.decoded.last().map(|p| p.document_id);
+        }
+
+        let p = self.decoded[self.next];
+        self.next += 1;
+        match self.next == self.decoded.len() {
+            true => (p, self.block_bytes),
+            false => (p, 0),
+        }
+    }
+
+    fn finish<W: Write>(&mut self, out: &mut W) -> usize {
+        self.flush(out)
+    }
+}
+
+impl<C: BlockCodec + Default> SkipEncoder for BlockEncoder<C> {
+    fn skip_pointers(&self) -> &[SkipPointer] {
+        &self.skips
+    }
+
+    fn resume_from(&mut self, skip: &SkipPointer) {
+        self.decoded.clear();
+        self.next = 0;
+        self.last_doc = Some(skip.doc_id);
+    }
+}
+
+/// the decoding state is not kept
+impl<C: BlockCodec + Default> Serializable for BlockEncoder<C> {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        count += self.pending.serialize(buf);
+        count += self.last_doc.serialize(buf);
+        count += self.skips.serialize(buf);
+        count += self.written.serialize(buf);
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        let mut count = 0;
+        count += self.pending.deserialize(buf);
+        count += self.last_doc.deserialize(buf);
+        count += self.skips.deserialize(buf);
+        count += self.written.deserialize(buf);
+        count
+    }
+}
+
+/// positions relative to the previous one within the same document, the first of each document is kept as is
+fn position_gaps(postings: &[Posting]) -> Vec<u32> {
+    let mut prev: Option<&Posting> = None;
+    postings
+        .iter()
+        .map(|p| {
+            let gap = match prev {
+                Some(q) if q.document_id == p.document_id => p.position - q.position,
+                _ => p.position,
+            };
+            prev = Some(p);
+            gap
+        })
+        .collect()
+}
+
+/// the document ids and position gaps of a block are delta encoded and each packed with [write_pfor]
+#[derive(Default, Eq, PartialEq, Debug, Clone)]
+pub struct PForDelta;
+
+pub type PForDeltaEncoder = BlockEncoder<PForDelta>;
+
+impl BlockCodec for PForDelta {
+    fn write_block<W: Write>(postings: &[Posting], base: u32, out: &mut W) -> usize {
+        let mut prev = base;
+        let doc_gaps = postings
+            .iter()
+            .map(|p| {
+                let gap = p.document_id - prev;
+                prev = p.document_id;
+                gap
+            })
+            .collect::<Vec<u32>>();
+
+        let mut count = Vbyte::into_vbyte_serialise(postings.len() as u32, out);
+        count += write_pfor(&doc_gaps, out);
+        count += write_pfor(&position_gaps(postings), out);
+        count
+    }
+
+    fn read_block<R: Read>(bytes: &mut R, base: u32, out: &mut Vec<Posting>) -> usize {
+        let (n, mut count) = Vbyte::from_vbyte_deserialise(bytes);
+        let mut doc_gaps = Vec::with_capacity(n as usize);
+        let mut position_gaps = Vec::with_capacity(n as usize);
+        count += read_pfor(bytes, n as usize, &mut doc_gaps);
+        count += read_pfor(bytes, n as usize, &mut position_gaps);
+
+        let mut doc_id = base;
+        let mut position = 0;
+        for (i, (d, p)) in doc_gaps.into_iter().zip(position_gaps).enumerate() {
+            position = match i > 0 && d == 0 {
+                true => position + p,
+                false => p,
+            };
+            doc_id += d;
+            out.push(Posting {
+                document_id: doc_id,
+                position,
+            });
+        }
+        count
+    }
+}
+
+/// the distinct document ids of a block are stored as an Elias-Fano sequence followed by how many postings each has,
+/// their positions are packed separately with [write_pfor]
+#[derive(Default, Eq, PartialEq, Debug, Clone)]
+pub struct EliasFano;
+
+pub type EliasFanoEncoder = BlockEncoder<EliasFano>;
+
+impl BlockCodec for EliasFano {
+    fn write_block<W: Write>(postings: &[Posting], base: u32, out: &mut W) -> usize {
+        let mut docs: Vec<u32> = Vec::default();
+        let mut freqs: Vec<u32> = Vec::default();
+        for p in postings {
+            match docs.last() {
+                Some(d) if *d == p.document_id - base => *freqs.last_mut().unwrap() += 1,
+                _ => {
+                    docs.push(p.document_id - base);
+                    freqs.push(1);
+                }
+            }
+        }
+
+        let m = docs.len() as u32;
+        let universe = *docs.last().unwrap();
+        let low_width = match universe / m {
+            0 => 0,
+            r => bit_width(r) - 1,
+        };
+
+        let mut count = Vbyte::into_vbyte_serialise(postings.len() as u32, out);
+        count += Vbyte::into_vbyte_serialise(m, out);
+        count += Vbyte::into_vbyte_serialise(universe, out);
+        out.write_u8(low_width).unwrap();
+        count += 1;
+
+        // the low bits are packed as they are, the high bits as a unary coded bit vector
+        let low_mask = (1u64 << low_width) as u32 - 1;
+        let lows = docs.iter().map(|d| d & low_mask).collect::<Vec<u32>>();
+        count += pack_bits(&lows, low_width, out);
+
+        let mut highs = vec![0u8; (m as usize + (universe >> low_width) as usize + 1 + 7) / 8];
+        for (i, d) in docs.iter().enumerate() {
+            let bit = (d >> low_width) as usize + i;
+            highs[bit / 8] |= 1 << (bit % 8);
+        }
+        out.write_all(&highs).unwrap();
+        count += highs.len();
+
+        freqs.iter_mut().for_each(|f| *f -= 1);
+        count += write_pfor(&freqs, out);
+        count += write_pfor(&position_gaps(postings), out);
+        count
+    }
+
+    fn read_block<R: Read>(bytes: &mut R, base: u32, out: &mut Vec<Posting>) -> usize {
+        let (n, count_n) = Vbyte::from_vbyte_deserialise(bytes);
+        let (m, count_m) = Vbyte::from_vbyte_deserialise(bytes);
+        let (universe, count_u) = Vbyte::from_vbyte_deserialise(bytes);
+        let low_width = bytes.read_u8().unwrap();
+        let mut count = count_n + count_m + count_u + 1;
+
+        let mut lows = Vec::with_capacity(m as usize);
+        count += unpack_bits(bytes, low_width, m as usize, &mut lows);
+
+        let mut highs = vec![0u8; (m as usize + (universe >> low_width) as usize + 1 + 7) / 8];
+        bytes.read_exact(&mut highs).unwrap();
+        count += highs.len();
+
+        let mut docs = Vec::with_capacity(m as usize);
+        let mut bit = 0;
+        while docs.len() < m as usize {
+            if (highs[bit / 8] >> (bit % 8)) & 1 == 1 {
+                let high = (bit - docs.len()) as u32;
+                docs.push(base + ((high << low_width) | lows[docs.len()]));
+            }
+            bit += 1;
+        }
+
+        let mut freqs = Vec::with_capacity(m as usize);
+        let mut position_gaps = Vec::with_capacity(n as usize);
+        count += read_pfor(bytes, m as usize, &mut freqs);
+        count += read_pfor(bytes, n as usize, &mut position_gaps);
+
+        let mut gaps = position_gaps.into_iter();
+        for (doc_id, f) in docs.into_iter().zip(freqs) {
+            let mut position = 0;
+            for _ in 0..=f {
+                position += gaps.next().unwrap();
+                out.push(Posting {
+                    document_id: doc_id,
+                    position,
+                });
+            }
+        }
+        count
+    }
+}
+
+/// the codec posting lists of an index are encoded with, picked when the index is built
+/// and recorded alongside every posting list
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum PostingCodec {
+    Vbyte(Vbyte),
+    PForDelta(PForDeltaEncoder),
+    EliasFano(EliasFanoEncoder),
+}
+
+impl Default for PostingCodec {
+    fn default() -> Self {
+        PostingCodec::Vbyte(Vbyte::default())
+    }
+}
+
+impl PostingCodec {
+    pub const NAMES: [&'static str; 3] = ["vbyte", "pfordelta", "eliasfano"];
+
+    /// a fresh codec from one of [PostingCodec::NAMES]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vbyte" => Some(PostingCodec::Vbyte(Default::default())),
+            "pfordelta" => Some(PostingCodec::PForDelta(Default::default())),
+            "eliasfano" => Some(PostingCodec::EliasFano(Default::default())),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostingCodec::Vbyte(_) => "vbyte",
+            PostingCodec::PForDelta(_) => "pfordelta",
+            PostingCodec::EliasFano(_) => "eliasfano",
+        }
+    }
+
+    /// whether postings are held back until the encoder is finished
+    pub fn is_blocked(&self) -> bool {
+        !matches!(self, PostingCodec::Vbyte(_))
+    }
+
+    /// a fresh codec from the tag it is stored under
+    pub fn from_tag(tag: u8) -> Result<Self, IndexError> {
+        PostingCodec::NAMES
+            .get(tag as usize)
+            .and_then(|name| PostingCodec::from_name(name))
+            .ok_or_else(|| IndexError {
+                msg: format!("Unknown posting codec tag {}", tag),
+                kind: IndexErrorKind::InvalidIndexState,
+            })
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            PostingCodec::Vbyte(_) => 0,
+            PostingCodec::PForDelta(_) => 1,
+            PostingCodec::EliasFano(_) => 2,
+        }
+    }
+}
+
+impl SequentialEncoder<Posting> for PostingCodec {
+    #[inline(always)]
+    fn encode<W: Write>(&mut self, curr: &Posting, out: &mut W) -> usize {
+        match self {
+            PostingCodec::Vbyte(e) => e.encode(curr, out),
+            PostingCodec::PForDelta(e) => e.encode(curr, out),
+            PostingCodec::EliasFano(e) => e.encode(curr, out),
+        }
+    }
+
+    #[inline(always)]
+    fn decode<R: Read>(&mut self, bytes: &mut R) -> (Posting, usize) {
+        match self {
+            PostingCodec::Vbyte(e) => e.decode(bytes),
+            PostingCodec::PForDelta(e) => e.decode(bytes),
+            PostingCodec::EliasFano(e) => e.decode(bytes),
+        }
+    }
+
+    fn finish<W: Write>(&mut self, out: &mut W) -> usize {
+        match self {
+            PostingCodec::Vbyte(e) => e.finish(out),
+            PostingCodec::PForDelta(e) => e.finish(out),
+            PostingCodec::EliasFano(e) => e.finish(out),
+        }
+    }
+
+    fn fresh(&self) -> Self {
+        PostingCodec::from_name(self.name()).unwrap()
+    }
+}
+
+impl SkipEncoder for PostingCodec {
+    fn skip_pointers(&self) -> &[SkipPointer] {
+        match self {
+            PostingCodec::Vbyte(e) => e.skip_pointers(),
+            PostingCodec::PForDelta(e) => e.skip_pointers(),
+            PostingCodec::EliasFano(e) => e.skip_pointers(),
+        }
+    }
+
+    fn resume_from(&mut self, skip: &SkipPointer) {
+        match self {
+            PostingCodec::Vbyte(e) => e.resume_from(skip),
+            PostingCodec::PForDelta(e) => e.resume_from(skip),
+            PostingCodec::EliasFano(e) => e.resume_from(skip),
+        }
+    }
+}
+
+/// stored as a tag naming the codec followed by its state
+impl Serializable for PostingCodec {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let count = self.tag().serialize(buf);
+        count
+            + match self {
+                PostingCodec::Vbyte(e) => e.serialize(buf),
+                PostingCodec::PForDelta(e) => e.serialize(buf),
+                PostingCodec::EliasFano(e) => e.serialize(buf),
+            }
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        self.try_deserialize(buf).unwrap()
+    }
+
+    fn try_deserialize<R: Read>(&mut self, buf: &mut R) -> Result<usize, IndexError> {
+        let mut tag = 0u8;
+        let count = tag.deserialize(buf);
+        *self = PostingCodec::from_tag(tag)?;
+        Ok(count
+            + match self {
+                PostingCodec::Vbyte(e) => e.deserialize(buf),
+                PostingCodec::PForDelta(e) => e.deserialize(buf),
+                PostingCodec::EliasFano(e) => e.deserialize(buf),
+            })
+    }
+}
@@ -0,0 +1,269 @@
+use std::fs;
+use std::io::Cursor;
+
+use uuid::Uuid;
+
+use super::serialization_tests::{expected_skips, skip_test_postings, skipped};
+use crate::{
+    get_document_with_text, EliasFanoEncoder, EncodedPostingList, Index, IndexErrorKind,
+    PForDeltaEncoder, Posting,
+    PostingCodec, PreIndex, SequentialEncoder, Serializable, SkipEncoder, SkipIterator,
+    SKIP_INTERVAL,
+};
+
+fn all_codecs() -> Vec<PostingCodec> {
+    PostingCodec::NAMES
+        .iter()
+        .map(|n| PostingCodec::from_name(n).unwrap())
+        .collect()
+}
+
+fn encoded(codec: &PostingCodec, postings: &[Posting]) -> EncodedPostingList<PostingCodec> {
+    let mut o = EncodedPostingList::with_encoder(codec.fresh());
+    postings.iter().for_each(|p| o.push(*p));
+    o.finish();
+    o
+}
+
+/// large gaps between documents and positions, so that some values do not fit the packed width
+fn sparse_postings() -> Vec<Posting> {
+    (0..300u32)
+        .flat_map(|d| {
+            let doc = d * 13 + (d / 50) * 10_000_000;
+            (0..(d % 3 + 1)).map(move |p| Posting {
+                document_id: doc,
+                position: p * (1 + (d % 9) * 40_000),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_codecs_round_trip() {
+    for postings in [skip_test_postings(), sparse_postings()] {
+        for codec in all_codecs() {
+            let o = encoded(&codec, &postings);
+            assert_eq!(
+                o.into_iter().collect::<Vec<Posting>>(),
+                postings,
+                "{}",
+                codec.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_block_codecs_round_trip_typed() {
+    let postings = skip_test_postings();
+
+    let pfor = EncodedPostingList::<PForDeltaEncoder>::from_iter(postings.clone());
+    let ef = EncodedPostingList::<EliasFanoEncoder>::from_iter(postings.clone());
+
+    assert_eq!(pfor.into_iter().collect::<Vec<Posting>>(), postings);
+    assert_eq!(ef.into_iter().collect::<Vec<Posting>>(), postings);
+}
+
+#[test]
+fn test_codecs_single_posting() {
+    let postings = vec![Posting {
+        document_id: 7,
+        position: 3,
+    }];
+    for codec in all_codecs() {
+        let o = encoded(&codec, &postings);
+        assert_eq!(o.into_iter().collect::<Vec<Posting>>(), postings);
+    }
+}
+
+#[test]
+fn test_block_codecs_hold_back_until_finished() {
+    let postings = skip_test_postings();
+
+    for name in ["pfordelta", "eliasfano"] {
+        let codec = PostingCodec::from_name(name).unwrap();
+        let mut o = EncodedPostingList::with_encoder(codec.fresh());
+        postings[..10].iter().for_each(|p| o.push(*p));
+        assert_eq!(o.byte_len(), 0);
+
+        // finishing part way through and carrying on is the same as encoding in one go
+        o.finish();
+        postings[10..].iter().for_each(|p| o.push(*p));
+        o.finish();
+        assert_eq!(o.into_iter().collect::<Vec<Posting>>(), postings);
+    }
+}
+
+#[test]
+fn test_block_codecs_skip_pointers_written() {
+    let postings = skip_test_postings();
+
+    for name in ["pfordelta", "eliasfano"] {
+        let o = encoded(&PostingCodec::from_name(name).unwrap(), &postings);
+        let skips = o.encoder().skip_pointers();
+
+        assert_eq!(skips.len(), (postings.len() - 1) / SKIP_INTERVAL as usize);
+        assert!(skips.windows(2).all(|w| w[0].doc_id <= w[1].doc_id));
+        assert!(skips.iter().all(|s| (s.offset as usize) < o.byte_len()));
+    }
+}
+
+#[test]
+fn test_codecs_skip_to() {
+    let targets = vec![
+        0, 1, 2, 5, 300, 301, 302, 302, 650, 900, 1198, 1199, 5000, 5001,
+    ];
+
+    for postings in [skip_test_postings(), sparse_postings()] {
+        for codec in all_codecs() {
+            let o = encoded(&codec, &postings);
+            assert_eq!(
+                skipped(o.into_iter(), &targets),
+                expected_skips(&postings, &targets),
+                "{}",
+                codec.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_codecs_skip_to_then_next() {
+    let postings = skip_test_postings();
+    let start = postings.iter().position(|p| p.document_id >= 700).unwrap();
+
+    for codec in all_codecs() {
+        let o = encoded(&codec, &postings);
+        let mut iter = o.into_iter();
+
+        assert_eq!(iter.skip_to(700).unwrap(), postings[start]);
+        assert_eq!(
+            iter.collect::<Vec<Posting>>(),
+            postings[start + 1..].to_vec()
+        );
+    }
+}
+
+#[test]
+fn test_codecs_serialized() {
+    let postings = skip_test_postings();
+
+    for codec in all_codecs() {
+        let mut o = encoded(&codec, &postings);
+        // postings held back are kept as well
+        o.push(Posting {
+            document_id: 5000,
+            position: 1,
+        });
+
+        let mut buffer = Vec::default();
+        o.serialize(&mut buffer);
+        let mut deserialized = EncodedPostingList::<PostingCodec>::default();
+        deserialized.deserialize(&mut Cursor::new(buffer));
+        deserialized.finish();
+        o.finish();
+
+        assert_eq!(deserialized.encoder().name(), codec.name());
+        assert_eq!(
+            deserialized.into_iter().collect::<Vec<Posting>>(),
+            o.into_iter().collect::<Vec<Posting>>()
+        );
+    }
+}
+
+#[test]
+fn test_codecs_unknown_tag() {
+    for (tag, codec) in all_codecs().iter().enumerate() {
+        assert_eq!(PostingCodec::from_tag(tag as u8).unwrap().name(), codec.name());
+    }
+
+    let err = PostingCodec::from_tag(PostingCodec::NAMES.len() as u8).unwrap_err();
+    assert!(matches!(err.kind, IndexErrorKind::InvalidIndexState));
+
+    // lists read back with a codec they don't know fail instead of panicking
+    let mut buffer = Vec::default();
+    encoded(&PostingCodec::default(), &skip_test_postings()).serialize(&mut buffer);
+    // the codec comes right after the length prefixed bytes
+    let mut len = 0u32;
+    len.deserialize(&mut buffer.as_slice());
+    buffer[4 + len as usize] = 200;
+
+    let mut deserialized = EncodedPostingList::<PostingCodec>::default();
+    let err = deserialized
+        .try_deserialize(&mut Cursor::new(buffer))
+        .unwrap_err();
+    assert!(matches!(err.kind, IndexErrorKind::InvalidIndexState));
+}
+
+fn codec_index(codec: PostingCodec) -> Index {
+    let mut pre_idx = PreIndex::default();
+    pre_idx.codec = codec;
+
+    for d in 0..400u32 {
+        let text = vec!["codectoken"; (d % 3 + 1) as usize].join(" ");
+        pre_idx
+            .add_document(get_document_with_text(
+                d * 2,
+                "codec",
+                vec![],
+                &format!("{} other", text),
+                vec![],
+                "",
+            ))
+            .unwrap();
+    }
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn streamed(idx: &Index, token: &str) -> Vec<Posting> {
+    idx.stream_postings(token).unwrap().collect()
+}
+
+#[test]
+fn test_index_built_with_each_codec() {
+    let expected = streamed(&codec_index(PostingCodec::default()), "codectoken");
+    assert_eq!(expected.len(), 799);
+
+    for codec in all_codecs() {
+        let name = codec.name();
+        let idx = codec_index(codec);
+
+        assert_eq!(idx.codec.name(), name);
+        assert_eq!(streamed(&idx, "codectoken"), expected, "{}", name);
+        assert_eq!(
+            idx.stream_postings("codectoken")
+                .unwrap()
+                .skip_to(501)
+                .unwrap()
+                .document_id,
+            502
+        );
+        assert_eq!(idx.tf("codectoken", 4), 3);
+        assert_eq!(
+            idx.stream_documents("codectoken")
+                .unwrap()
+                .map(|d| d.tf)
+                .sum::<u32>(),
+            799
+        );
+    }
+}
+
+#[test]
+fn test_snapshot_keeps_codec() {
+    let idx = codec_index(PostingCodec::from_name("eliasfano").unwrap());
+    let dir = std::env::temp_dir().join(format!("snapshot-{}", Uuid::new_v4()));
+
+    let manifest = idx.save_snapshot(&dir).unwrap();
+    assert_eq!(manifest.posting_codec.name(), "eliasfano");
+
+    let restored = Index::load_snapshot(&dir, 1000, 1000).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(restored.codec.name(), "eliasfano");
+    assert_eq!(
+        streamed(&restored, "codectoken"),
+        streamed(&idx, "codectoken")
+    );
+}
@@ -1,35 +1,24 @@
 use std::{
-    borrow::Borrow,
-    collections::VecDeque,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
     error::Error,
     fmt::Debug,
     fs::{remove_file, File},
-    hash::Hash,
-    io::{Read, Seek, Write},
-    ops::Deref,
+    io::{BufReader, Read, Seek, Write},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use crate::{EncodedPostingNode, Posting, SequentialEncoder, Serializable};
+use crate::{
+    default_cache_policy, CachePolicy, EncodedPostingNode, EvictionPolicy, IndexError, Posting,
+    SequentialEncoder, Serializable,
+};
 use default_env::default_env;
-use indexmap::IndexMap;
-use itertools::{FoldWhile, Itertools};
-use keyed_priority_queue::KeyedPriorityQueue;
-use log::info;
-use once_cell::sync::Lazy;
+use log::{error, info};
 use parking_lot::Mutex;
 use ternary_tree::Tst;
 use utils::MemFootprintCalculator;
-
-/// a hashmap from DiskHashMap id's to their file handles
-static FILE_HANDLES: Lazy<Mutex<[Option<File>; 10]>> = Lazy::new(|| Default::default());
-static FREE_SPACE_BLOCKS: Lazy<Mutex<[BTreeMap<u64, Vec<u64>>; 10]>> =
-    Lazy::new(|| Default::default());
-static IN_MEM_RECORDS: Lazy<Mutex<[u32; 10]>> = Lazy::new(|| Default::default());
-static RECORD_PRIORITIES: Lazy<Mutex<[KeyedPriorityQueue<u32, Priority>; 10]>> =
-    Lazy::new(|| Default::default());
+use uuid::Uuid;
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub struct Priority(pub u32);
@@ -58,73 +47,192 @@ impl From<u32> for Priority {
     }
 }
 
-#[derive(Debug)]
-pub enum Entry<V: Serializable, const ID: usize> {
-    Memory(V, u32),
-    Disk(u64, u32),
+/// the backing file of a single map along with the bookkeeping of which records are in memory,
+/// shared between the map and its entries. The file is removed once neither needs it anymore
+pub struct Storage {
+    /// read from at fixed offsets without holding the lock, so entries can be loaded in parallel
+    file: Arc<File>,
+    path: PathBuf,
+    /// offsets of the holes left in the file, by their size
+    free_space: BTreeMap<u64, Vec<u64>>,
+    in_mem_records: u32,
+    /// which of the records in memory is evicted next
+    policy: Box<dyn EvictionPolicy>,
+    policy_kind: CachePolicy,
+    file_len: u64,
+    live_bytes: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    /// how often each record was looked up since the counts were last taken
+    uses: HashMap<u32, u64>,
+    /// records handed out for changing since they were last measured, only kept if the policy needs sizes
+    resized: HashSet<u32>,
+}
+
+/// how the backing file of a map is used, and how often records had to be read from or written to it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiskStats {
+    pub file_bytes: u64,
+    /// bytes taken up by the records currently on disk
+    pub live_bytes: u64,
+    /// bytes left behind by records which were loaded back, reused by later evictions
+    pub free_bytes: u64,
+    pub holes: u32,
+    /// lookups of records which were already in memory
+    pub hits: u64,
+    /// lookups of records which had to be read back from disk. Each lookup counts once, even if the record
+    /// was evicted by another thread and read again before its caller got to it
+    pub misses: u64,
+    /// records written out to disk
+    pub evictions: u64,
+    /// the eviction policy of the map
+    pub policy: &'static str,
+    /// how much of the capacity is taken up, in records or bytes depending on the policy
+    pub cache_usage: u64,
 }
 
-impl<V: Serializable, const ID: usize> Entry<V, ID> {
-    pub fn set_id(&mut self, id: u32) {
-        match self {
-            Entry::Memory(_, ref mut i) => *i = id,
-            Entry::Disk(_, ref mut i) => *i = id,
+impl DiskStats {
+    /// the share of accesses to records which were already in memory
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            accesses => self.hits as f64 / accesses as f64,
         }
     }
 }
 
-impl<V: Serializable, const ID: usize> Default for Entry<V, ID> {
-    fn default() -> Self {
-        Self::Memory(V::default(), 0)
+impl Storage {
+    fn create(path: PathBuf, policy: CachePolicy) -> Result<Self, std::io::Error> {
+        let file = File::options()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+
+        Ok(Self {
+            file: Arc::new(file),
+            path,
+            free_space: BTreeMap::default(),
+            in_mem_records: 0,
+            policy: policy.create(),
+            policy_kind: policy,
+            file_len: 0,
+            live_bytes: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            uses: HashMap::default(),
+            resized: HashSet::default(),
+        })
+    }
+
+    fn stats(&self) -> DiskStats {
+        DiskStats {
+            file_bytes: self.file_len,
+            live_bytes: self.live_bytes,
+            free_bytes: self
+                .free_space
+                .iter()
+                .map(|(k, v)| k * v.len() as u64)
+                .sum(),
+            holes: self.free_space.values().map(|v| v.len() as u32).sum(),
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            policy: self.policy_kind.name(),
+            cache_usage: self.policy.usage(),
+        }
     }
 }
 
-impl<V: Serializable, const ID: usize> Serializable for Entry<V, ID> {
-    fn serialize<W: std::io::Write>(&self, buf: &mut W) -> usize {
-        match self {
-            Entry::Memory(v, _) => v.serialize(buf),
-            _ => panic!(),
-        }
+/// reads a file from a fixed offset on without moving its cursor, any number of them can share a file
+struct PositionalReader<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Read for PositionalReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(self.file, buf, self.offset)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(self.file, buf, self.offset)?;
+
+        self.offset += n as u64;
+        Ok(n)
     }
+}
 
-    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
-        match self {
-            Entry::Memory(v, _) => v.deserialize(buf),
-            _ => panic!(),
-        }
+/// deserializes the record at the given offset, returns it along with its size
+fn read_record<V: Serializable>(file: &File, offset: u64) -> Result<(V, u64), IndexError> {
+    let mut v = V::default();
+    let size = v.try_deserialize(&mut BufReader::new(PositionalReader { file, offset }))?;
+    Ok((v, size as u64))
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
     }
 }
 
-impl<V: Serializable + MemFootprintCalculator, const ID: usize> MemFootprintCalculator
-    for Entry<V, ID>
-{
+impl Debug for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Storage({})", self.path.display())
+    }
+}
+
+#[derive(Debug)]
+pub enum Slot<V: Serializable> {
+    Memory(V),
+    Disk(u64),
+}
+
+/// a record of a [DiskHashMap], either in memory or at an offset into the file of the map
+#[derive(Debug)]
+pub struct Entry<V: Serializable> {
+    slot: Slot<V>,
+    id: u32,
+    storage: Arc<Mutex<Storage>>,
+}
+
+impl<V: Serializable + MemFootprintCalculator> MemFootprintCalculator for Entry<V> {
     fn real_mem(&self) -> u64 {
-        match self {
-            Entry::Memory(v, _) => v.real_mem() + 8,
-            Entry::Disk(_, _) => 8,
+        match &self.slot {
+            Slot::Memory(v) => v.real_mem() + 16,
+            Slot::Disk(_) => 16,
         }
     }
 }
 
-impl<V: Serializable, const ID: usize> Entry<V, ID> {
+impl<V: Serializable> Entry<V> {
     pub fn into_inner(mut self) -> Result<V, Box<dyn Error>> {
-        match self {
-            Entry::Memory(v, _) => Ok(v),
-            Entry::Disk(_, _) => {
-                self.load()?;
-
-                match self {
-                    Entry::Memory(v, _) => Ok(v),
-                    Entry::Disk(_, _) => panic!(),
-                }
+        self.load()?;
+
+        match std::mem::replace(&mut self.slot, Slot::Disk(0)) {
+            Slot::Memory(v) => {
+                let mut storage = self.storage.lock();
+                storage.in_mem_records -= 1;
+                storage.policy.remove(self.id);
+                Ok(v)
             }
+            Slot::Disk(_) => panic!(),
+        }
+    }
+
+    /// where the entry sits in the backing file, none while in memory
+    fn disk_offset(&self) -> Option<u64> {
+        match self.slot {
+            Slot::Memory(_) => None,
+            Slot::Disk(offset) => Some(offset),
         }
     }
 
     pub fn is_loaded(&self) -> bool {
-        match self {
-            Entry::Memory(_, _) => true,
-            Entry::Disk(_, _) => false,
+        match self.slot {
+            Slot::Memory(_) => true,
+            Slot::Disk(_) => false,
         }
     }
 
@@ -133,59 +241,35 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
     pub fn get(&mut self) -> Result<&V, Box<dyn Error>> {
         self.load()?;
 
-        let id = match &self {
-            Entry::Memory(_, id) => id,
-            Entry::Disk(_, id) => id,
-        };
-
-        let mut lock = RECORD_PRIORITIES.lock();
-        let map = lock.get_mut(ID).unwrap();
-
-        let prio = *map.get_priority(id).unwrap();
-        map.set_priority(id, Priority::increase(prio)).unwrap();
+        self.storage.lock().policy.touch(self.id);
 
         self.get_mem()
     }
 
     // ensures the entry is not in memory
     fn unload(&mut self) -> Result<(), Box<dyn Error>> {
-        let (offset, id) = match self {
-            Entry::Memory(v, id) => {
+        let offset = match &mut self.slot {
+            Slot::Memory(v) => {
                 let prev = std::mem::take(v);
-                (
-                    Self::evict(
-                        &mut FILE_HANDLES.lock().get_mut(ID).unwrap().as_mut().unwrap(),
-                        prev,
-                    )?,
-                    id,
-                )
+                Self::evict(&mut self.storage.lock(), prev)?
             }
-            Entry::Disk(_, _) => return Ok(()),
+            Slot::Disk(_) => return Ok(()),
         };
 
-        *self = Entry::Disk(offset, *id);
+        self.slot = Slot::Disk(offset);
 
         Ok(())
     }
 
     // ensures the entry is in memory
     pub fn load(&mut self) -> Result<(), Box<dyn Error>> {
-        match self {
-            Entry::Memory(_v, _) => Ok(()),
-            Entry::Disk(offset, id) => {
-                RECORD_PRIORITIES
-                    .lock()
-                    .get_mut(ID)
-                    .unwrap()
-                    .push(*id, 0.into());
-
-                *self = Entry::Memory(
-                    Self::fetch(
-                        *offset,
-                        &mut FILE_HANDLES.lock().get_mut(ID).unwrap().as_mut().unwrap(),
-                    )?, // TODO: env variable
-                    *id,
-                );
+        match self.slot {
+            Slot::Memory(_) => Ok(()),
+            Slot::Disk(offset) => {
+                let (v, bytes) = Self::fetch(offset, &self.storage)?;
+                self.storage.lock().policy.admit(self.id, bytes);
+
+                self.slot = Slot::Memory(v);
                 Ok(())
             }
         }
@@ -194,30 +278,26 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
     // ensures the entry is in memory
     // then returns mutable reference to it
     pub fn get_mut(&mut self) -> Result<&mut V, Box<dyn Error>> {
-        match self {
-            Entry::Memory(ref mut v, _) => Ok(v),
-            Entry::Disk(offset, id) => {
-                *self = Entry::Memory(
-                    Self::fetch(
-                        *offset,
-                        &mut FILE_HANDLES.lock().get_mut(ID).unwrap().as_mut().unwrap(),
-                    )?, // TODO: env variable
-                    *id,
-                );
-
-                match self {
-                    Entry::Memory(ref mut v, _) => Ok(v),
-                    _ => panic!(),
-                }
-            }
+        self.load()?;
+
+        // the record may grow or shrink, it is measured again before the cache is next checked
+        let mut storage = self.storage.lock();
+        if storage.policy.needs_sizes() {
+            storage.resized.insert(self.id);
+        }
+        drop(storage);
+
+        match self.slot {
+            Slot::Memory(ref mut v) => Ok(v),
+            _ => panic!(),
         }
     }
 
     // tries to retrieve entry from memory, throws error if not present there
     pub fn get_mem(&self) -> Result<&V, Box<dyn Error>> {
-        match self {
-            Entry::Memory(ref v, _) => Ok(v),
-            Entry::Disk(_, _) => Err(Box::new(std::io::Error::new(
+        match self.slot {
+            Slot::Memory(ref v) => Ok(v),
+            Slot::Disk(_) => Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!("Data was not in memory."),
             ))),
@@ -225,30 +305,28 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
     }
 
     /// fetches the entry from the backing store at the given offset and records the free space gap left over for the hash map to make use of
-    /// later if needed
-    fn fetch(offset: u64, f: &mut File) -> Result<V, Box<dyn Error>> {
-        // open file and fill buffer
-        f.seek(std::io::SeekFrom::Start(offset))?;
-
-        // deserialize
-        let mut v = V::default();
-        let free_space = v.deserialize(f);
-        FREE_SPACE_BLOCKS
-            .lock()
-            .get_mut(ID)
-            .unwrap()
-            .entry(free_space as u64)
+    /// later if needed, returns it along with its size.
+    /// The storage is only locked for the bookkeeping, nothing else is written where the entry sits until its gap is recorded
+    fn fetch(offset: u64, storage: &Mutex<Storage>) -> Result<(V, u64), Box<dyn Error>> {
+        let file = Arc::clone(&storage.lock().file);
+        let (v, free_space) = read_record::<V>(&file, offset)?;
+
+        let mut storage = storage.lock();
+        storage
+            .free_space
+            .entry(free_space)
             .or_default()
             .push(offset);
 
-        *IN_MEM_RECORDS.lock().get_mut(ID).unwrap() += 1;
+        storage.in_mem_records += 1;
+        storage.live_bytes -= free_space;
 
-        Ok(v)
+        Ok((v, free_space))
     }
 
     /// evicts the entry into the backing store either at the first hole of smallest size or at the end of the store
     /// returns the offset into the backing store
-    fn evict(f: &mut File, v: V) -> Result<u64, Box<dyn Error>> {
+    fn evict(storage: &mut Storage, v: V) -> Result<u64, Box<dyn Error>> {
         // serialize into buffer to find out how many bytes necessary
         let mut buf = Vec::default();
         let space_needed = v.serialize(&mut buf) as u64;
@@ -256,11 +334,7 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
         // find space
         let offset: std::io::SeekFrom;
 
-        let mut lock = FREE_SPACE_BLOCKS.lock();
-
-        let space_map = lock
-            .get_mut(ID)
-            .expect(&format!("No free space record for id {}", ID));
+        let space_map = &mut storage.free_space;
 
         if space_map.is_empty() {
             offset = std::io::SeekFrom::End(0);
@@ -312,6 +386,7 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
         }
 
         // serialize to it, record stream position first
+        let mut f = &*storage.file;
         f.seek(offset)?;
         let abs_offset = f
             .stream_position()
@@ -321,69 +396,87 @@ impl<V: Serializable, const ID: usize> Entry<V, ID> {
         // make sure to flush
         f.flush()?;
 
-        let mut lock = IN_MEM_RECORDS.lock();
-
-        *lock.get_mut(ID).unwrap() -= 1;
+        storage.in_mem_records -= 1;
+        storage.live_bytes += space_needed;
+        storage.evictions += 1;
+        storage.file_len = storage.file_len.max(abs_offset + space_needed);
 
         Ok(abs_offset)
     }
 }
 
+/// the directory backing files are created in unless given one, `DISK_HASH_MAP_DIR` if set
+pub fn default_disk_hash_map_dir() -> PathBuf {
+    env::var("DISK_HASH_MAP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(default_env!("TMP_PATH", "/tmp")))
+}
+
 /// A hashmap which holds a limited number of records in main memory with the rest
 /// of the records held on disk
 /// records are swapped as necessary
-/// INVARIANT: The number of bytes of all values in the cache (as per their serialization)
+/// every map has a backing file and cache of its own, so any number of them can be used at once.
+/// Which records are evicted and what the capacity counts is up to its [CachePolicy]
+/// Records are read back from the file at their offset without locking the whole map,
+/// so any number of threads can load different records at once
+/// INVARIANT: The usage of the cache as measured by its policy
 /// will never exceed the capacity + largest value in the cache (due to the way bookkeping has to be done in entries)
-pub struct DiskHashMap<V, const ID: usize>
+pub struct DiskHashMap<V>
 where
     V: Serializable + Debug,
 {
-    map: Vec<Arc<Mutex<Entry<V, ID>>>>,
+    map: Vec<Arc<Mutex<Entry<V>>>>,
     tst: Tst<usize>,
-
-    /// how many records to allow in memory at one time during runtime
-    capacity: u32,
-    /// how many records to retain between batch evictions
-    persistent_capacity: u32,
+    storage: Arc<Mutex<Storage>>,
+    /// slots of removed keys, taken up again by new keys
+    free_slots: Vec<usize>,
+
+    /// how many records (or bytes) to allow in memory at one time during runtime
+    capacity: u64,
+    /// how many records (or bytes) to retain between batch evictions
+    persistent_capacity: u64,
     build_mode: bool,
 }
 
-impl<V, const ID: usize> DiskHashMap<V, ID>
+impl<V> DiskHashMap<V>
 where
     V: Serializable + Debug,
 {
     pub fn len(&self) -> usize {
-        return self.map.len();
+        return self.map.len() - self.free_slots.len();
     }
 
-    pub fn capacity(&self) -> u32 {
+    pub fn capacity(&self) -> u64 {
         return self.capacity;
     }
 
-    pub fn persistent_capacity(&self) -> u32 {
+    pub fn persistent_capacity(&self) -> u64 {
         return self.persistent_capacity;
     }
 
     /// finalizes the hashmap, cache evictions now happen per single request
     /// versus batched
     pub fn set_runtime_mode(&mut self) {
-        info!("Finalizing diskhashmap-{} construction", ID);
+        info!("Finalizing {} construction", self.path().display());
         self.build_mode = false;
     }
 
     pub fn cache_population(&self) -> u32 {
-        *IN_MEM_RECORDS.lock().get(ID).unwrap()
+        self.storage.lock().in_mem_records
     }
 
-    /// picks a victim to evict according to eviction policy and unloads it
-    fn evict_victim(&self) -> Option<&Arc<Mutex<Entry<V, ID>>>> {
+    /// how much of the capacity is taken up, in the unit of the policy
+    pub fn cache_usage(&self) -> u64 {
+        self.storage.lock().policy.usage()
+    }
 
-        let victim = RECORD_PRIORITIES
-            .lock()
-            .get_mut(ID)
-            .unwrap()
-            .pop()
-            .map(|(v, _)| v);
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.storage.lock().policy_kind
+    }
+
+    /// picks a victim to evict according to eviction policy and unloads it
+    fn evict_victim(&self) -> Option<&Arc<Mutex<Entry<V>>>> {
+        let victim = self.storage.lock().policy.victim();
 
         if let Some(v) = victim {
             let v = self.map.get(v as usize).unwrap();
@@ -398,52 +491,71 @@ where
         // figure out how many records are in memory
 
         // reduce this number if needed
-        let mut records = *IN_MEM_RECORDS.lock().get(ID).unwrap();
+        let mut records = self.cache_population();
         info!("Cleaning cache fully, current records: {:?}", records);
         while records > 0 {
             if self.evict_victim().is_none() {
                 break;
             }
-            records = *IN_MEM_RECORDS.lock().get(ID).unwrap();
+            records = self.cache_population();
         }
 
         info!(
             "Cleaned cache fully, current records: {:?}",
-            IN_MEM_RECORDS.lock().get(ID)
+            self.cache_population()
         );
     }
 
+    /// tells the policy the sizes of the records changed through `get_mut` since they were last measured.
+    /// Records still locked by someone are measured the next time round
+    fn measure_resized(&self) {
+        let resized = std::mem::take(&mut self.storage.lock().resized);
+        let mut held = Vec::default();
+
+        for id in resized {
+            let entry = match self.map[id as usize].try_lock() {
+                Some(v) => v,
+                None => {
+                    held.push(id);
+                    continue;
+                }
+            };
+            if let Slot::Memory(v) = &entry.slot {
+                let bytes = v.serialize(&mut std::io::sink()) as u64;
+                self.storage.lock().policy.resize(id, bytes);
+            }
+        }
+
+        self.storage.lock().resized.extend(held);
+    }
+
     /// evicts untill invariant is satisfied,
     /// in build mode cache is cleared in batches to save io
     fn evict_invariant(&self) {
-        let mut records = *IN_MEM_RECORDS.lock().get(ID).unwrap();
+        self.measure_resized();
+        let unit = self.cache_policy().unit();
+        let mut usage = self.cache_usage();
         if self.build_mode {
-            if records > self.capacity {
-                info!(
-                    "Cleaning cache, current records: {:?}",
-                    IN_MEM_RECORDS.lock().get(ID)
-                );
-                while records > self.persistent_capacity {
+            if usage > self.capacity {
+                info!("Cleaning cache, current {}: {:?}", unit, usage);
+                while usage > self.persistent_capacity {
                     if self.evict_victim().is_none() {
                         break;
                     }
-                    records = *IN_MEM_RECORDS.lock().get(ID).unwrap();
+                    usage = self.cache_usage();
                 }
-                info!(
-                    "Cleaned cache, current records: {:?}",
-                    IN_MEM_RECORDS.lock().get(ID)
-                );
+                info!("Cleaned cache, current {}: {:?}", unit, usage);
             }
         } else {
             loop {
-                if records > self.capacity {
-                    info!("Cache full at {} records", records);
+                if usage > self.capacity {
+                    info!("Cache full at {} {}", usage, unit);
                     let victim = self.evict_victim();
                     if victim.is_none() {
                         break;
                     }
                     info!("Evicting {:?} from cache.", victim);
-                    records = *IN_MEM_RECORDS.lock().get(ID).unwrap();
+                    usage = self.cache_usage();
                 } else {
                     break;
                 }
@@ -451,13 +563,131 @@ where
         }
     }
 
-    pub fn entry(&self, k: &str) -> Option<Arc<Mutex<Entry<V, ID>>>> {
+    /// loads the entry, counting a hit if it was in memory already and a miss otherwise
+    fn access(&self, v: &Arc<Mutex<Entry<V>>>) {
+        let mut lock = v.lock();
+        {
+            let mut storage = self.storage.lock();
+            match lock.is_loaded() {
+                true => storage.hits += 1,
+                false => storage.misses += 1,
+            }
+        }
+        lock.load().unwrap();
+    }
+
+    /// how often the record of each key was looked up through `lookup` since the last call,
+    /// the counts start over afterwards
+    pub fn take_uses(&self) -> Vec<(String, u64)> {
+        let uses = std::mem::take(&mut self.storage.lock().uses);
+        if uses.is_empty() {
+            return Vec::default();
+        }
+
+        self.keys()
+            .into_iter()
+            .filter_map(|(k, i)| uses.get(&(i as u32)).map(|c| (k, *c)))
+            .collect()
+    }
+
+    /// loads the records of the given keys, most wanted first, until the cache is full.
+    /// Loading them does not count as misses, and they are left to be evicted in reverse order.
+    /// Returns how many of them are in memory afterwards
+    pub fn warm<'a, I: IntoIterator<Item = &'a str>>(&self, keys: I) -> usize {
+        let mut warmed = Vec::default();
+        for k in keys {
+            if self.cache_usage() >= self.capacity {
+                break;
+            }
+
+            let entry = match self.tst.get(k).and_then(|i| self.map.get(*i)) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let mut lock = entry.lock();
+            lock.load().unwrap();
+            warmed.push(lock.id);
+        }
+
+        // the most wanted records are used last, so they are the last ones picked as victims
+        let mut storage = self.storage.lock();
+        for id in warmed.iter().rev() {
+            storage.policy.touch(*id);
+        }
+        drop(storage);
+
+        self.evict_invariant();
+
+        warmed
+            .iter()
+            .filter(|id| self.map[**id as usize].lock().is_loaded())
+            .count()
+    }
+
+    pub fn disk_stats(&self) -> DiskStats {
+        self.storage.lock().stats()
+    }
+
+    /// rewrites the records on disk back to back from the start of the file and shrinks it to fit, leaving no holes.
+    /// Records are moved in the order they sit in the file, so each only ever moves towards the start over ones already moved
+    pub fn compact(&mut self) -> Result<DiskStats, Box<dyn Error>> {
+        let before = self.disk_stats();
+
+        let mut on_disk = self
+            .map
+            .iter()
+            .filter_map(|e| e.lock().disk_offset().map(|o| (o, e)))
+            .collect::<Vec<_>>();
+        on_disk.sort_by_key(|(o, _)| *o);
+
+        let mut end = 0;
+        for (offset, entry) in on_disk {
+            let mut lock = entry.lock();
+            // loaded through an entry handed out before
+            if lock.disk_offset() != Some(offset) {
+                continue;
+            }
+
+            let storage = self.storage.lock();
+            let (v, _) = read_record::<V>(&storage.file, offset)?;
+
+            let mut buf = Vec::default();
+            let size = v.serialize(&mut buf) as u64;
+            if offset != end {
+                let mut f = &*storage.file;
+                f.seek(std::io::SeekFrom::Start(end))?;
+                f.write_all(&buf)?;
+            }
+
+            lock.slot = Slot::Disk(end);
+            end += size;
+        }
+
+        let mut storage = self.storage.lock();
+        storage.file.set_len(end)?;
+        (&*storage.file).flush()?;
+        storage.free_space.clear();
+        storage.file_len = end;
+        storage.live_bytes = end;
+
+        info!(
+            "Compacted {} from {} to {} bytes",
+            storage.path.display(),
+            before.file_bytes,
+            end
+        );
+
+        Ok(storage.stats())
+    }
+
+    pub fn entry(&self, k: &str) -> Option<Arc<Mutex<Entry<V>>>> {
         let o = self
             .tst
             .get(k)
             .and_then(|x| self.map.get(*x as usize))
             .map(|v| {
-                v.lock().load().unwrap(); // force a load, users can't unload so this preserves RAM invariant within this function
+                self.access(v); // force a load, users can't unload so this preserves RAM invariant within this function
                 Arc::clone(v)
             });
         // check invariant, evicts less used / freshest elements first idealy
@@ -467,9 +697,17 @@ where
         o
     }
 
-    pub fn entry_by_index(&self, x: usize) -> Option<Arc<Mutex<Entry<V, ID>>>> {
+    /// the entry of the key, counted as a use of it by `take_uses`.
+    /// Lookups made on behalf of queries go through here, scans of the whole map and the like use `entry`
+    pub fn lookup(&self, k: &str) -> Option<Arc<Mutex<Entry<V>>>> {
+        let i = *self.tst.get(k)?;
+        *self.storage.lock().uses.entry(i as u32).or_default() += 1;
+        self.entry(k)
+    }
+
+    pub fn entry_by_index(&self, x: usize) -> Option<Arc<Mutex<Entry<V>>>> {
         let o = self.map.get(x).map(|v| {
-            v.lock().load().unwrap(); // force a load, users can't unload so this preserves RAM invariant within this function
+            self.access(v); // force a load, users can't unload so this preserves RAM invariant within this function
             Arc::clone(v)
         });
         // check invariant, evicts less used / freshest elements first idealy
@@ -479,15 +717,25 @@ where
         o
     }
 
-    pub fn entry_wild_card(&self, k: &str) -> Vec<&Arc<Mutex<Entry<V, ID>>>> {
-        let mut v: Vec<&Arc<Mutex<Entry<V, ID>>>> = Vec::new();
+    /// all keys alongside the index of their record, in key order
+    pub fn keys(&self) -> Vec<(String, usize)> {
+        let mut out = Vec::with_capacity(self.map.len());
+        let mut it = self.tst.iter();
+        while let Some(i) = it.next() {
+            out.push((it.current_key(), *i));
+        }
+        out
+    }
+
+    pub fn entry_wild_card(&self, k: &str) -> Vec<&Arc<Mutex<Entry<V>>>> {
+        let mut v: Vec<&Arc<Mutex<Entry<V>>>> = Vec::new();
         self.tst
             .visit_crossword_values(k, '*', |s| v.push(self.map.get(*s as usize).unwrap()));
 
         v
     }
 
-    pub fn entry_or_default(&mut self, k: &str) -> Arc<Mutex<Entry<V, ID>>>
+    pub fn entry_or_default(&mut self, k: &str) -> Arc<Mutex<Entry<V>>>
 where {
         let v = self.tst.get(k).and_then(|x| self.map.get(*x as usize));
         let o = match v {
@@ -503,73 +751,143 @@ where {
         o
     }
 
-    pub fn path() -> PathBuf {
-        PathBuf::from(format!(
-            "{}/diskhashmap-{}",
-            default_env!("TMP_PATH", "/tmp"),
-            ID
-        ))
+    /// the backing file of the map
+    pub fn path(&self) -> PathBuf {
+        self.storage.lock().path.clone()
     }
 
-    pub fn insert(&mut self, k: &str, v: V) -> Option<Arc<Mutex<Entry<V, ID>>>> {
-        let idx = self.tst.get(k);
-
-        // self.map
-        // Arc::new(Mutex::new(Entry::Memory(v, self.map.len() as u32)))
+    /// adds the record or replaces the one already held under the key, whose space on disk is freed.
+    /// Entries of the replaced record handed out before must not be used afterwards
+    pub fn insert(&mut self, k: &str, v: V) -> Option<Arc<Mutex<Entry<V>>>> {
+        let idx = self.tst.get(k).cloned();
+        // the serialized size, only worked out if the policy needs it
+        let bytes = match self.storage.lock().policy.needs_sizes() {
+            true => v.serialize(&mut std::io::sink()) as u64,
+            false => 0,
+        };
 
-        // if the value is nothing, we need to make sure to remove its record
-        let old = match &idx {
+        let i = match idx {
+            Some(i) => {
+                self.release(i);
+                i
+            }
             None => {
-                self.tst.insert(k, self.map.len());
-                self.map.push(Arc::new(Mutex::new(Entry::Memory(
-                    v,
-                    self.map.len() as u32,
-                ))));
-
-                *IN_MEM_RECORDS.lock().get_mut(ID).unwrap() += 1;
-                RECORD_PRIORITIES
-                    .lock()
-                    .get_mut(ID)
-                    .unwrap()
-                    .push((self.map.len() - 1) as u32, 0.into());
+                let i = self.free_slots.pop().unwrap_or(self.map.len());
+                self.tst.insert(k, i);
+                i
+            }
+        };
+
+        let entry = Arc::new(Mutex::new(Entry {
+            slot: Slot::Memory(v),
+            id: i as u32,
+            storage: Arc::clone(&self.storage),
+        }));
+        let old = match i == self.map.len() {
+            true => {
+                self.map.push(entry);
                 None
             }
-            Some(i) => Some(std::mem::replace(
-                &mut self.map[**i],
-                Arc::new(Mutex::new(Entry::Memory(v, **i as u32))),
-            )),
+            // slots of removed keys hold nothing worth handing back
+            false => Some(std::mem::replace(&mut self.map[i], entry)).filter(|_| idx.is_some()),
         };
 
+        let mut storage = self.storage.lock();
+        storage.in_mem_records += 1;
+        storage.policy.admit(i as u32, bytes);
+        drop(storage);
+
         self.evict_invariant();
 
         old
     }
 
-    pub fn new(capacity: u32, persistent_capacity: u32, build_mode: bool) -> Self {
-        // create and open new file handle, store it in static var for entries
-        let path = Self::path();
-        remove_file(&path);
-
-        let fh = File::options()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(Self::path())
-            .expect(&format!("Could not allocate file for DiskHashMap-{}", ID));
+    /// drops the record of the key, freeing its space on disk. Returns whether the key was there.
+    /// Entries of the record handed out before must not be used afterwards
+    pub fn remove(&mut self, k: &str) -> bool {
+        let i = match self.tst.remove(k) {
+            Some(i) => i,
+            None => return false,
+        };
 
-        *FILE_HANDLES.lock().get_mut(ID).unwrap() = Some(fh);
-        *FREE_SPACE_BLOCKS.lock().get_mut(ID).unwrap() = BTreeMap::default();
-        *IN_MEM_RECORDS.lock().get_mut(ID).unwrap() = 0;
-        *RECORD_PRIORITIES.lock().get_mut(ID).unwrap() = KeyedPriorityQueue::default();
+        self.release(i);
+        self.map[i] = Arc::new(Mutex::new(Entry {
+            slot: Slot::Memory(V::default()),
+            id: i as u32,
+            storage: Arc::clone(&self.storage),
+        }));
+        let mut storage = self.storage.lock();
+        storage.uses.remove(&(i as u32));
+        storage.resized.remove(&(i as u32));
+        drop(storage);
+
+        self.free_slots.push(i);
+        true
+    }
+
+    /// forgets the record in the slot, its space on disk becomes a hole and the policy stops tracking it
+    fn release(&self, i: usize) {
+        let entry = self.map[i].lock();
+        let mut storage = self.storage.lock();
+        match entry.slot {
+            Slot::Memory(_) => {
+                storage.in_mem_records -= 1;
+                storage.policy.remove(i as u32);
+            }
+            // a record which can't be read back is left where it is
+            Slot::Disk(offset) => match read_record::<V>(&storage.file, offset) {
+                Ok((_, size)) => {
+                    storage.free_space.entry(size).or_default().push(offset);
+                    storage.live_bytes -= size;
+                }
+                Err(e) => error!("Could not free record {} of {}: {}", i, storage.path.display(), e),
+            },
+        }
+    }
 
-        // better safe than sorry
-        if path == Path::new("/") || path.as_os_str().len() == 0 {
-            panic!();
-        };
+    /// creates the map with its backing file in the default directory
+    pub fn new(capacity: u64, persistent_capacity: u64, build_mode: bool) -> Self {
+        Self::new_in(
+            default_disk_hash_map_dir(),
+            capacity,
+            persistent_capacity,
+            build_mode,
+        )
+    }
+
+    /// creates the map with a backing file of its own in the given directory
+    pub fn new_in<P: AsRef<Path>>(
+        dir: P,
+        capacity: u64,
+        persistent_capacity: u64,
+        build_mode: bool,
+    ) -> Self {
+        Self::with_policy(
+            dir,
+            capacity,
+            persistent_capacity,
+            build_mode,
+            default_cache_policy(),
+        )
+    }
+
+    /// creates the map with a backing file of its own in the given directory, evicting with the given policy
+    pub fn with_policy<P: AsRef<Path>>(
+        dir: P,
+        capacity: u64,
+        persistent_capacity: u64,
+        build_mode: bool,
+        policy: CachePolicy,
+    ) -> Self {
+        let path = dir.as_ref().join(format!("diskhashmap-{}", Uuid::new_v4()));
+        let storage = Storage::create(path.clone(), policy)
+            .expect(&format!("Could not allocate file for {}", path.display()));
 
         Self {
             map: Vec::default(),
             tst: Tst::new(),
+            storage: Arc::new(Mutex::new(storage)),
+            free_slots: Vec::default(),
             capacity: capacity,
             persistent_capacity,
             build_mode,
@@ -577,7 +895,7 @@ where {
     }
 }
 
-impl<V, const ID: usize> MemFootprintCalculator for DiskHashMap<V, ID>
+impl<V> MemFootprintCalculator for DiskHashMap<V>
 where
     V: Serializable + MemFootprintCalculator + Debug,
 {
@@ -586,7 +904,7 @@ where
     }
 }
 
-impl<V, const ID: usize> Default for DiskHashMap<V, ID>
+impl<V> Default for DiskHashMap<V>
 where
     V: Serializable + Debug,
 {
@@ -595,7 +913,7 @@ where
     }
 }
 
-// impl<V, const ID: usize> IntoIterator for DiskHashMap<V, ID>
+// impl<V> IntoIterator for DiskHashMap<V>
 // where
 //     V: Serializable + Debug,
 // {
@@ -611,15 +929,12 @@ where
 //     }
 // }
 
-pub trait TernaryFunctions<F, const ID: usize>
+pub trait TernaryFunctions<F>
 where
     F: SequentialEncoder<Posting> + Debug,
 {
     fn get_postings_count(&self, posting_node_index: usize) -> u32;
-    fn get_wildcard_postings(
-        &self,
-        token: &str,
-    ) -> Vec<&Arc<Mutex<Entry<EncodedPostingNode<F>, ID>>>>;
+    fn get_wildcard_postings(&self, token: &str) -> Vec<&Arc<Mutex<Entry<EncodedPostingNode<F>>>>>;
     fn find_nearest_neighbour_keys(
         &self,
         k: &str,
@@ -629,14 +944,11 @@ where
     ) -> Vec<String>;
 }
 
-impl<F, const ID: usize> TernaryFunctions<F, ID> for DiskHashMap<EncodedPostingNode<F>, ID>
+impl<F> TernaryFunctions<F> for DiskHashMap<EncodedPostingNode<F>>
 where
     F: SequentialEncoder<Posting> + Debug,
 {
-    fn get_wildcard_postings(
-        &self,
-        token: &str,
-    ) -> Vec<&Arc<Mutex<Entry<EncodedPostingNode<F>, ID>>>> {
+    fn get_wildcard_postings(&self, token: &str) -> Vec<&Arc<Mutex<Entry<EncodedPostingNode<F>>>>> {
         return self.entry_wild_card(token);
     }
 
@@ -1,9 +1,16 @@
-use std::path::PathBuf;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
 
 use more_asserts::assert_le;
+use uuid::Uuid;
 
-use crate::{DiskHashMap, Priority};
+use crate::{DiskHashMap, Priority, Serializable};
 
+fn serialized_len(v: &str) -> u64 {
+    let mut buf = Vec::default();
+    v.to_string().serialize(&mut buf) as u64
+}
 
 #[test]
 fn test_priority() {
@@ -19,7 +26,7 @@ fn test_priority() {
 
 #[test]
 fn test_disk_hash_map_build_mode_persistent() {
-    let mut d = DiskHashMap::<u32, 0>::new(3, 2, true);
+    let mut d = DiskHashMap::<u32>::new(3, 2, true);
 
     d.insert("0123", 32);
     d.insert("3210", 16);
@@ -44,7 +51,7 @@ fn test_disk_hash_map_build_mode_persistent() {
 
 #[test]
 fn test_disk_hash_map_above_capacity() {
-    let mut d = DiskHashMap::<u32, 0>::new(1, 1, false);
+    let mut d = DiskHashMap::<u32>::new(1, 1, false);
 
     d.insert("0123", 32);
     d.insert("3210", 16);
@@ -66,7 +73,7 @@ fn test_disk_hash_map_above_capacity() {
 
 #[test]
 fn test_disk_hash_map_various_holes() {
-    let mut d = DiskHashMap::<String, 0>::new(1, 1, false);
+    let mut d = DiskHashMap::<String>::new(1, 1, false);
 
     d.insert("0123", "1".to_string());
     d.insert("3210", "12".to_string());
@@ -92,7 +99,7 @@ fn test_disk_hash_map_various_holes() {
 
 #[test]
 fn test_disk_hash_map_various_holes2() {
-    let mut d = DiskHashMap::<String, 0>::new(3, 3, false);
+    let mut d = DiskHashMap::<String>::new(3, 3, false);
 
     d.insert("1023", "123".to_string());
     d.insert("3210", "12".to_string());
@@ -151,7 +158,7 @@ fn test_disk_hash_map_various_holes2() {
 
 #[test]
 fn test_disk_hash_map_above_capacity_shuffled() {
-    let mut d = DiskHashMap::<u32, 0>::new(1, 1, false);
+    let mut d = DiskHashMap::<u32>::new(1, 1, false);
 
     d.insert("3210", 16);
     d.insert("1021", 2);
@@ -172,7 +179,7 @@ fn test_disk_hash_map_above_capacity_shuffled() {
 
 #[test]
 fn test_disk_hash_map_zero_capacity() {
-    let mut d = DiskHashMap::<u32, 1>::new(0, 0, false);
+    let mut d = DiskHashMap::<u32>::new(0, 0, false);
 
     d.insert("0123", 32);
     d.insert("3210", 16);
@@ -185,7 +192,7 @@ fn test_disk_hash_map_zero_capacity() {
 
 #[test]
 fn test_disk_hash_map_insert_existing() {
-    let mut d = DiskHashMap::<u32, 2>::new(1, 1, false);
+    let mut d = DiskHashMap::<u32>::new(1, 1, false);
 
     d.insert("0123", 32);
     let o = d.insert("0123", 16);
@@ -198,24 +205,77 @@ fn test_disk_hash_map_insert_existing() {
 
 #[test]
 fn test_disk_hash_map_path() {
-    assert_eq!(
-        DiskHashMap::<u32, 4>::path(),
-        PathBuf::from("/tmp/diskhashmap-4")
-    );
-    assert_eq!(
-        DiskHashMap::<u32, 5>::path(),
-        PathBuf::from("/tmp/diskhashmap-5")
-    );
-    assert_eq!(
-        DiskHashMap::<u32, 6>::path(),
-        PathBuf::from("/tmp/diskhashmap-6")
-    );
+    let dir = std::env::temp_dir().join(format!("diskhashmaps-{}", Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+
+    let a = DiskHashMap::<u32>::new_in(&dir, 0, 0, false);
+    let b = DiskHashMap::<u32>::new_in(&dir, 0, 0, false);
+    assert_eq!(a.path().parent(), Some(dir.as_path()));
+    assert_ne!(a.path(), b.path());
+    assert!(a.path().exists());
+
+    // the file goes with the map
+    let path = a.path();
+    drop(a);
+    assert!(!path.exists());
+    assert!(b.path().exists());
+
+    drop(b);
+    fs::remove_dir(&dir).unwrap();
 }
 
+#[test]
+fn test_disk_hash_map_independent_instances() {
+    let mut a = DiskHashMap::<String>::new(0, 0, false);
+    let mut b = DiskHashMap::<String>::new(1, 1, false);
+
+    for i in 0..10 {
+        a.insert(&i.to_string(), format!("a{}", i));
+        b.insert(&i.to_string(), format!("b{}", i));
+    }
+    a.clean_cache();
+
+    // a map built afterwards leaves the others alone
+    let mut c = DiskHashMap::<String>::new(0, 0, false);
+    c.insert("0", "c0".to_string());
+    c.clean_cache();
+
+    for i in 0..10 {
+        assert_eq!(
+            *a.entry(&i.to_string()).unwrap().lock().get().unwrap(),
+            format!("a{}", i)
+        );
+        assert_eq!(
+            *b.entry(&i.to_string()).unwrap().lock().get().unwrap(),
+            format!("b{}", i)
+        );
+    }
+    assert_eq!(*c.entry("0").unwrap().lock().get().unwrap(), "c0");
+    assert_le!(a.cache_population(), 1);
+    assert_le!(b.cache_population(), 2);
+}
+
+#[test]
+fn test_disk_hash_map_entry_outlives_map() {
+    let mut d = DiskHashMap::<u32>::new(1, 1, false);
+    d.insert("0", 3);
+
+    let entry = d.entry("0").unwrap();
+    d.clean_cache();
+    assert!(!entry.lock().is_loaded());
+    let path = d.path();
+    drop(d);
+
+    // the backing file is kept until the last entry is gone
+    assert!(path.exists());
+    assert_eq!(*entry.lock().get().unwrap(), 3);
+    drop(entry);
+    assert!(!path.exists());
+}
 
 #[test]
 fn test_disk_hash_map_clean_cache_cache_pop() {
-    let mut d = DiskHashMap::<u32, 7>::new(2, 2, false);
+    let mut d = DiskHashMap::<u32>::new(2, 2, false);
 
     d.insert("0", 3);
     d.insert("1", 4);
@@ -228,7 +288,7 @@ fn test_disk_hash_map_clean_cache_cache_pop() {
 
 #[test]
 fn test_disk_hash_map_clean_cache_cache_then_retrieve() {
-    let mut d = DiskHashMap::<u32, 7>::new(0, 0, false);
+    let mut d = DiskHashMap::<u32>::new(0, 0, false);
 
     d.insert("0", 3);
     d.insert("1", 2);
@@ -249,13 +309,152 @@ fn test_disk_hash_map_clean_cache_cache_then_retrieve() {
 
 #[test]
 fn test_disk_hash_map_multiple_uses() {
-    drop(DiskHashMap::<u32, 8>::new(0, 0, false));
-    drop(DiskHashMap::<u32, 8>::new(0, 0, false));
-    drop(DiskHashMap::<u32, 8>::new(0, 0, false));
+    drop(DiskHashMap::<u32>::new(0, 0, false));
+    drop(DiskHashMap::<u32>::new(0, 0, false));
+    drop(DiskHashMap::<u32>::new(0, 0, false));
 }
 
 #[test]
 fn test_disk_hash_map_multiple_uses_consecutive() {
-    let _a = DiskHashMap::<u32, 9>::new(0, 0, false);
-    let _b = DiskHashMap::<u32, 9>::new(0, 0, false);
-}
\ No newline at end of file
+    let _a = DiskHashMap::<u32>::new(0, 0, false);
+    let _b = DiskHashMap::<u32>::new(0, 0, false);
+}
+
+#[test]
+fn test_disk_hash_map_disk_stats() {
+    let values = ["a", "bb", "cccc", "dddddddd"];
+    let mut d = DiskHashMap::<String>::new(1, 1, false);
+    for v in values {
+        d.insert(v, v.to_string());
+    }
+    d.clean_cache();
+
+    let total = values.iter().map(|v| serialized_len(v)).sum::<u64>();
+    let stats = d.disk_stats();
+    assert_eq!(stats.file_bytes, total);
+    assert_eq!(stats.live_bytes, total);
+    assert_eq!(stats.free_bytes, 0);
+    assert_eq!(stats.holes, 0);
+    assert_eq!(stats.evictions, 4);
+    assert_eq!(stats.misses, 0);
+
+    // loading leaves a hole behind, a second access is a hit
+    assert_eq!(*d.entry("cccc").unwrap().lock().get().unwrap(), "cccc");
+    assert_eq!(*d.entry("cccc").unwrap().lock().get().unwrap(), "cccc");
+    let stats = d.disk_stats();
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.holes, 1);
+    assert_eq!(stats.free_bytes, serialized_len("cccc"));
+    assert_eq!(stats.live_bytes + stats.free_bytes, stats.file_bytes);
+}
+
+#[test]
+fn test_disk_hash_map_replace_and_remove() {
+    let mut d = DiskHashMap::<String>::new(1, 1, false);
+    for v in ["a", "bb", "cccc"] {
+        d.insert(v, v.to_string());
+    }
+    d.clean_cache();
+
+    // replacing a record on disk frees its space
+    d.insert("bb", "xx".to_string());
+    let stats = d.disk_stats();
+    assert_eq!(stats.free_bytes, serialized_len("bb"));
+    assert_eq!(stats.live_bytes + stats.free_bytes, stats.file_bytes);
+    assert_eq!(d.cache_population(), 1);
+
+    // as does removing it, its slot goes to the next new key
+    assert!(d.remove("cccc"));
+    assert!(!d.remove("cccc"));
+    assert!(d.entry("cccc").is_none());
+    assert_eq!(d.len(), 2);
+    assert_eq!(
+        d.disk_stats().free_bytes,
+        serialized_len("bb") + serialized_len("cccc")
+    );
+
+    d.insert("dddd", "dddd".to_string());
+    assert_eq!(d.len(), 3);
+    assert_eq!(d.keys().iter().map(|(_, i)| *i).max(), Some(2));
+    assert_eq!(*d.entry("dddd").unwrap().lock().get().unwrap(), "dddd");
+    assert_eq!(*d.entry("bb").unwrap().lock().get().unwrap(), "xx");
+    assert_eq!(*d.entry("a").unwrap().lock().get().unwrap(), "a");
+}
+
+#[test]
+fn test_disk_hash_map_compact() {
+    let values = ["a", "bb", "cccc", "dddddddd", "eeeeeeeeeeeeeeee"];
+    let mut d = DiskHashMap::<String>::new(2, 2, false);
+    for v in values {
+        d.insert(v, v.to_string());
+    }
+    d.clean_cache();
+
+    // records grow when written back, so they no longer fit their old place
+    for v in ["bb", "dddddddd"] {
+        let entry = d.entry(v).unwrap();
+        let mut lock = entry.lock();
+        lock.get_mut().unwrap().push_str("grown");
+    }
+    d.clean_cache();
+    assert!(d.disk_stats().free_bytes > 0);
+
+    let stats = d.compact().unwrap();
+    assert_eq!(stats.free_bytes, 0);
+    assert_eq!(stats.holes, 0);
+    assert_eq!(stats.file_bytes, stats.live_bytes);
+    assert_eq!(fs::metadata(d.path()).unwrap().len(), stats.file_bytes);
+
+    for v in values {
+        let expected = match v {
+            "bb" | "dddddddd" => format!("{}grown", v),
+            _ => v.to_string(),
+        };
+        assert_eq!(*d.entry(v).unwrap().lock().get().unwrap(), expected);
+    }
+
+    // the compacted file is used like any other
+    d.clean_cache();
+    assert_eq!(d.disk_stats().live_bytes, stats.live_bytes);
+    assert_eq!(*d.entry("a").unwrap().lock().get().unwrap(), "a");
+}
+
+#[test]
+fn test_disk_hash_map_concurrent_loads() {
+    let values = (0..64).map(|i| "x".repeat(i + 1)).collect::<Vec<_>>();
+    let mut d = DiskHashMap::<String>::new(4, 4, true);
+    for v in &values {
+        d.insert(v, v.clone());
+    }
+    d.set_runtime_mode();
+    d.clean_cache();
+
+    // every thread reads all records in a different order, evicting those of the others on the way
+    let d = Arc::new(d);
+    let values = Arc::new(values);
+    let threads = (0..8)
+        .map(|t| {
+            let (d, values) = (Arc::clone(&d), Arc::clone(&values));
+            thread::spawn(move || {
+                for i in 0..values.len() {
+                    let v = &values[(i * 7 + t) % values.len()];
+                    assert_eq!(*d.entry(v).unwrap().lock().get().unwrap(), *v);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    // records evicted by another thread before they were read are loaded again, but only counted once
+    let stats = d.disk_stats();
+    assert_eq!(stats.hits + stats.misses, 8 * values.len() as u64);
+    assert_le!(d.cache_population(), 4 + 8);
+
+    d.clean_cache();
+    let stats = d.disk_stats();
+    assert_eq!(stats.live_bytes + stats.free_bytes, stats.file_bytes);
+    assert_eq!(*d.entry(&values[10]).unwrap().lock().get().unwrap(), values[10]);
+}
@@ -0,0 +1,225 @@
+use std::{cmp::Reverse, collections::HashMap, env};
+
+use keyed_priority_queue::KeyedPriorityQueue;
+use log::error;
+
+use crate::Priority;
+
+/// decides which of the records a [crate::DiskHashMap] holds in memory is written out next,
+/// and what the capacity of the map is measured in
+pub trait EvictionPolicy: Send {
+    /// a record was brought into memory, `bytes` is its serialized size if [EvictionPolicy::needs_sizes]
+    fn admit(&mut self, id: u32, bytes: u64);
+
+    /// a record in memory was used
+    fn touch(&mut self, id: u32);
+
+    /// a record in memory changed its serialized size to `bytes`, only told if [EvictionPolicy::needs_sizes]
+    fn resize(&mut self, _id: u32, _bytes: u64) {}
+
+    /// a record left memory without being picked as a victim
+    fn remove(&mut self, id: u32);
+
+    /// the record to evict next, it is no longer tracked afterwards
+    fn victim(&mut self) -> Option<u32>;
+
+    /// how much of the capacity the records in memory take up
+    fn usage(&self) -> u64;
+
+    /// true if records have to be measured when admitted
+    fn needs_sizes(&self) -> bool {
+        false
+    }
+}
+
+/// evicts the least recently used record first, capacity counts records
+#[derive(Default)]
+pub struct LruPolicy {
+    clock: u64,
+    queue: KeyedPriorityQueue<u32, Reverse<u64>>,
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn admit(&mut self, id: u32, _bytes: u64) {
+        self.clock += 1;
+        self.queue.push(id, Reverse(self.clock));
+    }
+
+    fn touch(&mut self, id: u32) {
+        self.clock += 1;
+        let _ = self.queue.set_priority(&id, Reverse(self.clock));
+    }
+
+    fn remove(&mut self, id: u32) {
+        self.queue.remove(&id);
+    }
+
+    fn victim(&mut self) -> Option<u32> {
+        self.queue.pop().map(|(id, _)| id)
+    }
+
+    fn usage(&self) -> u64 {
+        self.queue.len() as u64
+    }
+}
+
+/// evicts the least frequently used record first, ties go to the least recently used.
+/// Counts start over whenever a record is loaded back, capacity counts records
+#[derive(Default)]
+pub struct LfuPolicy {
+    clock: u64,
+    queue: KeyedPriorityQueue<u32, (Priority, Reverse<u64>)>,
+}
+
+impl EvictionPolicy for LfuPolicy {
+    fn admit(&mut self, id: u32, _bytes: u64) {
+        self.clock += 1;
+        self.queue.push(id, (0.into(), Reverse(self.clock)));
+    }
+
+    fn touch(&mut self, id: u32) {
+        self.clock += 1;
+        if let Some((uses, _)) = self.queue.get_priority(&id).cloned() {
+            let _ = self
+                .queue
+                .set_priority(&id, (Priority::increase(uses), Reverse(self.clock)));
+        }
+    }
+
+    fn remove(&mut self, id: u32) {
+        self.queue.remove(&id);
+    }
+
+    fn victim(&mut self) -> Option<u32> {
+        self.queue.pop().map(|(id, _)| id)
+    }
+
+    fn usage(&self) -> u64 {
+        self.queue.len() as u64
+    }
+}
+
+/// evicts the least recently used record first, capacity counts the serialized bytes of the records,
+/// measured when brought into memory and again after being changed
+#[derive(Default)]
+pub struct SizeAwarePolicy {
+    recency: LruPolicy,
+    sizes: HashMap<u32, u64>,
+    bytes: u64,
+}
+
+impl SizeAwarePolicy {
+    fn forget(&mut self, id: u32) {
+        if let Some(size) = self.sizes.remove(&id) {
+            self.bytes -= size;
+        }
+    }
+}
+
+impl EvictionPolicy for SizeAwarePolicy {
+    fn admit(&mut self, id: u32, bytes: u64) {
+        self.forget(id);
+        self.sizes.insert(id, bytes);
+        self.bytes += bytes;
+        self.recency.admit(id, bytes);
+    }
+
+    fn touch(&mut self, id: u32) {
+        self.recency.touch(id);
+    }
+
+    fn resize(&mut self, id: u32, bytes: u64) {
+        if let Some(size) = self.sizes.get_mut(&id) {
+            self.bytes = self.bytes - *size + bytes;
+            *size = bytes;
+        }
+    }
+
+    fn remove(&mut self, id: u32) {
+        self.forget(id);
+        self.recency.remove(id);
+    }
+
+    fn victim(&mut self) -> Option<u32> {
+        let id = self.recency.victim()?;
+        self.forget(id);
+        Some(id)
+    }
+
+    fn usage(&self) -> u64 {
+        self.bytes
+    }
+
+    fn needs_sizes(&self) -> bool {
+        true
+    }
+}
+
+/// the eviction policies a map can be created with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    Lru,
+    Lfu,
+    SizeAware,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy::Lru
+    }
+}
+
+impl CachePolicy {
+    pub const NAMES: [&'static str; 3] = ["lru", "lfu", "size"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lru" => Some(CachePolicy::Lru),
+            "lfu" => Some(CachePolicy::Lfu),
+            "size" => Some(CachePolicy::SizeAware),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CachePolicy::Lru => "lru",
+            CachePolicy::Lfu => "lfu",
+            CachePolicy::SizeAware => "size",
+        }
+    }
+
+    /// what the capacity of a map is measured in
+    pub fn unit(&self) -> &'static str {
+        match self {
+            CachePolicy::SizeAware => "bytes",
+            _ => "records",
+        }
+    }
+
+    pub fn create(&self) -> Box<dyn EvictionPolicy> {
+        match self {
+            CachePolicy::Lru => Box::<LruPolicy>::default(),
+            CachePolicy::Lfu => Box::<LfuPolicy>::default(),
+            CachePolicy::SizeAware => Box::<SizeAwarePolicy>::default(),
+        }
+    }
+}
+
+/// the policy maps are created with unless given one, `CACHE_POLICY` if set.
+/// Unknown policies are logged and replaced by the default one
+pub fn default_cache_policy() -> CachePolicy {
+    let name = match env::var("CACHE_POLICY") {
+        Ok(v) => v,
+        Err(_) => return CachePolicy::default(),
+    };
+    CachePolicy::from_name(&name).unwrap_or_else(|| {
+        error!(
+            "Unknown CACHE_POLICY {}, expected one of {:?}, using {}",
+            name,
+            CachePolicy::NAMES,
+            CachePolicy::default().name()
+        );
+        CachePolicy::default()
+    })
+}
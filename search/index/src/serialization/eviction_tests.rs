@@ -0,0 +1,193 @@
+use more_asserts::assert_le;
+
+use crate::{
+    default_disk_hash_map_dir, CachePolicy, DiskHashMap, EvictionPolicy, LfuPolicy, LruPolicy,
+    Serializable, SizeAwarePolicy,
+};
+
+fn victims(policy: &mut dyn EvictionPolicy) -> Vec<u32> {
+    std::iter::from_fn(|| policy.victim()).collect()
+}
+
+#[test]
+fn test_lru_policy() {
+    let mut p = LruPolicy::default();
+    (0..4).for_each(|id| p.admit(id, 0));
+    p.touch(0);
+    p.touch(2);
+    p.remove(3);
+
+    assert_eq!(p.usage(), 3);
+    assert_eq!(victims(&mut p), vec![1, 0, 2]);
+    assert_eq!(p.usage(), 0);
+}
+
+#[test]
+fn test_lfu_policy() {
+    let mut p = LfuPolicy::default();
+    (0..4).for_each(|id| p.admit(id, 0));
+    (0..3).for_each(|_| p.touch(1));
+    p.touch(3);
+    p.touch(0);
+
+    // 2 was never used, 3 was used as often as 0 but longer ago
+    assert_eq!(victims(&mut p), vec![2, 3, 0, 1]);
+
+    // counts start over once loaded back
+    p.admit(1, 0);
+    p.admit(2, 0);
+    p.touch(2);
+    assert_eq!(victims(&mut p), vec![1, 2]);
+}
+
+#[test]
+fn test_size_aware_policy() {
+    let mut p = SizeAwarePolicy::default();
+    p.admit(0, 100);
+    p.admit(1, 10);
+    p.admit(2, 1000);
+    assert_eq!(p.usage(), 1110);
+
+    p.touch(0);
+    assert_eq!(p.victim(), Some(1));
+    assert_eq!(p.usage(), 1100);
+
+    // resizing only affects records which are tracked
+    p.resize(2, 2000);
+    p.resize(1, 50);
+    assert_eq!(p.usage(), 2100);
+
+    // admitting again replaces the old size
+    p.admit(2, 5);
+    assert_eq!(p.usage(), 105);
+    p.remove(0);
+    assert_eq!(p.usage(), 5);
+    assert_eq!(victims(&mut p), vec![2]);
+    assert_eq!(p.usage(), 0);
+}
+
+#[test]
+fn test_cache_policy_names() {
+    for name in CachePolicy::NAMES {
+        assert_eq!(CachePolicy::from_name(name).unwrap().name(), name);
+    }
+    assert_eq!(CachePolicy::from_name("fifo"), None);
+    assert_eq!(CachePolicy::default(), CachePolicy::Lru);
+    assert_eq!(CachePolicy::SizeAware.unit(), "bytes");
+}
+
+fn map(policy: CachePolicy, capacity: u64) -> DiskHashMap<String> {
+    DiskHashMap::with_policy(
+        default_disk_hash_map_dir(),
+        capacity,
+        capacity,
+        false,
+        policy,
+    )
+}
+
+#[test]
+fn test_disk_hash_map_lru_keeps_recently_used() {
+    let mut d = map(CachePolicy::Lru, 2);
+    d.insert("a", "a".to_string());
+    d.insert("b", "b".to_string());
+    d.entry("a").unwrap().lock().get().unwrap();
+    d.insert("c", "c".to_string());
+
+    assert!(d.entry("a").unwrap().lock().is_loaded());
+    assert_eq!(d.disk_stats().evictions, 1);
+    assert_eq!(d.disk_stats().policy, "lru");
+
+    // b went out, so reading it back is a miss
+    assert_eq!(*d.entry("b").unwrap().lock().get().unwrap(), "b");
+    assert_eq!(d.disk_stats().misses, 1);
+}
+
+#[test]
+fn test_disk_hash_map_lfu_keeps_frequently_used() {
+    let mut d = map(CachePolicy::Lfu, 2);
+    d.insert("a", "a".to_string());
+    d.insert("b", "b".to_string());
+    for _ in 0..3 {
+        d.entry("a").unwrap().lock().get().unwrap();
+    }
+    d.entry("b").unwrap().lock().get().unwrap();
+    d.insert("c", "c".to_string());
+    d.insert("d", "d".to_string());
+
+    assert!(d.entry("a").unwrap().lock().is_loaded());
+    assert_eq!(d.cache_population(), 2);
+}
+
+#[test]
+fn test_disk_hash_map_size_aware_counts_bytes() {
+    let value = |n: usize| "x".repeat(n);
+    let size = |n: usize| value(n).serialize(&mut std::io::sink()) as u64;
+
+    let mut d = map(CachePolicy::SizeAware, size(100) + size(10));
+    d.insert("small", value(10));
+    d.insert("large", value(100));
+    assert_eq!(d.cache_population(), 2);
+    assert_eq!(d.cache_usage(), size(100) + size(10));
+
+    // going over by a handful of bytes is enough to evict the least recently used
+    d.insert("tiny", value(1));
+    assert_eq!(d.cache_population(), 2);
+    assert_eq!(d.disk_stats().evictions, 1);
+    assert_eq!(d.cache_usage(), size(100) + size(1));
+    assert_le!(d.cache_usage(), d.capacity());
+
+    // loading back counts the size read from disk
+    d.clean_cache();
+    assert_eq!(d.cache_usage(), 0);
+    assert_eq!(*d.entry("large").unwrap().lock().get().unwrap(), value(100));
+    assert_eq!(d.cache_usage(), size(100));
+}
+
+#[test]
+fn test_disk_hash_map_size_aware_measures_changes() {
+    let value = |n: usize| "x".repeat(n);
+    let size = |n: usize| value(n).serialize(&mut std::io::sink()) as u64;
+
+    let mut d = map(CachePolicy::SizeAware, size(100) + size(10));
+    d.insert("small", value(10));
+    d.insert("grows", value(1));
+
+    // records admitted small and grown in place count with their new size
+    d.entry("grows")
+        .unwrap()
+        .lock()
+        .get_mut()
+        .unwrap()
+        .push_str(&value(99));
+    d.entry("small").unwrap();
+    assert_eq!(d.cache_usage(), size(100) + size(10));
+
+    d.entry("grows")
+        .unwrap()
+        .lock()
+        .get_mut()
+        .unwrap()
+        .push_str(&value(10));
+    d.entry("small").unwrap();
+    assert_eq!(d.cache_population(), 1);
+    assert_le!(d.cache_usage(), d.capacity());
+}
+
+#[test]
+fn test_disk_stats_hit_rate() {
+    let mut d = map(CachePolicy::Lru, 1);
+    assert_eq!(d.disk_stats().hit_rate(), 0.0);
+
+    d.insert("a", "a".to_string());
+    d.insert("b", "b".to_string());
+    d.entry("b").unwrap().lock().get().unwrap();
+    d.entry("b").unwrap().lock().get().unwrap();
+    d.entry("a").unwrap().lock().get().unwrap();
+    d.entry("b").unwrap().lock().get().unwrap();
+
+    let stats = d.disk_stats();
+    assert_eq!((stats.hits, stats.misses), (2, 2));
+    assert_eq!(stats.hit_rate(), 0.5);
+    assert_eq!(stats.cache_usage, 1);
+}
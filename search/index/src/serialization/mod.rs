@@ -1,10 +1,37 @@
+pub mod codecs;
 pub mod disk_backing;
+pub mod eviction;
+pub mod positions;
+pub mod posting_stream;
 pub mod serialization;
+pub mod snapshot;
+pub mod term_usage;
 
 #[cfg(test)]
 pub mod serialization_tests;
 
+#[cfg(test)]
+pub mod codecs_tests;
+
 #[cfg(test)]
 pub mod disk_backing_tests;
 
-pub use {disk_backing::*, serialization::*};
+#[cfg(test)]
+pub mod eviction_tests;
+
+#[cfg(test)]
+pub mod positions_tests;
+
+#[cfg(test)]
+pub mod posting_stream_tests;
+
+#[cfg(test)]
+pub mod snapshot_tests;
+
+#[cfg(test)]
+pub mod term_usage_tests;
+
+pub use {
+    codecs::*, disk_backing::*, eviction::*, positions::*, posting_stream::*, serialization::*,
+    snapshot::*, term_usage::*,
+};
@@ -0,0 +1,145 @@
+use std::io::{Cursor, Read, Write};
+
+use utils::MemFootprintCalculator;
+
+use crate::{Posting, Serializable, VbyteEncoder};
+
+/// how many positions are written between the points a [EncodedPositionList] can be entered at
+pub const POSITION_SKIP_INTERVAL: u32 = 128;
+
+type Vbyte = VbyteEncoder<Posting, false>;
+
+/// the start of a document's positions which can be decoded without anything before it
+#[derive(Default, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct PositionSkip {
+    /// the document whose positions start here
+    pub doc_id: u32,
+    /// the number of positions before it
+    pub index: u32,
+    /// the byte offset of its first position
+    pub offset: u32,
+}
+
+impl Serializable for PositionSkip {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        self.doc_id.serialize(buf) + self.index.serialize(buf) + self.offset.serialize(buf)
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        self.doc_id.deserialize(buf) + self.index.deserialize(buf) + self.offset.deserialize(buf)
+    }
+}
+
+/// the positions of a token, a document at a time in the order of its document list.
+/// The first position of each document is stored as is and the rest as vbyte gaps, documents are not
+/// recorded so the list is only readable alongside the documents and their term frequencies
+#[derive(Default, Eq, PartialEq, Debug, Clone)]
+pub struct EncodedPositionList {
+    bytes: Vec<u8>,
+    /// a document start every [POSITION_SKIP_INTERVAL] positions or so
+    skips: Vec<PositionSkip>,
+    /// positions written so far
+    count: u32,
+    /// positions written since the last skip
+    since_skip: u32,
+}
+
+impl EncodedPositionList {
+    /// appends the sorted positions of the next document
+    pub fn push_document(&mut self, doc_id: u32, positions: &[u32]) {
+        if self.since_skip >= POSITION_SKIP_INTERVAL {
+            self.skips.push(PositionSkip {
+                doc_id,
+                index: self.count,
+                offset: self.bytes.len() as u32,
+            });
+            self.since_skip = 0;
+        }
+
+        let mut prev = 0;
+        for p in positions {
+            Vbyte::into_vbyte_serialise(p - prev, &mut self.bytes);
+            prev = *p;
+        }
+        self.count += positions.len() as u32;
+        self.since_skip += positions.len() as u32;
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// the number of positions in the list
+    pub fn len(&self) -> u32 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// the last skip at or before the given document
+    pub fn skip_before(&self, doc_id: u32) -> Option<PositionSkip> {
+        let i = self.skips.partition_point(|s| s.doc_id <= doc_id);
+        i.checked_sub(1).map(|i| self.skips[i])
+    }
+
+    /// the byte offset `n` positions after the one at `offset`, without decoding them
+    pub fn offset_after(&self, offset: usize, n: u32) -> usize {
+        let mut offset = offset;
+        let mut left = n;
+        while left > 0 {
+            // the last byte of each value has its continuation bit cleared
+            if self.bytes[offset] & 128 == 0 {
+                left -= 1;
+            }
+            offset += 1;
+        }
+        offset
+    }
+
+    /// decodes the `n` positions of a document starting at `offset`.
+    /// Returns the offset of the next document
+    pub fn decode<X: Extend<u32>>(&self, offset: usize, n: u32, out: &mut X) -> usize {
+        let mut cursor = Cursor::new(&self.bytes[..]);
+        cursor.set_position(offset as u64);
+
+        let mut offset = offset;
+        let mut position = 0;
+        for _ in 0..n {
+            let (gap, count) = Vbyte::from_vbyte_deserialise(&mut cursor);
+            position += gap;
+            offset += count;
+            out.extend(Some(position));
+        }
+        offset
+    }
+}
+
+impl MemFootprintCalculator for EncodedPositionList {
+    fn real_mem(&self) -> u64 {
+        self.bytes.len() as u64 + self.skips.len() as u64 * 12 + 8
+    }
+}
+
+impl Serializable for EncodedPositionList {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        count += self.bytes.serialize(buf);
+        count += self.skips.serialize(buf);
+        count += self.count.serialize(buf);
+        count += self.since_skip.serialize(buf);
+
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        let mut count = 0;
+        count += self.bytes.deserialize(buf);
+        count += self.skips.deserialize(buf);
+        count += self.count.deserialize(buf);
+        count += self.since_skip.deserialize(buf);
+
+        count
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::Cursor;
+
+use crate::{EncodedPositionList, Serializable, POSITION_SKIP_INTERVAL};
+
+/// document d holds the positions 0, 3, 6 .. for d % 5 + 1 of them
+fn position_documents() -> Vec<(u32, Vec<u32>)> {
+    (0..200u32)
+        .map(|d| (d * 3, (0..d % 5 + 1).map(|p| p * 3 + d).collect()))
+        .collect()
+}
+
+fn encoded() -> EncodedPositionList {
+    let mut o = EncodedPositionList::default();
+    position_documents()
+        .iter()
+        .for_each(|(d, ps)| o.push_document(*d, ps));
+    o
+}
+
+#[test]
+fn test_positions_round_trip() {
+    let o = encoded();
+
+    let mut offset = 0;
+    for (_, positions) in position_documents() {
+        let mut decoded = Vec::default();
+        offset = o.decode(offset, positions.len() as u32, &mut decoded);
+        assert_eq!(decoded, positions);
+    }
+    assert_eq!(offset, o.byte_len());
+    assert_eq!(o.len(), 600);
+}
+
+#[test]
+fn test_positions_skip_before() {
+    let o = encoded();
+    let documents = position_documents();
+
+    assert_eq!(o.skip_before(0), None);
+    for target in [0, 150, 299, 300, 301, 597, 1000] {
+        let skip = match o.skip_before(target) {
+            Some(v) => v,
+            None => continue,
+        };
+        assert!(skip.doc_id <= target);
+
+        // the skip lands on the start of its document
+        let before = documents
+            .iter()
+            .take_while(|(d, _)| *d < skip.doc_id)
+            .map(|(_, ps)| ps.len() as u32)
+            .sum::<u32>();
+        let (_, expected) = documents.iter().find(|(d, _)| *d == skip.doc_id).unwrap();
+        let mut decoded = Vec::default();
+        o.decode(skip.offset as usize, expected.len() as u32, &mut decoded);
+
+        assert_eq!(skip.index, before);
+        assert_eq!(&decoded, expected);
+        assert!(skip.index >= POSITION_SKIP_INTERVAL);
+    }
+    assert!(o.skip_before(1000).is_some());
+}
+
+#[test]
+fn test_positions_offset_after() {
+    let o = encoded();
+    let documents = position_documents();
+
+    let n = documents[..10].iter().map(|(_, ps)| ps.len() as u32).sum();
+    let mut decoded = Vec::default();
+    o.decode(
+        o.offset_after(0, n),
+        documents[10].1.len() as u32,
+        &mut decoded,
+    );
+
+    assert_eq!(decoded, documents[10].1);
+    assert_eq!(o.offset_after(0, o.len()), o.byte_len());
+}
+
+#[test]
+fn test_positions_serialized() {
+    let mut o = encoded();
+
+    let mut buffer = Vec::default();
+    o.serialize(&mut buffer);
+    let mut deserialized = EncodedPositionList::default();
+    deserialized.deserialize(&mut Cursor::new(buffer));
+    assert_eq!(deserialized, o);
+
+    // carries on where it was left
+    deserialized.push_document(1000, &[1, 2]);
+    o.push_document(1000, &[1, 2]);
+    assert_eq!(deserialized, o);
+}
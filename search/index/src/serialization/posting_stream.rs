@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{
+    DocumentFrequency, EncodedPostingNode, Entry, Posting, PostingCodec, SkipEncoder, SkipIterator,
+};
+
+/// how many documents are decoded each time a stream locks its entry
+pub const STREAM_BATCH_SIZE: usize = 256;
+
+/// decodes the documents of a node a batch at a time without touching their positions, holding on to its entry
+/// instead of a copy of the documents. The entry is only locked while decoding, it can be evicted in between
+/// and is loaded again when needed
+pub struct DocumentStream<E: SkipEncoder = PostingCodec> {
+    entry: Arc<Mutex<Entry<EncodedPostingNode<E>>>>,
+    decoder: E,
+    /// the first byte which hasn't been decoded yet
+    pos: usize,
+    /// decoded documents which haven't been taken yet, with the term frequency as position
+    buffer: VecDeque<Posting>,
+    finished: bool,
+}
+
+impl<E: SkipEncoder> DocumentStream<E> {
+    pub fn new(entry: Arc<Mutex<Entry<EncodedPostingNode<E>>>>) -> Self {
+        let decoder = entry.lock().get().unwrap().documents.encoder().fresh();
+        Self {
+            entry,
+            decoder,
+            pos: 0,
+            buffer: VecDeque::with_capacity(STREAM_BATCH_SIZE),
+            finished: false,
+        }
+    }
+
+    /// the number of documents in the whole node, including those already read
+    pub fn df(&self) -> u32 {
+        self.entry.lock().get().unwrap().df
+    }
+
+    /// the number of postings in the whole node, including those already read
+    pub fn postings_count(&self) -> u32 {
+        self.entry.lock().get().unwrap().postings_count
+    }
+
+    /// the next document, without moving past it
+    pub fn peek(&mut self) -> Option<DocumentFrequency> {
+        if self.buffer.is_empty() {
+            self.fill(None);
+        }
+        self.buffer.front().map(|p| (*p).into())
+    }
+
+    /// moves up to the first document at or after the given one, without moving past it
+    pub fn seek(&mut self, doc_id: u32) -> Option<DocumentFrequency> {
+        let mut target = Some(doc_id);
+        loop {
+            while let Some(p) = self.buffer.front() {
+                if p.document_id >= doc_id {
+                    return Some((*p).into());
+                }
+                self.buffer.pop_front();
+            }
+
+            if !self.fill(target.take()) {
+                return None;
+            }
+        }
+    }
+
+    /// decodes the next batch into the empty buffer, jumping ahead to the block holding `target` if given.
+    /// False once there is nothing left
+    fn fill(&mut self, target: Option<u32>) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        let mut lock = self.entry.lock();
+        let documents = &lock.get().unwrap().documents;
+
+        if let Some(skip) = target.and_then(|t| documents.skip_pointer_before(t)) {
+            if skip.offset as usize > self.pos {
+                self.pos = skip.offset as usize;
+                self.decoder.resume_from(&skip);
+            }
+        }
+
+        self.pos = documents.decode_from(
+            self.pos,
+            &mut self.decoder,
+            STREAM_BATCH_SIZE,
+            &mut self.buffer,
+        );
+        self.finished = self.pos >= documents.byte_len();
+
+        !self.buffer.is_empty()
+    }
+}
+
+impl<E: SkipEncoder> Iterator for DocumentStream<E> {
+    type Item = DocumentFrequency;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peek()?;
+        self.buffer.pop_front().map(|p| p.into())
+    }
+}
+
+/// the postings of a node with their positions, walks the documents through a [DocumentStream] and only
+/// decodes the positions of documents it stops at
+pub struct PostingStream<E: SkipEncoder = PostingCodec> {
+    entry: Arc<Mutex<Entry<EncodedPostingNode<E>>>>,
+    documents: DocumentStream<E>,
+    /// the number of positions before those of the next document in `documents`
+    index: u32,
+    /// a position index along with its byte offset, the closest known one at or before `index`
+    anchor: (u32, usize),
+    /// postings of the current document which haven't been taken yet
+    buffer: VecDeque<Posting>,
+}
+
+impl<E: SkipEncoder> PostingStream<E> {
+    pub fn new(entry: Arc<Mutex<Entry<EncodedPostingNode<E>>>>) -> Self {
+        Self {
+            documents: DocumentStream::new(entry.clone()),
+            entry,
+            index: 0,
+            anchor: (0, 0),
+            buffer: VecDeque::default(),
+        }
+    }
+
+    /// the number of postings in the whole node, including those already read
+    pub fn postings_count(&self) -> u32 {
+        self.documents.postings_count()
+    }
+
+    /// the next posting, without moving past it
+    pub fn peek(&mut self) -> Option<Posting> {
+        if self.buffer.is_empty() {
+            self.next_document();
+        }
+        self.buffer.front().cloned()
+    }
+
+    /// moves up to the first posting of the first document at or after the given one, without moving past it
+    pub fn seek(&mut self, doc_id: u32) -> Option<Posting> {
+        match self.buffer.front() {
+            Some(p) if p.document_id >= doc_id => return Some(*p),
+            _ => self.buffer.clear(),
+        }
+
+        // jumps to the closest document start the position list knows of
+        let skip = self
+            .entry
+            .lock()
+            .get()
+            .unwrap()
+            .positions
+            .skip_before(doc_id);
+        if let Some(skip) = skip.filter(|s| s.index > self.index) {
+            self.documents.seek(skip.doc_id);
+            self.index = skip.index;
+            self.anchor = (skip.index, skip.offset as usize);
+        }
+
+        // documents on the way are passed over without decoding their positions
+        while let Some(d) = self.documents.peek() {
+            if d.document_id >= doc_id {
+                break;
+            }
+            self.documents.next();
+            self.index += d.tf;
+        }
+
+        self.peek()
+    }
+
+    /// decodes the positions of the next document into the empty buffer
+    fn next_document(&mut self) {
+        let document = match self.documents.next() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let mut lock = self.entry.lock();
+        let positions = &lock.get().unwrap().positions;
+
+        let offset = positions.offset_after(self.anchor.1, self.index - self.anchor.0);
+        let end = positions.decode(
+            offset,
+            document.tf,
+            &mut Extender(&mut self.buffer, document.document_id),
+        );
+
+        self.index += document.tf;
+        self.anchor = (self.index, end);
+    }
+}
+
+/// pairs decoded positions with their document
+struct Extender<'a>(&'a mut VecDeque<Posting>, u32);
+
+impl Extend<u32> for Extender<'_> {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        let document_id = self.1;
+        self.0.extend(iter.into_iter().map(|position| Posting {
+            document_id,
+            position,
+        }));
+    }
+}
+
+impl<E: SkipEncoder> Iterator for PostingStream<E> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peek()?;
+        self.buffer.pop_front()
+    }
+}
+
+impl<E: SkipEncoder> SkipIterator for PostingStream<E> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        self.seek(doc_id)?;
+        self.buffer.pop_front()
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    get_document_with_text, DiskHashMap, DocumentFrequency, EncodedPostingNode, Index, Posting,
+    PostingStream,
+    PreIndex, SkipIterator, VbyteEncoder, STREAM_BATCH_SIZE,
+};
+
+/// a token spread over enough documents to need several batches and skip pointers
+fn stream_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    for d in 0..600u32 {
+        let text = vec!["streamtoken"; (d % 3 + 1) as usize].join(" ");
+        pre_idx
+            .add_document(get_document_with_text(
+                d * 2,
+                "stream",
+                vec![],
+                &text,
+                vec![],
+                "",
+            ))
+            .unwrap();
+    }
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn decoded(idx: &Index, token: &str) -> Vec<Posting> {
+    idx.get_postings(token)
+        .unwrap()
+        .lock()
+        .get()
+        .unwrap()
+        .postings()
+        .into_iter()
+        .collect()
+}
+
+#[test]
+fn test_posting_stream_matches_decoder() {
+    let idx = stream_index();
+    let postings = decoded(&idx, "streamtoken");
+
+    assert!(postings.len() > 2 * STREAM_BATCH_SIZE);
+    assert_eq!(
+        idx.stream_postings("streamtoken")
+            .unwrap()
+            .collect::<Vec<Posting>>(),
+        postings
+    );
+    assert!(idx.stream_postings("streammissing").is_none());
+}
+
+#[test]
+fn test_posting_stream_seek_and_skip() {
+    let idx = stream_index();
+    let postings = decoded(&idx, "streamtoken");
+    let mut stream = idx.stream_postings("streamtoken").unwrap();
+
+    assert_eq!(stream.peek(), Some(postings[0]));
+    assert_eq!(stream.seek(0), Some(postings[0]));
+
+    // odd documents don't have the token, so seeking lands on the next one
+    let start = postings.iter().position(|p| p.document_id >= 301).unwrap();
+    assert_eq!(stream.seek(301), Some(postings[start]));
+    assert_eq!(stream.seek(301), Some(postings[start]));
+    assert_eq!(stream.next(), Some(postings[start]));
+
+    let start = postings.iter().position(|p| p.document_id >= 900).unwrap();
+    assert_eq!(stream.skip_to(900), Some(postings[start]));
+    assert_eq!(
+        stream.collect::<Vec<Posting>>(),
+        postings[start + 1..].to_vec()
+    );
+
+    let mut stream = idx.stream_postings("streamtoken").unwrap();
+    assert_eq!(stream.skip_to(5000), None);
+    assert_eq!(stream.next(), None);
+}
+
+#[test]
+fn test_posting_stream_survives_eviction() {
+    let postings = (0..1000u32)
+        .map(|d| Posting {
+            document_id: d,
+            position: d % 4,
+        })
+        .collect::<Vec<Posting>>();
+
+    let mut d = DiskHashMap::<EncodedPostingNode<VbyteEncoder<Posting, true>>>::new(1, 1, false);
+    d.insert("long", postings.clone().into_iter().collect());
+    d.insert("other", postings[..10].iter().cloned().collect());
+
+    let mut stream = PostingStream::new(d.entry("long").unwrap());
+    let mut streamed = stream.by_ref().take(300).collect::<Vec<Posting>>();
+
+    // loading the other entry pushes the streamed one out to disk
+    d.entry("other").unwrap().lock().get().unwrap();
+    d.clean_cache();
+
+    streamed.extend(stream);
+    assert_eq!(streamed, postings);
+}
+
+#[test]
+fn test_document_stream() {
+    let idx = stream_index();
+    let expected = (0..600u32)
+        .map(|d| DocumentFrequency {
+            document_id: d * 2,
+            tf: d % 3 + 1,
+        })
+        .collect::<Vec<DocumentFrequency>>();
+
+    let stream = idx.stream_documents("streamtoken").unwrap();
+    assert_eq!(stream.df(), 600);
+    assert_eq!(stream.postings_count(), 1200);
+    assert_eq!(stream.collect::<Vec<DocumentFrequency>>(), expected);
+
+    let mut stream = idx.stream_documents("streamtoken").unwrap();
+    assert_eq!(stream.seek(301), Some(expected[151]));
+    assert_eq!(stream.next(), Some(expected[151]));
+    assert_eq!(stream.seek(1100), Some(expected[550]));
+    assert_eq!(stream.seek(5000), None);
+    assert!(idx.stream_documents("streammissing").is_none());
+}
+
+#[test]
+fn test_posting_stream_seeks_past_position_skips() {
+    let idx = stream_index();
+    let postings = decoded(&idx, "streamtoken");
+    let mut stream = idx.stream_postings("streamtoken").unwrap();
+
+    // targets far enough apart to jump over whole runs of positions
+    for target in [3, 5, 260, 261, 700, 702, 703, 1001, 1198] {
+        let expected = postings.iter().find(|p| p.document_id >= target).cloned();
+        assert_eq!(stream.seek(target), expected, "seek to {}", target);
+
+        // the rest of the document follows
+        let doc = expected.unwrap().document_id;
+        let rest = postings
+            .iter()
+            .filter(|p| p.document_id == doc)
+            .cloned()
+            .collect::<Vec<Posting>>();
+        let taken = std::iter::from_fn(|| stream.next())
+            .take(rest.len())
+            .collect::<Vec<Posting>>();
+        assert_eq!(taken, rest);
+    }
+}
+
+#[test]
+fn test_positions_within() {
+    let idx = stream_index();
+    let positions = idx.positions_within("streamtoken", vec![900, 4, 3, 1198]);
+
+    assert_eq!(positions.len(), 3);
+    assert_eq!(positions[&4], vec![0, 1, 2]);
+    assert_eq!(positions[&900], vec![0]);
+    assert_eq!(positions[&1198], vec![0, 1, 2]);
+    assert!(idx.positions_within("streammissing", vec![4]).is_empty());
+}
@@ -1,4 +1,7 @@
-use crate::{EncodedPostingNode, LastUpdatedDate, PosRange, Posting, PostingNode};
+use crate::{
+    DocumentMetaData, EncodedPostingNode, IndexError, LastUpdatedDate, PosRange, Posting,
+    PostingNode,
+};
 use byteorder::{LittleEndian, NativeEndian, ReadBytesExt, WriteBytesExt};
 use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 use core::fmt::Debug;
@@ -20,6 +23,25 @@ use utils::MemFootprintCalculator;
 pub trait SequentialEncoder<T: Serializable>: Serializable {
     fn encode<W: Write>(&mut self, curr: &T, out: &mut W) -> usize;
     fn decode<R: Read>(&mut self, bytes: &mut R) -> (T, usize);
+
+    /// writes out anything held back by [SequentialEncoder::encode], objects are only decodable once finished.
+    /// Encoding may carry on afterwards
+    fn finish<W: Write>(&mut self, _out: &mut W) -> usize {
+        0
+    }
+
+    /// an encoder of the same kind in its initial state, used to decode what this one encoded
+    fn fresh(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// an encoder which keeps track of points decoding can resume from
+pub trait SkipEncoder: SequentialEncoder<Posting> {
+    fn skip_pointers(&self) -> &[SkipPointer];
+
+    /// puts a decoder into the state it had just before the posting at the skip pointer
+    fn resume_from(&mut self, skip: &SkipPointer);
 }
 
 /// An compact representation of an encodable object, requires a [SequentialEncoder] object
@@ -61,10 +83,37 @@ impl<E: SequentialEncoder<T>, T: Serializable> EncodedSequentialObject<T, E> {
         }
     }
 
+    /// an empty object which encodes with the given encoder
+    pub fn with_encoder(encoder: E) -> Self {
+        Self::new(Vec::default(), encoder)
+    }
+
     pub fn byte_len(&self) -> usize {
         self.bytes.len()
     }
 
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    /// decodes up to `n` objects starting at byte `pos`, carrying on from the state the decoder was left in.
+    /// Returns the position of the first byte which wasn't decoded
+    pub fn decode_from<X: Extend<T>>(&self, pos: usize, decoder: &mut E, n: usize, out: &mut X) -> usize {
+        let mut cursor = Cursor::new(&self.bytes[..]);
+        cursor.set_position(pos as u64);
+
+        let mut pos = pos;
+        for _ in 0..n {
+            if pos >= self.bytes.len() {
+                break;
+            }
+            let (o, count) = decoder.decode(&mut cursor);
+            pos += count;
+            out.extend(Some(o));
+        }
+        pos
+    }
+
     /// encodes without using a previous node
     /// should only be used with encoders which don't require
     /// the previous object
@@ -72,6 +121,11 @@ impl<E: SequentialEncoder<T>, T: Serializable> EncodedSequentialObject<T, E> {
         // let mut c = Cursor::new(&mut self.bytes);
         self.encoder.encode(&n, &mut self.bytes);
     }
+
+    /// writes out whatever the encoder is holding back, needed before decoding
+    pub fn finish(&mut self) {
+        self.encoder.finish(&mut self.bytes);
+    }
 }
 
 /// An convenience iterator for decoding [EncodedSequentialObject]'s can be created with
@@ -83,7 +137,7 @@ where
     T: Serializable,
 {
     /// the underlying encoded object
-    // o: &'a EncodedSequentialObject<T, E>,
+    o: &'a EncodedSequentialObject<T, E>,
     cursor: Cursor<&'a [u8]>,
     decoder: E,
 
@@ -94,7 +148,7 @@ where
 
 impl<T: SequentialEncoder<Posting>> MemFootprintCalculator for EncodedPostingNode<T> {
     fn real_mem(&self) -> u64 {
-        self.postings.real_mem() + self.tf.real_mem() + self.df.real_mem()
+        self.documents.real_mem() + self.positions.real_mem() + self.df.real_mem()
     }
 }
 
@@ -122,18 +176,64 @@ where
     }
 }
 
+/// a stream of postings in document order which can jump ahead
+pub trait SkipIterator: Iterator<Item = Posting> {
+    /// the first posting of the first document at or after the given one, everything before it is passed over.
+    /// Only ever moves forward
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        while let Some(p) = self.next() {
+            if p.document_id >= doc_id {
+                return Some(p);
+            }
+        }
+        None
+    }
+}
+
+impl SkipIterator for std::vec::IntoIter<Posting> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        let skipped = self.as_slice().partition_point(|p| p.document_id < doc_id);
+        self.nth(skipped)
+    }
+}
+
+impl<E: SkipEncoder> EncodedSequentialObject<Posting, E> {
+    /// the start of the last block which only follows documents before the given one
+    pub fn skip_pointer_before(&self, doc_id: u32) -> Option<SkipPointer> {
+        let skips = self.encoder.skip_pointers();
+        let block = skips.partition_point(|s| s.doc_id < doc_id);
+        block.checked_sub(1).map(|i| skips[i])
+    }
+}
+
+/// jumps to the last block starting before the target using the skip pointers, then decodes from there
+impl<E: SkipEncoder> SkipIterator for DecoderIterator<'_, Posting, E> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        if let Some(skip) = self.o.skip_pointer_before(doc_id) {
+            // blocks already passed are never revisited
+            if skip.offset as usize > self.pos {
+                self.pos = skip.offset as usize;
+                self.cursor.set_position(skip.offset as u64);
+                self.decoder.resume_from(&skip);
+            }
+        }
+
+        while let Some(p) = self.next() {
+            if p.document_id >= doc_id {
+                return Some(p);
+            }
+        }
+        None
+    }
+}
+
 /// encoding
 impl<E: SequentialEncoder<T>, T: Serializable> FromIterator<T> for EncodedSequentialObject<T, E> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
-        let mut e = E::default();
-
-        let mut b = Vec::default();
-        iter.into_iter().fold(None, |a, v| {
-            e.encode(&v, &mut b);
-            Some(v)
-        });
-
-        EncodedSequentialObject::new(b.to_owned(), e)
+        let mut o = EncodedSequentialObject::with_encoder(E::default());
+        iter.into_iter().for_each(|v| o.push(v));
+        o.finish();
+        o
     }
 }
 
@@ -149,8 +249,9 @@ where
 
     fn into_iter(self) -> Self::IntoIter {
         Self::IntoIter {
+            o: self,
             pos: 0,
-            decoder: Default::default(),
+            decoder: self.encoder.fresh(),
             cursor: Cursor::new(&self.bytes),
             _ph: PhantomData::default(),
         }
@@ -174,11 +275,15 @@ where
     }
 
     fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
-        let len = buf.read_u32::<NativeEndian>().unwrap();
+        self.try_deserialize(buf).unwrap()
+    }
+
+    fn try_deserialize<R: Read>(&mut self, buf: &mut R) -> Result<usize, IndexError> {
+        let len = buf.read_u32::<NativeEndian>()?;
         for _ in 0..len {
-            self.bytes.push(buf.read_u8().unwrap());
+            self.bytes.push(buf.read_u8()?);
         }
-        self.encoder.deserialize(buf) + self.bytes.len() + 4
+        Ok(self.encoder.try_deserialize(buf)? + self.bytes.len() + 4)
     }
 }
 
@@ -287,17 +392,45 @@ impl DeltaEncoder {
 //     }
 // }
 
+/// roughly how many postings are encoded between skip pointers
+pub const SKIP_INTERVAL: u32 = 128;
+
+/// a point decoding can resume from, always the first posting of a document
+#[derive(Default, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct SkipPointer {
+    /// the document of the posting just before the offset
+    pub doc_id: u32,
+    /// the byte offset of the posting
+    pub offset: u32,
+}
+
+impl Serializable for SkipPointer {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        self.doc_id.serialize(buf) + self.offset.serialize(buf)
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        self.doc_id.deserialize(buf) + self.offset.deserialize(buf)
+    }
+}
+
 // B is a boolean flag to indiciate if we want to apply delta encoding or not
-#[derive(Default, Eq, PartialEq, Debug)]
+#[derive(Default, Eq, PartialEq, Debug, Clone)]
 pub struct VbyteEncoder<T: Serializable, const B: bool> {
     prev: Option<T>,
     in_streak: bool,
+    /// written every [SKIP_INTERVAL] postings at the next new document
+    skips: Vec<SkipPointer>,
+    /// bytes encoded so far
+    written: u32,
+    /// postings encoded since the last skip pointer
+    since_skip: u32,
 }
 
 impl<T: Serializable, const B: bool> VbyteEncoder<T, B> {
     #[cfg(target_endian = "little")]
     #[inline(always)]
-    fn into_vbyte_serialise<W: Write>(mut num: u32, out: &mut W) -> usize {
+    pub(crate) fn into_vbyte_serialise<W: Write>(mut num: u32, out: &mut W) -> usize {
         let mut count = 0;
         // on little endian, we take from the left so decoding is natural
         let mut curr_byte;
@@ -320,7 +453,7 @@ impl<T: Serializable, const B: bool> VbyteEncoder<T, B> {
     }
 
     #[inline(always)]
-    fn from_vbyte_deserialise<R: Read>(bytes: &mut R) -> (u32, usize) {
+    pub(crate) fn from_vbyte_deserialise<R: Read>(bytes: &mut R) -> (u32, usize) {
         let mut result: u32 = 0;
         let mut byte_total_count: usize = 0;
         let mut shift = 0;
@@ -364,6 +497,18 @@ impl<const B: bool> SequentialEncoder<Posting> for VbyteEncoder<Posting, B> {
             .map(|v| v.document_id != curr.document_id)
             .unwrap_or(true);
 
+        // a new document past the interval starts a block decoding can jump to,
+        // the offset lies past the end of streak marker so decoding resumes outside of a streak
+        if encode_doc_id && self.since_skip >= SKIP_INTERVAL {
+            if let Some(prev) = self.prev {
+                self.skips.push(SkipPointer {
+                    doc_id: prev.document_id,
+                    offset: self.written + self.in_streak as u32,
+                });
+                self.since_skip = 0;
+            }
+        }
+
         let mut count = 0;
 
         //Apply vbyte encoding
@@ -385,6 +530,8 @@ impl<const B: bool> SequentialEncoder<Posting> for VbyteEncoder<Posting, B> {
         count += Self::into_vbyte_serialise(to_encode.1, out);
 
         self.prev = Some(*curr);
+        self.written += count as u32;
+        self.since_skip += 1;
         count
     }
     #[inline(always)]
@@ -445,11 +592,29 @@ impl<const B: bool> SequentialEncoder<Posting> for VbyteEncoder<Posting, B> {
     }
 }
 
+impl<const B: bool> SkipEncoder for VbyteEncoder<Posting, B> {
+    fn skip_pointers(&self) -> &[SkipPointer] {
+        &self.skips
+    }
+
+    fn resume_from(&mut self, skip: &SkipPointer) {
+        // positions are only relative within a document, so the previous one does not matter
+        self.prev = Some(Posting {
+            document_id: skip.doc_id,
+            position: 0,
+        });
+        self.in_streak = false;
+    }
+}
+
 impl<T: Serializable, const B: bool> Serializable for VbyteEncoder<T, B> {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
         let mut count = 0;
         count += self.prev.serialize(buf);
         count += self.in_streak.serialize(buf);
+        count += self.skips.serialize(buf);
+        count += self.written.serialize(buf);
+        count += self.since_skip.serialize(buf);
         count
     }
 
@@ -457,6 +622,9 @@ impl<T: Serializable, const B: bool> Serializable for VbyteEncoder<T, B> {
         let mut count = 0;
         count += self.prev.deserialize(buf);
         count += self.in_streak.deserialize(buf);
+        count += self.skips.deserialize(buf);
+        count += self.written.deserialize(buf);
+        count += self.since_skip.deserialize(buf);
         count
     }
 }
@@ -541,6 +709,12 @@ pub trait Serializable: Default {
 
     fn serialize<W: Write>(&self, buf: &mut W) -> usize;
     fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize;
+
+    /// like `deserialize`, but fails on bytes which don't make up a known value instead of panicking.
+    /// Only needs implementing by types which can come across such bytes, and those holding them
+    fn try_deserialize<R: Read>(&mut self, buf: &mut R) -> Result<usize, IndexError> {
+        Ok(self.deserialize(buf))
+    }
 }
 
 impl<T: Serializable> Serializable for RefCell<T> {
@@ -577,6 +751,30 @@ impl Serializable for u32 {
     }
 }
 
+impl Serializable for u64 {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        buf.write_u64::<NativeEndian>(*self).unwrap();
+        8
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        *self = buf.read_u64::<NativeEndian>().unwrap();
+        8
+    }
+}
+
+impl Serializable for f64 {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        buf.write_f64::<NativeEndian>(*self).unwrap();
+        8
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        *self = buf.read_f64::<NativeEndian>().unwrap();
+        8
+    }
+}
+
 impl Serializable for i32 {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
         buf.write_i32::<NativeEndian>(*self).unwrap();
@@ -589,6 +787,18 @@ impl Serializable for i32 {
     }
 }
 
+impl Serializable for i16 {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        buf.write_i16::<NativeEndian>(*self).unwrap();
+        2
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        *self = buf.read_i16::<NativeEndian>().unwrap();
+        2
+    }
+}
+
 impl Serializable for u16 {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
         buf.write_u16::<NativeEndian>(*self).unwrap();
@@ -630,41 +840,41 @@ impl<N: Serializable, M: Serializable> Serializable for (N, M) {
     }
 }
 
-// impl Serializable for LastUpdatedDate {
-//     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
-//         let mut count = 0;
-//         buf.write_i32::<NativeEndian>(self.date_time.date().year() as i32) //year is defined as i32
-//             .unwrap();
-//         count += 4;
-//         buf.write_u8(self.date_time.date().month() as u8).unwrap();
-//         count += 1;
-//         buf.write_u8(self.date_time.date().day() as u8).unwrap();
-//         count += 1;
-//         buf.write_u8(self.date_time.time().hour() as u8).unwrap();
-//         count += 1;
-//         buf.write_u8(self.date_time.time().minute() as u8).unwrap();
-//         count += 1;
-//         buf.write_u8(self.date_time.time().second() as u8).unwrap();
-//         count += 1;
-
-//         count
-//     }
+impl Serializable for LastUpdatedDate {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        buf.write_i32::<NativeEndian>(self.date_time.date().year() as i32) //year is defined as i32
+            .unwrap();
+        count += 4;
+        buf.write_u8(self.date_time.date().month() as u8).unwrap();
+        count += 1;
+        buf.write_u8(self.date_time.date().day() as u8).unwrap();
+        count += 1;
+        buf.write_u8(self.date_time.time().hour() as u8).unwrap();
+        count += 1;
+        buf.write_u8(self.date_time.time().minute() as u8).unwrap();
+        count += 1;
+        buf.write_u8(self.date_time.time().second() as u8).unwrap();
+        count += 1;
 
-//     fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
-//         let year = buf.read_i32::<NativeEndian>().unwrap();
-//         let month = buf.read_u8().unwrap();
-//         let day = buf.read_u8().unwrap();
-
-//         let hour = buf.read_u8().unwrap();
-//         let min = buf.read_u8().unwrap();
-//         let sec = buf.read_u8().unwrap();
-
-//         let d = NaiveDate::from_ymd(year, month as u32, day as u32);
-//         let t = NaiveTime::from_hms(hour as u32, min as u32, sec as u32);
-//         self.date_time = NaiveDateTime::new(d, t);
-//         9
-//     }
-// }
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        let year = buf.read_i32::<NativeEndian>().unwrap();
+        let month = buf.read_u8().unwrap();
+        let day = buf.read_u8().unwrap();
+
+        let hour = buf.read_u8().unwrap();
+        let min = buf.read_u8().unwrap();
+        let sec = buf.read_u8().unwrap();
+
+        let d = NaiveDate::from_ymd(year, month as u32, day as u32);
+        let t = NaiveTime::from_hms(hour as u32, min as u32, sec as u32);
+        self.date_time = NaiveDateTime::new(d, t);
+        9
+    }
+}
 
 impl Serializable for String {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
@@ -676,9 +886,10 @@ impl Serializable for String {
 
     fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
         let len = buf.read_u32::<NativeEndian>().unwrap();
-        for _ in 0..len {
-            self.push(buf.read_u8().unwrap() as char);
-        }
+        let mut bytes = vec![0; len as usize];
+        buf.read_exact(&mut bytes).unwrap();
+        // bytes were written from a valid string, but keep going on corrupted input
+        self.push_str(&String::from_utf8_lossy(&bytes));
         len as usize + 4
     }
 }
@@ -709,6 +920,26 @@ impl<T: Serializable> Serializable for Option<T> {
     }
 }
 
+impl Serializable for DocumentMetaData {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        count += self.title.serialize(buf);
+        count += self.article_abstract.serialize(buf);
+        count += self.namespace.serialize(buf);
+        count += self.categories.serialize(buf);
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        let mut count = 0;
+        count += self.title.deserialize(buf);
+        count += self.article_abstract.deserialize(buf);
+        count += self.namespace.deserialize(buf);
+        count += self.categories.deserialize(buf);
+        count
+    }
+}
+
 impl Serializable for PosRange {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
         buf.write_u32::<NativeEndian>(self.start_pos).unwrap();
@@ -829,22 +1060,28 @@ where
 {
     fn serialize<W: Write>(&self, buf: &mut W) -> usize {
         let mut count = 0;
-        count += self.postings.serialize(buf);
+        count += self.documents.serialize(buf);
+        count += self.positions.serialize(buf);
         count += self.df.serialize(buf);
-        count += self.tf.serialize(buf);
         count += self.postings_count.serialize(buf);
+        count += self.max_tf.serialize(buf);
 
         count
     }
 
     fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        self.try_deserialize(buf).unwrap()
+    }
+
+    fn try_deserialize<R: Read>(&mut self, buf: &mut R) -> Result<usize, IndexError> {
         let mut count = 0;
-        count += self.postings.deserialize(buf);
+        count += self.documents.try_deserialize(buf)?;
+        count += self.positions.try_deserialize(buf)?;
         count += self.df.deserialize(buf);
-        count += self.tf.deserialize(buf);
         count += self.postings_count.deserialize(buf);
+        count += self.max_tf.deserialize(buf);
 
-        count
+        Ok(count)
     }
 }
 
@@ -4,7 +4,7 @@ use indexmap::IndexMap;
 
 use crate::{
     DeltaEncoder, EncodedSequentialObject, IdentityEncoder, PosRange, Posting, PostingNode,
-    SequentialEncoder, Serializable, VbyteEncoder,
+    SequentialEncoder, Serializable, SkipEncoder, SkipIterator, VbyteEncoder, SKIP_INTERVAL,
 };
 use chrono::NaiveDateTime;
 
@@ -42,6 +42,17 @@ test_serialize_deserialize!(test_serialize_int, u32, 69);
 test_serialize_deserialize!(test_serialize_int_2, u16, 2234 as u16);
 test_serialize_deserialize!(test_serialize_int_3, u8, 69 as u8);
 test_serialize_deserialize!(test_serialize_int_4, i32, 32980);
+test_serialize_deserialize!(test_serialize_int_5, u64, 1 << 40);
+test_serialize_deserialize!(test_serialize_float, f64, 0.15625);
+test_serialize_deserialize!(test_serialize_string_utf8, String, "Gödel – ∆".to_string());
+test_serialize_deserialize!(
+    test_serialize_last_updated_date,
+    LastUpdatedDate,
+    LastUpdatedDate {
+        date_time: NaiveDateTime::parse_from_str("2015-07-01 08:59:30", "%Y-%m-%d %H:%M:%S")
+            .unwrap(),
+    }
+);
 
 test_serialize_deserialize!(test_serialize_vec, Vec<u32>, vec![1, 2, 3, 4, 5, 6]);
 
@@ -1291,3 +1302,112 @@ fn test_from_and_to_iter() {
     assert_eq!(iter.next().unwrap(), target_1);
     assert_eq!(iter.next().unwrap(), target_2);
 }
+
+/// documents with a varying number of postings each, with gaps between document ids
+pub fn skip_test_postings() -> Vec<Posting> {
+    (0..400u32)
+        .flat_map(|d| {
+            (0..(d % 4 + 1)).map(move |p| Posting {
+                document_id: d * 3 + 1,
+                position: p * 5 + d % 7,
+            })
+        })
+        .collect()
+}
+
+/// the postings left over after skipping to each target in turn
+pub fn skipped<I: SkipIterator>(mut iter: I, targets: &[u32]) -> Vec<Option<Posting>> {
+    targets.iter().map(|t| iter.skip_to(*t)).collect()
+}
+
+pub fn expected_skips(postings: &[Posting], targets: &[u32]) -> Vec<Option<Posting>> {
+    let mut pos = 0;
+    targets
+        .iter()
+        .map(|t| {
+            while pos < postings.len() && postings[pos].document_id < *t {
+                pos += 1;
+            }
+            pos += 1;
+            postings.get(pos - 1).cloned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_vbyte_skip_pointers_written() {
+    let postings = skip_test_postings();
+    let o = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, true>>::from_iter(
+        postings.clone().into_iter(),
+    );
+
+    let skips = o.encoder().skip_pointers();
+    assert!(skips.len() >= postings.len() / (SKIP_INTERVAL as usize + 4));
+    assert!(skips.windows(2).all(|w| w[0].doc_id < w[1].doc_id));
+    assert!(skips.iter().all(|s| (s.offset as usize) < o.byte_len()));
+    // skipping doesn't change the encoding
+    assert_eq!(o.into_iter().collect::<Vec<Posting>>(), postings);
+}
+
+#[test]
+fn test_vbyte_skip_to() {
+    let postings = skip_test_postings();
+    let targets = vec![0, 1, 2, 5, 300, 301, 302, 302, 650, 900, 1198, 1199, 5000, 5001];
+
+    let delta = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, true>>::from_iter(
+        postings.clone().into_iter(),
+    );
+    let plain = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, false>>::from_iter(
+        postings.clone().into_iter(),
+    );
+
+    assert_eq!(
+        skipped(delta.into_iter(), &targets),
+        expected_skips(&postings, &targets)
+    );
+    assert_eq!(
+        skipped(plain.into_iter(), &targets),
+        expected_skips(&postings, &targets)
+    );
+    assert_eq!(
+        skipped(postings.clone().into_iter(), &targets),
+        expected_skips(&postings, &targets)
+    );
+}
+
+#[test]
+fn test_vbyte_skip_to_then_next() {
+    let postings = skip_test_postings();
+    let o = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, true>>::from_iter(
+        postings.clone().into_iter(),
+    );
+
+    let mut iter = o.into_iter();
+    let first = iter.skip_to(700).unwrap();
+    let start = postings.iter().position(|p| p.document_id >= 700).unwrap();
+
+    assert_eq!(first, postings[start]);
+    assert_eq!(iter.collect::<Vec<Posting>>(), postings[start + 1..].to_vec());
+}
+
+#[test]
+fn test_vbyte_skip_pointers_serialized() {
+    let postings = skip_test_postings();
+    let o = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, true>>::from_iter(
+        postings.clone().into_iter(),
+    );
+
+    let mut buffer = Vec::default();
+    o.serialize(&mut buffer);
+    let mut deserialized = EncodedSequentialObject::<Posting, VbyteEncoder<Posting, true>>::default();
+    deserialized.deserialize(&mut Cursor::new(buffer));
+
+    assert_eq!(
+        deserialized.encoder().skip_pointers(),
+        o.encoder().skip_pointers()
+    );
+    assert_eq!(
+        skipped(deserialized.into_iter(), &[900]),
+        expected_skips(&postings, &[900])
+    );
+}
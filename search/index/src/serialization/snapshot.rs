@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher;
+use log::info;
+
+use crate::index::{doc_text_store, next_generation};
+use crate::{
+    DiskHashMap, DocumentMetaData, EncodedPostingNode, Index, IndexError, IndexErrorKind, LastUpdatedDate, PosRange,
+    PostingCodec, SequentialEncoder, Serializable,
+};
+
+/// bumped whenever the layout or contents of any snapshot file change,
+/// snapshots written with a different version are rejected on load
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 10;
+
+/// first bytes of every snapshot manifest ("WSNP")
+const SNAPSHOT_MAGIC: u32 = 0x504e_5357;
+
+const MANIFEST_FILE: &str = "MANIFEST";
+const POSTINGS_SECTION: &str = "postings";
+const LINKS_SECTION: &str = "links";
+const INCOMING_LINKS_SECTION: &str = "incoming_links";
+const EXTENT_SECTION: &str = "extent";
+const LAST_UPDATED_DOCS_SECTION: &str = "last_updated_docs";
+const PAGE_RANK_SECTION: &str = "page_rank";
+const DOC_LENGTHS_SECTION: &str = "doc_lengths";
+const DOC_TEXTS_SECTION: &str = "doc_texts";
+const METADATA_SECTION: &str = "metadata";
+
+/// a single file of a snapshot, alongside what is needed to verify it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SnapshotSection {
+    pub name: String,
+    pub bytes: u64,
+    pub checksum: u32,
+}
+
+/// the self describing header of a snapshot directory
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    pub format_version: u32,
+    pub dump_id: u32,
+    /// the codec new posting lists are encoded with
+    pub posting_codec: PostingCodec,
+    pub sections: Vec<SnapshotSection>,
+}
+
+impl Serializable for SnapshotSection {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        count += self.name.serialize(buf);
+        count += self.bytes.serialize(buf);
+        count += self.checksum.serialize(buf);
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        let mut count = 0;
+        count += self.name.deserialize(buf);
+        count += self.bytes.deserialize(buf);
+        count += self.checksum.deserialize(buf);
+        count
+    }
+}
+
+impl Serializable for SnapshotManifest {
+    fn serialize<W: Write>(&self, buf: &mut W) -> usize {
+        let mut count = 0;
+        count += self.format_version.serialize(buf);
+        count += self.dump_id.serialize(buf);
+        count += self.posting_codec.serialize(buf);
+        count += self.sections.serialize(buf);
+        count
+    }
+
+    fn deserialize<R: Read>(&mut self, buf: &mut R) -> usize {
+        self.try_deserialize(buf).unwrap()
+    }
+
+    fn try_deserialize<R: Read>(&mut self, buf: &mut R) -> Result<usize, IndexError> {
+        let mut count = 0;
+        count += self.format_version.deserialize(buf);
+        count += self.dump_id.deserialize(buf);
+        count += self.posting_codec.try_deserialize(buf)?;
+        count += self.sections.deserialize(buf);
+        Ok(count)
+    }
+}
+
+impl SnapshotManifest {
+    pub fn section(&self, name: &str) -> Result<&SnapshotSection, IndexError> {
+        self.sections
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or(IndexError {
+                msg: format!("Snapshot is missing the {} section", name),
+                kind: IndexErrorKind::Snapshot,
+            })
+    }
+}
+
+/// passes writes through while keeping track of the length and checksum of everything written
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Hasher,
+    bytes: u64,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_section<F>(dir: &Path, name: &str, write: F) -> Result<SnapshotSection, IndexError>
+where
+    F: FnOnce(&mut ChecksumWriter<BufWriter<File>>),
+{
+    let mut w = ChecksumWriter {
+        inner: BufWriter::new(File::create(dir.join(name))?),
+        hasher: Hasher::new(),
+        bytes: 0,
+    };
+
+    write(&mut w);
+    w.flush()?;
+    w.inner.get_ref().sync_all()?;
+
+    Ok(SnapshotSection {
+        name: name.to_string(),
+        bytes: w.bytes,
+        checksum: w.hasher.finalize(),
+    })
+}
+
+/// checks the section on disk matches its manifest record before anything is deserialized from it
+fn verify_section(dir: &Path, section: &SnapshotSection) -> Result<(), IndexError> {
+    let mut f = BufReader::new(File::open(dir.join(&section.name))?);
+    let mut hasher = Hasher::new();
+    let mut bytes = 0;
+    let mut buf = [0; 1 << 16];
+
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        bytes += n as u64;
+    }
+
+    if bytes != section.bytes || hasher.finalize() != section.checksum {
+        return Err(IndexError {
+            msg: format!("Snapshot section {} is corrupted", section.name),
+            kind: IndexErrorKind::Snapshot,
+        });
+    }
+
+    Ok(())
+}
+
+fn read_section<T, F>(dir: &Path, manifest: &SnapshotManifest, name: &str, read: F) -> Result<T, IndexError>
+where
+    F: FnOnce(&mut BufReader<File>) -> Result<T, IndexError>,
+{
+    let section = manifest.section(name)?;
+    verify_section(dir, section)?;
+    let mut f = BufReader::new(File::open(dir.join(name))?);
+    read(&mut f)
+}
+
+fn read_section_value<T: Serializable>(
+    dir: &Path,
+    manifest: &SnapshotManifest,
+    name: &str,
+) -> Result<T, IndexError> {
+    read_section(dir, manifest, name, |r| {
+        let mut v = T::default();
+        v.try_deserialize(r)?;
+        Ok(v)
+    })
+}
+
+/// the manifest is stored as: magic, format version, body length, body checksum, body
+fn write_manifest(dir: &Path, manifest: &SnapshotManifest) -> Result<(), IndexError> {
+    let mut body = Vec::default();
+    manifest.serialize(&mut body);
+
+    let mut hasher = Hasher::new();
+    hasher.update(&body);
+
+    let mut f = BufWriter::new(File::create(dir.join(MANIFEST_FILE))?);
+    f.write_u32::<NativeEndian>(SNAPSHOT_MAGIC)?;
+    f.write_u32::<NativeEndian>(manifest.format_version)?;
+    f.write_u32::<NativeEndian>(body.len() as u32)?;
+    f.write_u32::<NativeEndian>(hasher.finalize())?;
+    f.write_all(&body)?;
+    f.flush()?;
+    f.get_ref().sync_all()?;
+
+    Ok(())
+}
+
+/// reads and validates the manifest of the snapshot at the given directory,
+/// a cheap way of finding out which dump a snapshot was built from without loading it
+pub fn read_snapshot_manifest<P: AsRef<Path>>(dir: P) -> Result<SnapshotManifest, IndexError> {
+    let mut f = BufReader::new(File::open(dir.as_ref().join(MANIFEST_FILE))?);
+
+    let invalid = |msg: String| IndexError {
+        msg,
+        kind: IndexErrorKind::Snapshot,
+    };
+
+    if f.read_u32::<NativeEndian>()? != SNAPSHOT_MAGIC {
+        return Err(invalid("Not a snapshot manifest".to_string()));
+    }
+
+    let version = f.read_u32::<NativeEndian>()?;
+    if version != SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid(format!(
+            "Snapshot format version {} is not supported, expected {}",
+            version, SNAPSHOT_FORMAT_VERSION
+        )));
+    }
+
+    let len = f.read_u32::<NativeEndian>()?;
+    let checksum = f.read_u32::<NativeEndian>()?;
+    let mut body = vec![0; len as usize];
+    f.read_exact(&mut body)?;
+
+    let mut hasher = Hasher::new();
+    hasher.update(&body);
+    if hasher.finalize() != checksum {
+        return Err(invalid("Snapshot manifest is corrupted".to_string()));
+    }
+
+    let mut manifest = SnapshotManifest::default();
+    manifest.try_deserialize(&mut body.as_slice())?;
+    Ok(manifest)
+}
+
+fn sibling_path(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    dir.with_file_name(name)
+}
+
+fn partial_path(dir: &Path) -> PathBuf {
+    sibling_path(dir, ".partial")
+}
+
+fn previous_path(dir: &Path) -> PathBuf {
+    sibling_path(dir, ".previous")
+}
+
+/// the directory the latest complete snapshot saved to the given one is in, if any.
+/// While a snapshot is being replaced the previous one is set aside next to it and stands in for it
+pub fn latest_snapshot_dir<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    let dir = dir.as_ref();
+    let previous = previous_path(dir);
+    if dir.exists() {
+        Some(dir.to_path_buf())
+    } else if previous.exists() {
+        Some(previous)
+    } else {
+        None
+    }
+}
+
+impl Index {
+    /// writes the index into a snapshot directory, replacing any previous snapshot there
+    /// the snapshot is assembled next to the target directory and moved into place once complete,
+    /// the previous one is only removed afterwards. A crash never leaves a half written snapshot behind
+    /// or none at all, see [latest_snapshot_dir]
+    pub fn save_snapshot<P: AsRef<Path>>(&self, dir: P) -> Result<SnapshotManifest, IndexError> {
+        let timer = Instant::now();
+        let dir = dir.as_ref();
+        let partial = partial_path(dir);
+
+        if partial.exists() {
+            fs::remove_dir_all(&partial)?;
+        }
+        fs::create_dir_all(&partial)?;
+
+        info!("Writing index snapshot to {}", dir.display());
+
+        let mut sections = Vec::default();
+
+        // every posting node is preceded by its token
+        sections.push(write_section(&partial, POSTINGS_SECTION, |w| {
+            let keys = self.posting_nodes.keys();
+            (keys.len() as u32).serialize(w);
+            for (k, i) in keys {
+                k.serialize(w);
+                self.posting_nodes
+                    .entry_by_index(i)
+                    .expect("Key without a record in posting nodes")
+                    .lock()
+                    .get()
+                    .unwrap()
+                    .serialize(w);
+            }
+        })?);
+        sections.push(write_section(&partial, LINKS_SECTION, |w| {
+            self.links.serialize(w);
+        })?);
+        sections.push(write_section(&partial, INCOMING_LINKS_SECTION, |w| {
+            self.incoming_links.serialize(w);
+        })?);
+        sections.push(write_section(&partial, EXTENT_SECTION, |w| {
+            self.extent.serialize(w);
+        })?);
+        sections.push(write_section(&partial, LAST_UPDATED_DOCS_SECTION, |w| {
+            self.last_updated_docs.serialize(w);
+        })?);
+        sections.push(write_section(&partial, PAGE_RANK_SECTION, |w| {
+            self.page_rank.serialize(w);
+        })?);
+        sections.push(write_section(&partial, DOC_LENGTHS_SECTION, |w| {
+            self.doc_lengths.serialize(w);
+        })?);
+        // laid out like a map from document id to text, read from the store one at a time
+        sections.push(write_section(&partial, DOC_TEXTS_SECTION, |w| {
+            let keys = self.doc_texts.keys();
+            (keys.len() as u32).serialize(w);
+            for (k, i) in keys {
+                k.parse::<u32>()
+                    .expect("Document text stored under something other than an id")
+                    .serialize(w);
+                self.doc_texts
+                    .entry_by_index(i)
+                    .expect("Key without a record in document texts")
+                    .lock()
+                    .get()
+                    .unwrap()
+                    .serialize(w);
+            }
+        })?);
+        sections.push(write_section(&partial, METADATA_SECTION, |w| {
+            self.metadata.serialize(w);
+        })?);
+
+        let manifest = SnapshotManifest {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            dump_id: self.dump_id,
+            posting_codec: self.codec.fresh(),
+            sections,
+        };
+        write_manifest(&partial, &manifest)?;
+
+        // a snapshot left aside by an earlier save is only stale if there is a newer one to set aside
+        let previous = previous_path(dir);
+        if dir.exists() {
+            if previous.exists() {
+                fs::remove_dir_all(&previous)?;
+            }
+            fs::rename(dir, &previous)?;
+        }
+        fs::rename(&partial, dir)?;
+        if previous.exists() {
+            fs::remove_dir_all(&previous)?;
+        }
+
+        info!("Took {}s", timer.elapsed().as_secs());
+        Ok(manifest)
+    }
+
+    /// reads back a snapshot written by [Index::save_snapshot], or the previous one if it was interrupted
+    /// every section is checked against the manifest before being deserialized, posting lists are streamed
+    /// into a fresh posting cache with the given capacities
+    pub fn load_snapshot<P: AsRef<Path>>(
+        dir: P,
+        posting_list_mem_limit: u64,
+        posting_list_persistent_mem_limit: u64,
+    ) -> Result<Self, IndexError> {
+        let timer = Instant::now();
+        let dir = latest_snapshot_dir(&dir).unwrap_or_else(|| dir.as_ref().to_path_buf());
+        let dir = dir.as_path();
+        let manifest = read_snapshot_manifest(dir)?;
+
+        info!(
+            "Loading index snapshot of dump {} from {}",
+            manifest.dump_id,
+            dir.display()
+        );
+
+        let mut posting_nodes: DiskHashMap<EncodedPostingNode<PostingCodec>> =
+            DiskHashMap::new(
+                posting_list_mem_limit,
+                posting_list_persistent_mem_limit,
+                true,
+            );
+
+        read_section(dir, &manifest, POSTINGS_SECTION, |r| {
+            let mut count = 0u32;
+            count.deserialize(r);
+            for _ in 0..count {
+                let mut k = String::default();
+                let mut v = EncodedPostingNode::default();
+                k.deserialize(r);
+                v.try_deserialize(r)?;
+                posting_nodes.insert(&k, v);
+            }
+            Ok(())
+        })?;
+        posting_nodes.set_runtime_mode();
+
+        let links: HashMap<u32, Vec<u32>> = read_section_value(dir, &manifest, LINKS_SECTION)?;
+        let incoming_links: HashMap<u32, Vec<u32>> =
+            read_section_value(dir, &manifest, INCOMING_LINKS_SECTION)?;
+        let extent: HashMap<String, HashMap<u32, PosRange>> =
+            read_section_value(dir, &manifest, EXTENT_SECTION)?;
+        let last_updated_docs: HashMap<u32, LastUpdatedDate> =
+            read_section_value(dir, &manifest, LAST_UPDATED_DOCS_SECTION)?;
+        let page_rank: HashMap<u32, f64> = read_section_value(dir, &manifest, PAGE_RANK_SECTION)?;
+        let doc_lengths: HashMap<u32, u32> =
+            read_section_value(dir, &manifest, DOC_LENGTHS_SECTION)?;
+        let mut doc_texts = doc_text_store(true);
+        read_section(dir, &manifest, DOC_TEXTS_SECTION, |r| {
+            let mut count = 0u32;
+            count.deserialize(r);
+            for _ in 0..count {
+                let mut doc_id = 0u32;
+                let mut text = String::default();
+                doc_id.deserialize(r);
+                text.deserialize(r);
+                doc_texts.insert(&doc_id.to_string(), text);
+            }
+            Ok(())
+        })?;
+        doc_texts.set_runtime_mode();
+        let metadata: HashMap<u32, DocumentMetaData> =
+            read_section_value(dir, &manifest, METADATA_SECTION)?;
+
+        info!("Took {}s", timer.elapsed().as_secs());
+
+        let mut index = Self {
+            dump_id: manifest.dump_id,
+            generation: next_generation(),
+            posting_nodes,
+            codec: manifest.posting_codec,
+            links,
+            incoming_links,
+            extent,
+            last_updated_docs,
+            page_rank,
+            doc_lengths,
+            doc_texts,
+            metadata,
+            title_ids: HashMap::default(),
+            field_lengths: HashMap::default(),
+            enabled: true,
+        };
+        index.compute_field_lengths();
+        index.compute_title_ids();
+
+        Ok(index)
+    }
+}
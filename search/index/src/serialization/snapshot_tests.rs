@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::{
+    get_document_with_text_and_links, latest_snapshot_dir, read_snapshot_manifest, Index, Posting, PreIndex,
+    SNAPSHOT_FORMAT_VERSION,
+};
+
+fn snapshot_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("snapshot-{}", Uuid::new_v4()))
+}
+
+fn build_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+    pre_idx.dump_id = 7;
+
+    pre_idx
+        .add_document(get_document_with_text_and_links(
+            0,
+            "d0",
+            vec![("infobox", "hello world"), ("infobox2", "hello")],
+            "eggs world",
+            vec!["this that", "that", "eggs"],
+            "hello world",
+            "1\t2",
+        ))
+        .unwrap();
+
+    pre_idx
+        .add_document(get_document_with_text_and_links(
+            1,
+            "d1",
+            vec![("infobox", "aaa aa"), ("infobox2", "aaa")],
+            "eggs world",
+            vec!["aaa aa", "aaa", "aa"],
+            "aaa aaa",
+            "0",
+        ))
+        .unwrap();
+
+    pre_idx
+        .add_document(get_document_with_text_and_links(
+            2,
+            "d2",
+            vec![],
+            "world bőrönd",
+            vec![],
+            "",
+            "0\t1",
+        ))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn postings_of(idx: &Index, token: &str) -> Vec<Posting> {
+    idx.get_postings(token)
+        .unwrap()
+        .lock()
+        .get()
+        .unwrap()
+        .postings()
+        .into_iter()
+        .collect()
+}
+
+/// the text of every document the index holds
+fn texts_of(idx: &Index) -> HashMap<u32, Option<String>> {
+    idx.doc_lengths
+        .keys()
+        .map(|d| (*d, idx.get_text(*d)))
+        .collect()
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let dir = snapshot_dir();
+    let idx = build_index();
+
+    let tokens = vec!["hello", "world", "eggs", "aaa", "that", "bőrönd"];
+    let postings = tokens
+        .iter()
+        .map(|t| postings_of(&idx, t))
+        .collect::<Vec<Vec<Posting>>>();
+    let dfs = tokens.iter().map(|t| idx.df(t)).collect::<Vec<u32>>();
+    let tfs = tokens.iter().map(|t| idx.tf(t, 0)).collect::<Vec<u32>>();
+    let links = idx.links.clone();
+    let incoming_links = idx.incoming_links.clone();
+    let page_rank = idx.page_rank.clone();
+    let last_updated_docs = idx.last_updated_docs.clone();
+    let doc_lengths = idx.doc_lengths.clone();
+    let doc_texts = texts_of(&idx);
+    let metadata = idx.metadata.clone();
+    let avg_doc_length = idx.avg_document_length();
+    let infobox_extent = idx.get_extent_for("infobox2", &1).map(|r| (r.start_pos, r.end_pos));
+    let posting_lists = idx.posting_nodes.len();
+
+    let manifest = idx.save_snapshot(&dir).unwrap();
+    assert_eq!(manifest.dump_id, 7);
+    assert_eq!(manifest.format_version, SNAPSHOT_FORMAT_VERSION);
+    assert_eq!(read_snapshot_manifest(&dir).unwrap(), manifest);
+
+    let loaded = Index::load_snapshot(&dir, 10000, 100).unwrap();
+
+    assert_eq!(loaded.get_dump_id(), 7);
+    assert_eq!(loaded.posting_nodes.len(), posting_lists);
+    for (i, t) in tokens.iter().enumerate() {
+        assert_eq!(postings_of(&loaded, t), postings[i]);
+        assert_eq!(loaded.df(t), dfs[i]);
+        assert_eq!(loaded.tf(t, 0), tfs[i]);
+    }
+    assert_eq!(loaded.links, links);
+    assert_eq!(loaded.incoming_links, incoming_links);
+    assert_eq!(loaded.page_rank, page_rank);
+    assert_eq!(loaded.last_updated_docs, last_updated_docs);
+    assert_eq!(loaded.doc_lengths, doc_lengths);
+    assert_eq!(texts_of(&loaded), doc_texts);
+    assert_eq!(loaded.metadata, metadata);
+    assert_eq!(loaded.get_document_id("d1"), Some(1));
+    assert_eq!(loaded.avg_document_length(), avg_doc_length);
+    assert_eq!(
+        loaded
+            .get_extent_for("infobox2", &1)
+            .map(|r| (r.start_pos, r.end_pos)),
+        infobox_extent
+    );
+    assert_eq!(loaded.get_number_of_documents(), 3);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_overwrites_previous() {
+    let dir = snapshot_dir();
+    let mut idx = build_index();
+
+    idx.save_snapshot(&dir).unwrap();
+    idx.dump_id = 8;
+    idx.save_snapshot(&dir).unwrap();
+
+    assert_eq!(read_snapshot_manifest(&dir).unwrap().dump_id, 8);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_interrupted_replace() {
+    let dir = snapshot_dir();
+    let previous = dir.with_file_name(format!(
+        "{}.previous",
+        dir.file_name().unwrap().to_str().unwrap()
+    ));
+    let mut idx = build_index();
+    idx.save_snapshot(&dir).unwrap();
+
+    // a crash after the old snapshot was set aside but before the new one took its place
+    fs::rename(&dir, &previous).unwrap();
+    assert_eq!(latest_snapshot_dir(&dir), Some(previous.clone()));
+    assert_eq!(Index::load_snapshot(&dir, 100, 100).unwrap().dump_id, 7);
+
+    // the next save takes its place
+    idx.dump_id = 8;
+    idx.save_snapshot(&dir).unwrap();
+    assert!(!previous.exists());
+    assert_eq!(latest_snapshot_dir(&dir), Some(dir.clone()));
+    assert_eq!(Index::load_snapshot(&dir, 100, 100).unwrap().dump_id, 8);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_corrupted_section() {
+    let dir = snapshot_dir();
+    let idx = build_index();
+
+    idx.save_snapshot(&dir).unwrap();
+
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(dir.join("links"))
+        .unwrap();
+    f.seek(SeekFrom::Start(4)).unwrap();
+    f.write_all(&[0xff, 0xff]).unwrap();
+    drop(f);
+
+    assert!(Index::load_snapshot(&dir, 10000, 100).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_wrong_version() {
+    let dir = snapshot_dir();
+    let idx = build_index();
+
+    idx.save_snapshot(&dir).unwrap();
+
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(dir.join("MANIFEST"))
+        .unwrap();
+    f.seek(SeekFrom::Start(4)).unwrap();
+    f.write_all(&(SNAPSHOT_FORMAT_VERSION + 1).to_ne_bytes())
+        .unwrap();
+    drop(f);
+
+    assert!(read_snapshot_manifest(&dir).is_err());
+    assert!(Index::load_snapshot(&dir, 10000, 100).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_missing() {
+    assert!(Index::load_snapshot(snapshot_dir(), 10000, 100).is_err());
+}
@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{IndexError, IndexErrorKind};
+
+/// how often the posting list of each term was looked up, carried over between indices so the cache of
+/// a rebuilt or restored index can be filled with the lists most likely to be needed.
+/// Saved as one `count<TAB>term` line per term
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TermUsage {
+    counts: HashMap<String, u64>,
+}
+
+impl TermUsage {
+    /// adds the given counts to those recorded so far
+    pub fn record<I: IntoIterator<Item = (String, u64)>>(&mut self, uses: I) {
+        for (term, count) in uses {
+            *self.counts.entry(term).or_default() += count;
+        }
+    }
+
+    pub fn count(&self, term: &str) -> u64 {
+        self.counts.get(term).cloned().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// the terms from most to least used, ties in term order
+    pub fn most_used(&self) -> Vec<&str> {
+        let mut terms = self
+            .counts
+            .iter()
+            .map(|(t, c)| (t.as_str(), *c))
+            .collect::<Vec<_>>();
+        terms.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b)));
+        terms.into_iter().map(|(t, _)| t).collect()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IndexError> {
+        let mut usage = TermUsage::default();
+        for line in BufReader::new(File::open(path.as_ref())?).lines() {
+            let line = line?;
+            let (count, term) = line
+                .split_once('\t')
+                .and_then(|(c, t)| c.parse::<u64>().ok().map(|c| (c, t)))
+                .ok_or_else(|| IndexError {
+                    msg: format!(
+                        "Malformed line {:?} in term usage {}",
+                        line,
+                        path.as_ref().display()
+                    ),
+                    kind: IndexErrorKind::InvalidIndexState,
+                })?;
+            usage.record(Some((term.to_string(), count)));
+        }
+        Ok(usage)
+    }
+
+    /// writes the counts next to the given path first and then moves them over it,
+    /// so a crash never leaves half a file behind
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IndexError> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for term in self.most_used() {
+            writeln!(out, "{}\t{}", self.counts[term], term)?;
+        }
+        out.flush()?;
+        drop(out);
+
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
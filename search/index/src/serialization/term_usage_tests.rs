@@ -0,0 +1,112 @@
+use std::fs;
+
+use uuid::Uuid;
+
+use crate::index::Index;
+use crate::utils::get_document_with_text;
+use crate::{DiskHashMap, PreIndex, TermUsage};
+
+#[test]
+fn test_term_usage_most_used() {
+    let mut usage = TermUsage::default();
+    usage.record(vec![
+        ("b".to_string(), 2),
+        ("a".to_string(), 2),
+        ("c".to_string(), 5),
+    ]);
+    usage.record(vec![("a".to_string(), 1), ("d".to_string(), 1)]);
+
+    assert_eq!(usage.len(), 4);
+    assert_eq!(usage.count("a"), 3);
+    assert_eq!(usage.count("e"), 0);
+    assert_eq!(usage.most_used(), vec!["c", "a", "b", "d"]);
+}
+
+#[test]
+fn test_term_usage_save_load() {
+    let path = std::env::temp_dir().join(format!("term-usage-{}", Uuid::new_v4()));
+    let mut usage = TermUsage::default();
+    usage.record(vec![
+        ("hello".to_string(), 3),
+        ("hello world".to_string(), 1),
+    ]);
+
+    usage.save(&path).unwrap();
+    assert_eq!(TermUsage::load(&path).unwrap(), usage);
+
+    fs::write(&path, "3\thello\nnot a count\n").unwrap();
+    assert!(TermUsage::load(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_disk_hash_map_take_uses() {
+    let mut d = DiskHashMap::<u32>::new(10, 10, false);
+    d.insert("a", 1);
+    d.insert("b", 2);
+    d.insert("c", 3);
+
+    d.lookup("a");
+    d.lookup("a");
+    d.lookup("c");
+    d.lookup("missing");
+    // scans over the records are not looked up on behalf of anyone
+    d.entry("b");
+    d.entry_by_index(0);
+
+    let mut uses = d.take_uses();
+    uses.sort();
+    assert_eq!(uses, vec![("a".to_string(), 2), ("c".to_string(), 1)]);
+    assert!(d.take_uses().is_empty());
+}
+
+#[test]
+fn test_disk_hash_map_warm() {
+    let mut d = DiskHashMap::<u32>::new(2, 2, false);
+    for (i, k) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        d.insert(k, i as u32);
+    }
+    d.clean_cache();
+
+    assert_eq!(d.warm(["d", "missing", "b", "a"]), 2);
+    assert_eq!(d.cache_population(), 2);
+    assert_eq!(d.disk_stats().misses, 0);
+
+    // the least wanted of the warmed records is evicted first
+    d.lookup("c");
+    assert!(d.entry_wild_card("d")[0].lock().is_loaded());
+    assert!(!d.entry_wild_card("b")[0].lock().is_loaded());
+
+    assert_eq!(*d.lookup("d").unwrap().lock().get().unwrap(), 3);
+    assert_eq!(d.disk_stats().hits, 1);
+
+    // warming is not counted as a use
+    let mut uses = d.take_uses();
+    uses.sort();
+    assert_eq!(uses, vec![("c".to_string(), 1), ("d".to_string(), 1)]);
+}
+
+#[test]
+fn test_index_counts_query_lookups_only() {
+    let mut pre_idx = PreIndex::default();
+    pre_idx
+        .add_document(get_document_with_text(1, "d1", vec![], "hello world", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(2, "d2", vec![], "hello", vec![], ""))
+        .unwrap();
+    let idx = Index::from_pre_index(pre_idx);
+    // building the index is not a use
+    assert!(idx.take_posting_uses().is_empty());
+
+    idx.stream_documents("hello");
+    idx.stream_postings("hello");
+    idx.stream_documents("world");
+    idx.tf("hello", 1);
+    idx.tf("hello", 2);
+    idx.df("world");
+
+    let mut uses = idx.take_posting_uses();
+    uses.sort();
+    assert_eq!(uses, vec![("hello".to_string(), 2), ("world".to_string(), 1)]);
+}
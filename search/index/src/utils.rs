@@ -25,7 +25,10 @@ impl MemFootprintCalculator for PostingNode {
 
 impl MemFootprintCalculator for DocumentMetaData {
     fn real_mem(&self) -> u64 {
-        self.last_updated_date.real_mem() + self.namespace.real_mem() + self.title.real_mem()
+        self.title.real_mem()
+            + self.article_abstract.real_mem()
+            + self.namespace.real_mem()
+            + self.categories.real_mem()
         // above already counts metadata
     }
 }
@@ -57,6 +60,8 @@ pub fn get_document_with_text(
 ) -> Box<Document> {
     Box::new(Document {
         title: title.to_string(),
+        article_abstract: String::default(),
+        namespace: 0,
         doc_id: id,
         infoboxes: infoboxes
             .iter()
@@ -82,6 +87,8 @@ pub fn get_document_with_text(
 pub fn get_document_with_links(id: u32, title: &str, links: &str) -> Box<Document> {
     Box::new(Document {
         title: title.to_string(),
+        article_abstract: String::default(),
+        namespace: 0,
         doc_id: id,
         infoboxes: vec![],
         main_text: String::default(),
@@ -95,6 +102,8 @@ pub fn get_document_with_links(id: u32, title: &str, links: &str) -> Box<Documen
 pub fn get_document_with_date_time(id: u32, title: &str, last_updated_date: &str) -> Box<Document> {
     Box::new(Document {
         title: title.to_string(),
+        article_abstract: String::default(),
+        namespace: 0,
         doc_id: id,
         infoboxes: vec![],
         main_text: String::default(),
@@ -4,7 +4,10 @@ use index::{
 };
 use parser::ast::{BinaryOp, Query, StructureElem, UnaryOp};
 use parser::parse_query;
-use retrieval::{execute_query, get_docs_within_hops, search::preprocess_query};
+use retrieval::{
+    execute_query, execute_query_documents_within, get_docs_within_hops, needs_positions,
+    score_query, search::preprocess_query, QueryBudget, Ranking,
+};
 use std::collections::HashMap;
 
 #[test]
@@ -154,9 +157,14 @@ fn test_relational_query() {
 #[test]
 fn test_distance_query() {
     let mut q = Query::DistanceQuery {
-        lhs: "worm".to_string(),
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery {
+            tokens: vec!["worm".to_string()],
+        }),
         dst: 2,
-        rhs: "bars".to_string(),
+        rhs: Box::new(Query::FreetextQuery {
+            tokens: vec!["bars".to_string()],
+        }),
     };
 
     preprocess_query(&mut q).unwrap();
@@ -164,9 +172,14 @@ fn test_distance_query() {
     assert_eq!(
         q,
         Query::DistanceQuery {
-            lhs: "worm".to_string(),
+            ordered: true,
+            lhs: Box::new(Query::FreetextQuery {
+                tokens: vec!["worm".to_string()],
+            }),
             dst: 2,
-            rhs: "bar".to_string(),
+            rhs: Box::new(Query::FreetextQuery {
+                tokens: vec!["bar".to_string()],
+            }),
         }
     )
 }
@@ -175,9 +188,14 @@ fn test_distance_query() {
 #[should_panic]
 fn test_distance_query_error() {
     let mut q = Query::DistanceQuery {
-        lhs: "the".to_string(),
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery {
+            tokens: vec!["the".to_string()],
+        }),
         dst: 2,
-        rhs: "bars".to_string(),
+        rhs: Box::new(Query::FreetextQuery {
+            tokens: vec!["bars".to_string()],
+        }),
     };
 
     preprocess_query(&mut q).unwrap();
@@ -187,6 +205,7 @@ fn test_distance_query_error() {
 fn test_phrase_query() {
     let mut q = Query::PhraseQuery {
         tks: vec!["the".to_string(), "bikes".to_string()],
+        offsets: vec![0, 1],
     };
 
     preprocess_query(&mut q).unwrap();
@@ -911,8 +930,13 @@ fn test_distance_query_execute() {
         execute_query(
             &Box::new(Query::DistanceQuery {
                 dst: 2,
-                lhs: "hello".to_string(),
-                rhs: "world".to_string(),
+                ordered: true,
+                lhs: Box::new(Query::FreetextQuery {
+                    tokens: vec!["hello".to_string()],
+                }),
+                rhs: Box::new(Query::FreetextQuery {
+                    tokens: vec!["world".to_string()],
+                }),
             }),
             &idx
         )
@@ -963,8 +987,13 @@ fn test_distance_query_overlap() {
         execute_query(
             &Box::new(Query::DistanceQuery {
                 dst: 3,
-                lhs: "hello".to_string(),
-                rhs: "world".to_string(),
+                ordered: true,
+                lhs: Box::new(Query::FreetextQuery {
+                    tokens: vec!["hello".to_string()],
+                }),
+                rhs: Box::new(Query::FreetextQuery {
+                    tokens: vec!["world".to_string()],
+                }),
             }),
             &idx
         )
@@ -1017,7 +1046,8 @@ fn test_phrase_query_execute() {
     assert_eq!(
         execute_query(
             &Box::new(Query::PhraseQuery {
-                tks: vec!["hello".to_string(), "world".to_string()]
+                tks: vec!["hello".to_string(), "world".to_string()],
+                offsets: vec![0, 1],
             }),
             &idx
         )
@@ -1077,6 +1107,7 @@ fn test_phrase_query_multiple() {
                 "world".to_string(),
                 "momma".to_string(),
             ],
+            offsets: vec![0, 1, 2],
         }),
         &idx,
     )
@@ -1138,6 +1169,7 @@ fn test_phrase_query_multiple_same_start() {
                 "world".to_string(),
                 "momma".to_string(),
             ],
+            offsets: vec![0, 1, 2],
         }),
         &idx,
     )
@@ -1226,57 +1258,101 @@ fn test_structure_search_citation() {
         ]
     );
 }
-// #[test]
-// fn test_structure_search_title() {
-//     let mut pre_idx = PreIndex::default();
+#[test]
+fn test_structure_search_title() {
+    let mut pre_idx = PreIndex::default();
 
-//     pre_idx
-//         .add_document(get_document_with_text(
-//             2,
-//             "d2",
-//             vec![("", "hello world")],
-//             "hello world",
-//             vec!["ddd ddd"],
-//             "ooo ppp",
-//         ))
-//         .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            2,
+            "d2",
+            vec![("", "hello world")],
+            "hello world",
+            vec!["ddd ddd"],
+            "ooo ppp",
+        ))
+        .unwrap();
 
-//     pre_idx
-//         .add_document(get_document_with_text(
-//             3,
-//             "d3",
-//             vec![("", "aaa bbb")],
-//             "hello world",
-//             vec!["hello world"],
-//             "ggg hhh",
-//         ))
-//         .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            3,
+            "d3",
+            vec![("", "aaa bbb")],
+            "hello world d3",
+            vec!["hello world"],
+            "ggg hhh",
+        ))
+        .unwrap();
 
-//     let idx = Index::from_pre_index(pre_idx);
+    let idx = Index::from_pre_index(pre_idx);
 
-//     assert_eq!(
-//         execute_query(
-//             &Box::new(Query::StructureQuery {
-//                 elem: StructureElem::Title,
-//                 sub: Box::new(Query::FreetextQuery {
-//                     tokens: vec!["d3".to_string()]
-//                 })
-//             }),
-//             &idx
-//         )
-//         .collect::<Vec<Posting>>(),
-//         vec![
-//             Posting {
-//                 document_id: 3,
-//                 position: 4
-//             },
-//             Posting {
-//                 document_id: 3,
-//                 position: 5
-//             },
-//         ]
-//     );
-// }
+    // the title comes after everything else
+    assert_eq!(
+        execute_query(
+            &Box::new(Query::StructureQuery {
+                elem: StructureElem::Title,
+                sub: Box::new(Query::FreetextQuery {
+                    tokens: vec!["d3".to_string()]
+                })
+            }),
+            &idx
+        )
+        .collect::<Vec<Posting>>(),
+        vec![Posting {
+            document_id: 3,
+            position: 9
+        },]
+    );
+}
+
+#[test]
+fn test_exact_title_ranked_first() {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(
+            1,
+            "Turing machine",
+            vec![],
+            "alan turing alan turing alan turing described the turing machine",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            2,
+            "Alan Turing",
+            vec![],
+            "mathematician and computer scientist",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            3,
+            "Enigma",
+            vec![],
+            "a cipher machine broken by a team including alan turing",
+            vec![],
+            "",
+        ))
+        .unwrap();
+
+    let idx = Index::from_pre_index(pre_idx);
+
+    for ranking in [Ranking::TfIdf, Ranking::Bm25, Ranking::Bm25F] {
+        let (_, mut query) = parse_query("Alan Turing").unwrap();
+        preprocess_query(&mut query).unwrap();
+
+        let mut postings = execute_query(&query, &idx).collect::<Vec<Posting>>();
+        let mut scored = score_query(&query, &idx, &mut postings, ranking);
+        scored.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+
+        assert_eq!(scored[0].doc_id, 2, "{:?}", ranking);
+    }
+}
 
 #[test]
 fn test_structure_search_infobox() {
@@ -1495,6 +1571,7 @@ fn test_preprocess_singular_phrase() {
     let mut q = 
         Box::new(Query::PhraseQuery {
             tks: vec!["april".to_string()],
+            offsets: vec![0],
         });
 
     preprocess_query(&mut q).unwrap();
@@ -1508,3 +1585,276 @@ fn test_preprocess_singular_phrase() {
 
   
 }
+
+fn not_query_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(1, "d1", vec![], "big whale", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(2, "d2", vec![], "big tuna", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(3, "d3", vec![], "whale tuna", vec![], ""))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn matching_documents(query: &str, idx: &Index) -> Vec<u32> {
+    let (_, mut query) = parse_query(query).unwrap();
+    preprocess_query(&mut query).unwrap();
+
+    let mut docs = execute_query(&query, idx)
+        .map(|p| p.document_id)
+        .collect::<Vec<u32>>();
+    docs.dedup();
+    docs
+}
+
+#[test]
+fn test_and_not_query() {
+    let idx = not_query_index();
+
+    assert_eq!(matching_documents("big AND NOT whale", &idx), vec![2]);
+
+    let negated_lhs = Box::new(Query::BinaryQuery {
+        op: BinaryOp::And,
+        lhs: Box::new(Query::UnaryQuery {
+            op: UnaryOp::Not,
+            sub: Box::new(Query::FreetextQuery {
+                tokens: vec!["whale".to_string()],
+            }),
+        }),
+        rhs: Box::new(Query::FreetextQuery {
+            tokens: vec!["big".to_string()],
+        }),
+    });
+    assert_eq!(
+        execute_query(&negated_lhs, &idx)
+            .map(|p| p.document_id)
+            .collect::<Vec<u32>>(),
+        vec![2]
+    );
+}
+
+#[test]
+fn test_implicit_and_not_query() {
+    let idx = not_query_index();
+
+    assert_eq!(matching_documents("big NOT whale", &idx), vec![2]);
+    assert_eq!(matching_documents("tuna NOT big", &idx), vec![3]);
+}
+
+#[test]
+fn test_standalone_not_query() {
+    let idx = not_query_index();
+
+    assert_eq!(matching_documents("NOT tuna", &idx), vec![1]);
+    assert_eq!(matching_documents("big OR NOT tuna", &idx), vec![1, 2]);
+}
+
+#[test]
+fn test_preprocess_phrase_keeps_gaps() {
+    let (_, mut q) = parse_query("\"the lord of the rings\"").unwrap();
+
+    preprocess_query(&mut q).unwrap();
+
+    assert_eq!(
+        q,
+        Box::new(Query::PhraseQuery {
+            tks: vec!["lord".to_string(), "ring".to_string()],
+            offsets: vec![0, 3],
+        })
+    );
+}
+
+#[test]
+fn test_phrase_query_exact_offsets() {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(1, "d1", vec![], "lord ring", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(2, "d2", vec![], "lord aaa ring", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(3, "d3", vec![], "lord aaa bbb ccc ring", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(4, "The Lord of the Rings", vec![], "", vec![], ""))
+        .unwrap();
+    // body text as the preprocessor leaves it, with an empty word for each stop word
+    pre_idx
+        .add_document(get_document_with_text(5, "d5", vec![], "aaa lord   ring", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(6, "d6", vec![], "lord  ring", vec![], ""))
+        .unwrap();
+
+    let idx = Index::from_pre_index(pre_idx);
+
+    let (_, mut q) = parse_query("\"lord of the rings\"").unwrap();
+    preprocess_query(&mut q).unwrap();
+
+    // stop words leave the same gaps in the text as in the query, anywhere in the document
+    assert_eq!(
+        execute_query(&q, &idx).collect::<Vec<Posting>>(),
+        vec![
+            Posting {
+                document_id: 4,
+                position: 1
+            },
+            Posting {
+                document_id: 4,
+                position: 4
+            },
+            Posting {
+                document_id: 5,
+                position: 1
+            },
+            Posting {
+                document_id: 5,
+                position: 4
+            },
+        ]
+    );
+}
+
+fn distance_query_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(1, "d1", vec![], "pumpkin pie aaa latte", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(2, "d2", vec![], "latte aaa pumpkin pie", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(3, "d3", vec![], "pumpkin aaa pie latte", vec![], ""))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+#[test]
+fn test_distance_query_phrase_operand() {
+    let idx = distance_query_index();
+
+    assert_eq!(matching_documents("#DIST,2,pumpkin pie,latte", &idx), vec![1]);
+    // d3 has pie next to latte, but not the phrase
+    assert_eq!(matching_documents("#NEAR,2,pumpkin pie,latte", &idx), vec![1, 2]);
+    assert_eq!(matching_documents("#NEAR,1,pumpkin pie,latte", &idx), Vec::<u32>::new());
+}
+
+#[test]
+fn test_nested_distance_query() {
+    let idx = distance_query_index();
+
+    assert_eq!(
+        matching_documents("#NEAR,3,aaa,#DIST,1,pie,latte", &idx),
+        vec![3]
+    );
+}
+
+fn edited_query_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    for (id, date) in [
+        (1, "2019-12-31 23:59:59"),
+        (2, "2020-01-01 00:00:00"),
+        (3, "2020-06-15 12:30:00"),
+        (4, "2021-01-01 08:00:00"),
+    ] {
+        let mut doc = get_document_with_text(id, "d", vec![], "pumpkin pie", vec![], "");
+        doc.last_updated_date = date.to_string();
+        pre_idx.add_document(doc).unwrap();
+    }
+
+    Index::from_pre_index(pre_idx)
+}
+
+#[test]
+fn test_edited_query() {
+    let idx = edited_query_index();
+
+    assert_eq!(
+        matching_documents("#EDITED,2020-01-01,2020-12-31,pumpkin", &idx),
+        vec![2, 3]
+    );
+    assert_eq!(
+        matching_documents("#EDITED,*,2020-01-01,pumpkin", &idx),
+        vec![1, 2]
+    );
+    assert_eq!(
+        matching_documents("#EDITED,2020-06-15,*,\"pumpkin pie\"", &idx),
+        vec![3, 4]
+    );
+    assert_eq!(
+        matching_documents("#EDITED,*,*,pumpkin", &idx),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(
+        matching_documents("#EDITED,2021-01-02,*,pumpkin", &idx),
+        Vec::<u32>::new()
+    );
+}
+
+#[test]
+fn test_edited_query_nested() {
+    let idx = edited_query_index();
+
+    assert_eq!(
+        matching_documents("pumpkin AND NOT #EDITED,2020-01-01,*,pie", &idx),
+        vec![1]
+    );
+    assert_eq!(
+        matching_documents("#TITLE #EDITED,2020-01-01,2020-01-01,d", &idx),
+        vec![2]
+    );
+}
+
+#[test]
+fn test_document_level_execution_matches_positional() {
+    let budget = QueryBudget::unlimited();
+
+    for idx in [not_query_index(), distance_query_index(), edited_query_index()] {
+        for query in [
+            "big",
+            "whale tuna",
+            "big AND NOT whale",
+            "NOT tuna",
+            "pumpkin OR latte",
+            "\"pumpkin pie\" AND latte",
+            "#NEAR,2,pumpkin pie,latte",
+            "#EDITED,2020-01-01,*,pumpkin",
+            "#TITLE d",
+        ] {
+            let (_, mut q) = parse_query(query).unwrap();
+            preprocess_query(&mut q).unwrap();
+
+            let mut docs = execute_query_documents_within(&q, &idx, &budget)
+                .map(|p| p.document_id)
+                .collect::<Vec<u32>>();
+            docs.dedup();
+            assert_eq!(docs, matching_documents(query, &idx), "{}", query);
+        }
+    }
+}
+
+#[test]
+fn test_needs_positions() {
+    for (query, expected) in [
+        ("pumpkin pie", false),
+        ("pumpkin AND NOT pie", false),
+        ("#EDITED,2020-01-01,*,pumpkin", false),
+        ("pumpkin OR \"pumpkin pie\"", true),
+        ("#NEAR,2,pumpkin,latte", true),
+        ("#TITLE pumpkin", true),
+    ] {
+        let (_, q) = parse_query(query).unwrap();
+        assert_eq!(needs_positions(&q), expected, "{}", query);
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::NaiveDate;
 use std::fmt::{self, Display};
 use strum_macros::IntoStaticStr;
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -54,13 +55,17 @@ pub enum Query {
         sub: Box<Query>,
     },
     PhraseQuery {
-        // TODO: can probably just compound over distance queries
         tks: Vec<String>,
+        // position of each token relative to the start of the phrase,
+        // words dropped during preprocessing leave gaps
+        offsets: Vec<u32>,
     },
     DistanceQuery {
         dst: u32,
-        lhs: String,
-        rhs: String,
+        // `#DIST` needs rhs to come after lhs, `#NEAR` takes either order
+        ordered: bool,
+        lhs: Box<Query>,
+        rhs: Box<Query>,
     },
     StructureQuery {
         elem: StructureElem,
@@ -71,6 +76,12 @@ pub enum Query {
         hops: u8,
         sub: Option<Box<Query>>,
     },
+    EditedQuery {
+        // inclusive bounds on the day documents were last edited, either may be left open
+        after: Option<NaiveDate>,
+        before: Option<NaiveDate>,
+        sub: Box<Query>,
+    },
     WildcardQuery {
         prefix: String, // before wildcard
         suffix: String, // after wildcard
@@ -113,12 +124,21 @@ impl Display for Query {
         match self {
             Query::BinaryQuery { op, lhs, rhs } => write!(f, "{} {} {}", lhs, op, rhs),
             Query::UnaryQuery { op, sub } => write!(f, "{} {}", op, sub),
-            Query::PhraseQuery { tks } => write!(f, "\"{}\"", tks.join(" ")),
-            Query::DistanceQuery { dst, lhs, rhs } => {
+            Query::PhraseQuery { tks, .. } => write!(f, "\"{}\"", tks.join(" ")),
+            Query::DistanceQuery {
+                dst,
+                ordered,
+                lhs,
+                rhs,
+            } => {
                 write!(
                     f,
                     "{},{},{},{}",
-                    crate::parser::DIST_TAG.to_uppercase(),
+                    if *ordered {
+                        crate::parser::DIST_TAG
+                    } else {
+                        crate::parser::NEAR_TAG
+                    },
                     dst,
                     lhs,
                     rhs
@@ -128,6 +148,17 @@ impl Display for Query {
             Query::RelationQuery { root, hops, sub } => {
                 write!(f, "#LINKSTO {},{},{}", root, hops, sub.as_ref().map_or("".to_string(), |v| v.to_string())) //TODO: Probably need to do this in a better way
             }
+            Query::EditedQuery { after, before, sub } => {
+                let bound = |d: &Option<NaiveDate>| d.map_or("*".to_string(), |v| v.to_string());
+                write!(
+                    f,
+                    "{},{},{},{}",
+                    crate::parser::EDITED_TAG,
+                    bound(after),
+                    bound(before),
+                    sub
+                )
+            }
             Query::WildcardQuery { prefix, suffix } => write!(f, "{}*{}", prefix, suffix),
             Query::FreetextQuery { tokens } => {
                 write!(f, "{}", tokens.join(" "))
@@ -1,19 +1,22 @@
 use std::collections::HashSet;
 
 use crate::ast::{BinaryOp, Query, StructureElem, UnaryOp};
+use chrono::NaiveDate;
 
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag, tag_no_case, take_until, take_while, take_while1},
     character::complete::{digit0, digit1, anychar},
     character::{is_alphanumeric, is_space},
-    combinator::{eof, not, peek},
-    multi::{many1, separated_list0},
+    combinator::{eof, not, peek, verify},
+    multi::{many1, separated_list0, separated_list1},
     sequence::terminated,
     IResult,
 };
 
 pub const DIST_TAG: &str = "#DIST";
+pub const NEAR_TAG: &str = "#NEAR";
+pub const EDITED_TAG: &str = "#EDITED";
 
 // Helper functions
 
@@ -69,27 +72,39 @@ pub fn is_and(nxt: &str) -> bool {
     return nxt == "AND";
 }
 
-// TODO: Consider more than single tokens (e.g.: #DIST,3,pumpkin pie,latte)
-// Note that this only considers single tokens
 pub fn parse_dist_query(nxt: &str) -> IResult<&str, Box<Query>> {
-    // `#DIST` `,` <number> `,` <term> `,` <term>        # Distance search
+    // (`#DIST` | `#NEAR`) `,` <number> `,` <operand> `,` <operand>        # Distance search
+    // anything left after the operands belongs to an enclosing query, so it is not a distance query on its own
+    terminated(parse_dist_operands, parse_separator_untill_eof)(nxt)
+}
+
+// the distance query at the start of the input, leaving whatever follows its operands.
+// Operands are single tokens, or when the number is followed by a comma, phrases and nested distance queries
+// separated by commas
+fn parse_dist_operands(nxt: &str) -> IResult<&str, Box<Query>> {
     let (nxt, _) = parse_separator(nxt)?;
-    let (nxt, _) = tag(DIST_TAG)(nxt)?;
+    let (nxt, op) = alt((tag(DIST_TAG), tag(NEAR_TAG)))(nxt)?;
     let (nxt, _) = parse_separator(nxt)?;
     let (nxt, d) = digit0(nxt)?;
-    let (nxt, _) = parse_separator(nxt)?;
-    let (nxt, t1) = parse_token(nxt)?;
-    let (nxt, _) = parse_separator(nxt)?;
-    let (nxt, t2) = parse_token(nxt)?;
-    let (remainder, _) = parse_separator(nxt)?;
-    
-    if remainder.len() > 0 {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            //the new struct, instead of the tuple
-            "Too many elements. Not a dist query.",
-            nom::error::ErrorKind::Tag,
-        )));
-    }
+    let (nxt, sep) = parse_separator(nxt)?;
+
+    let (nxt, lhs, rhs) = if sep.contains(',') {
+        let (nxt, lhs) = parse_dist_operand(nxt)?;
+        let (nxt, _) = parse_whitespace0(nxt)?;
+        let (nxt, _) = tag(",")(nxt)?;
+        let (nxt, rhs) = parse_dist_operand(nxt)?;
+        (nxt, lhs, rhs)
+    } else {
+        let (nxt, t1) = parse_token(nxt)?;
+        let (nxt, _) = parse_separator(nxt)?;
+        let (nxt, t2) = parse_token(nxt)?;
+
+        (
+            nxt,
+            Box::new(Query::FreetextQuery { tokens: vec![t1] }),
+            Box::new(Query::FreetextQuery { tokens: vec![t2] }),
+        )
+    };
 
     let dst: u32;
 
@@ -106,13 +121,35 @@ pub fn parse_dist_query(nxt: &str) -> IResult<&str, Box<Query>> {
 
     let dist_query = Query::DistanceQuery {
         dst: dst,
-        lhs: t1,
-        rhs: t2,
+        ordered: op == DIST_TAG,
+        lhs: lhs,
+        rhs: rhs,
     };
 
     Ok((nxt, Box::new(dist_query)))
 }
 
+// one side of a distance query, several words make up a phrase
+fn parse_dist_operand(nxt: &str) -> IResult<&str, Box<Query>> {
+    alt((parse_dist_operands, parse_phrase_query, parse_dist_words))(nxt)
+}
+
+// the words up to the next comma, stopping short of any operator joining the distance query to another
+fn parse_dist_words(nxt: &str) -> IResult<&str, Box<Query>> {
+    let (nxt, _) = parse_separator(nxt)?;
+    let (nxt, tokens) = separated_list1(
+        take_while1(|c| c != ',' && is_seperator(c)),
+        verify(parse_token, |t: &String| !is_and(t) && !is_or(t) && t != "NOT"),
+    )(nxt)?;
+
+    if tokens.len() == 1 {
+        return Ok((nxt, Box::new(Query::FreetextQuery { tokens })));
+    }
+
+    let offsets = (0..tokens.len() as u32).collect();
+    Ok((nxt, Box::new(Query::PhraseQuery { tks: tokens, offsets })))
+}
+
 pub fn parse_query(nxt: &str) -> IResult<&str, Box<Query>> {
     if nxt.chars().count() == 0 {
         return Err(nom::Err::Error(nom::error::Error::new(
@@ -125,6 +162,7 @@ pub fn parse_query(nxt: &str) -> IResult<&str, Box<Query>> {
     alt((
         terminated(parse_dist_query, parse_separator_untill_eof),
         terminated(parse_relation_query, parse_separator_untill_eof),
+        terminated(parse_edited_query, parse_separator_untill_eof),
         terminated(parse_structure_query, parse_separator_untill_eof),
         terminated(parse_not_query, parse_separator_untill_eof),
         terminated(parse_binary_query, parse_separator_untill_eof),
@@ -146,6 +184,7 @@ fn parse_query_sub(nxt: &str) -> IResult<&str, Box<Query>> {
     alt((
         parse_dist_query,
         parse_relation_query,
+        parse_edited_query,
         parse_structure_query,
         parse_not_query,
         parse_binary_query,
@@ -159,7 +198,10 @@ pub fn parse_structure_query(nxt: &str) -> IResult<&str, Box<Query>> {
     let (nxt, _) = parse_separator(nxt)?;
     let (nxt, struct_elem) = parse_structure_elem(nxt)?;
 
-    if struct_elem == StructureElem::Infobox("dist".to_string()) {
+    if struct_elem == StructureElem::Infobox("dist".to_string())
+        || struct_elem == StructureElem::Infobox("near".to_string())
+        || struct_elem == StructureElem::Infobox("edited".to_string())
+    {
         return Err(nom::Err::Error(nom::error::Error::new(
             //the new struct, instead of the tuple
             "detected DIST. Not a structure query",
@@ -315,8 +357,35 @@ pub fn parse_and_query(nxt: &str) -> IResult<&str, Box<Query>> {
     ));
 }
 
+// `a NOT b` is shorthand for `a AND NOT b`
+pub fn parse_and_not_query(nxt: &str) -> IResult<&str, Box<Query>> {
+    let (nxt, _) = parse_separator(nxt)?;
+    let (query2, query1) = alt((
+        take_until(" NOT"),
+        take_until(",NOT")
+    ))(nxt)?;
+
+    let (query2, _) = anychar(query2)?;
+    let (query2, _) = tag("NOT")(query2)?;
+    let (query2, _) = parse_separator1(query2)?;
+    let (_nxt, q1) = parse_query_sub(query1)?;
+    let (nxt, q2) = parse_query_sub(query2)?;
+
+    return Ok((
+        nxt,
+        Box::new(Query::BinaryQuery {
+            op: BinaryOp::And,
+            lhs: q1,
+            rhs: Box::new(Query::UnaryQuery {
+                op: UnaryOp::Not,
+                sub: q2,
+            }),
+        }),
+    ));
+}
+
 pub fn parse_binary_query(nxt: &str) -> IResult<&str, Box<Query>> {
-    alt((parse_and_query, parse_or_query))(nxt)
+    alt((parse_and_query, parse_or_query, parse_and_not_query))(nxt)
 }
 
 // TODO: Remove separators
@@ -395,6 +464,50 @@ pub fn parse_relation_query(nxt: &str) -> IResult<&str, Box<Query>> {
     alt((parse_nested_relation_query, parse_simple_relation_query))(nxt)
 }
 
+pub fn parse_edited_query(nxt: &str) -> IResult<&str, Box<Query>> {
+    // `#EDITED` `,` (<date> | `*`) `,` (<date> | `*`) `,` <query>      # Last edited within a date range
+    let (nxt, _) = parse_separator(nxt)?;
+    let (nxt, _) = tag_no_case(EDITED_TAG)(nxt)?;
+    let (nxt, _) = parse_separator(nxt)?;
+    let (nxt, after) = parse_date_bound(nxt)?;
+    let (nxt, _) = parse_separator(nxt)?;
+    let (nxt, before) = parse_date_bound(nxt)?;
+    let (nxt, _) = parse_separator(nxt)?;
+    let (nxt, sub) = parse_query_sub(nxt)?;
+
+    Ok((nxt, Box::new(Query::EditedQuery { after, before, sub })))
+}
+
+// `*` leaves the bound open
+fn parse_date_bound(nxt: &str) -> IResult<&str, Option<NaiveDate>> {
+    match tag::<&str, &str, nom::error::Error<&str>>("*")(nxt) {
+        Ok((nxt, _)) => Ok((nxt, None)),
+        Err(_) => parse_date(nxt).map(|(nxt, d)| (nxt, Some(d))),
+    }
+}
+
+/// parses a `YYYY-MM-DD` date
+pub fn parse_date(nxt: &str) -> IResult<&str, NaiveDate> {
+    let (nxt, y) = digit1(nxt)?;
+    let (nxt, _) = tag("-")(nxt)?;
+    let (nxt, m) = digit1(nxt)?;
+    let (nxt, _) = tag("-")(nxt)?;
+    let (nxt, d) = digit1(nxt)?;
+
+    let date = match (y.parse(), m.parse(), d.parse()) {
+        (Ok(y), Ok(m), Ok(d)) => NaiveDate::from_ymd_opt(y, m, d),
+        _ => None,
+    };
+
+    match date {
+        Some(v) => Ok((nxt, v)),
+        None => Err(nom::Err::Error(nom::error::Error::new(
+            "Not a valid date.",
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
 pub fn parse_phrase_query(nxt: &str) -> IResult<&str, Box<Query>> {
     let (nxt, _) = parse_separator(nxt)?;
     let (nxt, _) = tag("\"")(nxt)?;
@@ -403,5 +516,12 @@ pub fn parse_phrase_query(nxt: &str) -> IResult<&str, Box<Query>> {
     let (nxt, _) = parse_separator(nxt)?;
     let (nxt, _) = tag("\"")(nxt)?;
 
-    Ok((nxt, Box::new(Query::PhraseQuery { tks: tokens })))
+    let offsets = (0..tokens.len() as u32).collect();
+    Ok((
+        nxt,
+        Box::new(Query::PhraseQuery {
+            tks: tokens,
+            offsets,
+        }),
+    ))
 }
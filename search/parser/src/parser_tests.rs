@@ -2,6 +2,7 @@ use crate::{
     ast::{BinaryOp, Query, StructureElem, UnaryOp},
     parser::parse_query,
 };
+use chrono::NaiveDate;
 
 // AST Helper Functions
 
@@ -33,9 +34,9 @@ macro_rules! test_parse_to {
 
 test_parse_and_print!(test_parse_print_freetext, Box::new(Query::FreetextQuery { tokens: vec!["world".to_string(),"dog".to_string()] }));
 test_parse_and_print!(test_parse_print_freetext_2, Box::new(Query::FreetextQuery { tokens: vec!["world".to_string()] }));
-test_parse_and_print!(test_parse_print_phrase, Box::new(Query::PhraseQuery { tks: vec!["world".to_string(),"dog".to_string()] }));
-test_parse_and_print!(test_parse_print_phrase_2, Box::new(Query::PhraseQuery { tks: vec!["world".to_string()] }));
-test_parse_and_print!(test_parse_print_dist, Box::new(Query::DistanceQuery { dst: 3, lhs: "world".to_string(), rhs: "dog".to_string() }));
+test_parse_and_print!(test_parse_print_phrase, Box::new(Query::PhraseQuery { tks: vec!["world".to_string(),"dog".to_string()], offsets: vec![0, 1] }));
+test_parse_and_print!(test_parse_print_phrase_2, Box::new(Query::PhraseQuery { tks: vec!["world".to_string()], offsets: vec![0] }));
+test_parse_and_print!(test_parse_print_dist, Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["world".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["dog".to_string()] }) }));
 test_parse_and_print!(test_parse_print_structure, Box::new(Query::StructureQuery { 
     elem: StructureElem::Title, 
     sub:  Box::new(Query::FreetextQuery { tokens: vec!["world".to_string(),"dog".to_string()] })
@@ -142,8 +143,9 @@ test_parse_and_print!(test_parse_print_and, Box::new(Query::BinaryQuery {
 test_parse_to!(test_parse_complex_2, "#DIST 3 BOR AND",
 Box::new(Query::DistanceQuery { 
     dst: 3,
-    lhs: "BOR".to_string(),
-    rhs: "AND".to_string(),
+    ordered: true,
+    lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }),
 }));
 
 
@@ -151,7 +153,7 @@ test_parse_to!(test_parse_complex_3, "#LINKSTO 1337 3 #DIST 3 BOR AND",
 Box::new(Query::RelationQuery{ 
     root: 1337, 
     hops: 3, 
-    sub: Some(Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })) 
+    sub: Some(Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })) 
 }));
 
 test_parse_to!(test_parse_complex_4, "#LINKSTO 1337 3 #CATEGORY #DIST 3 BOR AND",
@@ -161,7 +163,7 @@ Box::new(Query::RelationQuery{
     sub: Some(
         Box::new(Query::StructureQuery { 
             elem: StructureElem::Category, 
-            sub: Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })
+            sub: Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })
         })
     ) 
 }));
@@ -176,7 +178,7 @@ Box::new(Query::RelationQuery{
             sub:  
             Box::new(Query::StructureQuery { 
                 elem: StructureElem::Category, 
-                sub: Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })
+                sub: Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })
             })
         })
        
@@ -193,7 +195,7 @@ Box::new(Query::RelationQuery{
             sub: 
             Box::new(Query::UnaryQuery { 
                 op: UnaryOp::Not, 
-                sub: Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })
+                sub: Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })
             }) 
         })
     ) 
@@ -211,8 +213,8 @@ Box::new(Query::RelationQuery{
                 op: UnaryOp::Not, 
                 sub: Box::new(Query::BinaryQuery { 
                     op: BinaryOp::And, 
-                    lhs: Box::new(Query::PhraseQuery { tks: vec!["april".to_string(),"may".to_string()] }), 
-                    rhs: Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })
+                    lhs: Box::new(Query::PhraseQuery { tks: vec!["april".to_string(),"may".to_string()], offsets: vec![0, 1] }), 
+                    rhs: Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })
                 })
             }) 
         })
@@ -232,7 +234,7 @@ Box::new(Query::StructureQuery{
                 sub: Box::new(Query::BinaryQuery { 
                     op: BinaryOp::And, 
                     lhs: Box::new(Query::WildcardQuery { prefix: "april".to_string(), suffix: "may".to_string() }), 
-                    rhs: Box::new(Query::DistanceQuery { dst: 3, lhs: "BOR".to_string(), rhs: "AND".to_string() })
+                    rhs: Box::new(Query::DistanceQuery { dst: 3, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["BOR".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["AND".to_string()] }) })
                 })
             }))
         })
@@ -245,7 +247,7 @@ Box::new(Query::BinaryQuery {
     lhs: Box::new(Query::FreetextQuery { tokens: vec!["a".to_string()] }), 
     rhs: Box::new(Query::BinaryQuery { 
         op: BinaryOp::And, 
-        lhs: Box::new(Query::PhraseQuery { tks: vec!["b".to_string()] }), 
+        lhs: Box::new(Query::PhraseQuery { tks: vec!["b".to_string()], offsets: vec![0] }), 
         rhs: Box::new(Query::UnaryQuery { 
             op: UnaryOp::Not, 
             sub: Box::new(Query::BinaryQuery { 
@@ -266,8 +268,9 @@ Box::new(Query::BinaryQuery {
                                     lhs: Box::new(Query::FreetextQuery { tokens: vec!["i".to_string()] }), 
                                     rhs: Box::new(Query::DistanceQuery { 
                                         dst: 1, 
-                                        lhs: "e".to_string(), 
-                                        rhs: "f".to_string()
+                                        ordered: true,
+                                        lhs: Box::new(Query::FreetextQuery { tokens: vec!["e".to_string()] }), 
+                                        rhs: Box::new(Query::FreetextQuery { tokens: vec!["f".to_string()] })
                                     })
                                 }) 
                             })
@@ -289,7 +292,7 @@ Box::new(Query::BinaryQuery {
         lhs: Box::new(Query::FreetextQuery { tokens: vec!["a".to_string()] }), 
         rhs: Box::new(Query::BinaryQuery { 
             op: BinaryOp::And, 
-            lhs: Box::new(Query::PhraseQuery { tks: vec!["b".to_string()] }), 
+            lhs: Box::new(Query::PhraseQuery { tks: vec!["b".to_string()], offsets: vec![0] }), 
             rhs:Box::new(Query::BinaryQuery { 
                     op: BinaryOp::And, 
                     lhs: Box::new(Query::FreetextQuery { tokens: vec!["d".to_string()] }), 
@@ -303,7 +306,7 @@ Box::new(Query::BinaryQuery {
                                 sub: Box::new(Query::BinaryQuery { 
                                     op: BinaryOp::And, 
                                     lhs: Box::new(Query::FreetextQuery { tokens: vec!["i".to_string()] }), 
-                                    rhs: Box::new(Query::DistanceQuery { dst: 1, lhs: "e".to_string(), rhs: "f".to_string() }) 
+                                    rhs: Box::new(Query::DistanceQuery { dst: 1, ordered: true, lhs: Box::new(Query::FreetextQuery { tokens: vec!["e".to_string()] }), rhs: Box::new(Query::FreetextQuery { tokens: vec!["f".to_string()] }) }) 
                                 }) 
                             })
                         }) 
@@ -331,13 +334,119 @@ fn test_freehand_query() {
     assert_eq!(tokens[1], "world");
 }
 
+test_parse_and_print!(test_parse_print_near, Box::new(Query::DistanceQuery {
+    dst: 2,
+    ordered: false,
+    lhs: Box::new(Query::PhraseQuery { tks: vec!["pumpkin".to_string(),"pie".to_string()], offsets: vec![0, 1] }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["latte".to_string()] })
+}));
+
+test_parse_to!(test_parse_dist_multi_word, "#DIST,3,pumpkin pie,latte",
+Box::new(Query::DistanceQuery {
+    dst: 3,
+    ordered: true,
+    lhs: Box::new(Query::PhraseQuery { tks: vec!["pumpkin".to_string(),"pie".to_string()], offsets: vec![0, 1] }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["latte".to_string()] })
+}));
+
+test_parse_to!(test_parse_near_phrase, "#NEAR, 2, whale, \"big tuna\"",
+Box::new(Query::DistanceQuery {
+    dst: 2,
+    ordered: false,
+    lhs: Box::new(Query::FreetextQuery { tokens: vec!["whale".to_string()] }),
+    rhs: Box::new(Query::PhraseQuery { tks: vec!["big".to_string(),"tuna".to_string()], offsets: vec![0, 1] })
+}));
+
+test_parse_to!(test_parse_nested_dist, "#NEAR,5,whale,#DIST,2,pumpkin pie,latte",
+Box::new(Query::DistanceQuery {
+    dst: 5,
+    ordered: false,
+    lhs: Box::new(Query::FreetextQuery { tokens: vec!["whale".to_string()] }),
+    rhs: Box::new(Query::DistanceQuery {
+        dst: 2,
+        ordered: true,
+        lhs: Box::new(Query::PhraseQuery { tks: vec!["pumpkin".to_string(),"pie".to_string()], offsets: vec![0, 1] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["latte".to_string()] })
+    })
+}));
+
+test_parse_to!(test_parse_nested_dist_lhs, "#NEAR,5,#DIST 2 pumpkin latte,whale",
+Box::new(Query::DistanceQuery {
+    dst: 5,
+    ordered: false,
+    lhs: Box::new(Query::DistanceQuery {
+        dst: 2,
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string()] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["latte".to_string()] })
+    }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["whale".to_string()] })
+}));
+
+test_parse_to!(test_parse_nested_dist_lhs_commas, "#DIST,3,#NEAR,2,a,b,c",
+Box::new(Query::DistanceQuery {
+    dst: 3,
+    ordered: true,
+    lhs: Box::new(Query::DistanceQuery {
+        dst: 2,
+        ordered: false,
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["a".to_string()] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["b".to_string()] })
+    }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["c".to_string()] })
+}));
+
+test_parse_to!(test_parse_dist_followed_by_and, "#DIST,3,a,b AND c",
+Box::new(Query::BinaryQuery {
+    op: BinaryOp::And,
+    lhs: Box::new(Query::DistanceQuery {
+        dst: 3,
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["a".to_string()] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["b".to_string()] })
+    }),
+    rhs: Box::new(Query::FreetextQuery { tokens: vec!["c".to_string()] })
+}));
+
+test_parse_and_print!(test_parse_print_edited, Box::new(Query::EditedQuery {
+    after: Some(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()),
+    before: None,
+    sub: Box::new(Query::FreetextQuery { tokens: vec!["cat".to_string(),"dog".to_string()] })
+}));
+
+test_parse_to!(test_parse_edited, "#EDITED, 2019-05-01, 2019-12-31, #TITLE pumpkin",
+Box::new(Query::EditedQuery {
+    after: Some(NaiveDate::from_ymd_opt(2019, 5, 1).unwrap()),
+    before: Some(NaiveDate::from_ymd_opt(2019, 12, 31).unwrap()),
+    sub: Box::new(Query::StructureQuery {
+        elem: StructureElem::Title,
+        sub: Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string()] })
+    })
+}));
+
+test_parse_to!(test_parse_edited_open, "#edited,*,*,\"pumpkin pie\"",
+Box::new(Query::EditedQuery {
+    after: None,
+    before: None,
+    sub: Box::new(Query::PhraseQuery { tks: vec!["pumpkin".to_string(),"pie".to_string()], offsets: vec![0, 1] })
+}));
+
+#[test]
+fn test_parse_edited_invalid_date() {
+    assert!(parse_query("#EDITED,2019-13-01,*,pumpkin").is_err());
+    assert!(parse_query("#EDITED,2019-02-30,*,pumpkin").is_err());
+    assert!(parse_query("#EDITED,yesterday,*,pumpkin").is_err());
+}
+
 #[test]
 fn test_dist_query() {
     let query = " #DIST , 3 , pumpkin,pie ";
     let (_s, dist_node) = parse_query(query).unwrap();
     match *dist_node {
-        Query::DistanceQuery { dst, lhs, rhs } => {
-            assert!(dst == 3 && lhs == "pumpkin" && rhs == "pie")
+        Query::DistanceQuery { dst, ordered, lhs, rhs } => {
+            assert!(dst == 3 && ordered);
+            assert_eq!(lhs, Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string()] }));
+            assert_eq!(rhs, Box::new(Query::FreetextQuery { tokens: vec!["pie".to_string()] }));
         }
         _ => assert!(false),
     }
@@ -348,8 +457,10 @@ fn test_dist_query_2() {
     let query = " #DIST 3 pumpkin pie ";
     let (_s, dist_node) = parse_query(query).unwrap();
     match *dist_node {
-        Query::DistanceQuery { dst, lhs, rhs } => {
-            assert!(dst == 3 && lhs == "pumpkin" && rhs == "pie")
+        Query::DistanceQuery { dst, ordered, lhs, rhs } => {
+            assert!(dst == 3 && ordered);
+            assert_eq!(lhs, Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string()] }));
+            assert_eq!(rhs, Box::new(Query::FreetextQuery { tokens: vec!["pie".to_string()] }));
         }
         _ => assert!(false),
     }
@@ -366,8 +477,9 @@ fn test_complex_dist_with_binary_query() {
     });
     let r = Box::new(Query::DistanceQuery {
         dst: 4,
-        lhs: "boris".to_string(),
-        rhs: "johnson".to_string(),
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["boris".to_string()] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["johnson".to_string()] }),
     });
     let expected = Box::new(
         Query::BinaryQuery { op: (BinaryOp::And), lhs: (l), rhs: (r) }
@@ -387,8 +499,9 @@ fn test_complex_dist_with_binary_query_2() {
     });
     let r1 = Box::new(Query::DistanceQuery {
         dst: 4,
-        lhs: "boris".to_string(),
-        rhs: "johnson".to_string(),
+        ordered: true,
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["boris".to_string()] }),
+        rhs: Box::new(Query::FreetextQuery { tokens: vec!["johnson".to_string()] }),
     });
     let r2 = Box::new(Query::StructureQuery { 
         elem: (StructureElem::Citation), 
@@ -458,13 +571,29 @@ test_parse_to!(test_simple_or_query,
         rhs: Box::new(Query::FreetextQuery { tokens: vec!["pie".to_string()] }) 
 }));
 
+test_parse_to!(test_simple_and_not_query,
+    "pumpkin NOT pie",
+    Box::new(Query::BinaryQuery { 
+        op: BinaryOp::And, 
+        lhs: Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string()] }), 
+        rhs: Box::new(Query::UnaryQuery { 
+            op: UnaryOp::Not, 
+            sub: Box::new(Query::FreetextQuery { tokens: vec!["pie".to_string()] }) 
+        }) 
+}));
+
+test_parse_to!(test_and_not_query_prefix_of_token,
+    "pumpkin NOTHING pie",
+    Box::new(Query::FreetextQuery { tokens: vec!["pumpkin".to_string(), "NOTHING".to_string(), "pie".to_string()] })
+);
+
 #[test]
 fn test_phrase_freetext_query () {
     let query = "fresh,AND,\"goat\"";
 
     let (_, actual) = parse_query(query).unwrap();
     let lhs = Box::new(Query::FreetextQuery { tokens: (vec!["fresh".to_string()]) });
-    let rhs = Box::new(Query::PhraseQuery { tks: (vec!["goat".to_string()]) });
+    let rhs = Box::new(Query::PhraseQuery { tks: (vec!["goat".to_string()]), offsets: vec![0] });
 
     let expected = Box::new(Query::BinaryQuery { op: (BinaryOp::And), lhs: (lhs), rhs: (rhs) });
 
@@ -773,7 +902,10 @@ fn test_compound_query_or_and_2() {
 fn test_parse_simple_phrase_query_no_spaces() {
     let query = "\"April\"";
     let expected_tks = vec!["April".to_string()];
-    let expected = Box::new(Query::PhraseQuery { tks: expected_tks });
+    let expected = Box::new(Query::PhraseQuery {
+        offsets: (0..expected_tks.len() as u32).collect(),
+        tks: expected_tks,
+    });
     let (_, actual) = parse_query(query).unwrap();
     assert!(actual == expected);
 }
@@ -790,7 +922,10 @@ fn test_parse_simple_phrase_query() {
         "tuna".to_string(),
         "sandwich".to_string(),
     ];
-    let expected = Box::new(Query::PhraseQuery { tks: expected_tks });
+    let expected = Box::new(Query::PhraseQuery {
+        offsets: (0..expected_tks.len() as u32).collect(),
+        tks: expected_tks,
+    });
     let (_, actual) = parse_query(query).unwrap();
     assert!(actual == expected);
 }
@@ -807,7 +942,10 @@ fn test_parse_complex_phrase_query() {
         "tuna".to_string(),
         "sandwich".to_string(),
     ];
-    let expected = Box::new(Query::PhraseQuery { tks: expected_tks });
+    let expected = Box::new(Query::PhraseQuery {
+        offsets: (0..expected_tks.len() as u32).collect(),
+        tks: expected_tks,
+    });
     let (_, actual) = parse_query(query).unwrap();
     assert!(actual == expected);
 }
@@ -0,0 +1,173 @@
+use index::{index_structs::Posting, SkipIterator};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// how many postings or documents pass between looking at the clock
+pub const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+/// why a query was cut short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    Time,
+    Postings,
+}
+
+const WITHIN_BUDGET: u8 = 0;
+const EXCEEDED_TIME: u8 = 1;
+const EXCEEDED_POSTINGS: u8 = 2;
+
+/// limits the work done for a single request, shared by everything executing and scoring its query.
+/// Once exceeded no further posting lists are read and scoring stops, leaving partial results behind.
+/// Lists left out only ever take documents away from the results, negations stop producing anything
+/// since their results would grow instead
+#[derive(Debug)]
+pub struct QueryBudget {
+    deadline: Option<Instant>,
+    max_postings: Option<u64>,
+    postings: AtomicU64,
+    exceeded: AtomicU8,
+}
+
+impl Default for QueryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl QueryBudget {
+    pub const fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            max_postings: None,
+            postings: AtomicU64::new(0),
+            exceeded: AtomicU8::new(WITHIN_BUDGET),
+        }
+    }
+
+    pub fn new(timeout: Option<Duration>, max_postings: Option<u64>) -> Self {
+        Self {
+            deadline: timeout.map(|t| Instant::now() + t),
+            max_postings,
+            ..Self::unlimited()
+        }
+    }
+
+    pub fn exceeded(&self) -> Option<BudgetExceeded> {
+        match self.exceeded.load(Ordering::Relaxed) {
+            EXCEEDED_TIME => Some(BudgetExceeded::Time),
+            EXCEEDED_POSTINGS => Some(BudgetExceeded::Postings),
+            _ => None,
+        }
+    }
+
+    /// the number of postings read so far
+    pub fn postings_read(&self) -> u64 {
+        self.postings.load(Ordering::Relaxed)
+    }
+
+    /// accounts for postings about to be read from the index, false if they are over budget
+    pub fn charge(&self, postings: u64) -> bool {
+        if self.exceeded().is_some() {
+            return false;
+        }
+
+        let total = self.postings.fetch_add(postings, Ordering::Relaxed) + postings;
+        if self.max_postings.map_or(false, |max| total > max) {
+            self.exceed(EXCEEDED_POSTINGS);
+            return false;
+        }
+
+        self.check_time()
+    }
+
+    /// false once the deadline has passed, postings which were already read can still be used up
+    /// after going over the posting limit
+    pub fn check_time(&self) -> bool {
+        if self.exceeded() == Some(BudgetExceeded::Time) {
+            return false;
+        }
+
+        match self.deadline {
+            Some(d) if Instant::now() >= d => {
+                self.exceed(EXCEEDED_TIME);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn exceed(&self, reason: u8) {
+        // the first reason sticks
+        let _ = self.exceeded.compare_exchange(
+            WITHIN_BUDGET,
+            reason,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// stops yielding postings once the budget runs out, looking at the clock every so often
+pub struct BudgetedIterator<'a, I> {
+    inner: I,
+    budget: &'a QueryBudget,
+    yielded: u64,
+}
+
+impl<'a, I> BudgetedIterator<'a, I> {
+    pub fn new(inner: I, budget: &'a QueryBudget) -> Self {
+        Self {
+            inner,
+            budget,
+            yielded: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Posting>> Iterator for BudgetedIterator<'_, I> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.yielded % CLOCK_CHECK_INTERVAL == 0 && !self.budget.check_time() {
+            return None;
+        }
+        self.yielded += 1;
+        self.inner.next()
+    }
+}
+
+impl<I: SkipIterator> SkipIterator for BudgetedIterator<'_, I> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        if self.yielded % CLOCK_CHECK_INTERVAL == 0 && !self.budget.check_time() {
+            return None;
+        }
+        self.yielded += 1;
+        self.inner.skip_to(doc_id)
+    }
+}
+
+/// stops yielding postings as soon as the budget runs out, for operators such as negations whose results
+/// would be wrong rather than partial with a sub query cut short. Sub queries are cut short before
+/// the budget reads as exceeded, so a posting computed from one is never let through
+pub struct UntilExceeded<'a, I> {
+    inner: I,
+    budget: &'a QueryBudget,
+}
+
+impl<'a, I> UntilExceeded<'a, I> {
+    pub fn new(inner: I, budget: &'a QueryBudget) -> Self {
+        Self { inner, budget }
+    }
+}
+
+impl<I: Iterator<Item = Posting>> Iterator for UntilExceeded<'_, I> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let posting = self.inner.next()?;
+        match self.budget.exceeded() {
+            Some(_) => None,
+            None => Some(posting),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::budget::{BudgetExceeded, QueryBudget};
+use crate::search::{execute_query, execute_query_within, score_query_within};
+use crate::Ranking;
+use index::index::Index;
+use index::utils::get_document_with_text;
+use index::{PreIndex, Posting};
+use parser::ast::Query;
+use parser::{BinaryOp, UnaryOp};
+use std::time::Duration;
+
+fn budget_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(1, "d1", vec![], "budgetx budgety", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(2, "d2", vec![], "budgetx", vec![], ""))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(3, "d3", vec![], "budgety budgety", vec![], ""))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn budget_query() -> Box<Query> {
+    Box::new(Query::FreetextQuery {
+        tokens: vec!["budgetx".to_string(), "budgety".to_string()],
+    })
+}
+
+fn documents<I: Iterator<Item = Posting>>(postings: I) -> Vec<u32> {
+    let mut docs = postings.map(|p| p.document_id).collect::<Vec<u32>>();
+    docs.dedup();
+    docs
+}
+
+#[test]
+fn test_budget_unlimited() {
+    let budget = QueryBudget::unlimited();
+
+    assert!(budget.charge(u32::MAX as u64));
+    assert!(budget.check_time());
+    assert_eq!(budget.exceeded(), None);
+    assert_eq!(budget.postings_read(), u32::MAX as u64);
+}
+
+#[test]
+fn test_budget_postings() {
+    let budget = QueryBudget::new(None, Some(10));
+
+    assert!(budget.charge(6));
+    assert!(!budget.charge(6));
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Postings));
+    // stays exceeded, but there is still time for what was read
+    assert!(!budget.charge(0));
+    assert!(budget.check_time());
+}
+
+#[test]
+fn test_budget_deadline() {
+    let budget = QueryBudget::new(Some(Duration::ZERO), None);
+
+    assert!(!budget.check_time());
+    assert!(!budget.charge(1));
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Time));
+}
+
+#[test]
+fn test_execute_within_postings_budget() {
+    let idx = budget_index();
+    let query = budget_query();
+
+    assert_eq!(documents(execute_query(&query, &idx)), vec![1, 2, 3]);
+
+    // budgetx fits, budgety would go over
+    let budget = QueryBudget::new(None, Some(2));
+    assert_eq!(
+        documents(execute_query_within(&query, &idx, &budget)),
+        vec![1, 2]
+    );
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Postings));
+}
+
+#[test]
+fn test_execute_within_deadline() {
+    let idx = budget_index();
+    let query = budget_query();
+
+    let budget = QueryBudget::new(Some(Duration::ZERO), None);
+    assert!(documents(execute_query_within(&query, &idx, &budget)).is_empty());
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Time));
+}
+
+#[test]
+fn test_score_within_budget() {
+    let idx = budget_index();
+    let query = budget_query();
+    let postings = execute_query(&query, &idx).collect::<Vec<Posting>>();
+
+    let budget = QueryBudget::new(Some(Duration::from_secs(60)), None);
+    let scored = score_query_within(&query, &idx, &mut postings.clone(), Ranking::TfIdf, &budget);
+    assert_eq!(scored.len(), 3);
+    assert_eq!(budget.exceeded(), None);
+
+    let budget = QueryBudget::new(Some(Duration::ZERO), None);
+    let scored = score_query_within(&query, &idx, &mut postings.clone(), Ranking::TfIdf, &budget);
+    assert!(scored.is_empty());
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Time));
+}
+
+fn token(t: &str) -> Box<Query> {
+    Box::new(Query::FreetextQuery {
+        tokens: vec![t.to_string()],
+    })
+}
+
+fn not(sub: Box<Query>) -> Box<Query> {
+    Box::new(Query::UnaryQuery {
+        op: UnaryOp::Not,
+        sub,
+    })
+}
+
+#[test]
+fn test_execute_within_budget_negation() {
+    let idx = budget_index();
+    let query = Box::new(Query::BinaryQuery {
+        op: BinaryOp::And,
+        lhs: token("budgetx"),
+        rhs: not(token("budgety")),
+    });
+    assert_eq!(documents(execute_query(&query, &idx)), vec![2]);
+
+    // budgety would go over, without it document 1 would be let through
+    let budget = QueryBudget::new(None, Some(2));
+    assert!(documents(execute_query_within(&query, &idx, &budget)).is_empty());
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Postings));
+
+    // every document fits, but not the ones to leave out
+    let query = not(token("budgetx"));
+    assert_eq!(documents(execute_query(&query, &idx)), vec![3]);
+    let budget = QueryBudget::new(None, Some(3));
+    assert!(documents(execute_query_within(&query, &idx, &budget)).is_empty());
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Postings));
+}
+
+#[test]
+fn test_unordered_distance_reads_operands_once() {
+    let idx = budget_index();
+    let distance = |ordered| {
+        Box::new(Query::DistanceQuery {
+            dst: 1,
+            ordered,
+            lhs: Box::new(Query::FreetextQuery {
+                tokens: vec!["budgetx".to_string()],
+            }),
+            rhs: Box::new(Query::FreetextQuery {
+                tokens: vec!["budgety".to_string()],
+            }),
+        })
+    };
+
+    let ordered = QueryBudget::unlimited();
+    assert_eq!(
+        documents(execute_query_within(&distance(true), &idx, &ordered)),
+        vec![1]
+    );
+    let unordered = QueryBudget::unlimited();
+    assert_eq!(
+        documents(execute_query_within(&distance(false), &idx, &unordered)),
+        vec![1]
+    );
+    assert_eq!(unordered.postings_read(), ordered.postings_read());
+}
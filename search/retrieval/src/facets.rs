@@ -0,0 +1,88 @@
+use index::{index::Index, index_structs::Posting, DocumentField};
+use std::collections::{HashMap, HashSet};
+
+/// the number of values returned per facet
+pub const FACET_LIMIT: usize = 20;
+
+/// a value results can be narrowed down by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Facet {
+    Category(String),
+    Infobox(String),
+}
+
+impl Facet {
+    pub fn matches(&self, doc_id: u32, index: &Index) -> bool {
+        match self {
+            Facet::Category(c) => index.get_categories(doc_id).iter().any(|v| v == c),
+            Facet::Infobox(t) => {
+                DocumentField::from(t.as_str()) == DocumentField::Infobox
+                    && index.get_extent_for(t, &doc_id).is_some()
+            }
+        }
+    }
+}
+
+/// the most common values of each facet with the number of documents holding them,
+/// ordered by count and then by value
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FacetCounts {
+    pub categories: Vec<(String, u32)>,
+    pub infoboxes: Vec<(String, u32)>,
+}
+
+/// drops the postings of documents which don't match every one of the facets
+pub fn filter_by_facets(postings: &mut Vec<Posting>, facets: &[Facet], index: &Index) {
+    if facets.is_empty() {
+        return;
+    }
+
+    let allowed = postings
+        .iter()
+        .map(|p| p.document_id)
+        .collect::<HashSet<u32>>()
+        .into_iter()
+        .filter(|d| facets.iter().all(|f| f.matches(*d, index)))
+        .collect::<HashSet<u32>>();
+
+    postings.retain(|p| allowed.contains(&p.document_id));
+}
+
+/// counts the facet values over the whole set of documents
+pub fn count_facets(documents: &HashSet<u32>, index: &Index, limit: usize) -> FacetCounts {
+    let mut categories: HashMap<&str, u32> = HashMap::default();
+    for d in documents {
+        for c in index.get_categories(*d) {
+            *categories.entry(c).or_default() += 1;
+        }
+    }
+
+    let infoboxes = index
+        .extent
+        .iter()
+        .filter(|(k, _)| DocumentField::from(k.as_str()) == DocumentField::Infobox)
+        .map(|(k, v)| {
+            let count = if documents.len() < v.len() {
+                documents.iter().filter(|d| v.contains_key(d)).count()
+            } else {
+                v.keys().filter(|d| documents.contains(d)).count()
+            };
+            (k.as_str(), count as u32)
+        })
+        .filter(|(_, count)| *count > 0);
+
+    FacetCounts {
+        categories: top_values(categories.into_iter(), limit),
+        infoboxes: top_values(infoboxes, limit),
+    }
+}
+
+fn top_values<'a, I: Iterator<Item = (&'a str, u32)>>(values: I, limit: usize) -> Vec<(String, u32)> {
+    let mut values = values.collect::<Vec<(&str, u32)>>();
+    values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    values
+        .into_iter()
+        .take(limit)
+        .map(|(v, c)| (v.to_string(), c))
+        .collect()
+}
@@ -0,0 +1,117 @@
+use crate::facets::{count_facets, filter_by_facets, Facet, FacetCounts};
+use index::index::Index;
+use index::utils::get_document_with_text;
+use index::{PreIndex, Posting};
+
+fn facet_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    pre_idx
+        .add_document(get_document_with_text(
+            1,
+            "d1",
+            vec![("infobox film", "aaa")],
+            "bbb",
+            vec![],
+            "1990 films\tAmerican films",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            2,
+            "d2",
+            vec![("infobox film", "aaa"), ("infobox actor", "ccc")],
+            "bbb",
+            vec!["ddd"],
+            "1990 films",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(3, "d3", vec![], "bbb", vec![], "Novels"))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn postings(docs: &[u32]) -> Vec<Posting> {
+    docs.iter()
+        .map(|d| Posting {
+            document_id: *d,
+            position: 1,
+        })
+        .collect()
+}
+
+fn documents(postings: &[Posting]) -> Vec<u32> {
+    postings.iter().map(|p| p.document_id).collect()
+}
+
+#[test]
+fn test_count_facets() {
+    let idx = facet_index();
+
+    assert_eq!(
+        count_facets(&vec![1, 2, 3].into_iter().collect(), &idx, 10),
+        FacetCounts {
+            categories: vec![
+                ("1990 films".to_string(), 2),
+                ("American films".to_string(), 1),
+                ("Novels".to_string(), 1)
+            ],
+            infoboxes: vec![
+                ("infobox film".to_string(), 2),
+                ("infobox actor".to_string(), 1)
+            ],
+        }
+    );
+}
+
+#[test]
+fn test_count_facets_subset_and_limit() {
+    let idx = facet_index();
+
+    assert_eq!(
+        count_facets(&vec![1, 3].into_iter().collect(), &idx, 1),
+        FacetCounts {
+            categories: vec![("1990 films".to_string(), 1)],
+            infoboxes: vec![("infobox film".to_string(), 1)],
+        }
+    );
+    assert_eq!(
+        count_facets(&Default::default(), &idx, 10),
+        FacetCounts::default()
+    );
+}
+
+#[test]
+fn test_filter_by_facets() {
+    let idx = facet_index();
+
+    let mut p = postings(&[1, 2, 2, 3]);
+    filter_by_facets(&mut p, &[], &idx);
+    assert_eq!(documents(&p), vec![1, 2, 2, 3]);
+
+    let mut p = postings(&[1, 2, 2, 3]);
+    filter_by_facets(&mut p, &[Facet::Category("American films".to_string())], &idx);
+    assert_eq!(documents(&p), vec![1]);
+
+    let mut p = postings(&[1, 2, 2, 3]);
+    filter_by_facets(&mut p, &[Facet::Infobox("infobox actor".to_string())], &idx);
+    assert_eq!(documents(&p), vec![2, 2]);
+
+    let mut p = postings(&[1, 2, 2, 3]);
+    filter_by_facets(
+        &mut p,
+        &[
+            Facet::Category("1990 films".to_string()),
+            Facet::Infobox("infobox film".to_string()),
+        ],
+        &idx,
+    );
+    assert_eq!(documents(&p), vec![1, 2, 2]);
+
+    // other structure elements aren't infobox types
+    let mut p = postings(&[1, 2, 2, 3]);
+    filter_by_facets(&mut p, &[Facet::Infobox("Citation".to_string())], &idx);
+    assert!(p.is_empty());
+}
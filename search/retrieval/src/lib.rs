@@ -1,12 +1,24 @@
 
+pub mod budget;
+pub mod facets;
 pub mod query_correction;
 pub mod scoring;
 pub mod search;
+pub mod snippets;
+pub mod top_k;
 
+#[cfg(test)]
+pub mod budget_tests;
+#[cfg(test)]
+pub mod facets_tests;
 #[cfg(test)]
 pub mod query_correction_tests;
 #[cfg(test)]
 pub mod scoring_tests;
 #[cfg(test)]
 pub mod search_tests;
-pub use {query_correction::*, scoring::*, search::*};
+#[cfg(test)]
+pub mod snippets_tests;
+#[cfg(test)]
+pub mod top_k_tests;
+pub use {budget::*, facets::*, query_correction::*, scoring::*, search::*, snippets::*, top_k::*};
@@ -104,7 +104,7 @@ pub fn correct_query_sub<'a>(
                 )),
             };
         }
-        Query::PhraseQuery { tks } => {
+        Query::PhraseQuery { tks, offsets } => {
             let new_tokens = mark_tokens_to_correct(
                 tks,
                 index,
@@ -114,41 +114,60 @@ pub fn correct_query_sub<'a>(
                 key_distance_append_amount,
                 only_most_appearances,
             );
-            let new_query = Query::PhraseQuery { tks: new_tokens };
+            let new_query = Query::PhraseQuery {
+                tks: new_tokens,
+                offsets: offsets.clone(),
+            };
             return new_query;
         }
-        Query::DistanceQuery { dst, ref lhs, rhs } => {
-            let new_lhs = mark_tokens_to_correct(
-                &vec![lhs.clone()],
-                index,
-                token_threshold,
-                number_of_tries,
-                key_distance,
-                key_distance_append_amount,
-                only_most_appearances,
-            )
-            .pop()
-            .unwrap_or(lhs.clone());
-            let new_rhs = mark_tokens_to_correct(
-                &vec![rhs.clone()],
+        Query::DistanceQuery {
+            dst,
+            ordered,
+            lhs,
+            rhs,
+        } => {
+            let new_query = Query::DistanceQuery {
+                dst: *dst,
+                ordered: *ordered,
+                lhs: Box::new(correct_query_sub(
+                    &lhs,
+                    index,
+                    token_threshold,
+                    number_of_tries,
+                    key_distance,
+                    key_distance_append_amount,
+                    only_most_appearances,
+                )),
+                rhs: Box::new(correct_query_sub(
+                    &rhs,
+                    index,
+                    token_threshold,
+                    number_of_tries,
+                    key_distance,
+                    key_distance_append_amount,
+                    only_most_appearances,
+                )),
+            };
+            return new_query;
+        }
+        Query::StructureQuery { elem, sub } => {
+            let new_sub = Box::new(correct_query_sub(
+                &sub,
                 index,
                 token_threshold,
                 number_of_tries,
                 key_distance,
                 key_distance_append_amount,
                 only_most_appearances,
-            )
-            .pop()
-            .unwrap_or(rhs.clone());
+            ));
 
-            let new_query = Query::DistanceQuery {
-                dst: *dst,
-                lhs: new_lhs,
-                rhs: new_rhs,
+            let new_query = Query::StructureQuery {
+                elem: elem.clone(),
+                sub: new_sub,
             };
             return new_query;
         }
-        Query::StructureQuery { elem, sub } => {
+        Query::EditedQuery { after, before, sub } => {
             let new_sub = Box::new(correct_query_sub(
                 &sub,
                 index,
@@ -159,8 +178,9 @@ pub fn correct_query_sub<'a>(
                 only_most_appearances,
             ));
 
-            let new_query = Query::StructureQuery {
-                elem: elem.clone(),
+            let new_query = Query::EditedQuery {
+                after: after.clone(),
+                before: before.clone(),
                 sub: new_sub,
             };
             return new_query;
@@ -1,5 +1,7 @@
-use index::index::Index;
-use parser::ast::Query;
+use std::collections::{HashMap, HashSet};
+
+use index::{index::Index, DocumentField, DocumentStream};
+use parser::ast::{Query, StructureElem};
 
 pub fn idf(df: f64, num_documents: u32) -> f64 {
     return ((num_documents as f64) / df).log10();
@@ -7,8 +9,12 @@ pub fn idf(df: f64, num_documents: u32) -> f64 {
 
 // Compute the tfidf for a single term
 pub fn tfidf_term(term: &str, doc_id: u32, index: &Index) -> f64 {
+    tfidf_weight(term, index.tf(term, doc_id), index)
+}
+
+/// the tfidf of a term appearing `tf` times
+fn tfidf_weight(term: &str, tf: u32, index: &Index) -> f64 {
     let num_documents = index.get_number_of_documents();
-    let tf = index.tf(term, doc_id);
     let idf_term = idf(index.df(term) as f64, num_documents);
     if tf == 0 {
         return 0.0;
@@ -17,6 +23,34 @@ pub fn tfidf_term(term: &str, doc_id: u32, index: &Index) -> f64 {
     }
 }
 
+/// the frequencies of the query terms within documents scored in ascending order, read by walking
+/// the documents of each term once instead of looking every document up in its posting list
+pub struct TermFrequencies {
+    streams: HashMap<String, DocumentStream>,
+}
+
+impl TermFrequencies {
+    pub fn new(query: &Box<Query>, index: &Index) -> Self {
+        let mut streams = HashMap::default();
+        for term in query_terms(query) {
+            if !streams.contains_key(&term) {
+                if let Some(s) = index.stream_documents(&term) {
+                    streams.insert(term, s);
+                }
+            }
+        }
+        Self { streams }
+    }
+
+    /// the number of times the term appears in the document, documents have to be asked for in ascending order
+    pub fn tf(&mut self, term: &str, doc_id: u32) -> u32 {
+        match self.streams.get_mut(term).and_then(|s| s.seek(doc_id)) {
+            Some(d) if d.document_id == doc_id => d.tf,
+            _ => 0,
+        }
+    }
+}
+
 // compute the tfidf over a number of terms with regads to  certain document
 pub fn tfidf_doc(terms: &Vec<String>, doc_id: u32, index: &Index) -> f64 {
     let mut score = 0.0;
@@ -26,24 +60,352 @@ pub fn tfidf_doc(terms: &Vec<String>, doc_id: u32, index: &Index) -> f64 {
     return score;
 }
 
+/// the highest tfidf the term can reach in any document, worked out from the stored `max_tf` on every query
+pub fn tfidf_upper_bound(term: &str, index: &Index) -> f64 {
+    let max_tf = index.max_tf(term);
+    if max_tf == 0 {
+        return 0.0;
+    }
+    (1.0 + (max_tf as f64).log10()) * idf(index.df(term) as f64, index.get_number_of_documents())
+}
+
 pub fn tfidf_query(document_id: u32, query: &Box<Query>, index: &Index) -> f64 {
+    tfidf_query_with(document_id, query, index, &mut TermFrequencies::new(query, index))
+}
+
+/// the tfidf of the document with the term frequencies read from the given streams
+pub fn tfidf_query_with(
+    document_id: u32,
+    query: &Box<Query>,
+    index: &Index,
+    frequencies: &mut TermFrequencies,
+) -> f64 {
+    query_score(query, &mut |term| {
+        tfidf_weight(term, frequencies.tf(term, document_id), index)
+    })
+}
+
+/// sums the given per term score over the terms of the query which a document is matched on
+fn query_score<F: FnMut(&str) -> f64>(query: &Box<Query>, term_score: &mut F) -> f64 {
     match &**query {
-        Query::FreetextQuery { tokens } => return tfidf_doc(&tokens, document_id, index),
+        Query::FreetextQuery { tokens } => tokens.iter().map(|t| term_score(t)).sum(),
         Query::BinaryQuery { op: _, lhs, rhs } => {
-            return tfidf_query(document_id, &lhs, index) + tfidf_query(document_id, &rhs, index)
+            query_score(&lhs, term_score) + query_score(&rhs, term_score)
+        }
+        // negated terms don't appear in matching documents
+        Query::UnaryQuery { op: _, sub: _ } => 0.0,
+        Query::PhraseQuery { tks, .. } => tks.iter().map(|t| term_score(t)).sum(),
+        Query::StructureQuery { elem: _, sub } => query_score(&sub, term_score),
+        Query::EditedQuery { sub, .. } => query_score(&sub, term_score),
+        Query::DistanceQuery { lhs, rhs, .. } => {
+            query_score(&lhs, term_score) + query_score(&rhs, term_score)
         }
-        Query::UnaryQuery { op: _, sub } => return -1000.0 *tfidf_query(document_id, &sub, index),
-        Query::PhraseQuery { tks } => return tfidf_doc(&tks, document_id, index),
-        Query::StructureQuery { elem: _, sub } => return tfidf_query(document_id, &sub, index),
         Query::RelationQuery {
             root: _,
             hops: _,
             sub,
         } => match sub {
-            Some(v) => return tfidf_query(document_id, &v, index),
-            _ => return 0.0,
+            Some(v) => query_score(&v, term_score),
+            _ => 0.0,
         },
-        _ => return 0.0,
+        _ => 0.0,
     }
 }
 
+/// the terms of the query which a document is matched on
+pub fn query_terms(query: &Box<Query>) -> Vec<String> {
+    match &**query {
+        Query::FreetextQuery { tokens } => tokens.clone(),
+        Query::BinaryQuery { op: _, lhs, rhs } => {
+            let mut terms = query_terms(&lhs);
+            terms.extend(query_terms(&rhs));
+            terms
+        }
+        Query::PhraseQuery { tks, .. } => tks.clone(),
+        Query::StructureQuery { elem: _, sub } => query_terms(&sub),
+        Query::EditedQuery { sub, .. } => query_terms(&sub),
+        Query::DistanceQuery { lhs, rhs, .. } => {
+            let mut terms = query_terms(&lhs);
+            terms.extend(query_terms(&rhs));
+            terms
+        }
+        Query::RelationQuery {
+            root: _,
+            hops: _,
+            sub: Some(v),
+        } => query_terms(&v),
+        _ => Vec::default(),
+    }
+}
+
+/// how relevance sorted results are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ranking {
+    TfIdf,
+    Bm25,
+    Bm25F,
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Ranking::TfIdf
+    }
+}
+
+/// term frequency saturation
+pub const BM25_K1: f64 = 1.2;
+/// strength of document length normalisation
+pub const BM25_B: f64 = 0.75;
+
+pub fn bm25_idf(df: f64, num_documents: u32) -> f64 {
+    (((num_documents as f64) - df + 0.5) / (df + 0.5) + 1.0).ln()
+}
+
+/// saturates a (pseudo) term frequency
+fn bm25_saturate(tf: f64) -> f64 {
+    tf * (BM25_K1 + 1.0) / (tf + BM25_K1)
+}
+
+/// length normalised term frequency, a zero average length leaves the frequency unchanged
+fn bm25_normalise(tf: f64, length: f64, avg_length: f64) -> f64 {
+    if avg_length == 0.0 {
+        return tf;
+    }
+    tf / (1.0 - BM25_B + BM25_B * length / avg_length)
+}
+
+pub fn bm25_term(term: &str, doc_id: u32, index: &Index) -> f64 {
+    bm25_weight(term, doc_id, index.tf(term, doc_id), index)
+}
+
+/// the BM25 score of a term appearing `tf` times in the document
+fn bm25_weight(term: &str, doc_id: u32, tf: u32, index: &Index) -> f64 {
+    if tf == 0 {
+        return 0.0;
+    }
+
+    let tf = bm25_normalise(
+        tf as f64,
+        index.get_document_length(doc_id) as f64,
+        index.avg_document_length(),
+    );
+
+    bm25_idf(index.df(term) as f64, index.get_number_of_documents()) * bm25_saturate(tf)
+}
+
+/// the highest BM25 score the term can reach in any document,
+/// length normalisation is bounded by that of an empty document
+pub fn bm25_upper_bound(term: &str, index: &Index) -> f64 {
+    let max_tf = index.max_tf(term);
+    if max_tf == 0 {
+        return 0.0;
+    }
+    bm25_idf(index.df(term) as f64, index.get_number_of_documents())
+        * bm25_saturate(bm25_normalise(max_tf as f64, 0.0, 1.0))
+}
+
+pub fn bm25_query(document_id: u32, query: &Box<Query>, index: &Index) -> f64 {
+    bm25_query_with(document_id, query, index, &mut TermFrequencies::new(query, index))
+}
+
+/// the BM25 score of the document with the term frequencies read from the given streams
+pub fn bm25_query_with(
+    document_id: u32,
+    query: &Box<Query>,
+    index: &Index,
+    frequencies: &mut TermFrequencies,
+) -> f64 {
+    query_score(query, &mut |term| {
+        bm25_weight(term, document_id, frequencies.tf(term, document_id), index)
+    })
+}
+
+/// how much an appearance in each field counts towards the BM25F term frequency
+pub fn bm25f_field_weight(field: DocumentField) -> f64 {
+    match field {
+        DocumentField::Title => 3.0,
+        DocumentField::Category => 1.5,
+        DocumentField::Infobox => 1.5,
+        DocumentField::Body => 1.0,
+        DocumentField::Citation => 0.5,
+    }
+}
+
+/// scores a fixed set of documents with BM25F, fields are told apart using the extent ranges.
+/// The positions of the query terms within those documents are decoded once up front, so this only pays off
+/// when scoring many documents for the same query
+pub struct Bm25FScorer<'a> {
+    index: &'a Index,
+    /// term -> document -> field -> term frequency
+    field_tfs: HashMap<String, HashMap<u32, HashMap<DocumentField, u32>>>,
+    /// document -> field -> length
+    field_lengths: HashMap<u32, HashMap<DocumentField, u32>>,
+}
+
+impl<'a> Bm25FScorer<'a> {
+    pub fn new(query: &Box<Query>, index: &'a Index, documents: &HashSet<u32>) -> Self {
+        let mut field_ranges = HashMap::with_capacity(documents.len());
+        let mut field_lengths = HashMap::with_capacity(documents.len());
+
+        for d in documents {
+            let ranges = index.get_field_ranges(*d);
+
+            let mut lengths: HashMap<DocumentField, u32> = HashMap::default();
+            for (f, r) in &ranges {
+                *lengths.entry(*f).or_default() += r.end_pos - r.start_pos;
+            }
+            let structured = lengths.values().sum::<u32>();
+            lengths.insert(
+                DocumentField::Body,
+                index.get_document_length(*d).saturating_sub(structured),
+            );
+
+            field_ranges.insert(*d, ranges);
+            field_lengths.insert(*d, lengths);
+        }
+
+        let mut field_tfs: HashMap<String, HashMap<u32, HashMap<DocumentField, u32>>> =
+            HashMap::default();
+        for term in query_terms(query) {
+            if field_tfs.contains_key(&term) {
+                continue;
+            }
+
+            let mut tfs: HashMap<u32, HashMap<DocumentField, u32>> = HashMap::default();
+            for (d, positions) in index.positions_within(&term, documents.iter().cloned()) {
+                let ranges = &field_ranges[&d];
+                for p in positions {
+                    let field = ranges
+                        .iter()
+                        .find(|(_, r)| p >= r.start_pos && p < r.end_pos)
+                        .map(|(f, _)| *f)
+                        .unwrap_or(DocumentField::Body);
+
+                    *tfs.entry(d).or_default().entry(field).or_default() += 1;
+                }
+            }
+            field_tfs.insert(term, tfs);
+        }
+
+        Self {
+            index,
+            field_tfs,
+            field_lengths,
+        }
+    }
+
+    pub fn score(&self, document_id: u32, query: &Box<Query>) -> f64 {
+        query_score(query, &mut |term| self.score_term(term, document_id))
+    }
+
+    fn score_term(&self, term: &str, document_id: u32) -> f64 {
+        let tfs = match self.field_tfs.get(term).and_then(|t| t.get(&document_id)) {
+            Some(v) => v,
+            None => return 0.0,
+        };
+
+        let tf = tfs
+            .iter()
+            .map(|(f, tf)| {
+                let length = self
+                    .field_lengths
+                    .get(&document_id)
+                    .and_then(|l| l.get(f))
+                    .cloned()
+                    .unwrap_or(0);
+
+                bm25f_field_weight(*f)
+                    * bm25_normalise(*tf as f64, length as f64, self.index.avg_field_length(*f))
+            })
+            .sum::<f64>();
+
+        bm25_idf(
+            self.index.df(term) as f64,
+            self.index.get_number_of_documents(),
+        ) * bm25_saturate(tf)
+    }
+}
+
+/// the share of relevance in the final score of a document
+pub const RELEVANCE_WEIGHT: f64 = 0.9;
+/// the share of page rank in the final score of a document
+pub const PAGE_RANK_WEIGHT: f64 = 0.1;
+
+/// added to the relevance of a document for every distinct query term in its title
+pub const TITLE_MATCH_BOOST: f64 = 2.0;
+/// added to the relevance of a document whose title is exactly the query, enough to rank it first
+pub const EXACT_TITLE_BOOST: f64 = 1000.0;
+
+/// finds which query terms appear in the titles of a fixed set of documents,
+/// the positions of the query terms within those titled documents are decoded once up front
+pub struct TitleMatches {
+    /// document -> distinct query terms within the title
+    title_terms: HashMap<u32, HashSet<String>>,
+    /// documents whose title is exactly the query
+    exact: HashSet<u32>,
+}
+
+impl TitleMatches {
+    pub fn new(query: &Box<Query>, index: &Index, documents: &HashSet<u32>) -> Self {
+        let title: String = StructureElem::Title.into();
+
+        let title_ranges = documents
+            .iter()
+            .filter_map(|d| index.get_extent_for(&title, d).map(|r| (*d, r)))
+            .collect::<HashMap<_, _>>();
+
+        let terms = query_terms(query);
+        let distinct = terms.iter().collect::<HashSet<_>>();
+
+        let mut title_terms: HashMap<u32, HashSet<String>> = HashMap::default();
+        for term in &distinct {
+            for (d, positions) in index.positions_within(term, title_ranges.keys().cloned()) {
+                let r = title_ranges[&d];
+                if positions.iter().any(|p| *p >= r.start_pos && *p < r.end_pos) {
+                    title_terms.entry(d).or_default().insert(term.to_string());
+                }
+            }
+        }
+
+        // only titles containing every term can be exact, those are checked against the stored tokens
+        let exact = title_terms
+            .iter()
+            .filter(|(_, t)| t.len() == distinct.len())
+            .filter(|(d, _)| is_exact_title(**d, &terms, index))
+            .map(|(d, _)| *d)
+            .collect();
+
+        Self { title_terms, exact }
+    }
+
+    /// the number of distinct query terms in the title of the document
+    pub fn matched_terms(&self, document_id: u32) -> usize {
+        self.title_terms
+            .get(&document_id)
+            .map(|t| t.len())
+            .unwrap_or(0)
+    }
+
+    /// true if the title consists of exactly the query terms in order
+    pub fn is_exact(&self, document_id: u32) -> bool {
+        self.exact.contains(&document_id)
+    }
+}
+
+/// true if the tokens indexed within the title of the document are exactly the given terms in order,
+/// words dropped from the title only leave empty positions behind
+pub fn is_exact_title(document_id: u32, terms: &Vec<String>, index: &Index) -> bool {
+    let title: String = StructureElem::Title.into();
+    let range = match index.get_extent_for(&title, &document_id) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    index.get_text(document_id).map_or(false, |text| {
+        text.split(' ')
+            .skip(range.start_pos as usize)
+            .take((range.end_pos - range.start_pos) as usize)
+            .filter(|w| !w.is_empty())
+            .eq(terms.iter().map(|t| t.as_str()))
+    })
+}
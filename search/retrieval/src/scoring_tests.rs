@@ -1,8 +1,12 @@
-use crate::scoring::{idf, tfidf_query, tfidf_term};
-use index::index::{Index};
+use crate::scoring::{
+    bm25_idf, bm25_query, bm25_query_with, bm25_term, idf, tfidf_query, tfidf_query_with,
+    tfidf_term, Bm25FScorer, TermFrequencies, TitleMatches, BM25_B, BM25_K1,
+};
+use index::index::Index;
 use index::utils::get_document_with_text;
 use index::PreIndex;
 use parser::ast::{BinaryOp, Query, UnaryOp};
+use std::collections::HashSet;
 
 #[test]
 fn test_idf() {
@@ -41,7 +45,7 @@ fn test_tfidf_term() {
 
     let df_whale = 2.0;
     let tf_whale_1 = 1.0;
-    let tf_whale_2 = 1.0;
+    let tf_whale_2 = 2.0; // including the title
 
     let num_documents = 2;
 
@@ -81,7 +85,7 @@ fn test_tfidf_term_2() {
     let idx = Index::from_pre_index(pre_idx);
 
     let df_big = 1.0;
-    let tf_big_2 = 2.0;
+    let tf_big_2 = 3.0; // including the title
 
     let num_documents = 2;
 
@@ -125,9 +129,9 @@ fn test_tfidf_simple_phrase_query() {
 
     let df_whale = 2.0;
     let df_big = 1.0;
-    let tf_big_2 = 2;
+    let tf_big_2 = 3; // including the title
     let tf_whale_1 = 1.0;
-    let tf_whale_2 = 1.0;
+    let tf_whale_2 = 2.0; // including the title
 
     let num_documents = 2;
 
@@ -182,9 +186,9 @@ fn test_simple_binary_query() {
 
     let df_whale = 2.0;
     let df_big = 1.0;
-    let tf_big_2 = 2;
+    let tf_big_2 = 3; // including the title
     let tf_whale_1 = 1.0;
-    let tf_whale_2 = 1.0;
+    let tf_whale_2 = 2.0; // including the title
 
     let num_documents = 2;
 
@@ -242,9 +246,9 @@ fn test_nested_binary_query() {
 
     let df_whale = 2.0;
     let df_big = 1.0;
-    let tf_big_2 = 2;
+    let tf_big_2 = 3; // including the title
     let tf_whale_1 = 1.0;
-    let tf_whale_2 = 1.0;
+    let tf_whale_2 = 2.0; // including the title
 
     let num_documents = 2;
 
@@ -332,9 +336,9 @@ fn test_nested_relation_query() {
 
     let df_whale = 2.0;
     let df_big = 1.0;
-    let tf_big_2 = 2;
+    let tf_big_2 = 3; // including the title
     let tf_whale_1 = 1.0;
-    let tf_whale_2 = 1.0;
+    let tf_whale_2 = 2.0; // including the title
 
     let num_documents = 2;
 
@@ -351,3 +355,157 @@ fn test_nested_relation_query() {
     assert_eq!(tfidf_1_expected, tfidf_1_actual);
     assert_eq!(tfidf_2_expected, tfidf_2_actual);
 }
+
+fn bm25_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+    pre_idx
+        .add_document(get_document_with_text(
+            1,
+            "d1",
+            vec![("", "whale")],
+            "big ocean tuna",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            2,
+            "d2",
+            vec![("", "tuna")],
+            "big ocean whale",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            3,
+            "d3",
+            vec![],
+            "small tuna sandwich with a very long description of nothing",
+            vec![],
+            "",
+        ))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+#[test]
+fn test_bm25_term() {
+    let idx = bm25_index();
+
+    // every title adds a token
+    let avg_dl = (5.0 + 5.0 + 11.0) / 3.0;
+    let norm_tf = 1.0 / (1.0 - BM25_B + BM25_B * 5.0 / avg_dl);
+    let expected = bm25_idf(2.0, 3) * norm_tf * (BM25_K1 + 1.0) / (norm_tf + BM25_K1);
+
+    assert!((bm25_term("whale", 1, &idx) - expected).abs() < 1e-9);
+    assert_eq!(bm25_term("whale", 3, &idx), 0.0);
+}
+
+#[test]
+fn test_term_frequencies_match_lookups() {
+    let idx = bm25_index();
+    let query = Box::new(Query::FreetextQuery {
+        tokens: vec!["whale".to_string(), "tuna".to_string(), "missing".to_string()],
+    });
+
+    // one pass over each term gives the same scores as looking every document up
+    let mut frequencies = TermFrequencies::new(&query, &idx);
+    for d in 1..=3 {
+        assert_eq!(frequencies.tf("whale", d), idx.tf("whale", d));
+        assert_eq!(frequencies.tf("missing", d), 0);
+    }
+
+    let mut tfidf = TermFrequencies::new(&query, &idx);
+    let mut bm25 = TermFrequencies::new(&query, &idx);
+    for d in 1..=3 {
+        let expected = ["whale", "tuna"].iter().map(|t| tfidf_term(t, d, &idx)).sum::<f64>();
+        assert!((tfidf_query_with(d, &query, &idx, &mut tfidf) - expected).abs() < 1e-9);
+        let expected = ["whale", "tuna"].iter().map(|t| bm25_term(t, d, &idx)).sum::<f64>();
+        assert!((bm25_query_with(d, &query, &idx, &mut bm25) - expected).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_bm25_prefers_shorter_documents() {
+    let idx = bm25_index();
+
+    let query = Box::new(Query::FreetextQuery {
+        tokens: vec!["tuna".to_string()],
+    });
+
+    assert!(bm25_query(1, &query, &idx) > bm25_query(3, &query, &idx));
+    assert_eq!(bm25_query(1, &query, &idx), bm25_query(2, &query, &idx));
+}
+
+#[test]
+fn test_bm25f_weights_fields() {
+    let mut pre_idx = PreIndex::default();
+    pre_idx
+        .add_document(get_document_with_text(
+            1,
+            "d1",
+            vec![("", "whale tuna")],
+            "big ocean",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            2,
+            "d2",
+            vec![("", "big ocean")],
+            "whale tuna",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    pre_idx
+        .add_document(get_document_with_text(
+            3,
+            "d3",
+            vec![("", "small fish")],
+            "tuna sandwich",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    let idx = Index::from_pre_index(pre_idx);
+
+    let query = Box::new(Query::FreetextQuery {
+        tokens: vec!["whale".to_string()],
+    });
+    let scorer = Bm25FScorer::new(&query, &idx, &HashSet::from_iter(vec![1, 2, 3]));
+
+    // whale is in the infobox of 1 and the body of 2, all fields are of average length
+    assert!(scorer.score(1, &query) > scorer.score(2, &query));
+    assert!(scorer.score(2, &query) > 0.0);
+    assert_eq!(scorer.score(3, &query), 0.0);
+}
+
+#[test]
+fn test_title_matches() {
+    let mut pre_idx = PreIndex::default();
+    for (id, title) in [(1, "whale shark"), (2, "shark whale"), (3, "big whale shark")] {
+        pre_idx
+            .add_document(get_document_with_text(id, title, vec![], "whale shark", vec![], ""))
+            .unwrap();
+    }
+    let idx = Index::from_pre_index(pre_idx);
+
+    let query = Box::new(Query::FreetextQuery {
+        tokens: vec!["whale".to_string(), "shark".to_string()],
+    });
+    let titles = TitleMatches::new(&query, &idx, &HashSet::from_iter(vec![1, 2, 3]));
+
+    assert_eq!(titles.matched_terms(1), 2);
+    assert_eq!(titles.matched_terms(3), 2);
+    assert!(titles.is_exact(1));
+    // same terms in a different order or with extra words are not exact
+    assert!(!titles.is_exact(2));
+    assert!(!titles.is_exact(3));
+}
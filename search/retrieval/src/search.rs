@@ -1,9 +1,14 @@
-use crate::tfidf_query;
+use crate::{
+    bm25_query_with, tfidf_query_with, Bm25FScorer, BudgetedIterator, QueryBudget, Ranking,
+    TermFrequencies, TitleMatches, CLOCK_CHECK_INTERVAL, EXACT_TITLE_BOOST, PAGE_RANK_WEIGHT,
+    RELEVANCE_WEIGHT, TITLE_MATCH_BOOST, UntilExceeded,
+};
 
-use index::{index::Index, index_structs::Posting, PosRange};
+use index::{
+    index::Index, index_structs::Posting, DocumentStream, PosRange, PostingStream, SkipIterator,
+};
 
 use itertools::Itertools;
-
 use log::info;
 use parser::errors::{QueryError, QueryErrorKind};
 use parser::{ast::Query, BinaryOp, UnaryOp};
@@ -12,8 +17,9 @@ use once_cell::sync::Lazy;
 
 
 use std::ops::Deref;
+use std::sync::Arc;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     iter::empty,
 };
 use utils::utils::merge;
@@ -32,6 +38,8 @@ pub struct ScoredRelationDocument {
 }
 
     
+static UNLIMITED_BUDGET: QueryBudget = QueryBudget::unlimited();
+
 static opts : Lazy<ProcessingOptions> = Lazy::new(|| ProcessingOptions::default());
 
 static opts_wild_card : Lazy<ProcessingOptions> = Lazy::new(|| ProcessingOptions {
@@ -50,6 +58,7 @@ pub fn preprocess_query(query: &mut Query) -> Result<(), QueryError> {
             drop(sub.as_mut().map(|c| Some(preprocess_query(c).ok()?)))
         }
         Query::StructureQuery { ref mut sub, .. } => preprocess_query(sub)?,
+        Query::EditedQuery { ref mut sub, .. } => preprocess_query(sub)?,
         Query::UnaryQuery { ref mut sub, .. } => preprocess_query(sub)?,
         Query::BinaryQuery {
             ref mut lhs,
@@ -59,27 +68,45 @@ pub fn preprocess_query(query: &mut Query) -> Result<(), QueryError> {
             preprocess_query(lhs)?;
             preprocess_query(rhs)?;
         }
-        ref mut q @ Query::PhraseQuery { .. }  => {
-
+        ref mut q @ Query::PhraseQuery { .. } => {
             let tks = match q {
-                Query::PhraseQuery { ref mut tks } => {
-                    *tks = tks
-                    .into_iter()
-                    .flat_map(|c| Preprocessor::process(opts.deref(), c.to_string()))
-                    .filter(|w| !w.trim().is_empty())
-                    .collect();
-                    if tks.len() <= 1{
+                Query::PhraseQuery {
+                    ref mut tks,
+                    ref mut offsets,
+                } => {
+                    // words which split into several tokens push back everything after them
+                    let mut shift = 0;
+                    let (new_tks, new_offsets): (Vec<String>, Vec<u32>) = tks
+                        .iter()
+                        .zip(offsets.iter())
+                        .flat_map(|(t, o)| {
+                            let processed = Preprocessor::process(opts.deref(), t.to_string())
+                                .into_iter()
+                                .filter(|w| !w.trim().is_empty())
+                                .collect::<Vec<String>>();
+                            let start = o + shift;
+                            shift += (processed.len() as u32).saturating_sub(1);
+                            processed
+                                .into_iter()
+                                .enumerate()
+                                .map(move |(i, w)| (w, start + i as u32))
+                        })
+                        .unzip();
+
+                    let first = new_offsets.first().cloned().unwrap_or(0);
+                    *tks = new_tks;
+                    *offsets = new_offsets.into_iter().map(|o| o - first).collect();
+
+                    if tks.len() <= 1 {
                         Some(tks.clone())
                     } else {
                         None
                     }
-                },
+                }
                 _ => panic!(),
             };
             if let Some(tks) = tks {
-                *q = Query::FreetextQuery {
-                    tokens: tks,
-                } 
+                *q = Query::FreetextQuery { tokens: tks }
             };
         }
         Query::FreetextQuery { ref mut tokens } => {
@@ -94,24 +121,19 @@ pub fn preprocess_query(query: &mut Query) -> Result<(), QueryError> {
             ref mut rhs,
             ..
         } => {
-            *lhs = Preprocessor::process(opts.deref(), lhs.clone())
-                .into_iter()
-                .next()
-                .ok_or(QueryError {
-                    kind: QueryErrorKind::InvalidSyntax,
-                    msg: "Distance query requires at least one individual word on each side"
-                        .to_string(),
-                    pos: lhs.to_string(),
-                })?;
-            *rhs = Preprocessor::process(opts.deref(), rhs.clone())
-                .into_iter()
-                .next()
-                .ok_or(QueryError {
-                    kind: QueryErrorKind::InvalidSyntax,
-                    msg: "Distance query requires at least one individual word on each side"
-                        .to_string(),
-                    pos: rhs.to_string(),
-                })?;
+            for side in [lhs, rhs] {
+                preprocess_query(side)?;
+                if let Query::FreetextQuery { ref tokens } = **side {
+                    if tokens.is_empty() {
+                        return Err(QueryError {
+                            kind: QueryErrorKind::InvalidSyntax,
+                            msg: "Distance query requires at least one individual word on each side"
+                                .to_string(),
+                            pos: side.to_string(),
+                        });
+                    }
+                }
+            }
         }
         Query::WildcardQuery {
             ref mut prefix,
@@ -134,18 +156,26 @@ pub fn preprocess_query(query: &mut Query) -> Result<(), QueryError> {
 }
 
 pub struct PostingIterator<'a> {
-    wrapped: Box<dyn Iterator<Item = Posting> + 'a>,
+    wrapped: Box<dyn SkipIterator + 'a>,
 }
 
 impl<'a> PostingIterator<'a> {
+    /// wraps postings which can only be skipped over one at a time
     pub fn new<T: Iterator<Item = Posting> + 'a>(o: T) -> Self {
+        Self {
+            wrapped: Box::new(Sequential(o)),
+        }
+    }
+
+    /// wraps postings which can jump ahead on their own
+    pub fn with_skips<T: SkipIterator + 'a>(o: T) -> Self {
         Self {
             wrapped: Box::new(o),
         }
     }
 
     pub fn rewrap<T: Iterator<Item = Posting> + 'a>(mut me: Self, o: T) -> Self {
-        me.wrapped = Box::new(o);
+        me.wrapped = Box::new(Sequential(o));
         me
     }
 }
@@ -158,42 +188,98 @@ impl Iterator for PostingIterator<'_> {
     }
 }
 
-//TODO: get rid of posting copying, do stuff by reference, + batch postings list in case we run out of memory
+impl SkipIterator for PostingIterator<'_> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        self.wrapped.skip_to(doc_id)
+    }
+}
+
+/// skips by reading through every posting
+struct Sequential<T>(T);
+
+impl<T: Iterator<Item = Posting>> Iterator for Sequential<T> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<T: Iterator<Item = Posting>> SkipIterator for Sequential<T> {}
+
+/// executes the query without any limits on the work done
 pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIterator<'a> {
+    execute_query_within(query, index, &UNLIMITED_BUDGET)
+}
+
+/// executes the query until the budget runs out, after which the remaining postings are left out
+pub fn execute_query_within<'a>(
+    query: &'a Box<Query>,
+    index: &'a Index,
+    budget: &'a QueryBudget,
+) -> PostingIterator<'a> {
+    PostingIterator::with_skips(BudgetedIterator::new(
+        execute(query, index, budget, true),
+        budget,
+    ))
+}
+
+/// executes the query for the documents it matches, each may come with just one posting at position 0.
+/// Positions are only read for the phrases, distances and structure elements within the query
+pub fn execute_query_documents_within<'a>(
+    query: &'a Box<Query>,
+    index: &'a Index,
+    budget: &'a QueryBudget,
+) -> PostingIterator<'a> {
+    PostingIterator::with_skips(BudgetedIterator::new(
+        execute(query, index, budget, false),
+        budget,
+    ))
+}
+
+/// executes the query, without `positional` the postings of terms only stand for their documents
+/// and carry no positions, parts of the query which need positions read them regardless
+fn execute<'a>(
+    query: &'a Box<Query>,
+    index: &'a Index,
+    budget: &'a QueryBudget,
+    positional: bool,
+) -> PostingIterator<'a> {
     match **query {
         Query::DistanceQuery {
             ref dst,
+            ref ordered,
             ref lhs,
             ref rhs,
         } => {
-            let lhs = index.get_postings(lhs).map(|v| {
-                v.lock()
-                    .get()
-                    .unwrap()
-                    .postings
-                    .into_iter()
-                    .collect::<Vec<Posting>>()
-            });
-            let rhs = index.get_postings(rhs).map(|v| {
-                v.lock()
-                    .get()
-                    .unwrap()
-                    .postings
-                    .into_iter()
-                    .collect::<Vec<Posting>>()
-            });
-
-            if lhs.is_none() || rhs.is_none() {
-                return PostingIterator {
-                    wrapped: Box::new(empty::<Posting>()),
-                };
+            if *ordered {
+                return PostingIterator::new(DistanceMergeIterator::new(
+                    *dst,
+                    Box::new(execute(lhs, index, budget, true)),
+                    Box::new(execute(rhs, index, budget, true)),
+                ));
             }
 
-            PostingIterator::new(DistanceMergeIterator::new(
+            // both directions are merged from the same postings, so each operand is only read once
+            let lhs = execute(lhs, index, budget, true)
+                .into_iter()
+                .collect::<Vec<Posting>>();
+            let rhs = execute(rhs, index, budget, true)
+                .into_iter()
+                .collect::<Vec<Posting>>();
+            let forward = DistanceMergeIterator::new(
                 *dst,
-                Box::new(lhs.unwrap().into_iter()),
-                Box::new(rhs.unwrap().into_iter()),
-            ))
+                Box::new(PostingIterator::with_skips(lhs.clone().into_iter())),
+                Box::new(PostingIterator::with_skips(rhs.clone().into_iter())),
+            );
+            let backward = DistanceMergeIterator::new(
+                *dst,
+                Box::new(PostingIterator::with_skips(rhs.into_iter())),
+                Box::new(PostingIterator::with_skips(lhs.into_iter())),
+            );
+            PostingIterator::new(
+                UnionMergeIterator::new(Box::new(forward), Box::new(backward)).dedup(),
+            )
         }
         Query::RelationQuery {
             root: id,
@@ -202,10 +288,14 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
         } => {
             let mut subset = HashMap::default();
             get_docs_within_hops(id, *hops, &mut subset, index);
+            // without the whole neighbourhood nothing can be said of which documents are in it
+            if !budget.charge(subset.len() as u64) {
+                return PostingIterator::new(empty::<Posting>());
+            }
 
             match sub {
                 Some(q) => PostingIterator::new(
-                    execute_query(q, index)
+                    execute(q, index, budget, positional)
                         .into_iter()
                         .filter(move |v| subset.contains_key(&v.document_id)),
                 ),
@@ -219,7 +309,7 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
                         .collect::<Vec<Posting>>();
                     o.sort();
 
-                    PostingIterator::new(o.into_iter())
+                    PostingIterator::with_skips(o.into_iter())
                 }
             }
         }
@@ -233,23 +323,22 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
             wild_token.push_str("*");
             wild_token.push_str(suffix);
             let vec_encoded_postings = index.posting_nodes.entry_wild_card(&wild_token);
-            vec_encoded_postings.iter().fold(init, |a, iter| {
-                PostingIterator::new(UnionMergeIterator::new(
-                    Box::new(a),
-                    Box::new(
-                        iter.lock()
-                            .get()
-                            .unwrap()
-                            .postings
-                            .into_iter()
-                            .collect::<Vec<Posting>>()
-                            .into_iter(),
-                    ),
-                ))
-            })
+            vec_encoded_postings
+                .into_iter()
+                .filter_map(|v| match positional {
+                    true => budgeted_postings(PostingStream::new(Arc::clone(v)), budget)
+                        .map(PostingIterator::with_skips),
+                    false => budgeted_documents(DocumentStream::new(Arc::clone(v)), budget),
+                })
+                .fold(init, |a, stream| {
+                    PostingIterator::with_skips(UnionMergeIterator::new(
+                        Box::new(a),
+                        Box::new(stream),
+                    ))
+                })
         }
         Query::StructureQuery { ref elem, ref sub } => {
-            PostingIterator::new(execute_query(sub, index).filter(|c| {
+            PostingIterator::new(execute(sub, index, budget, true).filter(|c| {
                 let s: String = elem.clone().into();
                 match index.get_extent_for(&s, &c.document_id) {
                     Some(PosRange { start_pos, end_pos }) => {
@@ -259,69 +348,70 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
                 }
             }))
         }
-        Query::PhraseQuery { ref tks } => {
-            let init = PostingIterator::new(empty::<Posting>());
-
-            tks.iter()
-                .tuple_windows()
-                .map(|(a, b)| {
-                    let l = index.get_postings(a).map(|v| {
-                        v.lock()
-                            .get()
-                            .unwrap()
-                            .postings
-                            .into_iter()
-                            .collect::<Vec<Posting>>()
-                    });
-                    let r = index.get_postings(b).map(|v| {
-                        v.lock()
-                            .get()
-                            .unwrap()
-                            .postings
-                            .into_iter()
-                            .collect::<Vec<Posting>>()
-                    });
-                    (l, r)
-                })
-                .enumerate()
-                .fold(init, |a, (i, (l, r))| {
-                    let lhs: Box<dyn Iterator<Item = Posting>> =
-                        Box::new(l.unwrap_or_default().into_iter());
-                    let rhs: Box<dyn Iterator<Item = Posting>> =
-                        Box::new(r.unwrap_or_default().into_iter());
-
-                    let curr = DistanceMergeIterator::new(1, lhs, rhs);
-
-                    if i != 0 {
-                        PostingIterator::new(DistanceMergeIterator::new(
-                            i as u32,
-                            Box::new(a),
-                            Box::new(curr),
-                        ))
-                    } else {
-                        PostingIterator::rewrap(a, curr)
-                    }
-                })
-        }
+        Query::EditedQuery {
+            ref after,
+            ref before,
+            ref sub,
+        } => PostingIterator::new(execute(sub, index, budget, positional).filter(move |c| {
+            match index.get_last_updated_date(c.document_id) {
+                Some(d) => {
+                    let day = d.date_time.date();
+                    after.map_or(true, |a| day >= a) && before.map_or(true, |b| day <= b)
+                }
+                None => false,
+            }
+        })),
+        Query::PhraseQuery {
+            ref tks,
+            ref offsets,
+        } => PostingIterator::with_skips(phrase_postings(tks, offsets, index, budget).into_iter()),
 
         Query::UnaryQuery { ref op, ref sub } => match op {
-            UnaryOp::Not => execute_query(sub, index), // soft not
+            // a lone NOT can only be answered against every document
+            UnaryOp::Not => PostingIterator::new(UntilExceeded::new(
+                DifferenceMergeIterator::new(
+                    Box::new(all_documents(index, budget)),
+                    Box::new(execute(sub, index, budget, positional)),
+                ),
+                budget,
+            )),
         },
+        Query::BinaryQuery {
+            op: BinaryOp::And,
+            ref lhs,
+            ref rhs,
+        } if negated(lhs).is_some() || negated(rhs).is_some() => {
+            // evaluate `a AND NOT b` directly as a difference, rather than intersecting with the complement
+            let (positive, negative) = match negated(rhs) {
+                Some(v) => (lhs, v),
+                None => (rhs, negated(lhs).unwrap()),
+            };
+
+            // documents cut from the negative side would show up in the results, so nothing is produced instead
+            PostingIterator::new(UntilExceeded::new(
+                DifferenceMergeIterator::new(
+                    Box::new(execute(positive, index, budget, positional)),
+                    Box::new(execute(negative, index, budget, positional)),
+                ),
+                budget,
+            ))
+        }
         Query::BinaryQuery {
             ref op,
             ref lhs,
             ref rhs,
         } => {
-            let sub_l = execute_query(lhs, index);
-            let sub_r = execute_query(rhs, index);
+            let sub_l = execute(lhs, index, budget, positional);
+            let sub_r = execute(rhs, index, budget, positional);
             match op {
                 BinaryOp::And => PostingIterator::new(IntersectionMergeIterator::new(
                     Box::new(sub_l),
                     Box::new(sub_r),
                 )),
-                BinaryOp::Or => {
-                    PostingIterator::new(UnionMergeIterator::new(Box::new(sub_l), Box::new(sub_r)))
-                }
+                BinaryOp::Or => PostingIterator::with_skips(UnionMergeIterator::new(
+                    Box::new(sub_l),
+                    Box::new(sub_r),
+                )),
             }
         }
         Query::FreetextQuery { ref tokens } => {
@@ -329,19 +419,19 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
 
             tokens
                 .iter()
-                .filter_map(|v| index.get_postings(v))
-                .fold(init, |a, iter| {
-                    PostingIterator::new(UnionMergeIterator::new(
+                .filter_map(|v| match positional {
+                    true => index
+                        .stream_postings(v)
+                        .and_then(|s| budgeted_postings(s, budget))
+                        .map(PostingIterator::with_skips),
+                    false => index
+                        .stream_documents(v)
+                        .and_then(|s| budgeted_documents(s, budget)),
+                })
+                .fold(init, |a, stream| {
+                    PostingIterator::with_skips(UnionMergeIterator::new(
                         Box::new(a),
-                        Box::new(
-                            iter.lock()
-                                .get()
-                                .unwrap()
-                                .postings
-                                .into_iter()
-                                .collect::<Vec<Posting>>()
-                                .into_iter(),
-                        ),
+                        Box::new(stream),
                     ))
                 })
         }
@@ -349,6 +439,151 @@ pub fn execute_query<'a>(query: &'a Box<Query>, index: &'a Index) -> PostingIter
     }
 }
 
+/// the sub query of a negated query
+fn negated(query: &Box<Query>) -> Option<&Box<Query>> {
+    match **query {
+        Query::UnaryQuery {
+            op: UnaryOp::Not,
+            ref sub,
+        } => Some(sub),
+        _ => None,
+    }
+}
+
+/// the stream, or none if reading all of it would go over budget.
+/// Leaving it out marks the budget as exceeded, which the negations above it check for
+fn budgeted_postings(
+    stream: PostingStream,
+    budget: &QueryBudget,
+) -> Option<PostingStream> {
+    match budget.charge(stream.postings_count() as u64) {
+        true => Some(stream),
+        false => None,
+    }
+}
+
+/// the documents of the stream as postings at position 0, or none if reading all of them would go over budget
+fn budgeted_documents<'a>(
+    stream: DocumentStream,
+    budget: &QueryBudget,
+) -> Option<PostingIterator<'a>> {
+    match budget.charge(stream.df() as u64) {
+        true => Some(PostingIterator::with_skips(DocumentPostings(stream))),
+        false => None,
+    }
+}
+
+/// stands in for the postings of a token where only its documents matter
+struct DocumentPostings(DocumentStream);
+
+impl Iterator for DocumentPostings {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|d| Posting {
+            document_id: d.document_id,
+            position: 0,
+        })
+    }
+}
+
+impl SkipIterator for DocumentPostings {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        self.0.seek(doc_id)?;
+        self.next()
+    }
+}
+
+/// true if the query holds a phrase, distance or structure query, which can only be answered with positions
+pub fn needs_positions(query: &Box<Query>) -> bool {
+    match **query {
+        Query::DistanceQuery { .. } | Query::PhraseQuery { .. } | Query::StructureQuery { .. } => {
+            true
+        }
+        Query::BinaryQuery {
+            ref lhs, ref rhs, ..
+        } => needs_positions(lhs) || needs_positions(rhs),
+        Query::UnaryQuery { ref sub, .. } | Query::EditedQuery { ref sub, .. } => {
+            needs_positions(sub)
+        }
+        Query::RelationQuery { ref sub, .. } => sub.as_ref().map_or(false, needs_positions),
+        _ => false,
+    }
+}
+
+/// a single posting for every document in the index in document order, or none if they are over budget
+fn all_documents(index: &Index, budget: &QueryBudget) -> impl Iterator<Item = Posting> {
+    let mut ids = Vec::default();
+    if budget.charge(index.last_updated_docs.len() as u64) {
+        ids.extend(index.last_updated_docs.keys().cloned());
+    }
+    ids.sort();
+
+    ids.into_iter().map(|document_id| Posting {
+        document_id,
+        position: 0,
+    })
+}
+
+/// every posting of every occurrence of the phrase, that is wherever each token appears
+/// at exactly its offset from the first one
+fn phrase_postings(
+    tks: &[String],
+    offsets: &[u32],
+    index: &Index,
+    budget: &QueryBudget,
+) -> Vec<Posting> {
+    let mut streams = Vec::with_capacity(tks.len());
+    for t in tks {
+        match index.stream_postings(t).and_then(|v| budgeted_postings(v, budget)) {
+            Some(v) => streams.push(v),
+            None => return Vec::default(),
+        }
+    }
+
+    let (first, rest) = match streams.split_first_mut() {
+        Some(v) if offsets.len() == tks.len() => v,
+        _ => return Vec::default(),
+    };
+
+    // the positions of the other tokens within the document the first token is currently in
+    let mut document = None;
+    let mut positions: Vec<HashSet<u32>> = vec![HashSet::default(); rest.len()];
+
+    let mut matched = BTreeSet::default();
+    while let Some(p) = first.next() {
+        if document != Some(p.document_id) {
+            document = Some(p.document_id);
+            for (stream, ps) in rest.iter_mut().zip(positions.iter_mut()) {
+                ps.clear();
+                stream.seek(p.document_id);
+                while let Some(q) = stream.peek().filter(|q| q.document_id == p.document_id) {
+                    ps.insert(q.position);
+                    stream.next();
+                }
+            }
+        }
+
+        let occurrence = offsets
+            .iter()
+            .map(|o| Posting {
+                document_id: p.document_id,
+                position: p.position + o - offsets[0],
+            })
+            .collect::<Vec<Posting>>();
+
+        if occurrence[1..]
+            .iter()
+            .zip(positions.iter())
+            .all(|(o, ps)| ps.contains(&o.position))
+        {
+            matched.extend(occurrence);
+        }
+    }
+
+    matched.into_iter().collect()
+}
+
 /// own endpoint for relational query, scoring for it should happen here (i.e. Page Rank)
 pub fn execute_relational_query<'a>(
     query: &'a Box<Query>,
@@ -444,11 +679,36 @@ pub fn score_query(
     query: &Box<Query>,
     index: &Index,
     postings: &mut Vec<Posting>,
+    ranking: Ranking,
+) -> Vec<ScoredDocument> {
+    score_query_within(query, index, postings, ranking, &UNLIMITED_BUDGET)
+}
+
+/// scores documents until the budget runs out, those left over are not returned
+pub fn score_query_within(
+    query: &Box<Query>,
+    index: &Index,
+    postings: &mut Vec<Posting>,
+    ranking: Ranking,
+    budget: &QueryBudget,
 ) -> Vec<ScoredDocument> {
     postings.dedup_by_key(|v| v.document_id);
     let mut scored_documents = Vec::default();
 
-    for post in postings {
+    let documents = postings.iter().map(|p| p.document_id).collect();
+    let titles = TitleMatches::new(query, index, &documents);
+    let bm25f = match ranking {
+        Ranking::Bm25F => Some(Bm25FScorer::new(query, index, &documents)),
+        _ => None,
+    };
+    // the postings are in document order, so the frequencies are read in a single pass over each term
+    let mut frequencies = TermFrequencies::new(query, index);
+
+    for (i, post) in postings.iter().enumerate() {
+        if i as u64 % CLOCK_CHECK_INTERVAL == 0 && !budget.check_time() {
+            break;
+        }
+
         let mut page_rank = 0.0;
         let pr = index.page_rank.get(&post.document_id);
         match pr {
@@ -456,9 +716,28 @@ pub fn score_query(
             _ => page_rank = 0.0,
         };
 
+        let relevance = match ranking {
+            Ranking::TfIdf => tfidf_query_with(post.document_id, query, index, &mut frequencies),
+            Ranking::Bm25 => bm25_query_with(post.document_id, query, index, &mut frequencies),
+            Ranking::Bm25F => bm25f
+                .as_ref()
+                .map(|s| s.score(post.document_id, query))
+                .unwrap_or(0.0),
+        };
+
+        // BM25F already weighs title matches through its field weights
+        let mut title_boost = match ranking {
+            Ranking::Bm25F => 0.0,
+            _ => titles.matched_terms(post.document_id) as f64 * TITLE_MATCH_BOOST,
+        };
+        if titles.is_exact(post.document_id) {
+            title_boost += EXACT_TITLE_BOOST;
+        }
+        let relevance = relevance + title_boost;
+
         scored_documents.push(ScoredDocument {
             doc_id: post.document_id,
-            score: tfidf_query(post.document_id, query, index) * 0.9 + page_rank * 0.1,
+            score: relevance * RELEVANCE_WEIGHT + page_rank * PAGE_RANK_WEIGHT,
         });
     }
     return scored_documents;
@@ -481,16 +760,13 @@ enum UnionMergeState {
 }
 
 pub struct UnionMergeIterator<'a> {
-    left_iter: Box<dyn Iterator<Item = Posting> + 'a>,
-    right_iter: Box<dyn Iterator<Item = Posting> + 'a>,
+    left_iter: Box<dyn SkipIterator + 'a>,
+    right_iter: Box<dyn SkipIterator + 'a>,
     state: UnionMergeState,
     last: (Option<Posting>, Option<Posting>),
 }
 impl<'a> UnionMergeIterator<'a> {
-    pub fn new(
-        l: Box<dyn Iterator<Item = Posting> + 'a>,
-        r: Box<dyn Iterator<Item = Posting> + 'a>,
-    ) -> Self {
+    pub fn new(l: Box<dyn SkipIterator + 'a>, r: Box<dyn SkipIterator + 'a>) -> Self {
         Self {
             left_iter: l,
             right_iter: r,
@@ -498,6 +774,23 @@ impl<'a> UnionMergeIterator<'a> {
             last: (None, None),
         }
     }
+
+    /// takes the lower of the current postings
+    fn pick(&mut self) -> Option<Posting> {
+        match self.last {
+            (None, None) => self.state = UnionMergeState::None, // loop around
+            (None, Some(_)) => self.state = UnionMergeState::Right, // pick right
+            (Some(_), None) => self.state = UnionMergeState::Left, // pick left
+            (Some(l), Some(r)) if l <= r => self.state = UnionMergeState::Left,
+            _ => self.state = UnionMergeState::Right,
+        }
+
+        match self.state {
+            UnionMergeState::Left => self.last.0,
+            UnionMergeState::Right => self.last.1,
+            UnionMergeState::None => None,
+        }
+    }
 }
 
 impl<'a> Iterator for UnionMergeIterator<'a> {
@@ -518,19 +811,31 @@ impl<'a> Iterator for UnionMergeIterator<'a> {
             UnionMergeState::None => (self.left_iter.next(), self.right_iter.next()),
         };
 
-        match self.last {
-            (None, None) => self.state = UnionMergeState::None, // loop around
-            (None, Some(_)) => self.state = UnionMergeState::Right, // pick right
-            (Some(_), None) => self.state = UnionMergeState::Left, // pick left
-            (Some(l), Some(r)) if l <= r => self.state = UnionMergeState::Left,
-            _ => self.state = UnionMergeState::Right,
-        }
+        self.pick()
+    }
+}
 
-        match self.state {
-            UnionMergeState::Left => self.last.0,
-            UnionMergeState::Right => self.last.1,
-            UnionMergeState::None => None,
-        }
+impl<'a> SkipIterator for UnionMergeIterator<'a> {
+    fn skip_to(&mut self, doc_id: u32) -> Option<Posting> {
+        // the side which wasn't picked last time still holds a posting, which is kept if it is far enough
+        let (left, right) = match self.state {
+            UnionMergeState::Left => (None, self.last.1),
+            UnionMergeState::Right => (self.last.0, None),
+            UnionMergeState::None => (None, None),
+        };
+
+        self.last = (
+            match left {
+                Some(l) if l.document_id >= doc_id => Some(l),
+                _ => self.left_iter.skip_to(doc_id),
+            },
+            match right {
+                Some(r) if r.document_id >= doc_id => Some(r),
+                _ => self.right_iter.skip_to(doc_id),
+            },
+        );
+
+        self.pick()
     }
 }
 
@@ -547,17 +852,14 @@ pub enum IntersectionMergeState {
 }
 
 pub struct IntersectionMergeIterator<'a> {
-    pub left_iter: Box<dyn Iterator<Item = Posting> + 'a>,
-    pub right_iter: Box<dyn Iterator<Item = Posting> + 'a>,
+    pub left_iter: Box<dyn SkipIterator + 'a>,
+    pub right_iter: Box<dyn SkipIterator + 'a>,
     pub state: IntersectionMergeState,
     pub curr: (Option<Posting>, Option<Posting>),
 }
 
 impl<'a> IntersectionMergeIterator<'a> {
-    pub fn new(
-        l: Box<dyn Iterator<Item = Posting> + 'a>,
-        r: Box<dyn Iterator<Item = Posting> + 'a>,
-    ) -> Self {
+    pub fn new(l: Box<dyn SkipIterator + 'a>, r: Box<dyn SkipIterator + 'a>) -> Self {
         Self {
             left_iter: l,
             right_iter: r,
@@ -609,13 +911,15 @@ impl<'a> IntersectionMergeIterator<'a> {
                             self.state = IntersectionMergeState::RightThenLeftStart;
                             break;
                         }
-                    } else {
-                        if l <= r {
-                            skip_side = SkipSide::Left;
-                        } else {
-                            skip_side = SkipSide::Right;
-                        }
                     }
+
+                    // the side which is behind jumps straight to the document of the other
+                    self.curr = if l.document_id < r.document_id {
+                        (self.left_iter.skip_to(r.document_id), self.curr.1)
+                    } else {
+                        (self.curr.0, self.right_iter.skip_to(l.document_id))
+                    };
+                    continue;
                 }
             }
 
@@ -646,6 +950,9 @@ impl<'a> Iterator for IntersectionMergeIterator<'a> {
     }
 }
 
+/// the merged postings are read through, the inputs skip while merging
+impl<'a> SkipIterator for IntersectionMergeIterator<'a> {}
+
 // ------
 // Difference
 // ------
@@ -653,15 +960,13 @@ impl<'a> Iterator for IntersectionMergeIterator<'a> {
 pub enum DifferenceMergeState {
     None,
     Left,
-    LeftThenSkipRightStart,
-    LeftThenSkipRightFinish,
 }
 
+/// yields the left postings of every document which has no postings on the right
 pub struct DifferenceMergeIterator<'a> {
     left_iter: Box<dyn Iterator<Item = Posting> + 'a>,
     right_iter: Box<dyn Iterator<Item = Posting> + 'a>,
     state: DifferenceMergeState,
-    highest_doc_r: i32,
     curr: (Option<Posting>, Option<Posting>),
 }
 
@@ -674,7 +979,6 @@ impl<'a> DifferenceMergeIterator<'a> {
             left_iter: l,
             right_iter: r,
             state: DifferenceMergeState::None,
-            highest_doc_r: -1,
             curr: (None, None),
         }
     }
@@ -685,49 +989,30 @@ impl<'a> DifferenceMergeIterator<'a> {
                 // last time left side was 'get', advance it
                 (self.left_iter.next(), self.curr.1)
             }
-            DifferenceMergeState::LeftThenSkipRightFinish => {
-                // last time right side was 'get', advance it
-                (self.curr.0, self.right_iter.next())
-            }
             DifferenceMergeState::None => (self.left_iter.next(), self.right_iter.next()),
-            DifferenceMergeState::LeftThenSkipRightStart => {
-                self.state = DifferenceMergeState::LeftThenSkipRightFinish;
-                (self.left_iter.next(), self.right_iter.next())
-            }
         };
 
+        // the right side only ever moves past documents lower than the current left one,
+        // so a left posting is excluded exactly when the right side sits on the same document
         let mut skip_side;
         loop {
             match self.curr {
-                (None, None) => {
+                (None, _) => {
                     self.state = DifferenceMergeState::None;
                     break;
                 }
-                (None, Some(r)) => {
-                    skip_side = SkipSide::Right;
-                    self.highest_doc_r = r.document_id as i32;
-                }
-                (Some(l), None) => {
-                    if l.document_id != self.highest_doc_r as u32 {
-                        self.state = DifferenceMergeState::Left;
-                        break;
-                    } else {
-                        skip_side = SkipSide::Left;
-                    }
+                (Some(_), None) => {
+                    self.state = DifferenceMergeState::Left;
+                    break;
                 }
                 (Some(l), Some(r)) => {
-                    self.highest_doc_r = r.document_id as i32;
-
                     if l.document_id < r.document_id {
                         self.state = DifferenceMergeState::Left;
                         break;
-                    } else if l <= r {
+                    } else if l.document_id == r.document_id {
                         skip_side = SkipSide::Left
-                    } else if l > r {
-                        skip_side = SkipSide::Right
                     } else {
-                        self.state = DifferenceMergeState::LeftThenSkipRightStart;
-                        break;
+                        skip_side = SkipSide::Right
                     }
                 }
             };
@@ -741,11 +1026,8 @@ impl<'a> DifferenceMergeIterator<'a> {
 
     fn get(&self) -> Option<Posting> {
         match self.state {
-            DifferenceMergeState::Left | DifferenceMergeState::LeftThenSkipRightStart => {
-                self.curr.0
-            }
+            DifferenceMergeState::Left => self.curr.0,
             DifferenceMergeState::None => None,
-            _ => panic!(), // should not receive any skips here
         }
     }
 }
@@ -772,8 +1054,8 @@ enum DistanceMergeState {
 }
 
 pub struct DistanceMergeIterator<'a> {
-    left_iter: Box<dyn Iterator<Item = Posting> + 'a>,
-    right_iter: Box<dyn Iterator<Item = Posting> + 'a>,
+    left_iter: Box<dyn SkipIterator + 'a>,
+    right_iter: Box<dyn SkipIterator + 'a>,
     state: DistanceMergeState,
     dst: u32,
     in_streak: bool,
@@ -782,11 +1064,7 @@ pub struct DistanceMergeIterator<'a> {
 }
 
 impl<'a> DistanceMergeIterator<'a> {
-    pub fn new(
-        dst: u32,
-        l: Box<dyn Iterator<Item = Posting> + 'a>,
-        r: Box<dyn Iterator<Item = Posting> + 'a>,
-    ) -> Self {
+    pub fn new(dst: u32, l: Box<dyn SkipIterator + 'a>, r: Box<dyn SkipIterator + 'a>) -> Self {
         Self {
             left_iter: l,
             right_iter: r,
@@ -833,11 +1111,17 @@ impl<'a> DistanceMergeIterator<'a> {
                         }
                     }
 
-                    if l < r {
-                        self.in_streak = false;
+                    self.in_streak = false;
+                    // postings of different documents can't be near each other, so jump the one behind ahead
+                    if l.document_id < r.document_id {
+                        self.curr = (self.left_iter.skip_to(r.document_id), self.curr.1);
+                        continue;
+                    } else if r.document_id < l.document_id {
+                        self.curr = (self.curr.0, self.right_iter.skip_to(l.document_id));
+                        continue;
+                    } else if l < r {
                         skip_side = SkipSide::Left
                     } else {
-                        self.in_streak = false;
                         skip_side = SkipSide::Right
                     }
                 }
@@ -882,3 +1166,6 @@ impl<'a> Iterator for DistanceMergeIterator<'a> {
         self.get()
     }
 }
+
+/// the merged postings are read through, the inputs skip while merging
+impl<'a> SkipIterator for DistanceMergeIterator<'a> {}
@@ -1,7 +1,8 @@
 use crate::{
-    DifferenceMergeIterator, DistanceMergeIterator, IntersectionMergeIterator, UnionMergeIterator,
+    DifferenceMergeIterator, DistanceMergeIterator, IntersectionMergeIterator, PostingIterator,
+    UnionMergeIterator,
 };
-use index::Posting;
+use index::{EncodedPostingList, Posting, SkipIterator, VbyteEncoder};
 
 // --------
 // Union
@@ -515,6 +516,46 @@ fn test_difference_merge_iterator_empty_both() {
 // // Distance
 // // --------
 
+#[test]
+fn test_difference_merge_iterator_excludes_whole_documents() {
+    let left = vec![
+        Posting {
+            document_id: 1,
+            position: 5,
+        },
+        Posting {
+            document_id: 1,
+            position: 9,
+        },
+        Posting {
+            document_id: 4,
+            position: 0,
+        },
+    ];
+
+    let right = vec![
+        Posting {
+            document_id: 1,
+            position: 2,
+        },
+        Posting {
+            document_id: 2,
+            position: 0,
+        },
+    ];
+
+    let iter =
+        DifferenceMergeIterator::new(Box::new(left.into_iter()), Box::new(right.into_iter()));
+
+    assert_eq!(
+        vec![Posting {
+            document_id: 4,
+            position: 0,
+        }],
+        iter.collect::<Vec<Posting>>()
+    );
+}
+
 #[test]
 fn test_distance_merge_iterator() {
     let left = vec![
@@ -779,3 +820,104 @@ fn test_distance_merge_iterator_many_docs() {
 
     assert_eq!(target, iter.collect::<Vec<Posting>>());
 }
+
+// --------
+// Skipping
+// --------
+
+/// a long list with several postings in most documents
+fn long_postings() -> Vec<Posting> {
+    (0..2000u32)
+        .flat_map(|d| {
+            (0..d % 3 + 1).map(move |p| Posting {
+                document_id: d,
+                position: p * 2 + d % 5,
+            })
+        })
+        .collect()
+}
+
+fn short_postings() -> Vec<Posting> {
+    vec![
+        Posting {
+            document_id: 3,
+            position: 4,
+        },
+        Posting {
+            document_id: 700,
+            position: 1,
+        },
+        Posting {
+            document_id: 700,
+            position: 3,
+        },
+        Posting {
+            document_id: 1501,
+            position: 2,
+        },
+        Posting {
+            document_id: 2500,
+            position: 0,
+        },
+    ]
+}
+
+#[test]
+fn test_union_merge_iterator_skip_to() {
+    let mut iter = UnionMergeIterator::new(
+        Box::new(long_postings().into_iter()),
+        Box::new(short_postings().into_iter()),
+    );
+
+    let mut all = long_postings();
+    all.extend(short_postings());
+    all.sort();
+
+    assert_eq!(iter.next(), Some(all[0]));
+    for target in [3, 3, 700, 1500, 1999] {
+        let expected = all.iter().find(|p| p.document_id >= target).cloned();
+        let skipped = iter.skip_to(target);
+        assert_eq!(skipped, expected, "skip to {}", target);
+        all.retain(|p| Some(*p) > skipped);
+    }
+    assert_eq!(iter.collect::<Vec<Posting>>(), all);
+}
+
+#[test]
+fn test_intersection_merge_iterator_skips_encoded() {
+    let long = EncodedPostingList::<VbyteEncoder<Posting, true>>::from_iter(long_postings());
+
+    // reading through every posting instead of skipping
+    let target = IntersectionMergeIterator::new(
+        Box::new(PostingIterator::new(long_postings().into_iter())),
+        Box::new(PostingIterator::new(short_postings().into_iter())),
+    )
+    .collect::<Vec<Posting>>();
+    assert!(target.iter().any(|p| p.document_id == 1501));
+
+    let iter = IntersectionMergeIterator::new(
+        Box::new(long.into_iter()),
+        Box::new(short_postings().into_iter()),
+    );
+    assert_eq!(iter.collect::<Vec<Posting>>(), target);
+}
+
+#[test]
+fn test_distance_merge_iterator_skips_encoded() {
+    let long = EncodedPostingList::<VbyteEncoder<Posting, true>>::from_iter(long_postings());
+
+    let target = DistanceMergeIterator::new(
+        1,
+        Box::new(PostingIterator::new(long_postings().into_iter())),
+        Box::new(PostingIterator::new(short_postings().into_iter())),
+    )
+    .collect::<Vec<Posting>>();
+    assert!(target.iter().any(|p| p.document_id == 700));
+
+    let iter = DistanceMergeIterator::new(
+        1,
+        Box::new(long.into_iter()),
+        Box::new(short_postings().into_iter()),
+    );
+    assert_eq!(iter.collect::<Vec<Posting>>(), target);
+}
@@ -0,0 +1,118 @@
+use crate::execute_query;
+use index::{index::Index, index_structs::Posting, SkipIterator};
+use parser::ast::Query;
+use std::collections::{BTreeSet, HashMap};
+
+/// the number of words shown in a snippet
+pub const SNIPPET_LENGTH: usize = 30;
+
+/// a window of a document's text,
+/// highlights are the character ranges (start inclusive, end exclusive) of the matched words within it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// the matched positions of every document in the postings
+pub fn matched_positions(postings: &[Posting]) -> HashMap<u32, Vec<u32>> {
+    let mut positions: HashMap<u32, Vec<u32>> = HashMap::default();
+    for p in postings {
+        positions.entry(p.document_id).or_default().push(p.position);
+    }
+    positions
+}
+
+/// the matched positions of the given documents alone, for results gathered without positions.
+/// The query is executed again with its positions, jumping straight to each of the documents
+pub fn matched_positions_within(
+    query: &Box<Query>,
+    index: &Index,
+    documents: &[u32],
+) -> HashMap<u32, Vec<u32>> {
+    let mut documents = documents.to_vec();
+    documents.sort_unstable();
+
+    let mut postings = execute_query(query, index);
+    let mut positions: HashMap<u32, Vec<u32>> = HashMap::default();
+    let mut current = postings.next();
+    for d in documents {
+        if current.map_or(false, |p| p.document_id < d) {
+            current = postings.skip_to(d);
+        }
+        while let Some(p) = current.filter(|p| p.document_id == d) {
+            positions.entry(d).or_default().push(p.position);
+            current = postings.next();
+        }
+    }
+    positions
+}
+
+/// builds the snippet of a document around the window holding most of its matched positions,
+/// documents without any start from their first word
+pub fn build_snippet(
+    doc_id: u32,
+    positions: &[u32],
+    index: &Index,
+    length: usize,
+) -> Option<Snippet> {
+    // only the text of the document the snippet is for is read
+    let text = index.get_text(doc_id)?;
+    let words = text.split(' ').collect::<Vec<&str>>();
+    let matched = positions
+        .iter()
+        .map(|p| *p as usize)
+        .filter(|p| *p < words.len())
+        .collect::<BTreeSet<usize>>();
+
+    let start = snippet_start(&matched, words.len(), length);
+    let end = (start + length).min(words.len());
+
+    let mut snippet = Snippet::default();
+    let mut chars = 0;
+    for (pos, word) in (start..end).zip(&words[start..end]) {
+        // positions without a token
+        if word.is_empty() {
+            continue;
+        }
+
+        if chars > 0 {
+            snippet.text.push(' ');
+            chars += 1;
+        }
+
+        let word_chars = word.chars().count();
+        if matched.contains(&pos) {
+            snippet.highlights.push((chars, chars + word_chars));
+        }
+        snippet.text.push_str(word);
+        chars += word_chars;
+    }
+
+    Some(snippet)
+}
+
+/// picks the window with the most matches, leaving a quarter of it as leading context
+fn snippet_start(matched: &BTreeSet<usize>, words: usize, length: usize) -> usize {
+    let context = length / 4;
+    let span = length - context;
+    let positions = matched.iter().cloned().collect::<Vec<usize>>();
+
+    let mut best: Option<(usize, usize)> = None;
+    let mut end = 0;
+    for (i, p) in positions.iter().enumerate() {
+        end = end.max(i);
+        while end < positions.len() && positions[end] < p + span {
+            end += 1;
+        }
+        if best.map(|(count, _)| end - i > count).unwrap_or(true) {
+            best = Some((end - i, *p));
+        }
+    }
+
+    match best {
+        // keep the window full towards the end of the document
+        Some((_, p)) => p.saturating_sub(context).min(words.saturating_sub(length)),
+        None => 0,
+    }
+}
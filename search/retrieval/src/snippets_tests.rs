@@ -0,0 +1,131 @@
+use crate::execute_query;
+use crate::snippets::{build_snippet, matched_positions, matched_positions_within, Snippet};
+use index::index::Index;
+use index::utils::get_document_with_text;
+use index::{PreIndex, Posting};
+use parser::ast::Query;
+
+fn numbered_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+    let text = (0..40).map(|i| format!("w{}", i)).collect::<Vec<String>>();
+
+    pre_idx
+        .add_document(get_document_with_text(0, "t", vec![], &text.join(" "), vec![], ""))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+#[test]
+fn test_snippet_around_matches() {
+    let idx = numbered_index();
+
+    assert_eq!(
+        build_snippet(0, &[21, 20], &idx, 8),
+        Some(Snippet {
+            text: "w18 w19 w20 w21 w22 w23 w24 w25".to_string(),
+            highlights: vec![(8, 11), (12, 15)],
+        })
+    );
+}
+
+#[test]
+fn test_snippet_densest_window() {
+    let idx = numbered_index();
+
+    let snippet = build_snippet(0, &[2, 30, 31, 32], &idx, 8).unwrap();
+    assert_eq!(snippet.text, "w28 w29 w30 w31 w32 w33 w34 w35");
+    assert_eq!(snippet.highlights, vec![(8, 11), (12, 15), (16, 19)]);
+}
+
+#[test]
+fn test_snippet_without_matches() {
+    let idx = numbered_index();
+
+    let snippet = build_snippet(0, &[], &idx, 4).unwrap();
+    assert_eq!(snippet.text, "w0 w1 w2 w3");
+    assert!(snippet.highlights.is_empty());
+}
+
+#[test]
+fn test_snippet_end_of_document() {
+    let idx = numbered_index();
+
+    // the title is the last word
+    let snippet = build_snippet(0, &[39], &idx, 8).unwrap();
+    assert_eq!(snippet.text, "w33 w34 w35 w36 w37 w38 w39 t");
+    assert_eq!(snippet.highlights, vec![(24, 27)]);
+}
+
+#[test]
+fn test_snippet_skips_gaps() {
+    let mut pre_idx = PreIndex::default();
+    pre_idx
+        .add_document(get_document_with_text(
+            3,
+            "The Lord of the Rings",
+            vec![],
+            "bőrönd aaa",
+            vec![],
+            "",
+        ))
+        .unwrap();
+    let idx = Index::from_pre_index(pre_idx);
+
+    // offsets count characters rather than bytes
+    assert_eq!(
+        build_snippet(3, &[3], &idx, 10),
+        Some(Snippet {
+            text: "bőrönd aaa lord ring".to_string(),
+            highlights: vec![(11, 15)],
+        })
+    );
+    assert_eq!(build_snippet(4, &[3], &idx, 10), None);
+}
+
+#[test]
+fn test_matched_positions() {
+    let postings = vec![
+        Posting {
+            document_id: 1,
+            position: 4,
+        },
+        Posting {
+            document_id: 1,
+            position: 7,
+        },
+        Posting {
+            document_id: 2,
+            position: 0,
+        },
+    ];
+
+    let positions = matched_positions(&postings);
+    assert_eq!(positions.get(&1), Some(&vec![4, 7]));
+    assert_eq!(positions.get(&2), Some(&vec![0]));
+    assert_eq!(positions.get(&3), None);
+}
+
+#[test]
+fn test_matched_positions_within() {
+    let mut pre_idx = PreIndex::default();
+    for d in 0..300u32 {
+        let text = format!("w{} lorem w{} ipsum lorem", d % 7, d % 5);
+        pre_idx
+            .add_document(get_document_with_text(d, "t", vec![], &text, vec![], ""))
+            .unwrap();
+    }
+    let idx = Index::from_pre_index(pre_idx);
+    let query = Box::new(Query::FreetextQuery {
+        tokens: vec!["w3".to_string(), "lorem".to_string()],
+    });
+
+    let all = matched_positions(&execute_query(&query, &idx).collect::<Vec<Posting>>());
+    let documents = vec![250, 3, 17, 299, 1000];
+    let within = matched_positions_within(&query, &idx, &documents);
+
+    assert_eq!(within.len(), 4);
+    for d in &documents[..4] {
+        assert_eq!(within[d], all[d], "document {}", d);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    bm25_query_with, bm25_upper_bound, is_exact_title, tfidf_query_with, tfidf_upper_bound,
+    QueryBudget, Ranking, ScoredDocument, TermFrequencies, CLOCK_CHECK_INTERVAL, EXACT_TITLE_BOOST, PAGE_RANK_WEIGHT,
+    RELEVANCE_WEIGHT, TITLE_MATCH_BOOST,
+};
+use index::{
+    index::Index, index_structs::DocumentFrequency, DocumentStream, PosRange, PostingStream,
+};
+use parser::ast::{Query, StructureElem};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+/// relative headroom given to upper bounds, so rounding never skips a document belonging in the top k
+const BOUND_SLACK: f64 = 1e-9;
+
+/// the documents of a single query term, positions are only read for the documents whose title is checked.
+/// Every document and position read is charged to the budget, the cursor ends once it runs out
+struct TermCursor<'a> {
+    documents: DocumentStream,
+    /// trails behind the documents, moved up when the positions of the current one are needed
+    postings: PostingStream,
+    current: Option<DocumentFrequency>,
+    /// the most the term can add to the relevance of a document containing it
+    upper_bound: f64,
+    budget: &'a QueryBudget,
+}
+
+impl<'a> TermCursor<'a> {
+    fn new(
+        documents: DocumentStream,
+        postings: PostingStream,
+        upper_bound: f64,
+        budget: &'a QueryBudget,
+    ) -> Self {
+        let mut cursor = Self {
+            documents,
+            postings,
+            current: None,
+            upper_bound,
+            budget,
+        };
+        cursor.next_document();
+        cursor
+    }
+
+    fn doc(&self) -> Option<u32> {
+        self.current.map(|d| d.document_id)
+    }
+
+    /// true if the term appears at a position within the range in the current document
+    fn appears_within(&mut self, range: &PosRange) -> bool {
+        let doc = match self.doc() {
+            Some(v) => v,
+            None => return false,
+        };
+
+        self.postings.seek(doc);
+        while let Some(p) = self.postings.peek().filter(|p| p.document_id == doc) {
+            if !self.budget.charge(1) {
+                return false;
+            }
+            if p.position >= range.start_pos && p.position < range.end_pos {
+                return true;
+            }
+            self.postings.next();
+        }
+        false
+    }
+
+    /// moves to the first document at or after the target
+    fn seek(&mut self, target: u32) {
+        if self.doc().map_or(false, |d| d < target) {
+            self.documents.seek(target);
+            self.next_document();
+        }
+    }
+
+    /// moves past the current document
+    fn next_document(&mut self) {
+        self.current = match self.budget.charge(1) {
+            true => self.documents.next(),
+            false => None,
+        };
+    }
+}
+
+/// orders scored documents from worst to best, ties go to the lower document id
+struct Ranked(ScoredDocument);
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .score
+            .partial_cmp(&other.0.score)
+            .unwrap_or(Ordering::Equal)
+            .then(other.0.doc_id.cmp(&self.0.doc_id))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// true for the queries `top_k_query_within` can rank, free text ranked by TF-IDF or BM25
+pub fn supports_top_k(query: &Box<Query>, ranking: Ranking) -> bool {
+    matches!(
+        (&**query, ranking),
+        (Query::FreetextQuery { .. }, Ranking::TfIdf | Ranking::Bm25)
+    )
+}
+
+/// the k best documents of a free text query, best first with ties broken by document id.
+/// Documents whose upper bound cannot beat the current k-th best are skipped without being scored (WAND),
+/// the result is the same as scoring every match. Returns None for the queries `supports_top_k` rejects,
+/// which have to be scored exhaustively
+pub fn top_k_query_within(
+    query: &Box<Query>,
+    index: &Index,
+    k: usize,
+    ranking: Ranking,
+    budget: &QueryBudget,
+) -> Option<Vec<ScoredDocument>> {
+    let tokens = match &**query {
+        Query::FreetextQuery { tokens } if supports_top_k(query, ranking) => tokens,
+        _ => return None,
+    };
+    if k == 0 {
+        return Some(Vec::default());
+    }
+
+    // repeated terms count once per occurrence
+    let mut weights: HashMap<&str, f64> = HashMap::default();
+    for t in tokens {
+        *weights.entry(t.as_str()).or_default() += 1.0;
+    }
+
+    let mut cursors = Vec::with_capacity(weights.len());
+    for (term, weight) in &weights {
+        let documents = match index.stream_documents(term) {
+            Some(v) => v,
+            None => continue,
+        };
+        let postings = index.stream_postings(term).unwrap();
+        let term_bound = match ranking {
+            Ranking::Bm25 => bm25_upper_bound(term, index),
+            _ => tfidf_upper_bound(term, index),
+        };
+
+        cursors.push(TermCursor::new(
+            documents,
+            postings,
+            weight * term_bound + TITLE_MATCH_BOOST,
+            budget,
+        ));
+    }
+
+    // a title can only be exact if every term appears somewhere
+    let exact_possible = cursors.len() == weights.len();
+    let max_page_rank = index.page_rank.values().cloned().fold(0.0, f64::max);
+    let title: String = StructureElem::Title.into();
+    // documents are scored in ascending order
+    let mut frequencies = TermFrequencies::new(query, index);
+
+    let mut top: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(k + 1);
+    let mut evaluated = 0;
+    loop {
+        cursors.sort_by_key(|c| (c.doc().is_none(), c.doc()));
+        let threshold = match top.len() == k {
+            true => top.peek().map(|r| r.0 .0.score),
+            false => None,
+        };
+
+        // the first document which could beat the threshold given the terms up to and including it
+        let mut relevance_bound = 0.0;
+        let mut pivot = None;
+        for (i, c) in cursors.iter().enumerate() {
+            let doc = match c.doc() {
+                Some(d) => d,
+                None => break,
+            };
+            relevance_bound += c.upper_bound;
+
+            let exact_bound = match exact_possible && i + 1 == cursors.len() {
+                true => EXACT_TITLE_BOOST,
+                false => 0.0,
+            };
+            let bound = (relevance_bound + exact_bound) * RELEVANCE_WEIGHT
+                + max_page_rank * PAGE_RANK_WEIGHT;
+            if threshold.map_or(true, |t| bound * (1.0 + BOUND_SLACK) >= t) {
+                pivot = Some((i, doc));
+                break;
+            }
+        }
+
+        let (p, pivot_doc) = match pivot {
+            Some(v) => v,
+            None => break,
+        };
+
+        if cursors[0].doc() != Some(pivot_doc) {
+            // nothing before the pivot can make it, so skip straight to it
+            for c in cursors[..p].iter_mut() {
+                c.seek(pivot_doc);
+            }
+            continue;
+        }
+
+        if evaluated % CLOCK_CHECK_INTERVAL == 0 && !budget.check_time() {
+            break;
+        }
+        evaluated += 1;
+
+        // the same score the exhaustive path gives the document
+        let title_range = index.get_extent_for(&title, &pivot_doc);
+        let matched_terms = cursors
+            .iter_mut()
+            .filter(|c| c.doc() == Some(pivot_doc))
+            .map(|c| title_range.map_or(false, |r| c.appears_within(r)))
+            .filter(|m| *m)
+            .count();
+
+        let relevance = match ranking {
+            Ranking::Bm25 => bm25_query_with(pivot_doc, query, index, &mut frequencies),
+            _ => tfidf_query_with(pivot_doc, query, index, &mut frequencies),
+        };
+        let mut title_boost = matched_terms as f64 * TITLE_MATCH_BOOST;
+        if matched_terms == weights.len() && is_exact_title(pivot_doc, tokens, index) {
+            title_boost += EXACT_TITLE_BOOST;
+        }
+        let relevance = relevance + title_boost;
+        let page_rank = index.page_rank.get(&pivot_doc).cloned().unwrap_or(0.0);
+
+        top.push(Reverse(Ranked(ScoredDocument {
+            doc_id: pivot_doc,
+            score: relevance * RELEVANCE_WEIGHT + page_rank * PAGE_RANK_WEIGHT,
+        })));
+        if top.len() > k {
+            top.pop();
+        }
+
+        for c in cursors.iter_mut().filter(|c| c.doc() == Some(pivot_doc)) {
+            c.next_document();
+        }
+    }
+
+    Some(
+        top.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(Ranked(d))| d)
+            .collect(),
+    )
+}
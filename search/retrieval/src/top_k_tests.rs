@@ -0,0 +1,183 @@
+use crate::budget::{BudgetExceeded, QueryBudget};
+use crate::search::{execute_query, score_query, ScoredDocument};
+use crate::top_k::{supports_top_k, top_k_query_within};
+use crate::Ranking;
+use index::index::Index;
+use index::utils::get_document_with_text;
+use index::{Posting, PreIndex};
+use parser::ast::Query;
+use std::cmp::Ordering;
+
+const TERMS: [&str; 3] = ["wandalpha", "wandbeta", "wandgamma"];
+
+fn top_k_index() -> Index {
+    let mut pre_idx = PreIndex::default();
+
+    for d in 1..=40u32 {
+        let mut words = Vec::default();
+        for (i, t) in TERMS.iter().enumerate() {
+            for _ in 0..(d * (i as u32 + 3)) % 5 {
+                words.push(*t);
+            }
+        }
+        for _ in 0..d % 7 {
+            words.push("wandfiller");
+        }
+        let title = match d % 9 {
+            0 => "wandalpha wandbeta",
+            4 => "wandgamma",
+            _ => "wandother",
+        };
+
+        pre_idx
+            .add_document(get_document_with_text(
+                d,
+                &format!("{} {}", title, d),
+                vec![],
+                &words.join(" "),
+                vec![],
+                "",
+            ))
+            .unwrap();
+    }
+    pre_idx
+        .add_document(get_document_with_text(
+            41,
+            "wandbeta wandgamma",
+            vec![],
+            "wandbeta",
+            vec![],
+            "",
+        ))
+        .unwrap();
+
+    Index::from_pre_index(pre_idx)
+}
+
+fn freetext(tokens: &[&str]) -> Box<Query> {
+    Box::new(Query::FreetextQuery {
+        tokens: tokens.iter().map(|t| t.to_string()).collect(),
+    })
+}
+
+/// scores every match and sorts them, the way relevance ordered results are produced without top k
+fn exhaustive(query: &Box<Query>, index: &Index, ranking: Ranking) -> Vec<(u32, f64)> {
+    let mut postings = execute_query(query, index).collect::<Vec<Posting>>();
+    let mut scored = score_query(query, index, &mut postings, ranking);
+    scored.sort_unstable_by(|d1, d2| {
+        d2.score
+            .partial_cmp(&d1.score)
+            .unwrap_or(Ordering::Less)
+            .then(d1.doc_id.cmp(&d2.doc_id))
+    });
+    scored.into_iter().map(|d| (d.doc_id, d.score)).collect()
+}
+
+fn ranked(docs: Vec<ScoredDocument>) -> Vec<(u32, f64)> {
+    docs.into_iter().map(|d| (d.doc_id, d.score)).collect()
+}
+
+#[test]
+fn test_top_k_matches_exhaustive() {
+    let idx = top_k_index();
+    let queries = vec![
+        freetext(&["wandalpha"]),
+        freetext(&["wandalpha", "wandbeta"]),
+        freetext(&["wandbeta", "wandgamma"]),
+        freetext(&["wandalpha", "wandbeta", "wandgamma", "wandmissing"]),
+        freetext(&["wandgamma", "wandgamma", "wandfiller"]),
+    ];
+
+    for query in &queries {
+        for ranking in [Ranking::TfIdf, Ranking::Bm25] {
+            let expected = exhaustive(query, &idx, ranking);
+            assert!(!expected.is_empty());
+
+            for k in 1..=expected.len() + 2 {
+                let top =
+                    top_k_query_within(query, &idx, k, ranking, &QueryBudget::unlimited()).unwrap();
+                assert_eq!(
+                    ranked(top),
+                    expected.iter().take(k).cloned().collect::<Vec<_>>(),
+                    "top {} of {} ranked by {:?}",
+                    k,
+                    query,
+                    ranking
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_top_k_exact_title_first() {
+    let idx = top_k_index();
+    let query = freetext(&["wandbeta", "wandgamma"]);
+
+    let top =
+        top_k_query_within(&query, &idx, 1, Ranking::TfIdf, &QueryBudget::unlimited()).unwrap();
+    assert_eq!(top.iter().map(|d| d.doc_id).collect::<Vec<u32>>(), vec![41]);
+}
+
+#[test]
+fn test_top_k_empty() {
+    let idx = top_k_index();
+
+    let top = top_k_query_within(
+        &freetext(&["wandalpha"]),
+        &idx,
+        0,
+        Ranking::TfIdf,
+        &QueryBudget::unlimited(),
+    );
+    assert_eq!(top.map(|t| t.len()), Some(0));
+
+    let top = top_k_query_within(
+        &freetext(&["wandmissing"]),
+        &idx,
+        10,
+        Ranking::Bm25,
+        &QueryBudget::unlimited(),
+    );
+    assert_eq!(top.map(|t| t.len()), Some(0));
+}
+
+#[test]
+fn test_top_k_unsupported() {
+    let idx = top_k_index();
+    let phrase = Box::new(Query::PhraseQuery {
+        tks: vec!["wandalpha".to_string(), "wandbeta".to_string()],
+        offsets: vec![0, 1],
+    });
+
+    assert!(top_k_query_within(
+        &freetext(&["wandalpha"]),
+        &idx,
+        10,
+        Ranking::Bm25F,
+        &QueryBudget::unlimited()
+    )
+    .is_none());
+    assert!(
+        top_k_query_within(&phrase, &idx, 10, Ranking::TfIdf, &QueryBudget::unlimited()).is_none()
+    );
+    assert!(supports_top_k(&freetext(&["wandalpha"]), Ranking::Bm25));
+    assert!(!supports_top_k(&phrase, Ranking::Bm25));
+}
+
+#[test]
+fn test_top_k_within_postings_budget() {
+    let idx = top_k_index();
+    let query = freetext(&TERMS);
+
+    let budget = QueryBudget::unlimited();
+    let all = top_k_query_within(&query, &idx, 100, Ranking::TfIdf, &budget).unwrap();
+    assert!(budget.postings_read() > 10);
+
+    // the documents read count towards the budget, reading stops once it runs out
+    let budget = QueryBudget::new(None, Some(10));
+    let partial = top_k_query_within(&query, &idx, 100, Ranking::TfIdf, &budget).unwrap();
+    assert_eq!(budget.exceeded(), Some(BudgetExceeded::Postings));
+    assert!(!partial.is_empty());
+    assert!(partial.len() < all.len());
+}